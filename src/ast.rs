@@ -1,28 +1,198 @@
+pub use crate::types::Type;
+
+/// A range of bytes in the source code, with `end` excluded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// Returns the smallest span containing both `self` and `other`
+    pub fn to(&self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+
+    /// Moves the span forward by `offset` bytes
+    pub fn shifted(&self, offset: usize) -> Span {
+        Span::new(self.start + offset, self.end + offset)
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Function<'input> {
     pub name: &'input str,
-    pub args: Vec<&'input str>,
+    pub args: Vec<FunctionArgument<'input>>,
+    /// The type after `->`, or `i64` if not specified
+    pub return_type: Type,
     pub block: Block<'input>,
+    pub span: Span,
+    /// The span of just the name, which is where the errors about the function point to
+    pub name_span: Span,
+    /// The text of the `///` comments preceding the function, one line for each comment
+    pub doc_comment: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FunctionArgument<'input> {
+    pub name: &'input str,
+    /// The declared type, or `i64` if not specified
+    pub ty: Type,
+    pub span: Span,
+}
+
+/// A module-level `const NAME = expression;`, evaluated at compile time
+#[derive(Debug, PartialEq)]
+pub struct Constant<'input> {
+    pub name: &'input str,
+    pub expression: Expression<'input>,
+    pub span: Span,
+    pub name_span: Span,
+    pub doc_comment: Option<String>,
+}
+
+/// An `extern fn name(args);` declaration, for a function provided by the host program
+#[derive(Debug, PartialEq)]
+pub struct ExternFunction<'input> {
+    pub name: &'input str,
+    pub args: Vec<FunctionArgument<'input>>,
+    pub return_type: Type,
+    pub span: Span,
+    pub name_span: Span,
+    pub doc_comment: Option<String>,
+}
+
+/// An `import "path";` declaration, which lets the file call the functions of another
+/// one, qualified by the name of its module
+#[derive(Debug, PartialEq)]
+pub struct Import<'input> {
+    pub path: &'input str,
+    /// The span of the path, including the quotes
+    pub span: Span,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Program<'input> {
+    pub imports: Vec<Import<'input>>,
+    pub functions: Vec<Function<'input>>,
+    pub constants: Vec<Constant<'input>>,
+    pub extern_functions: Vec<ExternFunction<'input>>,
+}
+
+impl Program<'_> {
+    /// Moves all the spans forward by `offset` bytes. When a program is made of several
+    /// files, each of them is parsed on its own and then shifted, so that the spans of
+    /// different files never overlap and still tell where each error is
+    pub fn shift_spans(&mut self, offset: usize) {
+        for import in self.imports.iter_mut() {
+            import.span = import.span.shifted(offset);
+        }
+        for function in self.functions.iter_mut() {
+            function.span = function.span.shifted(offset);
+            function.name_span = function.name_span.shifted(offset);
+            for arg in function.args.iter_mut() {
+                arg.span = arg.span.shifted(offset);
+            }
+            shift_block_spans(&mut function.block, offset);
+        }
+        for constant in self.constants.iter_mut() {
+            constant.span = constant.span.shifted(offset);
+            constant.name_span = constant.name_span.shifted(offset);
+            constant.expression.shift_spans(offset);
+        }
+        for extern_function in self.extern_functions.iter_mut() {
+            extern_function.span = extern_function.span.shifted(offset);
+            extern_function.name_span = extern_function.name_span.shifted(offset);
+            for arg in extern_function.args.iter_mut() {
+                arg.span = arg.span.shifted(offset);
+            }
+        }
+    }
+}
+
+fn shift_block_spans(block: &mut Block, offset: usize) {
+    for element in block.iter_mut() {
+        element.span = element.span.shifted(offset);
+        match &mut element.kind {
+            BlockElementKind::LetStatement { expression, .. }
+            | BlockElementKind::AssignmentStatement { expression, .. }
+            | BlockElementKind::ReturnStatement(expression)
+            | BlockElementKind::ExpressionStatement(expression) => expression.shift_spans(offset),
+            BlockElementKind::IfStatement {
+                condition,
+                then_block,
+                else_block,
+            } => {
+                condition.shift_spans(offset);
+                shift_block_spans(then_block, offset);
+                if let Some(else_block) = else_block {
+                    shift_block_spans(else_block, offset);
+                }
+            }
+            BlockElementKind::WhileStatement { condition, body } => {
+                condition.shift_spans(offset);
+                shift_block_spans(body, offset);
+            }
+            BlockElementKind::NestedBlock(nested) => shift_block_spans(nested, offset),
+            BlockElementKind::BreakStatement | BlockElementKind::ContinueStatement => {}
+        }
+    }
+}
+
+/// A source file of a program. The files importing it call its functions as
+/// `name::function`, where `name` is the one of the file without the extension
+#[derive(Debug, PartialEq)]
+pub struct Module<'input> {
+    pub path: &'input str,
+    pub program: Program<'input>,
 }
 
-pub type Program<'input> = Vec<Function<'input>>;
+#[derive(Debug, PartialEq)]
+pub struct BlockElement<'input> {
+    pub kind: BlockElementKind<'input>,
+    pub span: Span,
+}
 
 #[derive(Debug, PartialEq)]
-pub enum BlockElement<'input> {
+pub enum BlockElementKind<'input> {
     LetStatement {
         name: &'input str,
+        /// The declared type. If missing, it is the type of the expression
+        ty: Option<Type>,
         expression: Expression<'input>,
     },
+    /// `name = expression`. The parser also produces it for compound assignments such
+    /// as `x += 1` and for `x++`, by expanding them to `x = x + 1`
     AssignmentStatement {
         name: &'input str,
         expression: Expression<'input>,
     },
     ReturnStatement(Expression<'input>),
+    IfStatement {
+        condition: Expression<'input>,
+        then_block: Block<'input>,
+        else_block: Option<Block<'input>>,
+    },
+    WhileStatement {
+        condition: Expression<'input>,
+        body: Block<'input>,
+    },
+    BreakStatement,
+    ContinueStatement,
     NestedBlock(Block<'input>),
+    /// An expression whose value is discarded
+    ExpressionStatement(Expression<'input>),
 }
 
 pub type Block<'input> = Vec<BlockElement<'input>>;
 
+/// A call to `name`, which can either be a function or a variable containing the
+/// handle of one. The functions of imported modules are called with a qualified name,
+/// such as `finance::npv`
 #[derive(Debug, PartialEq)]
 pub struct FunctionCall<'input> {
     pub name: &'input str,
@@ -30,13 +200,124 @@ pub struct FunctionCall<'input> {
 }
 
 #[derive(Debug, PartialEq)]
-pub enum Expression<'input> {
+pub struct Expression<'input> {
+    pub kind: ExpressionKind<'input>,
+    pub span: Span,
+    /// The type of the value, which is `i64` until the type checker has run
+    pub ty: Type,
+}
+
+impl Expression<'_> {
+    fn shift_spans(&mut self, offset: usize) {
+        self.span = self.span.shifted(offset);
+        match &mut self.kind {
+            ExpressionKind::Identifier(_)
+            | ExpressionKind::Number(_)
+            | ExpressionKind::UnsignedNumber(_)
+            | ExpressionKind::Float(_)
+            | ExpressionKind::Boolean(_)
+            | ExpressionKind::FunctionHandle(_) => {}
+            ExpressionKind::Negate(operand)
+            | ExpressionKind::BitwiseNot(operand)
+            | ExpressionKind::LogicalNot(operand)
+            | ExpressionKind::Cast(operand, _) => operand.shift_spans(offset),
+            ExpressionKind::Add(left, right)
+            | ExpressionKind::Sub(left, right)
+            | ExpressionKind::Mul(left, right)
+            | ExpressionKind::Div(left, right)
+            | ExpressionKind::Rem(left, right)
+            | ExpressionKind::BitwiseAnd(left, right)
+            | ExpressionKind::BitwiseOr(left, right)
+            | ExpressionKind::BitwiseXor(left, right)
+            | ExpressionKind::ShiftLeft(left, right)
+            | ExpressionKind::ShiftRight(left, right)
+            | ExpressionKind::Equal(left, right)
+            | ExpressionKind::NotEqual(left, right)
+            | ExpressionKind::LessThan(left, right)
+            | ExpressionKind::LessThanOrEqual(left, right)
+            | ExpressionKind::GreaterThan(left, right)
+            | ExpressionKind::GreaterThanOrEqual(left, right)
+            | ExpressionKind::LogicalAnd(left, right)
+            | ExpressionKind::LogicalOr(left, right) => {
+                left.shift_spans(offset);
+                right.shift_spans(offset);
+            }
+            ExpressionKind::FunctionCall(call) => {
+                for arg in call.args.iter_mut() {
+                    arg.shift_spans(offset);
+                }
+            }
+            ExpressionKind::Match { scrutinee, arms } => {
+                scrutinee.shift_spans(offset);
+                for arm in arms.iter_mut() {
+                    arm.span = arm.span.shifted(offset);
+                    arm.expression.shift_spans(offset);
+                    for pattern in arm.patterns.iter_mut().flatten() {
+                        pattern.span = pattern.span.shifted(offset);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ExpressionKind<'input> {
     Identifier(&'input str),
     Number(i64),
-    Negate(Box<Self>),
-    Add(Box<Self>, Box<Self>),
-    Sub(Box<Self>, Box<Self>),
-    Mul(Box<Self>, Box<Self>),
-    Div(Box<Self>, Box<Self>),
+    /// An integer literal above `i64::MAX`, stored as its bit pattern. Only a `u64` can
+    /// hold it
+    UnsignedNumber(i64),
+    Float(f64),
+    Boolean(bool),
+    Negate(Box<Expression<'input>>),
+    Add(Box<Expression<'input>>, Box<Expression<'input>>),
+    Sub(Box<Expression<'input>>, Box<Expression<'input>>),
+    Mul(Box<Expression<'input>>, Box<Expression<'input>>),
+    Div(Box<Expression<'input>>, Box<Expression<'input>>),
+    Rem(Box<Expression<'input>>, Box<Expression<'input>>),
+    BitwiseAnd(Box<Expression<'input>>, Box<Expression<'input>>),
+    BitwiseOr(Box<Expression<'input>>, Box<Expression<'input>>),
+    BitwiseXor(Box<Expression<'input>>, Box<Expression<'input>>),
+    BitwiseNot(Box<Expression<'input>>),
+    ShiftLeft(Box<Expression<'input>>, Box<Expression<'input>>),
+    ShiftRight(Box<Expression<'input>>, Box<Expression<'input>>),
+    Equal(Box<Expression<'input>>, Box<Expression<'input>>),
+    NotEqual(Box<Expression<'input>>, Box<Expression<'input>>),
+    LessThan(Box<Expression<'input>>, Box<Expression<'input>>),
+    LessThanOrEqual(Box<Expression<'input>>, Box<Expression<'input>>),
+    GreaterThan(Box<Expression<'input>>, Box<Expression<'input>>),
+    GreaterThanOrEqual(Box<Expression<'input>>, Box<Expression<'input>>),
+    LogicalNot(Box<Expression<'input>>),
+    LogicalAnd(Box<Expression<'input>>, Box<Expression<'input>>),
+    LogicalOr(Box<Expression<'input>>, Box<Expression<'input>>),
     FunctionCall(FunctionCall<'input>),
+    /// `&name`, the handle of a function, which can be stored in a variable and then
+    /// called through it. Its value is the `FunctionId` of the function. Like in calls,
+    /// the name can be qualified by a module
+    FunctionHandle(&'input str),
+    /// `expression as type`. The type checker also adds these wherever a value is
+    /// implicitly widened
+    Cast(Box<Expression<'input>>, Type),
+    /// `match scrutinee { 0 => a, 1 | 2 => b, _ => c }`
+    Match {
+        scrutinee: Box<Expression<'input>>,
+        arms: Vec<MatchArm<'input>>,
+    },
+}
+
+/// An arm of a `match`, whose expression is the result when the value matches
+#[derive(Debug, PartialEq)]
+pub struct MatchArm<'input> {
+    /// The values matched by the arm, or `None` for `_`, which matches any value
+    pub patterns: Option<Vec<MatchPattern>>,
+    pub expression: Expression<'input>,
+    pub span: Span,
+}
+
+/// An integer literal in an arm of a `match`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MatchPattern {
+    pub value: i64,
+    pub span: Span,
 }
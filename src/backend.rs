@@ -1,6 +1,15 @@
+use std::{cell::Cell, mem::offset_of};
+
 use thiserror::Error;
 
-use crate::{frontend::FunctionId, ir::CompiledFunction};
+use crate::{
+    frontend::{FunctionId, HostFunctionId},
+    ir::{
+        BasicBlock, BlockId, CompiledFunction, IrInstruction, IrRegister, OverflowMode,
+        RegisterClass,
+    },
+    types::Type,
+};
 
 pub trait MachineCodeGenerator {
     fn generate_machine_code(
@@ -10,6 +19,7 @@ pub trait MachineCodeGenerator {
     ) -> Result<GeneratedMachineCode, BackendError>;
 }
 
+#[derive(Debug)]
 pub struct GeneratedMachineCode {
     pub asm: String,
     pub machine_code: Vec<u8>,
@@ -21,31 +31,355 @@ pub enum BackendError {
     NotImplemented(String),
 }
 
-pub type JitFn = fn(i64, i64, i64, i64, i64, i64) -> i64;
+pub type JitFn = extern "C" fn(i64, i64, i64, i64, i64, i64) -> i64;
+
+/// Why the generated code stopped, stored in `RuntimeContext::trap`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i64)]
+pub enum TrapCode {
+    /// A `load` or `store` accessed an element outside of the memory supplied by the host.
+    /// The index of the element is stored in `RuntimeContext::trap_value`
+    OutOfBounds = 1,
+    /// An integer `+`, `-` or `*` overflowed, in checked mode
+    Overflow = 2,
+    /// An integer division or remainder had a zero divisor
+    DivisionByZero = 3,
+    /// `MIN / -1` or `MIN % -1`, whose result does not fit in the signed type. Narrower
+    /// types only trap in checked mode
+    DivisionOverflow = 4,
+    /// An indirect call through a value that is not the handle of any function
+    InvalidFunctionHandle = 5,
+    /// An indirect call with a number of arguments different from the one of the callee
+    ArityMismatch = 6,
+}
+
+impl TrapCode {
+    pub const ALL: [TrapCode; 6] = [
+        TrapCode::OutOfBounds,
+        TrapCode::Overflow,
+        TrapCode::DivisionByZero,
+        TrapCode::DivisionOverflow,
+        TrapCode::InvalidFunctionHandle,
+        TrapCode::ArityMismatch,
+    ];
+
+    pub fn from_value(value: i64) -> Option<TrapCode> {
+        TrapCode::ALL.into_iter().find(|code| *code as i64 == value)
+    }
+}
+
+/// The function called by a direct or by an indirect call
+#[derive(Debug, Clone, Copy)]
+pub enum Callee {
+    Function(FunctionId),
+    /// The register containing the handle of the function, which must have been
+    /// checked already
+    Handle(IrRegister),
+}
+
+/// Whether the instruction at `index` of the block is a call whose result is returned
+/// right away. The backends compile these as tail calls, which jump directly to the
+/// callee and reuse the frame of the caller, so that recursion in tail position runs in
+/// constant stack space. Since the callee returns to our caller, its traps are handled
+/// there as usual. Indirect calls are tail calls too, once their handle is checked.
+pub fn is_tail_call(block: &BasicBlock, index: usize) -> bool {
+    match (&block.body[index], block.body.get(index + 1)) {
+        (
+            IrInstruction::Call { dest, .. } | IrInstruction::CallIndirect { dest, .. },
+            Some(IrInstruction::Ret { reg }),
+        ) => dest == reg,
+        _ => false,
+    }
+}
+
+/// The checks that the generated code does before an integer division. They are
+/// skipped when the divisor is a constant for which they could never fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DivisorChecks {
+    /// Whether to trap with `TrapCode::DivisionByZero` if the divisor is zero
+    pub zero: bool,
+    /// The dividend for which to trap with `TrapCode::DivisionOverflow` if the divisor
+    /// is -1, i.e. the minimum of the type
+    pub overflow: Option<i64>,
+}
+
+impl DivisorChecks {
+    pub fn new(function: &CompiledFunction, ty: Type, divisor: IrRegister) -> Self {
+        let value = constant_value(function, divisor);
+        Self {
+            zero: !matches!(value, Some(v) if v != 0),
+            // Narrower values are divided as 64-bit ones, so `MIN / -1` has a result
+            // that only needs to be rejected in checked mode
+            overflow: match value {
+                Some(v) if v != -1 => None,
+                _ if ty.is_float() || ty.bounds().0 == 0 => None,
+                _ if ty != Type::I64 && function.overflow_mode != OverflowMode::Checked => None,
+                _ => Some(ty.bounds().0 as i64),
+            },
+        }
+    }
+}
+
+/// The value of a register, if its only definition is a `mvi`
+fn constant_value(function: &CompiledFunction, register: IrRegister) -> Option<i64> {
+    let mut definitions = function
+        .instructions()
+        .filter(|instruction| instruction.dest() == Some(register));
+    match (definitions.next(), definitions.next()) {
+        (Some(IrInstruction::Mvi { val, .. }), None) => Some(*val),
+        _ => None,
+    }
+}
+
+/// The labels of the code that handles the traps, which the backends place after all
+/// the blocks of a function. That code is not a real block, but its labels have ids
+/// that follow the ones of the blocks, so that jumps to it are resolved in the same way
+#[derive(Debug, Default)]
+pub struct TrapLabels {
+    /// Records an out-of-bounds access, whose index and the address of the runtime
+    /// context are in registers chosen by each backend
+    pub out_of_bounds: BlockId,
+    /// Returns immediately, after a trap has been recorded
+    pub exit: BlockId,
+    pub out_of_bounds_used: bool,
+    pub exit_used: bool,
+    /// The other traps that the function can raise, which only need to record their code
+    used_traps: Vec<TrapCode>,
+}
+
+impl TrapLabels {
+    pub fn new(num_blocks: usize) -> Self {
+        Self {
+            out_of_bounds: BlockId(num_blocks),
+            exit: BlockId(num_blocks + 1),
+            ..Default::default()
+        }
+    }
+
+    /// The label of the code recording a trap other than an out-of-bounds access. Trap
+    /// codes start from 1, which is the out-of-bounds one, so these follow the exit
+    pub fn label(&self, code: TrapCode) -> BlockId {
+        BlockId(self.out_of_bounds.0 + code as usize)
+    }
+
+    /// The number of labels, including the ones of the blocks
+    pub fn num_labels(&self) -> usize {
+        self.out_of_bounds.0 + TrapCode::ALL.len() + 1
+    }
+
+    /// Records that the code jumps to the label of the given trap
+    pub fn use_trap(&mut self, code: TrapCode) {
+        if !self.used_traps.contains(&code) {
+            self.used_traps.push(code);
+        }
+    }
+
+    /// The traps whose handlers must be generated, in the order in which they were
+    /// first used. Each handler records its code and then reaches the exit, which
+    /// follows the out-of-bounds handler: the flag tells whether it must jump there
+    /// because other handlers are in the way.
+    pub fn take_handlers(&mut self) -> Vec<(TrapCode, bool)> {
+        let used_traps = std::mem::take(&mut self.used_traps);
+        self.exit_used |= !used_traps.is_empty() || self.out_of_bounds_used;
+        let num_used_traps = used_traps.len();
+        used_traps
+            .into_iter()
+            .enumerate()
+            .map(|(index, code)| (code, index + 1 < num_used_traps || self.out_of_bounds_used))
+            .collect()
+    }
+}
+
+/// The jump tables used by a function. The backends place them after all the code,
+/// and their labels follow the ones of the traps
+#[derive(Debug, Default)]
+pub struct JumpTables {
+    first_label: usize,
+    tables: Vec<(BlockId, Vec<BlockId>)>,
+}
+
+impl JumpTables {
+    pub fn new(trap_labels: &TrapLabels) -> Self {
+        Self {
+            first_label: trap_labels.num_labels(),
+            tables: Vec::new(),
+        }
+    }
+
+    /// Records a jump table, returning its label
+    pub fn add(&mut self, targets: &[BlockId]) -> BlockId {
+        let label = BlockId(self.first_label + self.tables.len());
+        self.tables.push((label, targets.to_vec()));
+        label
+    }
+
+    /// The number of labels, including the ones of the blocks and of the traps
+    pub fn num_labels(&self) -> usize {
+        self.first_label + self.tables.len()
+    }
+
+    /// The tables recorded so far, with their labels, in the order they were added
+    pub fn take(&mut self) -> Vec<(BlockId, Vec<BlockId>)> {
+        std::mem::take(&mut self.tables)
+    }
+
+    /// The entries of the table with the given label, which are the offsets of its
+    /// targets from the start of the table
+    pub fn offsets(label: BlockId, targets: &[BlockId], block_offsets: &[i32]) -> Vec<i64> {
+        targets
+            .iter()
+            .map(|target| (block_offsets[target.0] - block_offsets[label.0]) as i64)
+            .collect()
+    }
+}
+
+/// The state shared between the host and the generated code while running a program.
+/// The generated code knows its address and reads or writes the fields directly, at
+/// the offsets given by the associated constants, so its layout must not change.
+///
+/// When the generated code traps, it stores the reason in `trap` and returns from every
+/// active jitted function, without executing anything else.
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct RuntimeContext {
+    /// Address of the memory supplied by the host for `load` and `store`
+    pub memory: Cell<i64>,
+    /// Number of elements in `memory`
+    pub memory_length: Cell<i64>,
+    /// A `TrapCode`, or 0 if the code did not trap
+    pub trap: Cell<i64>,
+    /// Additional information about the trap, whose meaning depends on the code
+    pub trap_value: Cell<i64>,
+}
+
+impl RuntimeContext {
+    pub const MEMORY_OFFSET: i32 = offset_of!(RuntimeContext, memory) as i32;
+    pub const MEMORY_LENGTH_OFFSET: i32 = offset_of!(RuntimeContext, memory_length) as i32;
+    pub const TRAP_OFFSET: i32 = offset_of!(RuntimeContext, trap) as i32;
+    pub const TRAP_VALUE_OFFSET: i32 = offset_of!(RuntimeContext, trap_value) as i32;
+}
+
+/// The arity stored for the functions that indirect calls cannot reach, since they pass
+/// and return only integers. Calling them through a handle is like using an invalid one
+pub const NOT_CALLABLE_INDIRECTLY: i64 = -1;
+
+/// The name and the classes of the arguments and of the result of a function of the
+/// program, which the host checks before calling it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionSignature {
+    pub name: String,
+    pub argument_classes: Vec<RegisterClass>,
+    pub return_class: RegisterClass,
+}
 
 #[derive(Debug)]
 pub struct CompiledFunctionCatalog {
     // Indexed by FunctionId, which are dense. Thus, we can use a simple Vec
-    // and avoid the extra cost of an hash map
+    // and avoid the extra cost of an hash map. Its capacity is reserved upfront, so
+    // it never moves and tail calls can read the addresses directly
     addresses: Vec<JitFn>,
+    /// The number of arguments of each function, indexed by FunctionId, which the
+    /// generated code checks before an indirect call. It is `NOT_CALLABLE_INDIRECTLY`
+    /// for the functions taking or returning floats
+    arities: Vec<i64>,
+    /// Indexed by FunctionId
+    signatures: Vec<FunctionSignature>,
+    /// Indexed by HostFunctionId. These are known before compiling, so the generated
+    /// code can call them directly
+    host_function_addresses: Vec<usize>,
+    runtime_context: RuntimeContext,
 }
 
 impl CompiledFunctionCatalog {
-    pub fn new(program: &[CompiledFunction]) -> Self {
+    pub fn new(program: &[CompiledFunction], host_function_addresses: Vec<usize>) -> Self {
         Self {
             addresses: Vec::with_capacity(program.len()),
+            arities: program
+                .iter()
+                .map(|function| {
+                    if function.arg_classes.contains(&RegisterClass::Float)
+                        || function.return_class() == RegisterClass::Float
+                    {
+                        NOT_CALLABLE_INDIRECTLY
+                    } else {
+                        function.num_args() as i64
+                    }
+                })
+                .collect(),
+            signatures: program
+                .iter()
+                .map(|function| FunctionSignature {
+                    name: function.name.to_string(),
+                    argument_classes: function.arg_classes.clone(),
+                    return_class: function.return_class(),
+                })
+                .collect(),
+            host_function_addresses,
+            runtime_context: RuntimeContext::default(),
         }
     }
 
     /// Stores a function pointer. Requirement: it must be called in order of `id`
     /// and for each function in the program
     pub fn store_function_pointer(&mut self, id: FunctionId, fun_ptr: JitFn) {
-        assert!(id.0 == self.addresses.len());
-        self.addresses.insert(id.0, fun_ptr);
+        assert!(id.0 == self.addresses.len() && id.0 < self.addresses.capacity());
+        self.addresses.push(fun_ptr);
     }
 
     pub fn get_function_pointer(&self, id: FunctionId) -> JitFn {
         assert!(id.0 < self.addresses.len());
         self.addresses[id.0]
     }
+
+    /// The address where the pointer to the given function is stored, once it has been
+    /// compiled. The generated code reads it when doing a tail call, since the callee
+    /// might not exist yet while compiling the caller
+    pub fn function_pointer_address(&self, id: FunctionId) -> usize {
+        assert!(id.0 < self.num_functions());
+        self.addresses.as_ptr() as usize + id.0 * size_of::<JitFn>()
+    }
+
+    /// Finds a function by name, which is qualified for the functions of imported modules
+    pub fn find_function(&self, name: &str) -> Option<FunctionId> {
+        self.signatures
+            .iter()
+            .position(|signature| signature.name == name)
+            .map(FunctionId)
+    }
+
+    pub fn signature(&self, id: FunctionId) -> &FunctionSignature {
+        &self.signatures[id.0]
+    }
+
+    pub fn get_host_function_address(&self, id: HostFunctionId) -> usize {
+        assert!(id.0 < self.host_function_addresses.len());
+        self.host_function_addresses[id.0]
+    }
+
+    /// The number of functions of the program, whose handles go from 0 to this value
+    pub fn num_functions(&self) -> usize {
+        self.arities.len()
+    }
+
+    /// The address of the function pointers, indexed by FunctionId, which indirect tail
+    /// calls read.
+    pub fn function_pointers_address(&self) -> usize {
+        self.addresses.as_ptr() as usize
+    }
+
+    /// The address of the number of arguments of the functions, stored as an `i64` for
+    /// each of them. It does not move while the catalog is alive.
+    pub fn arities_address(&self) -> usize {
+        self.arities.as_ptr() as usize
+    }
+
+    pub fn runtime_context(&self) -> &RuntimeContext {
+        &self.runtime_context
+    }
+
+    /// The address of the runtime context, which the generated code uses to access it.
+    /// Like the catalog itself, it must not move while the generated code is alive
+    pub fn runtime_context_address(&self) -> usize {
+        &self.runtime_context as *const RuntimeContext as usize
+    }
 }
@@ -4,14 +4,41 @@ use std::{
 };
 
 use crate::{
-    backend::{BackendError, CompiledFunctionCatalog, GeneratedMachineCode, MachineCodeGenerator},
+    backend::{
+        self, BackendError, Callee, CompiledFunctionCatalog, DivisorChecks, GeneratedMachineCode,
+        JumpTables, MachineCodeGenerator, RuntimeContext, TrapCode, TrapLabels,
+        NOT_CALLABLE_INDIRECTLY,
+    },
+    backend_intrinsic_expansion::expand_intrinsics,
+    backend_phi_elimination::eliminate_phis,
     backend_register_allocator::{self, AllocatedLocation},
-    ir::{ArgumentIndex, BinOpOperator::*, CompiledFunction, IrInstruction},
-    jit::jit_call_trampoline,
+    ir::{
+        ArgumentIndex, BinOpOperator, BinOpOperator::*, BlockId, CompiledFunction, IrInstruction,
+        IrRegister, OverflowMode, RegisterClass,
+    },
+    jit::{jit_call_trampoline, jit_call_trampoline_float},
+    types::Type,
 };
 use Aarch64Instruction::*;
 use Register::*;
 
+/// The number of registers used to pass the arguments of each class, x0-x7 and d0-d7
+const NUM_ARGUMENT_REGISTERS: usize = 8;
+
+/// The registers where an instruction loads its operands that were spilled to the
+/// stack, and computes its result before storing it there. They are callee-saved, so
+/// that none of the code we generate uses them for anything else, and are saved in the
+/// frame of the functions that spill values of their class
+const SCRATCH_REGISTERS: [Register; 3] = [X20, X21, X22];
+const FLOAT_SCRATCH_REGISTERS: [Register; 3] = [D8, D9, D10];
+
+/// The offset from x29 of the first spilled value, which comes after the saved x29 and x30
+const SPILL_AREA_OFFSET: u32 = 16;
+
+/// The largest frame that the `stp` of the prologue and the `ldp` of the epilogue can
+/// reserve and release, since their offset is a 7-bit multiple of 8
+const MAX_FRAME_SIZE: u32 = 496;
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum Register {
     X0,
@@ -46,6 +73,41 @@ enum Register {
     X29,
     X30,
     Sp,
+    /// The zero register, which shares the encoding of `sp`
+    Xzr,
+    /// The floating-point registers, which have their own numbering
+    D0,
+    D1,
+    D2,
+    D3,
+    D4,
+    D5,
+    D6,
+    D7,
+    D8,
+    D9,
+    D10,
+    D11,
+    D12,
+    D13,
+    D14,
+    D15,
+    D16,
+    D17,
+    D18,
+    D19,
+    D20,
+    D21,
+    D22,
+    D23,
+    D24,
+    D25,
+    D26,
+    D27,
+    D28,
+    D29,
+    D30,
+    D31,
 }
 
 impl Register {
@@ -83,8 +145,78 @@ impl Register {
             X29 => 29,
             X30 => 30,
             Sp => 31,
+            Xzr => 31,
+            D0 => 0,
+            D1 => 1,
+            D2 => 2,
+            D3 => 3,
+            D4 => 4,
+            D5 => 5,
+            D6 => 6,
+            D7 => 7,
+            D8 => 8,
+            D9 => 9,
+            D10 => 10,
+            D11 => 11,
+            D12 => 12,
+            D13 => 13,
+            D14 => 14,
+            D15 => 15,
+            D16 => 16,
+            D17 => 17,
+            D18 => 18,
+            D19 => 19,
+            D20 => 20,
+            D21 => 21,
+            D22 => 22,
+            D23 => 23,
+            D24 => 24,
+            D25 => 25,
+            D26 => 26,
+            D27 => 27,
+            D28 => 28,
+            D29 => 29,
+            D30 => 30,
+            D31 => 31,
         }
     }
+
+    fn is_float(&self) -> bool {
+        matches!(
+            self,
+            D0 | D1
+                | D2
+                | D3
+                | D4
+                | D5
+                | D6
+                | D7
+                | D8
+                | D9
+                | D10
+                | D11
+                | D12
+                | D13
+                | D14
+                | D15
+                | D16
+                | D17
+                | D18
+                | D19
+                | D20
+                | D21
+                | D22
+                | D23
+                | D24
+                | D25
+                | D26
+                | D27
+                | D28
+                | D29
+                | D30
+                | D31
+        )
+    }
 }
 
 impl Display for Register {
@@ -122,6 +254,69 @@ impl Display for Register {
             X29 => write!(f, "x29"),
             X30 => write!(f, "x30"),
             Sp => write!(f, "sp"),
+            Xzr => write!(f, "xzr"),
+            _ => write!(f, "d{}", self.index()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Condition {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// Unsigned lower
+    Lo,
+    /// Unsigned lower or same
+    Ls,
+    /// Unsigned higher
+    Hi,
+    /// Unsigned higher or same
+    Hs,
+    /// Negative. After comparing floats, it means "less than" and is false if either
+    /// value was NaN
+    Mi,
+    /// Signed overflow
+    Vs,
+}
+
+impl Condition {
+    fn code(&self) -> u32 {
+        match self {
+            Condition::Eq => 0b0000,
+            Condition::Ne => 0b0001,
+            Condition::Lt => 0b1011,
+            Condition::Le => 0b1101,
+            Condition::Gt => 0b1100,
+            Condition::Ge => 0b1010,
+            Condition::Lo => 0b0011,
+            Condition::Ls => 0b1001,
+            Condition::Hi => 0b1000,
+            Condition::Hs => 0b0010,
+            Condition::Mi => 0b0100,
+            Condition::Vs => 0b0110,
+        }
+    }
+}
+
+impl Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Condition::Eq => write!(f, "eq"),
+            Condition::Ne => write!(f, "ne"),
+            Condition::Lt => write!(f, "lt"),
+            Condition::Le => write!(f, "le"),
+            Condition::Gt => write!(f, "gt"),
+            Condition::Ge => write!(f, "ge"),
+            Condition::Lo => write!(f, "lo"),
+            Condition::Ls => write!(f, "ls"),
+            Condition::Hi => write!(f, "hi"),
+            Condition::Hs => write!(f, "hs"),
+            Condition::Mi => write!(f, "mi"),
+            Condition::Vs => write!(f, "vs"),
         }
     }
 }
@@ -129,6 +324,42 @@ impl Display for Register {
 enum Aarch64Instruction {
     Nop,
     Ret,
+    /// Not a real instruction: marks the beginning of a block, and generates no code
+    Label {
+        block: BlockId,
+    },
+    /// Unconditional branch. The offset, relative to this instruction, is computed once
+    /// all the code has been generated
+    B {
+        target: BlockId,
+        offset: i32,
+    },
+    /// Branch if the register is zero
+    Cbz {
+        register: Register,
+        target: BlockId,
+        offset: i32,
+    },
+    /// Branch if the register is not zero
+    Cbnz {
+        register: Register,
+        target: BlockId,
+        offset: i32,
+    },
+    /// Loads the address of a label. Like for the branches, the offset is relative to
+    /// this instruction
+    Adr {
+        destination: Register,
+        target: BlockId,
+        offset: i32,
+    },
+    /// Not a real instruction: the data of a jump table, placed after the code. Every
+    /// entry is the offset of a target from `label`, which marks the start of the table
+    JumpTable {
+        label: BlockId,
+        targets: Vec<BlockId>,
+        offsets: Vec<i64>,
+    },
     MovImmToReg {
         register: Register,
         value: i64,
@@ -145,6 +376,12 @@ enum Aarch64Instruction {
         reg1: Register,
         reg2: Register,
     },
+    /// Like `AddRegToReg`, but also sets the flags
+    AddsRegToReg {
+        destination: Register,
+        reg1: Register,
+        reg2: Register,
+    },
     SubRegToReg {
         destination: Register,
         reg1: Register,
@@ -155,14 +392,93 @@ enum Aarch64Instruction {
         reg1: Register,
         reg2: Register,
     },
+    /// The upper 64 bits of the 128-bit product of two signed values
+    Smulh {
+        destination: Register,
+        reg1: Register,
+        reg2: Register,
+    },
+    /// The upper 64 bits of the 128-bit product of two unsigned values
+    Umulh {
+        destination: Register,
+        reg1: Register,
+        reg2: Register,
+    },
     DivRegToReg {
         destination: Register,
         reg1: Register,
         reg2: Register,
     },
+    UdivRegToReg {
+        destination: Register,
+        reg1: Register,
+        reg2: Register,
+    },
+    /// destination = minuend - reg1 * reg2
+    Msub {
+        destination: Register,
+        reg1: Register,
+        reg2: Register,
+        minuend: Register,
+    },
+    AndRegToReg {
+        destination: Register,
+        reg1: Register,
+        reg2: Register,
+    },
+    OrrRegToReg {
+        destination: Register,
+        reg1: Register,
+        reg2: Register,
+    },
+    EorRegToReg {
+        destination: Register,
+        reg1: Register,
+        reg2: Register,
+    },
+    /// Shifts reg1 left by reg2, modulo 64
+    LslRegToReg {
+        destination: Register,
+        reg1: Register,
+        reg2: Register,
+    },
+    /// Shifts reg1 right by reg2, modulo 64, preserving the sign
+    AsrRegToReg {
+        destination: Register,
+        reg1: Register,
+        reg2: Register,
+    },
+    /// Shifts reg1 right by reg2, modulo 64, filling it with zeros
+    LsrRegToReg {
+        destination: Register,
+        reg1: Register,
+        reg2: Register,
+    },
+    /// Shifts the source right by a constant amount, preserving the sign
+    AsrImm {
+        destination: Register,
+        source: Register,
+        amount: u32,
+    },
+    /// Copies the lowest `width` bits of the source, zero-extending them
+    Ubfx {
+        destination: Register,
+        source: Register,
+        width: u32,
+    },
+    /// Copies the lowest `width` bits of the source, sign-extending them
+    Sbfx {
+        destination: Register,
+        source: Register,
+        width: u32,
+    },
     Blr {
         register: Register,
     },
+    /// Jumps to the address contained in the register
+    Br {
+        register: Register,
+    },
     Str {
         source: Register,
         base: Register,
@@ -173,6 +489,18 @@ enum Aarch64Instruction {
         base: Register,
         offset: u32,
     },
+    /// str source, [base, index, lsl #3]
+    StrRegOffset {
+        source: Register,
+        base: Register,
+        index: Register,
+    },
+    /// ldr destination, [base, index, lsl #3]
+    LdrRegOffset {
+        destination: Register,
+        base: Register,
+        index: Register,
+    },
     Stp {
         reg1: Register,
         reg2: Register,
@@ -190,6 +518,92 @@ enum Aarch64Instruction {
         source: Register,
         destination: Register,
     },
+    Mvn {
+        source: Register,
+        destination: Register,
+    },
+    /// Sets the flags according to `reg1 - reg2`
+    CmpRegToReg {
+        reg1: Register,
+        reg2: Register,
+    },
+    /// Sets the flags according to `register - value`
+    CmpImm {
+        register: Register,
+        value: u32,
+    },
+    /// Sets the destination to 1 if the condition holds, to 0 otherwise
+    Cset {
+        destination: Register,
+        condition: Condition,
+    },
+    /// Sets the destination to reg1 if the condition holds, to reg2 otherwise
+    Csel {
+        destination: Register,
+        reg1: Register,
+        reg2: Register,
+        condition: Condition,
+    },
+    /// Sets the destination to the negation of the source if the condition holds, to
+    /// the source otherwise
+    Cneg {
+        destination: Register,
+        source: Register,
+        condition: Condition,
+    },
+    /// Counts the bits set in each of the low 8 bytes of a float register
+    Cnt {
+        source: Register,
+        destination: Register,
+    },
+    /// Sums the low 8 bytes of a float register, storing the result in the lowest byte
+    /// of the destination and clearing the others
+    Addv {
+        source: Register,
+        destination: Register,
+    },
+    /// Moves between two float registers, or between a float and a general purpose
+    /// register, copying the bits
+    Fmov {
+        source: Register,
+        destination: Register,
+    },
+    Fadd {
+        destination: Register,
+        reg1: Register,
+        reg2: Register,
+    },
+    Fsub {
+        destination: Register,
+        reg1: Register,
+        reg2: Register,
+    },
+    Fmul {
+        destination: Register,
+        reg1: Register,
+        reg2: Register,
+    },
+    Fdiv {
+        destination: Register,
+        reg1: Register,
+        reg2: Register,
+    },
+    /// Sets the flags according to the comparison of two floats
+    Fcmp {
+        reg1: Register,
+        reg2: Register,
+    },
+    /// Converts a signed integer to a float
+    Scvtf {
+        source: Register,
+        destination: Register,
+    },
+    /// Converts a float to a signed integer, rounding towards zero. Out of range values
+    /// are saturated and NaN becomes zero
+    Fcvtzs {
+        source: Register,
+        destination: Register,
+    },
 }
 
 impl Display for Aarch64Instruction {
@@ -197,6 +611,26 @@ impl Display for Aarch64Instruction {
         match self {
             Nop => write!(f, "nop"),
             Ret => write!(f, "ret"),
+            Label { block } => write!(f, ".L{}:", block.0),
+            B { target, .. } => write!(f, "b    .L{}", target.0),
+            Cbz {
+                register, target, ..
+            } => write!(f, "cbz  {}, .L{}", register, target.0),
+            Cbnz {
+                register, target, ..
+            } => write!(f, "cbnz {}, .L{}", register, target.0),
+            Adr {
+                destination,
+                target,
+                ..
+            } => write!(f, "adr  {}, .L{}", destination, target.0),
+            JumpTable { label, targets, .. } => {
+                let entries: Vec<String> = targets
+                    .iter()
+                    .map(|target| format!(".quad .L{}-.L{}", target.0, label.0))
+                    .collect();
+                write!(f, "{}", entries.join("\n"))
+            }
             MovImmToReg { register, value } => {
                 write!(f, "movz {}, {}", register, value)
             }
@@ -214,6 +648,11 @@ impl Display for Aarch64Instruction {
                 reg1,
                 reg2,
             } => write!(f, "add  {}, {}, {}", destination, reg1, reg2),
+            AddsRegToReg {
+                destination,
+                reg1,
+                reg2,
+            } => write!(f, "adds {}, {}, {}", destination, reg1, reg2),
             SubRegToReg {
                 destination,
                 reg1,
@@ -224,12 +663,79 @@ impl Display for Aarch64Instruction {
                 reg1,
                 reg2,
             } => write!(f, "mul  {}, {}, {}", destination, reg1, reg2),
+            Smulh {
+                destination,
+                reg1,
+                reg2,
+            } => write!(f, "smulh {}, {}, {}", destination, reg1, reg2),
+            Umulh {
+                destination,
+                reg1,
+                reg2,
+            } => write!(f, "umulh {}, {}, {}", destination, reg1, reg2),
             DivRegToReg {
                 destination,
                 reg1,
                 reg2,
             } => write!(f, "sdiv {}, {}, {}", destination, reg1, reg2),
+            UdivRegToReg {
+                destination,
+                reg1,
+                reg2,
+            } => write!(f, "udiv {}, {}, {}", destination, reg1, reg2),
+            Msub {
+                destination,
+                reg1,
+                reg2,
+                minuend,
+            } => write!(f, "msub {}, {}, {}, {}", destination, reg1, reg2, minuend),
+            AndRegToReg {
+                destination,
+                reg1,
+                reg2,
+            } => write!(f, "and  {}, {}, {}", destination, reg1, reg2),
+            OrrRegToReg {
+                destination,
+                reg1,
+                reg2,
+            } => write!(f, "orr  {}, {}, {}", destination, reg1, reg2),
+            EorRegToReg {
+                destination,
+                reg1,
+                reg2,
+            } => write!(f, "eor  {}, {}, {}", destination, reg1, reg2),
+            LslRegToReg {
+                destination,
+                reg1,
+                reg2,
+            } => write!(f, "lsl  {}, {}, {}", destination, reg1, reg2),
+            AsrRegToReg {
+                destination,
+                reg1,
+                reg2,
+            } => write!(f, "asr  {}, {}, {}", destination, reg1, reg2),
+            LsrRegToReg {
+                destination,
+                reg1,
+                reg2,
+            } => write!(f, "lsr  {}, {}, {}", destination, reg1, reg2),
+            AsrImm {
+                destination,
+                source,
+                amount,
+            } => write!(f, "asr  {}, {}, #{}", destination, source, amount),
+            Ubfx {
+                destination,
+                source,
+                width,
+            } => write!(f, "ubfx {}, {}, #0, #{}", destination, source, width),
+            Sbfx {
+                destination,
+                source,
+                width,
+            } => write!(f, "sbfx {}, {}, #0, #{}", destination, source, width),
             Blr { register } => write!(f, "blr {}", register),
+            Br { register } => write!(f, "br   {}", register),
             Str {
                 source,
                 base,
@@ -240,6 +746,16 @@ impl Display for Aarch64Instruction {
                 base,
                 offset,
             } => write!(f, "ldr  {}, [{}, #{}]", destination, base, offset),
+            StrRegOffset {
+                source,
+                base,
+                index,
+            } => write!(f, "str  {}, [{}, {}, lsl #3]", source, base, index),
+            LdrRegOffset {
+                destination,
+                base,
+                index,
+            } => write!(f, "ldr  {}, [{}, {}, lsl #3]", destination, base, index),
             Stp {
                 reg1,
                 reg2,
@@ -264,6 +780,73 @@ impl Display for Aarch64Instruction {
                 source,
                 destination,
             } => write!(f, "neg  {}, {}", destination, source),
+            Mvn {
+                source,
+                destination,
+            } => write!(f, "mvn  {}, {}", destination, source),
+            CmpRegToReg { reg1, reg2 } => write!(f, "cmp  {}, {}", reg1, reg2),
+            CmpImm { register, value } => write!(f, "cmp  {}, #{}", register, value),
+            Cset {
+                destination,
+                condition,
+            } => write!(f, "cset {}, {}", destination, condition),
+            Csel {
+                destination,
+                reg1,
+                reg2,
+                condition,
+            } => write!(f, "csel {}, {}, {}, {}", destination, reg1, reg2, condition),
+            Cneg {
+                destination,
+                source,
+                condition,
+            } => write!(f, "cneg {}, {}, {}", destination, source, condition),
+            Cnt {
+                source,
+                destination,
+            } => write!(
+                f,
+                "cnt  v{}.8b, v{}.8b",
+                destination.index(),
+                source.index()
+            ),
+            Addv {
+                source,
+                destination,
+            } => write!(f, "addv b{}, v{}.8b", destination.index(), source.index()),
+            Fmov {
+                source,
+                destination,
+            } => write!(f, "fmov {}, {}", destination, source),
+            Fadd {
+                destination,
+                reg1,
+                reg2,
+            } => write!(f, "fadd {}, {}, {}", destination, reg1, reg2),
+            Fsub {
+                destination,
+                reg1,
+                reg2,
+            } => write!(f, "fsub {}, {}, {}", destination, reg1, reg2),
+            Fmul {
+                destination,
+                reg1,
+                reg2,
+            } => write!(f, "fmul {}, {}, {}", destination, reg1, reg2),
+            Fdiv {
+                destination,
+                reg1,
+                reg2,
+            } => write!(f, "fdiv {}, {}, {}", destination, reg1, reg2),
+            Fcmp { reg1, reg2 } => write!(f, "fcmp {}, {}", reg1, reg2),
+            Scvtf {
+                source,
+                destination,
+            } => write!(f, "scvtf {}, {}", destination, source),
+            Fcvtzs {
+                source,
+                destination,
+            } => write!(f, "fcvtzs {}, {}", destination, source),
         }
     }
 }
@@ -276,21 +859,105 @@ impl Aarch64Instruction {
     const MOV: u32 = 0xAA0003E0;
     const MOV_SP_TO_REG: u32 = 0x910003e0;
     const ADD: u32 = 0x8B000000;
+    const ADDS: u32 = 0xAB000000;
     const SUBS: u32 = 0xEB000000;
     const MUL: u32 = 0x9B007C00;
+    const SMULH: u32 = 0x9B407C00;
+    const UMULH: u32 = 0x9BC07C00;
     const SDIV: u32 = 0x9AC00C00;
+    const UDIV: u32 = 0x9AC00800;
     const BLR: u32 = 0xD63F0000;
+    const BR: u32 = 0xD61F0000;
     const STR: u32 = 0xF9000000;
     const LDR: u32 = 0xF9400000;
+    const STR_REG_OFFSET: u32 = 0xF8207800;
+    const LDR_REG_OFFSET: u32 = 0xF8607800;
     const STP: u32 = 0xA9000000;
     const STP_PRE_INDEX: u32 = 0xA9800000;
     const LDP: u32 = 0xA8C00000;
     const NEG: u32 = 0xCB0003E0;
+    const B: u32 = 0x14000000;
+    const CBZ: u32 = 0xB4000000;
+    const CBNZ: u32 = 0xB5000000;
+    const ADR: u32 = 0x10000000;
+    const CMP: u32 = 0xEB00001F;
+    const CMP_IMM: u32 = 0xF100001F;
+    const CSET: u32 = 0x9A9F07E0;
+    const CSEL: u32 = 0x9A800000;
+    const CNEG: u32 = 0xDA800400;
+    const CNT: u32 = 0x0E205800;
+    const ADDV: u32 = 0x0E31B800;
+    const MSUB: u32 = 0x9B008000;
+    const AND: u32 = 0x8A000000;
+    const ORR: u32 = 0xAA000000;
+    const EOR: u32 = 0xCA000000;
+    const LSLV: u32 = 0x9AC02000;
+    const ASRV: u32 = 0x9AC02800;
+    const LSRV: u32 = 0x9AC02400;
+    const UBFM: u32 = 0xD3400000;
+    const SBFM: u32 = 0x93400000;
+    const ASR_IMM: u32 = 0x9340FC00;
+    const MVN: u32 = 0xAA2003E0;
+    const FMOV: u32 = 0x1E604000;
+    const FMOV_TO_FLOAT: u32 = 0x9E670000;
+    const FMOV_FROM_FLOAT: u32 = 0x9E660000;
+    const FADD: u32 = 0x1E602800;
+    const FSUB: u32 = 0x1E603800;
+    const FMUL: u32 = 0x1E600800;
+    const FDIV: u32 = 0x1E601800;
+    const FCMP: u32 = 0x1E602000;
+    const SCVTF: u32 = 0x9E620000;
+    const FCVTZS: u32 = 0x9E780000;
+    const STR_FLOAT: u32 = 0xFD000000;
+    const LDR_FLOAT: u32 = 0xFD400000;
 
     fn make_machine_code(&self) -> Vec<u8> {
         match self {
             Nop => vec![0xD5, 0x03, 0x20, 0x1F],
             Ret => vec![0xC0, 0x03, 0x5F, 0xD6],
+            Label { .. } => vec![],
+
+            B { offset, .. } => {
+                let mut i = Self::B;
+                i |= ((offset >> 2) as u32) & 0x3FFFFFF;
+                i.to_le_bytes().to_vec()
+            }
+
+            Cbz {
+                register, offset, ..
+            } => {
+                let mut i = Self::CBZ;
+                i |= (((offset >> 2) as u32) & 0x7FFFF) << 5;
+                i |= register.index();
+                i.to_le_bytes().to_vec()
+            }
+
+            Cbnz {
+                register, offset, ..
+            } => {
+                let mut i = Self::CBNZ;
+                i |= (((offset >> 2) as u32) & 0x7FFFF) << 5;
+                i |= register.index();
+                i.to_le_bytes().to_vec()
+            }
+
+            Adr {
+                destination,
+                offset,
+                ..
+            } => {
+                // The two low bits of the offset go in immlo, the others in immhi
+                let mut i = Self::ADR;
+                i |= ((*offset as u32) & 0x3) << 29;
+                i |= (((offset >> 2) as u32) & 0x7FFFF) << 5;
+                i |= destination.index();
+                i.to_le_bytes().to_vec()
+            }
+
+            JumpTable { offsets, .. } => offsets
+                .iter()
+                .flat_map(|offset| offset.to_le_bytes())
+                .collect(),
 
             MovImmToReg { register, value } => {
                 // Note: there are a lot more efficient encoding: for example, we always
@@ -350,6 +1017,12 @@ impl Aarch64Instruction {
                 reg2,
             } => Self::encode_three_reg_op(Self::ADD, destination, reg1, reg2),
 
+            AddsRegToReg {
+                destination,
+                reg1,
+                reg2,
+            } => Self::encode_three_reg_op(Self::ADDS, destination, reg1, reg2),
+
             SubRegToReg {
                 destination,
                 reg1,
@@ -362,14 +1035,112 @@ impl Aarch64Instruction {
                 reg2,
             } => Self::encode_three_reg_op(Self::MUL, destination, reg1, reg2),
 
+            Smulh {
+                destination,
+                reg1,
+                reg2,
+            } => Self::encode_three_reg_op(Self::SMULH, destination, reg1, reg2),
+
+            Umulh {
+                destination,
+                reg1,
+                reg2,
+            } => Self::encode_three_reg_op(Self::UMULH, destination, reg1, reg2),
+
             DivRegToReg {
                 destination,
                 reg1,
                 reg2,
             } => Self::encode_three_reg_op(Self::SDIV, destination, reg1, reg2),
 
-            Blr { register } => {
-                let mut i = Self::BLR;
+            UdivRegToReg {
+                destination,
+                reg1,
+                reg2,
+            } => Self::encode_three_reg_op(Self::UDIV, destination, reg1, reg2),
+
+            Msub {
+                destination,
+                reg1,
+                reg2,
+                minuend,
+            } => {
+                let mut i: u32 = Self::MSUB;
+                i |= reg2.index() << 16;
+                i |= minuend.index() << 10;
+                i |= reg1.index() << 5;
+                i |= destination.index();
+                i.to_le_bytes().to_vec()
+            }
+
+            AndRegToReg {
+                destination,
+                reg1,
+                reg2,
+            } => Self::encode_three_reg_op(Self::AND, destination, reg1, reg2),
+
+            OrrRegToReg {
+                destination,
+                reg1,
+                reg2,
+            } => Self::encode_three_reg_op(Self::ORR, destination, reg1, reg2),
+
+            EorRegToReg {
+                destination,
+                reg1,
+                reg2,
+            } => Self::encode_three_reg_op(Self::EOR, destination, reg1, reg2),
+
+            LslRegToReg {
+                destination,
+                reg1,
+                reg2,
+            } => Self::encode_three_reg_op(Self::LSLV, destination, reg1, reg2),
+
+            AsrRegToReg {
+                destination,
+                reg1,
+                reg2,
+            } => Self::encode_three_reg_op(Self::ASRV, destination, reg1, reg2),
+
+            LsrRegToReg {
+                destination,
+                reg1,
+                reg2,
+            } => Self::encode_three_reg_op(Self::LSRV, destination, reg1, reg2),
+
+            AsrImm {
+                destination,
+                source,
+                amount,
+            } => {
+                let mut i: u32 = Self::ASR_IMM;
+                i |= amount << 16;
+                i |= source.index() << 5;
+                i |= destination.index();
+                i.to_le_bytes().to_vec()
+            }
+
+            Ubfx {
+                destination,
+                source,
+                width,
+            } => Self::encode_bitfield_extract(Self::UBFM, destination, source, *width),
+
+            Sbfx {
+                destination,
+                source,
+                width,
+            } => Self::encode_bitfield_extract(Self::SBFM, destination, source, *width),
+
+            Blr { register } => {
+                let mut i = Self::BLR;
+                i |= register.index() << 5;
+                i.to_le_bytes().to_vec()
+            }
+
+            Br { register } => {
+                let mut i = Self::BR;
                 i |= register.index() << 5;
                 i.to_le_bytes().to_vec()
             }
@@ -379,7 +1150,11 @@ impl Aarch64Instruction {
                 base,
                 offset,
             } => {
-                let mut i = Self::STR;
+                let mut i = if source.is_float() {
+                    Self::STR_FLOAT
+                } else {
+                    Self::STR
+                };
                 i |= base.index() << 5;
                 i |= source.index();
                 i |= (offset >> 3) << 10;
@@ -391,13 +1166,29 @@ impl Aarch64Instruction {
                 base,
                 offset,
             } => {
-                let mut i = Self::LDR;
+                let mut i = if destination.is_float() {
+                    Self::LDR_FLOAT
+                } else {
+                    Self::LDR
+                };
                 i |= base.index() << 5;
                 i |= destination.index();
                 i |= (offset >> 3) << 10;
                 i.to_le_bytes().to_vec()
             }
 
+            StrRegOffset {
+                source,
+                base,
+                index,
+            } => Self::encode_three_reg_op(Self::STR_REG_OFFSET, source, base, index),
+
+            LdrRegOffset {
+                destination,
+                base,
+                index,
+            } => Self::encode_three_reg_op(Self::LDR_REG_OFFSET, destination, base, index),
+
             Stp {
                 reg1,
                 reg2,
@@ -413,7 +1204,7 @@ impl Aarch64Instruction {
                 i |= reg1.index();
                 i |= reg2.index() << 10;
                 i |= base.index() << 5;
-                let offset: u32 = unsafe { std::mem::transmute((offset >> 3) & 0x7F) };
+                let offset: u32 = ((offset >> 3) & 0x7F) as u32;
                 i |= offset << 15;
                 i.to_le_bytes().to_vec()
             }
@@ -428,7 +1219,7 @@ impl Aarch64Instruction {
                 i |= reg1.index();
                 i |= reg2.index() << 10;
                 i |= base.index() << 5;
-                let offset: u32 = unsafe { std::mem::transmute((offset >> 3) & 0x7F) };
+                let offset: u32 = ((offset >> 3) & 0x7F) as u32;
                 i |= offset << 15;
                 i.to_le_bytes().to_vec()
             }
@@ -442,6 +1233,157 @@ impl Aarch64Instruction {
                 i |= destination.index();
                 i.to_le_bytes().to_vec()
             }
+
+            Mvn {
+                source,
+                destination,
+            } => {
+                let mut i: u32 = Self::MVN;
+                i |= source.index() << 16;
+                i |= destination.index();
+                i.to_le_bytes().to_vec()
+            }
+
+            CmpImm { register, value } => {
+                let mut i: u32 = Self::CMP_IMM;
+                i |= (value & 0xFFF) << 10;
+                i |= register.index() << 5;
+                i.to_le_bytes().to_vec()
+            }
+
+            CmpRegToReg { reg1, reg2 } => {
+                let mut i: u32 = Self::CMP;
+                i |= reg2.index() << 16;
+                i |= reg1.index() << 5;
+                i.to_le_bytes().to_vec()
+            }
+
+            Cset {
+                destination,
+                condition,
+            } => {
+                // cset is an alias of csinc with the inverted condition, and inverting
+                // a condition simply means flipping its lowest bit
+                let mut i: u32 = Self::CSET;
+                i |= (condition.code() ^ 1) << 12;
+                i |= destination.index();
+                i.to_le_bytes().to_vec()
+            }
+
+            Csel {
+                destination,
+                reg1,
+                reg2,
+                condition,
+            } => {
+                let mut i: u32 = Self::CSEL;
+                i |= reg2.index() << 16;
+                i |= condition.code() << 12;
+                i |= reg1.index() << 5;
+                i |= destination.index();
+                i.to_le_bytes().to_vec()
+            }
+
+            Cneg {
+                destination,
+                source,
+                condition,
+            } => {
+                // cneg is an alias of csneg with both operands equal to the source and
+                // the inverted condition
+                let mut i: u32 = Self::CNEG;
+                i |= source.index() << 16;
+                i |= (condition.code() ^ 1) << 12;
+                i |= source.index() << 5;
+                i |= destination.index();
+                i.to_le_bytes().to_vec()
+            }
+
+            Cnt {
+                source,
+                destination,
+            } => {
+                let mut i: u32 = Self::CNT;
+                i |= source.index() << 5;
+                i |= destination.index();
+                i.to_le_bytes().to_vec()
+            }
+
+            Addv {
+                source,
+                destination,
+            } => {
+                let mut i: u32 = Self::ADDV;
+                i |= source.index() << 5;
+                i |= destination.index();
+                i.to_le_bytes().to_vec()
+            }
+
+            Fmov {
+                source,
+                destination,
+            } => {
+                let mut i: u32 = match (source.is_float(), destination.is_float()) {
+                    (true, true) => Self::FMOV,
+                    (false, true) => Self::FMOV_TO_FLOAT,
+                    (true, false) => Self::FMOV_FROM_FLOAT,
+                    (false, false) => unreachable!("fmov needs a float register"),
+                };
+                i |= source.index() << 5;
+                i |= destination.index();
+                i.to_le_bytes().to_vec()
+            }
+
+            Fadd {
+                destination,
+                reg1,
+                reg2,
+            } => Self::encode_three_reg_op(Self::FADD, destination, reg1, reg2),
+
+            Fsub {
+                destination,
+                reg1,
+                reg2,
+            } => Self::encode_three_reg_op(Self::FSUB, destination, reg1, reg2),
+
+            Fmul {
+                destination,
+                reg1,
+                reg2,
+            } => Self::encode_three_reg_op(Self::FMUL, destination, reg1, reg2),
+
+            Fdiv {
+                destination,
+                reg1,
+                reg2,
+            } => Self::encode_three_reg_op(Self::FDIV, destination, reg1, reg2),
+
+            Fcmp { reg1, reg2 } => {
+                let mut i: u32 = Self::FCMP;
+                i |= reg2.index() << 16;
+                i |= reg1.index() << 5;
+                i.to_le_bytes().to_vec()
+            }
+
+            Scvtf {
+                source,
+                destination,
+            } => {
+                let mut i: u32 = Self::SCVTF;
+                i |= source.index() << 5;
+                i |= destination.index();
+                i.to_le_bytes().to_vec()
+            }
+
+            Fcvtzs {
+                source,
+                destination,
+            } => {
+                let mut i: u32 = Self::FCVTZS;
+                i |= source.index() << 5;
+                i |= destination.index();
+                i.to_le_bytes().to_vec()
+            }
         }
     }
 
@@ -452,6 +1394,21 @@ impl Aarch64Instruction {
         i0.to_le_bytes().to_vec()
     }
 
+    /// Encodes ubfm or sbfm extracting the lowest `width` bits, i.e. with immr = 0
+    /// and imms = width - 1
+    fn encode_bitfield_extract(
+        base: u32,
+        destination: &Register,
+        source: &Register,
+        width: u32,
+    ) -> Vec<u8> {
+        let mut i: u32 = base;
+        i |= (width - 1) << 10;
+        i |= source.index() << 5;
+        i |= destination.index();
+        i.to_le_bytes().to_vec()
+    }
+
     fn encode_three_reg_op(
         base: u32,
         destination: &Register,
@@ -473,6 +1430,15 @@ pub struct Aarch64Generator {
     max_stack_offset: u32,
     used_registers: Vec<Register>,
     used_args_registers: Vec<Register>,
+    /// Size of the stack area where values are spilled, right after x29 and x30
+    spill_area_size: u32,
+    /// The scratch registers that we use, which are callee-saved and thus stored in the
+    /// frame, after the spilled values
+    saved_registers: Vec<Register>,
+    /// Labels of the code, placed after all the blocks, that handles the traps
+    trap_labels: TrapLabels,
+    /// The jump tables used by the function, which are placed after all the code
+    jump_tables: JumpTables,
 }
 
 impl MachineCodeGenerator for Aarch64Generator {
@@ -481,259 +1447,555 @@ impl MachineCodeGenerator for Aarch64Generator {
         function: &CompiledFunction,
         function_catalog: &CompiledFunctionCatalog,
     ) -> Result<GeneratedMachineCode, BackendError> {
+        let function = &eliminate_phis(&expand_intrinsics(function));
         self.allocate_registers(function);
-        self.compute_used_args_registers(function)?;
+        self.compute_used_args_registers(function);
+        self.trap_labels = TrapLabels::new(function.blocks.len());
+        self.jump_tables = JumpTables::new(&self.trap_labels);
 
         let mut instructions = Vec::new();
         let mut index_of_ldp_to_fix = Vec::new();
-        self.stack_offset += 16;
+        let mut index_of_stack_argument_ldr_to_fix = Vec::new();
+        self.stack_offset =
+            SPILL_AREA_OFFSET + self.spill_area_size + 8 * self.saved_registers.len() as u32;
         self.max_stack_offset = self.stack_offset;
 
         // This will be overwritten at the end, once we have completed computation
         // of the necessary stack depth
         instructions.push(Nop);
         instructions.push(MovSpToReg { destination: X29 });
+        for (index, register) in self.saved_registers.iter().enumerate() {
+            instructions.push(Str {
+                source: *register,
+                base: X29,
+                offset: self.saved_register_offset(index),
+            });
+        }
 
-        for instruction in function.body.iter() {
-            match instruction {
-                IrInstruction::Mvi { dest, val } => {
-                    let AllocatedLocation::Register { register } = self.locations[dest.0] else {
-                        return Err(BackendError::NotImplemented(
-                            "move immediate to stack".to_string(),
-                        ));
-                    };
+        for (block_index, block) in function.blocks.iter().enumerate() {
+            // The entry block is never the target of a jump
+            if block_index > 0 {
+                instructions.push(Label { block: block.id });
+            }
+            let next_block = function.blocks.get(block_index + 1).map(|b| b.id);
 
-                    instructions.push(MovImmToReg {
-                        register,
-                        value: *val,
-                    })
-                }
+            for (index, instruction) in block.body.iter().enumerate() {
+                match instruction {
+                    IrInstruction::Mvi { dest, val } => {
+                        let register = self.destination_register(function, dest);
+                        if register.is_float() {
+                            // Floats are moved as their bits
+                            instructions.push(MovImmToReg {
+                                register: X16,
+                                value: *val,
+                            });
+                            instructions.push(Fmov {
+                                source: X16,
+                                destination: register,
+                            });
+                        } else {
+                            instructions.push(MovImmToReg {
+                                register,
+                                value: *val,
+                            })
+                        }
+                        self.store(&mut instructions, register, dest);
+                    }
 
-                IrInstruction::MvArg { dest, arg } => {
-                    let location = Self::get_argument_location(*arg)?;
-                    let AllocatedLocation::Register { register: source } = location else {
-                        return Err(BackendError::NotImplemented(
-                            "move argument from stack".to_string(),
-                        ));
-                    };
-
-                    let AllocatedLocation::Register {
-                        register: destination,
-                    } = self.locations[dest.0]
-                    else {
-                        return Err(BackendError::NotImplemented(
-                            "move argument to stack".to_string(),
-                        ));
-                    };
-
-                    instructions.push(MovRegToReg {
-                        source,
-                        destination,
-                    });
-                }
+                    IrInstruction::Mv { dest, src } => {
+                        let source = self.operand_register(&mut instructions, function, src, 0);
+                        self.store(&mut instructions, source, dest);
+                    }
 
-                IrInstruction::Ret { reg } => {
-                    let AllocatedLocation::Register { register: source } = self.locations[reg.0]
-                    else {
-                        return Err(BackendError::NotImplemented(
-                            "return value from stack".to_string(),
-                        ));
-                    };
-
-                    instructions.push(MovRegToReg {
-                        source,
-                        destination: X0,
-                    });
+                    IrInstruction::Phi { .. } => {
+                        unreachable!("phi instructions should have been eliminated")
+                    }
 
-                    // We will replace this with the correct LDP at the end,
-                    // once the final stack depth has been computed
-                    index_of_ldp_to_fix.push(instructions.len());
-                    instructions.push(Nop);
+                    IrInstruction::Jmp { target } => {
+                        if Some(*target) != next_block {
+                            instructions.push(B {
+                                target: *target,
+                                offset: 0,
+                            });
+                        }
+                    }
 
-                    instructions.push(Ret);
-                }
+                    IrInstruction::Br {
+                        cond,
+                        if_true,
+                        if_false,
+                    } => {
+                        let register = self.operand_register(&mut instructions, function, cond, 0);
 
-                IrInstruction::Neg { dest, op } => {
-                    let AllocatedLocation::Register { register: source } = self.locations[op.0]
-                    else {
-                        return Err(BackendError::NotImplemented(
-                            "negate stack value".to_string(),
-                        ));
-                    };
-
-                    let AllocatedLocation::Register {
-                        register: destination,
-                    } = self.locations[dest.0]
-                    else {
-                        return Err(BackendError::NotImplemented(
-                            "store negation to stack value".to_string(),
-                        ));
-                    };
-
-                    instructions.push(Neg {
-                        destination,
-                        source,
-                    });
-                }
+                        instructions.push(Cbz {
+                            register,
+                            target: *if_false,
+                            offset: 0,
+                        });
+                        if Some(*if_true) != next_block {
+                            instructions.push(B {
+                                target: *if_true,
+                                offset: 0,
+                            });
+                        }
+                    }
 
-                IrInstruction::BinOp {
-                    operator,
-                    dest,
-                    op1,
-                    op2,
-                } => {
-                    let AllocatedLocation::Register { register: reg1 } = self.locations[op1.0]
-                    else {
-                        return Err(BackendError::NotImplemented(
-                            "binop when one operand is in stack".to_string(),
-                        ));
-                    };
-                    let AllocatedLocation::Register { register: reg2 } = self.locations[op2.0]
-                    else {
-                        return Err(BackendError::NotImplemented(
-                            "binop when one operand is in stack".to_string(),
-                        ));
-                    };
-                    let AllocatedLocation::Register {
-                        register: destination,
-                    } = self.locations[dest.0]
-                    else {
-                        return Err(BackendError::NotImplemented(
-                            "binop when destination is in stack".to_string(),
-                        ));
-                    };
-
-                    instructions.push(match operator {
-                        Add => AddRegToReg {
-                            destination,
-                            reg1,
-                            reg2,
-                        },
-                        Sub => SubRegToReg {
-                            destination,
-                            reg1,
-                            reg2,
-                        },
-                        Mul => MulRegToReg {
+                    IrInstruction::Switch {
+                        value,
+                        first,
+                        targets,
+                        default,
+                    } => {
+                        let register = self.operand_register(&mut instructions, function, value, 0);
+
+                        // Values below `first` wrap around to huge unsigned indexes, so a
+                        // single comparison catches both the ones before and after the table
+                        if *first != 0 {
+                            instructions.push(MovImmToReg {
+                                register: X16,
+                                value: *first,
+                            });
+                            instructions.push(SubRegToReg {
+                                destination: X17,
+                                reg1: register,
+                                reg2: X16,
+                            });
+                        } else {
+                            instructions.push(MovRegToReg {
+                                source: register,
+                                destination: X17,
+                            });
+                        }
+                        instructions.push(MovImmToReg {
+                            register: X16,
+                            value: targets.len() as i64,
+                        });
+                        Self::compare(&mut instructions, X8, X17, X16, Condition::Lo);
+                        instructions.push(Cbz {
+                            register: X8,
+                            target: *default,
+                            offset: 0,
+                        });
+                        let table = self.jump_tables.add(targets);
+                        instructions.push(Adr {
+                            destination: X16,
+                            target: table,
+                            offset: 0,
+                        });
+                        instructions.push(LdrRegOffset {
+                            destination: X8,
+                            base: X16,
+                            index: X17,
+                        });
+                        instructions.push(AddRegToReg {
+                            destination: X16,
+                            reg1: X16,
+                            reg2: X8,
+                        });
+                        instructions.push(Br { register: X16 });
+                    }
+
+                    IrInstruction::MvArg { dest, arg } => {
+                        match Self::get_argument_location(function, *arg) {
+                            AllocatedLocation::Register { register: source } => {
+                                self.store(&mut instructions, source, dest);
+                            }
+                            AllocatedLocation::Stack { offset } => {
+                                // The arguments are where the stack pointer was before our
+                                // frame was reserved, so the offset is fixed at the end
+                                let destination = self.destination_register(function, dest);
+                                index_of_stack_argument_ldr_to_fix.push(instructions.len());
+                                instructions.push(Ldr {
+                                    destination,
+                                    base: X29,
+                                    offset: (offset * 8) as u32,
+                                });
+                                self.store(&mut instructions, destination, dest);
+                            }
+                        }
+                    }
+
+                    IrInstruction::Ret { reg } => {
+                        let source = self.operand_register(&mut instructions, function, reg, 0);
+                        instructions.push(Self::copy(source, Self::result_register(source)));
+                        self.restore_saved_registers(&mut instructions);
+
+                        // We will replace this with the correct LDP at the end,
+                        // once the final stack depth has been computed
+                        index_of_ldp_to_fix.push(instructions.len());
+                        instructions.push(Nop);
+
+                        instructions.push(Ret);
+                    }
+
+                    IrInstruction::Neg { dest, op } => {
+                        let source = self.operand_register(&mut instructions, function, op, 0);
+                        let destination = self.destination_register(function, dest);
+                        instructions.push(Neg {
                             destination,
-                            reg1,
-                            reg2,
-                        },
-                        Div => DivRegToReg {
+                            source,
+                        });
+                        self.store(&mut instructions, destination, dest);
+                    }
+
+                    IrInstruction::BitwiseNot { dest, op } => {
+                        let source = self.operand_register(&mut instructions, function, op, 0);
+                        let destination = self.destination_register(function, dest);
+                        instructions.push(Mvn {
                             destination,
-                            reg1,
-                            reg2,
-                        },
-                    });
-                }
+                            source,
+                        });
+                        self.store(&mut instructions, destination, dest);
+                    }
+
+                    IrInstruction::Abs { dest, op }
+                    | IrInstruction::Sign { dest, op }
+                    | IrInstruction::Popcount { dest, op } => {
+                        let source = self.operand_register(&mut instructions, function, op, 0);
+                        let destination = self.destination_register(function, dest);
+                        Self::unary_intrinsic(&mut instructions, instruction, destination, source);
+                        self.store(&mut instructions, destination, dest);
+                    }
 
-                IrInstruction::Call {
-                    dest,
-                    name: _,
-                    function_id: called_function_id,
-                    args: call_args,
-                } => {
-                    let fn_catalog_addr: usize =
-                        function_catalog as *const CompiledFunctionCatalog as usize;
-                    let jit_call_trampoline_address: usize = jit_call_trampoline as usize;
-
-                    self.push(&mut instructions, X0);
-
-                    // We will put the jump address in X19
-                    self.push(&mut instructions, X19);
-
-                    // Store all registers being used. We should skip the destination one
-                    // for this instruction, since we will overwrite it, but whatever.
-                    // We generate horrible code anyway... what's one more push/pop pair? :-D
-                    let used_registers = self.used_registers.clone();
-                    for used_register in used_registers.iter().cloned() {
-                        self.push(&mut instructions, used_register);
+                    IrInstruction::Cast { dest, op, ty } => {
+                        let source = self.operand_register(&mut instructions, function, op, 0);
+                        let destination = self.destination_register(function, dest);
+                        Self::normalize(&mut instructions, *ty, destination, source);
+                        self.store(&mut instructions, destination, dest);
                     }
-                    let used_args_registers = self.used_args_registers.clone();
-                    for used_arg_register in used_args_registers.iter().cloned() {
-                        if used_arg_register != X0 {
-                            self.push(&mut instructions, used_arg_register);
+
+                    IrInstruction::BinOp {
+                        operator,
+                        ty,
+                        dest,
+                        op1,
+                        op2,
+                    } => {
+                        let reg1 = self.operand_register(&mut instructions, function, op1, 0);
+                        let reg2 = self.operand_register(&mut instructions, function, op2, 1);
+                        let destination = self.destination_register(function, dest);
+
+                        if ty.is_float() {
+                            Self::float_binop(
+                                &mut instructions,
+                                *operator,
+                                destination,
+                                reg1,
+                                reg2,
+                            );
+                            self.store(&mut instructions, destination, dest);
+                            continue;
+                        }
+
+                        match operator {
+                            Add | Sub | Mul if function.overflow_mode != OverflowMode::Wrapping => {
+                                self.arithmetic_with_overflow(
+                                    &mut instructions,
+                                    *operator,
+                                    *ty,
+                                    function.overflow_mode,
+                                    destination,
+                                    reg1,
+                                    reg2,
+                                )
+                            }
+                            Add | Sub | Mul => Self::wrapping_arithmetic(
+                                &mut instructions,
+                                *operator,
+                                destination,
+                                reg1,
+                                reg2,
+                            ),
+                            Div => {
+                                self.check_divisor(
+                                    &mut instructions,
+                                    reg1,
+                                    reg2,
+                                    DivisorChecks::new(function, *ty, *op2),
+                                );
+                                instructions.push(if ty.is_unsigned() {
+                                    UdivRegToReg {
+                                        destination,
+                                        reg1,
+                                        reg2,
+                                    }
+                                } else {
+                                    DivRegToReg {
+                                        destination,
+                                        reg1,
+                                        reg2,
+                                    }
+                                });
+                            }
+                            Rem => {
+                                self.check_divisor(
+                                    &mut instructions,
+                                    reg1,
+                                    reg2,
+                                    DivisorChecks::new(function, *ty, *op2),
+                                );
+                                // The register allocator never assigns the destination to
+                                // one of the operands, so we can use it for the quotient
+                                instructions.push(if ty.is_unsigned() {
+                                    UdivRegToReg {
+                                        destination,
+                                        reg1,
+                                        reg2,
+                                    }
+                                } else {
+                                    DivRegToReg {
+                                        destination,
+                                        reg1,
+                                        reg2,
+                                    }
+                                });
+                                instructions.push(Msub {
+                                    destination,
+                                    reg1: destination,
+                                    reg2,
+                                    minuend: reg1,
+                                });
+                            }
+                            BitwiseAnd => instructions.push(AndRegToReg {
+                                destination,
+                                reg1,
+                                reg2,
+                            }),
+                            BitwiseOr => instructions.push(OrrRegToReg {
+                                destination,
+                                reg1,
+                                reg2,
+                            }),
+                            BitwiseXor => instructions.push(EorRegToReg {
+                                destination,
+                                reg1,
+                                reg2,
+                            }),
+                            ShiftLeft => Self::shift_logical(
+                                &mut instructions,
+                                LslRegToReg {
+                                    destination,
+                                    reg1,
+                                    reg2,
+                                },
+                                destination,
+                                reg2,
+                            ),
+                            ShiftRight if ty.is_unsigned() => Self::shift_logical(
+                                &mut instructions,
+                                LsrRegToReg {
+                                    destination,
+                                    reg1,
+                                    reg2,
+                                },
+                                destination,
+                                reg2,
+                            ),
+                            ShiftRight => {
+                                // Shifting by 63 already fills the register with the sign
+                                // bit, so we clamp the amount to 63
+                                instructions.push(CmpImm {
+                                    register: reg2,
+                                    value: 63,
+                                });
+                                instructions.push(MovImmToReg {
+                                    register: destination,
+                                    value: 63,
+                                });
+                                instructions.push(Csel {
+                                    destination,
+                                    reg1: reg2,
+                                    reg2: destination,
+                                    condition: Condition::Ls,
+                                });
+                                instructions.push(AsrRegToReg {
+                                    destination,
+                                    reg1,
+                                    reg2: destination,
+                                });
+                            }
+                            Equal | NotEqual | LessThan | LessThanOrEqual | GreaterThan
+                            | GreaterThanOrEqual => Self::compare(
+                                &mut instructions,
+                                destination,
+                                reg1,
+                                reg2,
+                                Self::comparison_condition(*operator, ty.is_unsigned()),
+                            ),
+                            Min | Max => {
+                                let operator = if *operator == Min {
+                                    LessThan
+                                } else {
+                                    GreaterThan
+                                };
+                                instructions.push(CmpRegToReg { reg1, reg2 });
+                                instructions.push(Csel {
+                                    destination,
+                                    reg1,
+                                    reg2,
+                                    condition: Self::comparison_condition(
+                                        operator,
+                                        ty.is_unsigned(),
+                                    ),
+                                });
+                            }
+                            Pow | Gcd => unreachable!("expanded before code generation"),
                         }
+                        self.store(&mut instructions, destination, dest);
                     }
 
-                    // jit_call_trampoline(function_catalog_ptr, called_function_index, args)
-                    instructions.push(MovImmToReg {
-                        register: X0,
-                        value: fn_catalog_addr as i64,
-                    });
-                    instructions.push(MovImmToReg {
-                        register: X1,
-                        value: called_function_id.0 as i64,
-                    });
+                    IrInstruction::IntToFloat { dest, op } => {
+                        let source = self.operand_register(&mut instructions, function, op, 0);
+                        let destination = self.destination_register(function, dest);
+                        instructions.push(Scvtf {
+                            source,
+                            destination,
+                        });
+                        self.store(&mut instructions, destination, dest);
+                    }
 
-                    // Fill arguments
-                    for (call_arg, actual_arg) in call_args.iter().enumerate() {
-                        let shifted_call_arg = call_arg + 2; // X0 and X1 are already used
-                        let AllocatedLocation::Register {
-                            register: actual_arg_register,
-                        } = self.locations[actual_arg.0]
-                        else {
-                            return Err(BackendError::NotImplemented(
-                                "passing arguments to function from stack".to_string(),
-                            ));
-                        };
-
-                        let arg_location = Self::get_argument_location((shifted_call_arg).into())?;
-                        let AllocatedLocation::Register {
-                            register: call_convention_arg_register,
-                        } = arg_location
-                        else {
-                            return Err(BackendError::NotImplemented(
-                                "functions with more than 8 arguments".to_string(),
-                            ));
-                        };
-
-                        instructions.push(MovRegToReg {
-                            source: actual_arg_register,
-                            destination: call_convention_arg_register,
+                    IrInstruction::FloatToInt { dest, op } => {
+                        let source = self.operand_register(&mut instructions, function, op, 0);
+                        let destination = self.destination_register(function, dest);
+                        // fcvtzs already saturates and converts NaN to zero
+                        instructions.push(Fcvtzs {
+                            source,
+                            destination,
                         });
+                        self.store(&mut instructions, destination, dest);
                     }
-                    instructions.push(MovImmToReg {
-                        register: X19,
-                        value: jit_call_trampoline_address as i64,
-                    });
 
-                    // We can finally do the actual call!
-                    instructions.push(Blr { register: X19 });
+                    IrInstruction::Call {
+                        function_id, args, ..
+                    } if backend::is_tail_call(block, index) => {
+                        self.tail_call(
+                            &mut instructions,
+                            function,
+                            function_catalog,
+                            Callee::Function(*function_id),
+                            args,
+                            &mut index_of_ldp_to_fix,
+                        )?;
+                        // The callee returns directly to our caller, so we skip the `ret`
+                        break;
+                    }
 
-                    // Restore registers
-                    for used_arg_register in used_args_registers.iter().cloned() {
-                        if used_arg_register != X0 {
-                            self.pop(&mut instructions, used_arg_register);
-                        }
+                    IrInstruction::CallIndirect { handle, args, .. }
+                        if backend::is_tail_call(block, index) =>
+                    {
+                        self.check_function_handle(
+                            &mut instructions,
+                            function,
+                            handle,
+                            args.len(),
+                            function_catalog,
+                        );
+                        self.tail_call(
+                            &mut instructions,
+                            function,
+                            function_catalog,
+                            Callee::Handle(*handle),
+                            args,
+                            &mut index_of_ldp_to_fix,
+                        )?;
+                        break;
+                    }
+
+                    IrInstruction::Call {
+                        dest,
+                        name: _,
+                        function_id,
+                        args,
+                    } => self.call_through_trampoline(
+                        &mut instructions,
+                        function,
+                        function_catalog,
+                        Callee::Function(*function_id),
+                        args,
+                        dest,
+                    )?,
+
+                    IrInstruction::CallIndirect { dest, handle, args } => {
+                        self.check_function_handle(
+                            &mut instructions,
+                            function,
+                            handle,
+                            args.len(),
+                            function_catalog,
+                        );
+                        self.call_through_trampoline(
+                            &mut instructions,
+                            function,
+                            function_catalog,
+                            Callee::Handle(*handle),
+                            args,
+                            dest,
+                        )?
                     }
-                    for used_register in used_registers.iter().rev().cloned() {
-                        self.pop(&mut instructions, used_register);
+
+                    IrInstruction::CallHost {
+                        dest,
+                        name: _,
+                        host_function_id,
+                        args: call_args,
+                    } => {
+                        // Host functions are called directly, without the trampoline
+                        let address = function_catalog.get_host_function_address(*host_function_id);
+                        self.call(&mut instructions, function, address, &[], call_args, dest)?;
+                    }
+
+                    IrInstruction::Load { dest, base, index } => {
+                        let destination = self.destination_register(function, dest);
+                        self.check_bounds(
+                            &mut instructions,
+                            function,
+                            base,
+                            index,
+                            function_catalog,
+                        );
+                        instructions.push(LdrRegOffset {
+                            destination,
+                            base: X16,
+                            index: X17,
+                        });
+                        self.store(&mut instructions, destination, dest);
                     }
-                    self.pop(&mut instructions, X19);
-
-                    // Copy result (x0) to the opportune register
-                    let AllocatedLocation::Register {
-                        register: destination,
-                    } = self.locations[dest.0]
-                    else {
-                        return Err(BackendError::NotImplemented(
-                            "move register to stack".to_string(),
-                        ));
-                    };
-
-                    instructions.push(MovRegToReg {
-                        source: X0,
-                        destination,
-                    });
 
-                    self.pop(&mut instructions, X0);
+                    IrInstruction::Store { base, index, value } => {
+                        let source = self.operand_register(&mut instructions, function, value, 2);
+                        self.check_bounds(
+                            &mut instructions,
+                            function,
+                            base,
+                            index,
+                            function_catalog,
+                        );
+                        instructions.push(StrRegOffset {
+                            source,
+                            base: X16,
+                            index: X17,
+                        });
+                    }
                 }
             }
         }
 
+        self.generate_trap_handlers(
+            &mut instructions,
+            &mut index_of_ldp_to_fix,
+            function_catalog,
+        );
+        let num_labels = self.jump_tables.num_labels();
+        self.generate_jump_tables(&mut instructions);
+
         // Replace the prologue and epilogue, now that we know the maximum stack depth
         let stack_depth_to_reserve = (self.max_stack_offset + 15) & 0xFFFFFFF0; // Must be 16-byte aligned
+        if stack_depth_to_reserve > MAX_FRAME_SIZE {
+            return Err(BackendError::NotImplemented(format!(
+                "stack frames larger than {} bytes",
+                MAX_FRAME_SIZE
+            )));
+        }
         instructions[0] = Stp {
             reg1: X29,
             reg2: X30,
@@ -749,6 +2011,52 @@ impl MachineCodeGenerator for Aarch64Generator {
                 offset: stack_depth_to_reserve as i32,
             };
         }
+        for ldr_to_fix_index in index_of_stack_argument_ldr_to_fix {
+            if let Ldr { offset, .. } = &mut instructions[ldr_to_fix_index] {
+                *offset += stack_depth_to_reserve;
+            }
+        }
+
+        // Now that the position of every block is known, we can compute the branch offsets
+        let mut block_offsets = vec![0; num_labels];
+        let mut offset = 0;
+        for instruction in instructions.iter() {
+            if let Label { block } = instruction {
+                block_offsets[block.0] = offset;
+            }
+            offset += instruction.make_machine_code().len() as i32;
+        }
+        let mut offset = 0;
+        for instruction in instructions.iter_mut() {
+            match instruction {
+                B {
+                    target,
+                    offset: branch_offset,
+                }
+                | Cbz {
+                    target,
+                    offset: branch_offset,
+                    ..
+                }
+                | Cbnz {
+                    target,
+                    offset: branch_offset,
+                    ..
+                }
+                | Adr {
+                    target,
+                    offset: branch_offset,
+                    ..
+                } => *branch_offset = block_offsets[target.0] - offset,
+                JumpTable {
+                    label,
+                    targets,
+                    offsets,
+                } => *offsets = JumpTables::offsets(*label, targets, &block_offsets),
+                _ => {}
+            }
+            offset += instruction.make_machine_code().len() as i32;
+        }
 
         // Done!
         let mut asm = String::new();
@@ -763,6 +2071,9 @@ impl MachineCodeGenerator for Aarch64Generator {
 
 impl Aarch64Generator {
     fn allocate_registers(&mut self, function: &CompiledFunction) {
+        self.used_registers.clear();
+        self.used_args_registers.clear();
+        self.saved_registers.clear();
         let allocations = backend_register_allocator::allocate::<Register>(
             function,
             vec![
@@ -770,6 +2081,11 @@ impl Aarch64Generator {
                 // TODO: add X19-X28 (callee-saved registers) and save them before modifying
                 X9, X10, X11, X12, X13, X14, X15,
             ],
+            vec![
+                // Only the lowest 64 bits of d8-d15 are callee-saved, so we stick to
+                // the caller-saved registers as well
+                D16, D17, D18, D19, D20, D21, D22, D23, D24, D25, D26, D27, D28, D29, D30, D31,
+            ],
         );
         self.locations = allocations;
 
@@ -784,31 +2100,215 @@ impl Aarch64Generator {
                 }
             }
         }
+
+        // The slots of the spilled values are the same for the whole function, and
+        // each class needs its scratch registers only if some of its values are spilled
+        let mut spill_area_size = 0;
+        for (index, location) in self.locations.iter().enumerate() {
+            if let AllocatedLocation::Stack { offset } = location {
+                spill_area_size = max(spill_area_size, *offset as u32 + 8);
+                let scratch_registers = Self::scratch_registers(function, &IrRegister::new(index));
+                if !self.saved_registers.contains(&scratch_registers[0]) {
+                    self.saved_registers.extend(scratch_registers);
+                }
+            }
+        }
+        self.spill_area_size = spill_area_size;
     }
 
-    fn compute_used_args_registers(
-        &mut self,
-        function: &CompiledFunction,
-    ) -> Result<(), BackendError> {
-        for arg in 0..function.num_args {
-            let location = Self::get_argument_location(arg.into())?;
-            match location {
-                AllocatedLocation::Register { register } => {
-                    self.used_args_registers.push(register);
+    fn compute_used_args_registers(&mut self, function: &CompiledFunction) {
+        for arg in 0..function.num_args() {
+            if let AllocatedLocation::Register { register } =
+                Self::get_argument_location(function, arg.into())
+            {
+                self.used_args_registers.push(register);
+            }
+        }
+    }
+
+    /// Moves between two registers, of either kind
+    fn copy(source: Register, destination: Register) -> Aarch64Instruction {
+        if source.is_float() || destination.is_float() {
+            Fmov {
+                source,
+                destination,
+            }
+        } else {
+            MovRegToReg {
+                source,
+                destination,
+            }
+        }
+    }
+
+    /// The register where the values of the same kind of `register` are returned
+    fn result_register(register: Register) -> Register {
+        if register.is_float() {
+            D0
+        } else {
+            X0
+        }
+    }
+
+    /// Emits an arithmetic operation or a comparison between floats. The conditions
+    /// after a comparison are chosen so that they are all false when one of the
+    /// operands is NaN, except for `!=`
+    fn float_binop(
+        instructions: &mut Vec<Aarch64Instruction>,
+        operator: BinOpOperator,
+        destination: Register,
+        reg1: Register,
+        reg2: Register,
+    ) {
+        let condition = match operator {
+            Add => {
+                return instructions.push(Fadd {
+                    destination,
+                    reg1,
+                    reg2,
+                })
+            }
+            Sub => {
+                return instructions.push(Fsub {
+                    destination,
+                    reg1,
+                    reg2,
+                })
+            }
+            Mul => {
+                return instructions.push(Fmul {
+                    destination,
+                    reg1,
+                    reg2,
+                })
+            }
+            Div => {
+                return instructions.push(Fdiv {
+                    destination,
+                    reg1,
+                    reg2,
+                })
+            }
+            Equal => Condition::Eq,
+            NotEqual => Condition::Ne,
+            LessThan => Condition::Mi,
+            LessThanOrEqual => Condition::Ls,
+            GreaterThan => Condition::Gt,
+            GreaterThanOrEqual => Condition::Ge,
+            _ => unreachable!("{} is not supported for floats", operator),
+        };
+        instructions.push(Fcmp { reg1, reg2 });
+        instructions.push(Cset {
+            destination,
+            condition,
+        });
+    }
+
+    /// The scratch registers for the values of the class of `reg`
+    fn scratch_registers(function: &CompiledFunction, reg: &IrRegister) -> [Register; 3] {
+        match function.register_class(*reg) {
+            RegisterClass::Integer => SCRATCH_REGISTERS,
+            RegisterClass::Float => FLOAT_SCRATCH_REGISTERS,
+        }
+    }
+
+    /// The offset from x29 of the slot of a value spilled to the stack
+    fn spill_offset(offset: usize) -> u32 {
+        SPILL_AREA_OFFSET + offset as u32
+    }
+
+    /// The offset from x29 where the n-th of the saved registers is stored
+    fn saved_register_offset(&self, index: usize) -> u32 {
+        SPILL_AREA_OFFSET + self.spill_area_size + 8 * index as u32
+    }
+
+    /// Reloads the callee-saved registers stored by the prologue, before leaving the frame
+    fn restore_saved_registers(&self, instructions: &mut Vec<Aarch64Instruction>) {
+        for (index, register) in self.saved_registers.iter().enumerate() {
+            instructions.push(Ldr {
+                destination: *register,
+                base: X29,
+                offset: self.saved_register_offset(index),
+            });
+        }
+    }
+
+    /// Copies the value of the given ir register into the hardware register `destination`
+    fn load(
+        &self,
+        instructions: &mut Vec<Aarch64Instruction>,
+        reg: &IrRegister,
+        destination: Register,
+    ) {
+        match self.locations[reg.0] {
+            AllocatedLocation::Register { register } => {
+                if register != destination {
+                    instructions.push(Self::copy(register, destination));
                 }
-                AllocatedLocation::Stack { offset: _ } => {
-                    return Err(BackendError::NotImplemented(
-                        "functions with more than 8 arguments".to_string(),
-                    ))
+            }
+            AllocatedLocation::Stack { offset } => instructions.push(Ldr {
+                destination,
+                base: X29,
+                offset: Self::spill_offset(offset),
+            }),
+        }
+    }
+
+    /// Copies the hardware register `source` into the location of the given ir register
+    fn store(
+        &self,
+        instructions: &mut Vec<Aarch64Instruction>,
+        source: Register,
+        reg: &IrRegister,
+    ) {
+        match self.locations[reg.0] {
+            AllocatedLocation::Register { register } => {
+                if register != source {
+                    instructions.push(Self::copy(source, register));
                 }
             }
+            AllocatedLocation::Stack { offset } => instructions.push(Str {
+                source,
+                base: X29,
+                offset: Self::spill_offset(offset),
+            }),
+        }
+    }
+
+    /// Returns the hardware register containing the given ir register. If it has been
+    /// spilled to the stack, it will be loaded in the n-th scratch register of its class,
+    /// so each operand of an instruction must use a different one
+    fn operand_register(
+        &self,
+        instructions: &mut Vec<Aarch64Instruction>,
+        function: &CompiledFunction,
+        reg: &IrRegister,
+        scratch: usize,
+    ) -> Register {
+        match self.locations[reg.0] {
+            AllocatedLocation::Register { register } => register,
+            AllocatedLocation::Stack { .. } => {
+                let register = Self::scratch_registers(function, reg)[scratch];
+                self.load(instructions, reg, register);
+                register
+            }
+        }
+    }
+
+    /// Returns the hardware register where an instruction should compute the value of the
+    /// given ir register, which must then be passed to `store`. For spilled values it is
+    /// the last scratch register, which no operand uses
+    fn destination_register(&self, function: &CompiledFunction, reg: &IrRegister) -> Register {
+        match self.locations[reg.0] {
+            AllocatedLocation::Register { register } => register,
+            AllocatedLocation::Stack { .. } => Self::scratch_registers(function, reg)[2],
         }
-        Ok(())
     }
 
     fn push(&mut self, instructions: &mut Vec<Aarch64Instruction>, register: Register) {
         self.stack_offset += 8;
-        self.max_stack_offset = max(self.max_stack_offset, self.stack_offset);
+        // The slot starts at stack_offset, so the frame must extend past its end
+        self.max_stack_offset = max(self.max_stack_offset, self.stack_offset + 8);
         instructions.push(Str {
             source: register,
             base: X29,
@@ -825,129 +2325,956 @@ impl Aarch64Generator {
         self.stack_offset -= 8;
     }
 
-    fn get_argument_location(
-        arg: ArgumentIndex,
-    ) -> Result<AllocatedLocation<Register>, BackendError> {
-        let arg: usize = arg.into();
-        // Should probably use some macro...
-        match arg {
-            0 => Ok(AllocatedLocation::Register { register: X0 }),
-            1 => Ok(AllocatedLocation::Register { register: X1 }),
-            2 => Ok(AllocatedLocation::Register { register: X2 }),
-            3 => Ok(AllocatedLocation::Register { register: X3 }),
-            4 => Ok(AllocatedLocation::Register { register: X4 }),
-            5 => Ok(AllocatedLocation::Register { register: X5 }),
-            6 => Ok(AllocatedLocation::Register { register: X6 }),
-            7 => Ok(AllocatedLocation::Register { register: X7 }),
-            _ => Err(BackendError::NotImplemented(
-                "support for more than 8 arguments".to_string(),
-            )),
-        }
+    /// Emits the given shift into `destination`. The hardware only considers the lowest
+    /// 6 bits of the amount, so we need to explicitly clear the result when it is not in
+    /// the range 0..64 (negative amounts look huge when considered unsigned)
+    fn shift_logical(
+        instructions: &mut Vec<Aarch64Instruction>,
+        shift: Aarch64Instruction,
+        destination: Register,
+        amount: Register,
+    ) {
+        instructions.push(shift);
+        instructions.push(CmpImm {
+            register: amount,
+            value: 64,
+        });
+        instructions.push(Csel {
+            destination,
+            reg1: destination,
+            reg2: Xzr,
+            condition: Condition::Lo,
+        });
     }
-}
-
-#[cfg(test)]
-mod test {
-    use super::*;
-    use crate::{backend::CompiledFunctionCatalog, frontend, parser::*};
-    use proptest::prelude::*;
-    use trim_margin::MarginTrimmable;
 
-    fn assert_encodes_as(instruction: Aarch64Instruction, expected_machine_code: Vec<u8>) {
-        let machine_code = instruction.make_machine_code();
-        assert_eq!(expected_machine_code, machine_code);
+    /// The condition under which the comparison `operator` holds
+    fn comparison_condition(operator: BinOpOperator, unsigned: bool) -> Condition {
+        match (operator, unsigned) {
+            (Equal, _) => Condition::Eq,
+            (NotEqual, _) => Condition::Ne,
+            (LessThan, false) => Condition::Lt,
+            (LessThan, true) => Condition::Lo,
+            (LessThanOrEqual, false) => Condition::Le,
+            (LessThanOrEqual, true) => Condition::Ls,
+            (GreaterThan, false) => Condition::Gt,
+            (GreaterThan, true) => Condition::Hi,
+            (GreaterThanOrEqual, false) => Condition::Ge,
+            (GreaterThanOrEqual, true) => Condition::Hs,
+            _ => unreachable!("{} is not a comparison", operator),
+        }
     }
 
-    #[test]
-    fn can_encode_move_immediate_16_bit() {
-        assert_encodes_as(
-            MovImmToReg {
-                register: X1,
-                value: 123,
-            },
-            vec![0x61, 0x0F, 0x80, 0xD2],
-        );
+    /// Sets the destination to 1 if the condition holds between the two registers,
+    /// or to 0 otherwise
+    fn compare(
+        instructions: &mut Vec<Aarch64Instruction>,
+        destination: Register,
+        reg1: Register,
+        reg2: Register,
+        condition: Condition,
+    ) {
+        instructions.push(CmpRegToReg { reg1, reg2 });
+        instructions.push(Cset {
+            destination,
+            condition,
+        });
     }
 
-    #[test]
-    fn can_encode_move_immediate_32_bit() {
-        assert_encodes_as(
-            MovImmToReg {
-                register: X1,
-                value: 1234567,
-            },
-            vec![0xE1, 0xD0, 0x9A, 0xD2, 0x41, 0x02, 0xA0, 0xF2],
-        );
+    /// Emits `abs`, `sign` or `popcount`. There is no instruction counting the bits of
+    /// a general purpose register, so for `popcount` we go through d0, which is free
+    /// since the arguments have already been moved out of it
+    fn unary_intrinsic(
+        instructions: &mut Vec<Aarch64Instruction>,
+        intrinsic: &IrInstruction,
+        destination: Register,
+        source: Register,
+    ) {
+        match intrinsic {
+            IrInstruction::Abs { .. } => {
+                instructions.push(CmpImm {
+                    register: source,
+                    value: 0,
+                });
+                instructions.push(Cneg {
+                    destination,
+                    source,
+                    condition: Condition::Lt,
+                });
+            }
+            IrInstruction::Sign { .. } => {
+                instructions.push(CmpImm {
+                    register: source,
+                    value: 0,
+                });
+                instructions.push(Cset {
+                    destination,
+                    condition: Condition::Ne,
+                });
+                instructions.push(Cneg {
+                    destination,
+                    source: destination,
+                    condition: Condition::Lt,
+                });
+            }
+            IrInstruction::Popcount { .. } => {
+                instructions.push(Fmov {
+                    source,
+                    destination: D0,
+                });
+                instructions.push(Cnt {
+                    source: D0,
+                    destination: D0,
+                });
+                instructions.push(Addv {
+                    source: D0,
+                    destination: D0,
+                });
+                instructions.push(Fmov {
+                    source: D0,
+                    destination,
+                });
+            }
+            _ => unreachable!("{} is not a unary intrinsic", intrinsic),
+        }
     }
 
-    #[test]
-    fn can_encode_move_immediate_48_bit() {
-        assert_encodes_as(
-            MovImmToReg {
-                register: X1,
-                value: 12345678901,
-            },
-            vec![
-                0xA1, 0x86, 0x83, 0xD2, 0x81, 0xFB, 0xBB, 0xF2, 0x41, 0x00, 0xC0, 0xF2,
-            ],
-        );
+    /// Places the jump tables after the code. Their entries are filled once the
+    /// offsets of the blocks are known
+    fn generate_jump_tables(&mut self, instructions: &mut Vec<Aarch64Instruction>) {
+        for (label, targets) in self.jump_tables.take() {
+            instructions.push(Label { block: label });
+            instructions.push(JumpTable {
+                label,
+                offsets: vec![0; targets.len()],
+                targets,
+            });
+        }
     }
 
-    #[test]
-    fn can_encode_move_immediate_64_bit() {
-        assert_encodes_as(
-            MovImmToReg {
-                register: X1,
-                value: 1234567890123456,
-            },
-            vec![
-                0x01, 0x58, 0x97, 0xd2, 0x41, 0x91, 0xA7, 0xF2, 0xA1, 0x5A, 0xCC, 0xF2, 0x81, 0x00,
-                0xE0, 0xF2,
-            ],
-        );
+    /// Computes the index of the accessed element `base + index` in x17, and branches to
+    /// the out-of-bounds handler if it is not lower than the length of the memory.
+    /// Treating the index as unsigned means negative values are rejected as well.
+    /// Afterwards, x16 contains the address of the memory. x8, x16 and x17 are never
+    /// allocated, so we can use them as scratch.
+    fn check_bounds(
+        &mut self,
+        instructions: &mut Vec<Aarch64Instruction>,
+        function: &CompiledFunction,
+        base: &IrRegister,
+        index: &IrRegister,
+        function_catalog: &CompiledFunctionCatalog,
+    ) {
+        let base = self.operand_register(instructions, function, base, 0);
+        let index = self.operand_register(instructions, function, index, 1);
+        instructions.push(AddRegToReg {
+            destination: X17,
+            reg1: base,
+            reg2: index,
+        });
+        instructions.push(MovImmToReg {
+            register: X16,
+            value: function_catalog.runtime_context_address() as i64,
+        });
+        instructions.push(Ldr {
+            destination: X8,
+            base: X16,
+            offset: RuntimeContext::MEMORY_LENGTH_OFFSET as u32,
+        });
+        Self::compare(instructions, X8, X17, X8, Condition::Lo);
+        instructions.push(Cbz {
+            register: X8,
+            target: self.trap_labels.out_of_bounds,
+            offset: 0,
+        });
+        instructions.push(Ldr {
+            destination: X16,
+            base: X16,
+            offset: RuntimeContext::MEMORY_OFFSET as u32,
+        });
+        self.trap_labels.out_of_bounds_used = true;
     }
 
-    #[test]
-    fn can_encode_move_reg_to_reg() {
-        assert_encodes_as(
-            MovRegToReg {
-                source: X8,
-                destination: X9,
+    /// Computes `reg1 (operator) reg2` for `+`, `-` and `*`, wrapping around on overflow.
+    /// The flags are set by `-`, but not by the other ones
+    fn wrapping_arithmetic(
+        instructions: &mut Vec<Aarch64Instruction>,
+        operator: BinOpOperator,
+        destination: Register,
+        reg1: Register,
+        reg2: Register,
+    ) {
+        instructions.push(match operator {
+            Add => AddRegToReg {
+                destination,
+                reg1,
+                reg2,
             },
-            vec![0xE9, 0x03, 0x08, 0xAA],
-        );
+            Sub => SubRegToReg {
+                destination,
+                reg1,
+                reg2,
+            },
+            _ => MulRegToReg {
+                destination,
+                reg1,
+                reg2,
+            },
+        });
     }
 
-    #[test]
-    fn can_encode_mov_sp_to_reg() {
-        assert_encodes_as(
-            MovSpToReg { destination: X29 },
-            vec![0xFD, 0x03, 0x00, 0x91],
-        );
+    /// Computes `reg1 (operator) reg2` for `+`, `-` and `*` in checked or saturating
+    /// mode, leaving in the destination a result already normalized to `ty`. The
+    /// destination must not be one of the operands. Clobbers x8, x16 and x17.
+    #[allow(clippy::too_many_arguments)]
+    fn arithmetic_with_overflow(
+        &mut self,
+        instructions: &mut Vec<Aarch64Instruction>,
+        operator: BinOpOperator,
+        ty: Type,
+        mode: OverflowMode,
+        destination: Register,
+        reg1: Register,
+        reg2: Register,
+    ) {
+        let saturating = mode == OverflowMode::Saturating;
+        let (min, max) = ty.bounds();
+
+        if ty.is_narrow() {
+            // The operands are normalized, so the 64-bit result is exact, and it does not
+            // fit in the type if normalizing it changes it
+            Self::wrapping_arithmetic(instructions, operator, destination, reg1, reg2);
+            if saturating {
+                // For signed types, the sign of the exact result tells which bound we
+                // went past. For unsigned ones, only a subtraction can go below zero.
+                if ty.is_unsigned() {
+                    instructions.push(MovImmToReg {
+                        register: X17,
+                        value: if operator == Sub { min } else { max } as i64,
+                    });
+                } else {
+                    Self::saturated_value(instructions, destination, max as i64);
+                }
+            }
+            Self::normalize(instructions, ty, X16, destination);
+            instructions.push(CmpRegToReg {
+                reg1: X16,
+                reg2: destination,
+            });
+            self.handle_overflow(instructions, mode, Condition::Ne, destination, X16);
+            return;
+        }
+
+        let condition = if ty.is_unsigned() {
+            // The carry flag is set by an addition that goes past the maximum, and cleared
+            // by a subtraction that goes below zero
+            match operator {
+                Add => {
+                    instructions.push(AddsRegToReg {
+                        destination,
+                        reg1,
+                        reg2,
+                    });
+                    Condition::Hs
+                }
+                Sub => {
+                    instructions.push(SubRegToReg {
+                        destination,
+                        reg1,
+                        reg2,
+                    });
+                    Condition::Lo
+                }
+                _ => {
+                    instructions.push(Umulh {
+                        destination: X16,
+                        reg1,
+                        reg2,
+                    });
+                    instructions.push(MulRegToReg {
+                        destination,
+                        reg1,
+                        reg2,
+                    });
+                    instructions.push(CmpImm {
+                        register: X16,
+                        value: 0,
+                    });
+                    Condition::Ne
+                }
+            }
+        } else {
+            // The result saturates to i64::MAX or i64::MIN depending on the sign of the
+            // first operand, or of the product of the signs for a multiplication
+            let sign_source = match operator {
+                Add => {
+                    instructions.push(AddsRegToReg {
+                        destination,
+                        reg1,
+                        reg2,
+                    });
+                    reg1
+                }
+                Sub => {
+                    instructions.push(SubRegToReg {
+                        destination,
+                        reg1,
+                        reg2,
+                    });
+                    reg1
+                }
+                _ => {
+                    // The product fits if the upper half is just the sign of the lower one
+                    instructions.push(Smulh {
+                        destination: X16,
+                        reg1,
+                        reg2,
+                    });
+                    instructions.push(MulRegToReg {
+                        destination,
+                        reg1,
+                        reg2,
+                    });
+                    instructions.push(AsrImm {
+                        destination: X17,
+                        source: destination,
+                        amount: 63,
+                    });
+                    instructions.push(CmpRegToReg {
+                        reg1: X16,
+                        reg2: X17,
+                    });
+                    if saturating {
+                        instructions.push(EorRegToReg {
+                            destination: X17,
+                            reg1,
+                            reg2,
+                        });
+                    }
+                    X17
+                }
+            };
+            if saturating {
+                Self::saturated_value(instructions, sign_source, i64::MAX);
+            }
+            if operator == Mul {
+                Condition::Ne
+            } else {
+                Condition::Vs
+            }
+        };
+        if saturating && ty.is_unsigned() {
+            instructions.push(MovImmToReg {
+                register: X17,
+                value: if operator == Sub { min } else { max } as i64,
+            });
+        }
+        self.handle_overflow(instructions, mode, condition, destination, destination);
     }
 
-    #[test]
-    fn can_encode_add_reg_to_reg() {
-        assert_encodes_as(
-            AddRegToReg {
-                destination: X0,
-                reg1: X9,
-                reg2: X10,
-            },
-            vec![0x20, 0x01, 0x0A, 0x8B],
-        );
+    /// Stores in x17 `max` if `sign_source` is positive, or the minimum of the same type,
+    /// i.e. `!max`, if it is negative. Clobbers x16, but does not change the flags
+    fn saturated_value(
+        instructions: &mut Vec<Aarch64Instruction>,
+        sign_source: Register,
+        max: i64,
+    ) {
+        instructions.push(AsrImm {
+            destination: X17,
+            source: sign_source,
+            amount: 63,
+        });
+        instructions.push(MovImmToReg {
+            register: X16,
+            value: max,
+        });
+        instructions.push(EorRegToReg {
+            destination: X17,
+            reg1: X17,
+            reg2: X16,
+        });
     }
 
-    #[test]
-    fn can_encode_sub_reg_to_reg() {
-        assert_encodes_as(
-            SubRegToReg {
-                destination: X0,
-                reg1: X9,
-                reg2: X10,
-            },
-            vec![0x20, 0x01, 0x0A, 0xEB],
-        );
+    /// After an operation that sets the flags, branches to the overflow trap if the
+    /// condition holds. In saturating mode, instead, sets the destination to the value
+    /// in x17 if the condition holds, or to `result` otherwise
+    fn handle_overflow(
+        &mut self,
+        instructions: &mut Vec<Aarch64Instruction>,
+        mode: OverflowMode,
+        condition: Condition,
+        destination: Register,
+        result: Register,
+    ) {
+        if mode == OverflowMode::Saturating {
+            instructions.push(Csel {
+                destination,
+                reg1: X17,
+                reg2: result,
+                condition,
+            });
+        } else {
+            instructions.push(Cset {
+                destination: X8,
+                condition,
+            });
+            self.trap_if_not_zero(instructions, X8, TrapCode::Overflow);
+        }
+    }
+
+    /// Branches to the code that records the given trap if the register is not zero
+    fn trap_if_not_zero(
+        &mut self,
+        instructions: &mut Vec<Aarch64Instruction>,
+        register: Register,
+        code: TrapCode,
+    ) {
+        instructions.push(Cbnz {
+            register,
+            target: self.trap_labels.label(code),
+            offset: 0,
+        });
+        self.trap_labels.use_trap(code);
+    }
+
+    /// Branches to the code that records the given trap if the register is zero
+    fn trap_if_zero(
+        &mut self,
+        instructions: &mut Vec<Aarch64Instruction>,
+        register: Register,
+        code: TrapCode,
+    ) {
+        instructions.push(Cbz {
+            register,
+            target: self.trap_labels.label(code),
+            offset: 0,
+        });
+        self.trap_labels.use_trap(code);
+    }
+
+    /// Traps if the divisor is zero, or if we are computing `MIN / -1`. Unlike on x64,
+    /// `sdiv` does not fail, but its results would be wrong
+    fn check_divisor(
+        &mut self,
+        instructions: &mut Vec<Aarch64Instruction>,
+        dividend: Register,
+        divisor: Register,
+        checks: DivisorChecks,
+    ) {
+        if checks.zero {
+            self.trap_if_zero(instructions, divisor, TrapCode::DivisionByZero);
+        }
+        if let Some(min) = checks.overflow {
+            // (dividend ^ MIN) | !divisor is zero only if both are zero
+            instructions.push(MovImmToReg {
+                register: X16,
+                value: min,
+            });
+            instructions.push(EorRegToReg {
+                destination: X16,
+                reg1: X16,
+                reg2: dividend,
+            });
+            instructions.push(Mvn {
+                source: divisor,
+                destination: X17,
+            });
+            instructions.push(OrrRegToReg {
+                destination: X16,
+                reg1: X16,
+                reg2: X17,
+            });
+            self.trap_if_zero(instructions, X16, TrapCode::DivisionOverflow);
+        }
+    }
+
+    /// Truncates the source to the given type, and then sign- or zero-extends it back
+    /// to 64 bits
+    fn normalize(
+        instructions: &mut Vec<Aarch64Instruction>,
+        ty: Type,
+        destination: Register,
+        source: Register,
+    ) {
+        match ty {
+            Type::I64 | Type::U64 | Type::F64 => instructions.push(MovRegToReg {
+                destination,
+                source,
+            }),
+            Type::I32 => instructions.push(Sbfx {
+                destination,
+                source,
+                width: 32,
+            }),
+            Type::U32 => instructions.push(Ubfx {
+                destination,
+                source,
+                width: 32,
+            }),
+            Type::U8 => instructions.push(Ubfx {
+                destination,
+                source,
+                width: 8,
+            }),
+            Type::Bool => {
+                instructions.push(CmpImm {
+                    register: source,
+                    value: 0,
+                });
+                instructions.push(Cset {
+                    destination,
+                    condition: Condition::Ne,
+                });
+            }
+        }
+    }
+
+    /// Generates the code that the checks branch to, after all the blocks of the
+    /// function. The out-of-bounds handler expects the index in x17, and the address of
+    /// the runtime context in x16
+    fn generate_trap_handlers(
+        &mut self,
+        instructions: &mut Vec<Aarch64Instruction>,
+        index_of_ldp_to_fix: &mut Vec<usize>,
+        function_catalog: &CompiledFunctionCatalog,
+    ) {
+        for (code, jump_to_exit) in self.trap_labels.take_handlers() {
+            instructions.push(Label {
+                block: self.trap_labels.label(code),
+            });
+            instructions.push(MovImmToReg {
+                register: X16,
+                value: function_catalog.runtime_context_address() as i64,
+            });
+            instructions.push(MovImmToReg {
+                register: X8,
+                value: code as i64,
+            });
+            instructions.push(Str {
+                source: X8,
+                base: X16,
+                offset: RuntimeContext::TRAP_OFFSET as u32,
+            });
+            if jump_to_exit {
+                instructions.push(B {
+                    target: self.trap_labels.exit,
+                    offset: 0,
+                });
+            }
+        }
+        if self.trap_labels.out_of_bounds_used {
+            instructions.push(Label {
+                block: self.trap_labels.out_of_bounds,
+            });
+            instructions.push(Str {
+                source: X17,
+                base: X16,
+                offset: RuntimeContext::TRAP_VALUE_OFFSET as u32,
+            });
+            instructions.push(MovImmToReg {
+                register: X8,
+                value: TrapCode::OutOfBounds as i64,
+            });
+            instructions.push(Str {
+                source: X8,
+                base: X16,
+                offset: RuntimeContext::TRAP_OFFSET as u32,
+            });
+        }
+        if self.trap_labels.exit_used {
+            instructions.push(Label {
+                block: self.trap_labels.exit,
+            });
+            self.restore_saved_registers(instructions);
+            index_of_ldp_to_fix.push(instructions.len());
+            instructions.push(Nop);
+            instructions.push(Ret);
+        }
+    }
+
+    /// Calls one of our functions through `jit_call_trampoline`, stopping if it traps
+    fn call_through_trampoline(
+        &mut self,
+        instructions: &mut Vec<Aarch64Instruction>,
+        function: &CompiledFunction,
+        function_catalog: &CompiledFunctionCatalog,
+        callee: Callee,
+        call_args: &[IrRegister],
+        dest: &IrRegister,
+    ) -> Result<(), BackendError> {
+        // jit_call_trampoline(function_catalog_ptr, called_function_index, args)
+        let fn_catalog_addr = function_catalog as *const CompiledFunctionCatalog as i64;
+        let jit_call_trampoline_address: usize = match function.register_class(*dest) {
+            RegisterClass::Integer => jit_call_trampoline as *const () as usize,
+            RegisterClass::Float => jit_call_trampoline_float as *const () as usize,
+        };
+        match callee {
+            Callee::Function(id) => self.call(
+                instructions,
+                function,
+                jit_call_trampoline_address,
+                &[fn_catalog_addr, id.0 as i64],
+                call_args,
+                dest,
+            )?,
+            // The handle is passed just like the first argument of the call
+            Callee::Handle(handle) => self.call(
+                instructions,
+                function,
+                jit_call_trampoline_address,
+                &[fn_catalog_addr],
+                &[&[handle], call_args].concat(),
+                dest,
+            )?,
+        }
+
+        // If the callee trapped, we must stop too
+        instructions.push(MovImmToReg {
+            register: X16,
+            value: function_catalog.runtime_context_address() as i64,
+        });
+        instructions.push(Ldr {
+            destination: X16,
+            base: X16,
+            offset: RuntimeContext::TRAP_OFFSET as u32,
+        });
+        instructions.push(Cbnz {
+            register: X16,
+            target: self.trap_labels.exit,
+            offset: 0,
+        });
+        self.trap_labels.exit_used = true;
+        Ok(())
+    }
+
+    /// Traps if the handle of an indirect call does not refer to a function, or if the
+    /// function does not take `num_args` arguments
+    fn check_function_handle(
+        &mut self,
+        instructions: &mut Vec<Aarch64Instruction>,
+        function: &CompiledFunction,
+        handle: &IrRegister,
+        num_args: usize,
+        function_catalog: &CompiledFunctionCatalog,
+    ) {
+        let handle = self.operand_register(instructions, function, handle, 0);
+        // Negative handles are very large unsigned values, so one comparison is enough
+        instructions.push(MovImmToReg {
+            register: X16,
+            value: function_catalog.num_functions() as i64,
+        });
+        Self::compare(instructions, X8, handle, X16, Condition::Lo);
+        self.trap_if_zero(instructions, X8, TrapCode::InvalidFunctionHandle);
+        instructions.push(MovImmToReg {
+            register: X16,
+            value: function_catalog.arities_address() as i64,
+        });
+        instructions.push(LdrRegOffset {
+            destination: X16,
+            base: X16,
+            index: handle,
+        });
+        instructions.push(MovImmToReg {
+            register: X17,
+            value: NOT_CALLABLE_INDIRECTLY,
+        });
+        Self::compare(instructions, X8, X16, X17, Condition::Ne);
+        self.trap_if_zero(instructions, X8, TrapCode::InvalidFunctionHandle);
+        instructions.push(MovImmToReg {
+            register: X17,
+            value: num_args as i64,
+        });
+        Self::compare(instructions, X8, X16, X17, Condition::Eq);
+        self.trap_if_zero(instructions, X8, TrapCode::ArityMismatch);
+    }
+
+    /// Calls the function at `address`, passing first the `immediate_args` and then the
+    /// values of `call_args`, and stores the result in `dest`
+    fn call(
+        &mut self,
+        instructions: &mut Vec<Aarch64Instruction>,
+        function: &CompiledFunction,
+        address: usize,
+        immediate_args: &[i64],
+        call_args: &[IrRegister],
+        dest: &IrRegister,
+    ) -> Result<(), BackendError> {
+        let result_register = Self::result_register(self.destination_register(function, dest));
+        self.push(instructions, result_register);
+
+        // We will put the jump address in X19
+        self.push(instructions, X19);
+
+        // Store all registers being used. We should skip the destination one
+        // for this instruction, since we will overwrite it, but whatever.
+        // We generate horrible code anyway... what's one more push/pop pair? :-D
+        let used_registers = self.used_registers.clone();
+        for used_register in used_registers.iter().cloned() {
+            self.push(instructions, used_register);
+        }
+        let used_args_registers = self.used_args_registers.clone();
+        for used_arg_register in used_args_registers.iter().cloned() {
+            if used_arg_register != result_register {
+                self.push(instructions, used_arg_register);
+            }
+        }
+
+        for (index, value) in immediate_args.iter().enumerate() {
+            let register = Self::argument_register(false, index)?;
+            instructions.push(MovImmToReg {
+                register,
+                value: *value,
+            });
+        }
+
+        // Fill arguments. The integer and the float ones are numbered separately, and
+        // the first integer registers are already used by the immediate arguments
+        let mut num_integer_args = immediate_args.len();
+        let mut num_float_args = 0;
+        for actual_arg in call_args.iter() {
+            let class = function.register_class(*actual_arg);
+            let position = match class {
+                RegisterClass::Integer => &mut num_integer_args,
+                RegisterClass::Float => &mut num_float_args,
+            };
+            let call_convention_arg_register =
+                Self::argument_register(class == RegisterClass::Float, *position)?;
+            *position += 1;
+
+            self.load(instructions, actual_arg, call_convention_arg_register);
+        }
+        instructions.push(MovImmToReg {
+            register: X19,
+            value: address as i64,
+        });
+
+        // We can finally do the actual call!
+        instructions.push(Blr { register: X19 });
+
+        // Restore registers
+        for used_arg_register in used_args_registers.iter().cloned() {
+            if used_arg_register != result_register {
+                self.pop(instructions, used_arg_register);
+            }
+        }
+        for used_register in used_registers.iter().rev().cloned() {
+            self.pop(instructions, used_register);
+        }
+        self.pop(instructions, X19);
+
+        // Copy result (x0 or d0) to the opportune register
+        self.store(instructions, result_register, dest);
+
+        self.pop(instructions, result_register);
+        Ok(())
+    }
+
+    /// Where the given argument is passed: a register chosen by its position among the
+    /// arguments of the same class, or its position among the ones that the caller
+    /// pushes on the stack
+    fn get_argument_location(
+        function: &CompiledFunction,
+        arg: ArgumentIndex,
+    ) -> AllocatedLocation<Register> {
+        let is_float = function.arg_classes[usize::from(arg)] == RegisterClass::Float;
+        let position = function.position_in_class(arg);
+        if position >= NUM_ARGUMENT_REGISTERS {
+            return AllocatedLocation::Stack {
+                offset: function.position_on_stack(
+                    arg,
+                    NUM_ARGUMENT_REGISTERS,
+                    NUM_ARGUMENT_REGISTERS,
+                ),
+            };
+        }
+        let register =
+            Self::argument_register(is_float, position).expect("position has been checked");
+        AllocatedLocation::Register { register }
+    }
+
+    /// Calls one of our functions whose result we return right away, by jumping to it
+    /// after having restored the frame pointer, the link register and the stack of our
+    /// caller. The arguments are passed in the same registers used by a normal call, but
+    /// we do not go through `jit_call_trampoline`
+    fn tail_call(
+        &mut self,
+        instructions: &mut Vec<Aarch64Instruction>,
+        function: &CompiledFunction,
+        function_catalog: &CompiledFunctionCatalog,
+        callee: Callee,
+        call_args: &[IrRegister],
+        index_of_ldp_to_fix: &mut Vec<usize>,
+    ) -> Result<(), BackendError> {
+        // The values are never allocated to the argument registers, so filling them
+        // cannot overwrite the other arguments
+        let mut num_integer_args = 0;
+        let mut num_float_args = 0;
+        for actual_arg in call_args.iter() {
+            let class = function.register_class(*actual_arg);
+            let position = match class {
+                RegisterClass::Integer => &mut num_integer_args,
+                RegisterClass::Float => &mut num_float_args,
+            };
+            let call_convention_arg_register =
+                Self::argument_register(class == RegisterClass::Float, *position)?;
+            *position += 1;
+
+            self.load(instructions, actual_arg, call_convention_arg_register);
+        }
+
+        // The callee might not have been compiled yet, so we read its address from the
+        // catalog when doing the call
+        match callee {
+            Callee::Function(id) => {
+                instructions.push(MovImmToReg {
+                    register: X16,
+                    value: function_catalog.function_pointer_address(id) as i64,
+                });
+                instructions.push(Ldr {
+                    destination: X16,
+                    base: X16,
+                    offset: 0,
+                });
+            }
+            Callee::Handle(handle) => {
+                let handle = self.operand_register(instructions, function, &handle, 0);
+                instructions.push(MovImmToReg {
+                    register: X16,
+                    value: function_catalog.function_pointers_address() as i64,
+                });
+                instructions.push(LdrRegOffset {
+                    destination: X16,
+                    base: X16,
+                    index: handle,
+                });
+            }
+        }
+
+        // Like for a `ret`, we will replace this with the correct LDP at the end
+        self.restore_saved_registers(instructions);
+        index_of_ldp_to_fix.push(instructions.len());
+        instructions.push(Nop);
+
+        instructions.push(Br { register: X16 });
+        Ok(())
+    }
+
+    /// The register used for the n-th integer or float argument
+    fn argument_register(is_float: bool, position: usize) -> Result<Register, BackendError> {
+        let registers: [Register; NUM_ARGUMENT_REGISTERS] = if is_float {
+            [D0, D1, D2, D3, D4, D5, D6, D7]
+        } else {
+            [X0, X1, X2, X3, X4, X5, X6, X7]
+        };
+        registers.get(position).copied().ok_or_else(|| {
+            BackendError::NotImplemented(
+                "support for more than 8 integer or 8 float arguments".to_string(),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{backend::CompiledFunctionCatalog, frontend, frontend::FunctionId, parser::*};
+    use proptest::prelude::*;
+    use trim_margin::MarginTrimmable;
+
+    fn assert_encodes_as(instruction: Aarch64Instruction, expected_machine_code: Vec<u8>) {
+        let machine_code = instruction.make_machine_code();
+        assert_eq!(expected_machine_code, machine_code);
+    }
+
+    #[test]
+    fn can_encode_move_immediate_16_bit() {
+        assert_encodes_as(
+            MovImmToReg {
+                register: X1,
+                value: 123,
+            },
+            vec![0x61, 0x0F, 0x80, 0xD2],
+        );
+    }
+
+    #[test]
+    fn can_encode_move_immediate_32_bit() {
+        assert_encodes_as(
+            MovImmToReg {
+                register: X1,
+                value: 1234567,
+            },
+            vec![0xE1, 0xD0, 0x9A, 0xD2, 0x41, 0x02, 0xA0, 0xF2],
+        );
+    }
+
+    #[test]
+    fn can_encode_move_immediate_48_bit() {
+        assert_encodes_as(
+            MovImmToReg {
+                register: X1,
+                value: 12345678901,
+            },
+            vec![
+                0xA1, 0x86, 0x83, 0xD2, 0x81, 0xFB, 0xBB, 0xF2, 0x41, 0x00, 0xC0, 0xF2,
+            ],
+        );
+    }
+
+    #[test]
+    fn can_encode_move_immediate_64_bit() {
+        assert_encodes_as(
+            MovImmToReg {
+                register: X1,
+                value: 1234567890123456,
+            },
+            vec![
+                0x01, 0x58, 0x97, 0xd2, 0x41, 0x91, 0xA7, 0xF2, 0xA1, 0x5A, 0xCC, 0xF2, 0x81, 0x00,
+                0xE0, 0xF2,
+            ],
+        );
+    }
+
+    #[test]
+    fn can_encode_move_reg_to_reg() {
+        assert_encodes_as(
+            MovRegToReg {
+                source: X8,
+                destination: X9,
+            },
+            vec![0xE9, 0x03, 0x08, 0xAA],
+        );
+    }
+
+    #[test]
+    fn can_encode_mov_sp_to_reg() {
+        assert_encodes_as(
+            MovSpToReg { destination: X29 },
+            vec![0xFD, 0x03, 0x00, 0x91],
+        );
+    }
+
+    #[test]
+    fn can_encode_add_reg_to_reg() {
+        assert_encodes_as(
+            AddRegToReg {
+                destination: X0,
+                reg1: X9,
+                reg2: X10,
+            },
+            vec![0x20, 0x01, 0x0A, 0x8B],
+        );
+    }
+
+    #[test]
+    fn can_encode_sub_reg_to_reg() {
+        assert_encodes_as(
+            SubRegToReg {
+                destination: X0,
+                reg1: X9,
+                reg2: X10,
+            },
+            vec![0x20, 0x01, 0x0A, 0xEB],
+        );
     }
 
     #[test]
@@ -979,6 +3306,31 @@ mod test {
         assert_encodes_as(Blr { register: X1 }, vec![0x20, 0x00, 0x3F, 0xD6]);
     }
 
+    #[test]
+    fn can_encode_br() {
+        assert_encodes_as(Br { register: X16 }, vec![0x00, 0x02, 0x1F, 0xD6]);
+    }
+
+    #[test]
+    fn can_encode_adr() {
+        assert_encodes_as(
+            Adr {
+                destination: X16,
+                target: BlockId(0),
+                offset: 0x1234,
+            },
+            vec![0xB0, 0x91, 0x00, 0x10],
+        );
+        assert_encodes_as(
+            Adr {
+                destination: X0,
+                target: BlockId(0),
+                offset: -7,
+            },
+            vec![0xC0, 0xFF, 0xFF, 0x30],
+        );
+    }
+
     #[test]
     fn can_encode_str() {
         assert_encodes_as(
@@ -1005,6 +3357,22 @@ mod test {
             },
             vec![0xA4, 0x10, 0x00, 0xF9],
         );
+        assert_encodes_as(
+            Str {
+                source: X20,
+                base: X29,
+                offset: 32,
+            },
+            vec![0xB4, 0x13, 0x00, 0xF9],
+        );
+        assert_encodes_as(
+            Str {
+                source: D10,
+                base: X29,
+                offset: 56,
+            },
+            vec![0xAA, 0x1F, 0x00, 0xFD],
+        );
     }
 
     #[test]
@@ -1033,6 +3401,22 @@ mod test {
             },
             vec![0xA4, 0x10, 0x40, 0xF9],
         );
+        assert_encodes_as(
+            Ldr {
+                destination: X22,
+                base: X29,
+                offset: 504,
+            },
+            vec![0xB6, 0xFF, 0x40, 0xF9],
+        );
+        assert_encodes_as(
+            Ldr {
+                destination: D8,
+                base: X29,
+                offset: 16,
+            },
+            vec![0xA8, 0x0B, 0x40, 0xFD],
+        );
     }
 
     #[test]
@@ -1124,16 +3508,419 @@ mod test {
     }
 
     #[test]
-    fn can_compile_trivial_function() {
-        let program = parse_program("fn main() { let a = 42; return a; }").unwrap();
-        let compiled = frontend::compile(program).unwrap();
+    fn can_encode_cmp() {
+        assert_encodes_as(
+            CmpRegToReg {
+                reg1: X9,
+                reg2: X10,
+            },
+            vec![0x3F, 0x01, 0x0A, 0xEB],
+        );
+    }
+
+    #[test]
+    fn can_encode_cset() {
+        let cases = [
+            (Condition::Eq, 0x17),
+            (Condition::Ne, 0x07),
+            (Condition::Lt, 0xA7),
+            (Condition::Le, 0xC7),
+            (Condition::Gt, 0xD7),
+            (Condition::Ge, 0xB7),
+            (Condition::Hi, 0x97),
+            (Condition::Hs, 0x37),
+        ];
+        for (condition, second_byte) in cases {
+            assert_encodes_as(
+                Cset {
+                    destination: X11,
+                    condition,
+                },
+                vec![0xEB, second_byte, 0x9F, 0x9A],
+            );
+        }
+    }
+
+    #[test]
+    fn can_encode_bitwise_operations() {
+        assert_encodes_as(
+            AndRegToReg {
+                destination: X11,
+                reg1: X9,
+                reg2: X10,
+            },
+            vec![0x2B, 0x01, 0x0A, 0x8A],
+        );
+        assert_encodes_as(
+            OrrRegToReg {
+                destination: X11,
+                reg1: X9,
+                reg2: X10,
+            },
+            vec![0x2B, 0x01, 0x0A, 0xAA],
+        );
+        assert_encodes_as(
+            EorRegToReg {
+                destination: X11,
+                reg1: X9,
+                reg2: X10,
+            },
+            vec![0x2B, 0x01, 0x0A, 0xCA],
+        );
+        assert_encodes_as(
+            Mvn {
+                source: X9,
+                destination: X11,
+            },
+            vec![0xEB, 0x03, 0x29, 0xAA],
+        );
+    }
+
+    #[test]
+    fn can_encode_shifts() {
+        assert_encodes_as(
+            LslRegToReg {
+                destination: X11,
+                reg1: X9,
+                reg2: X10,
+            },
+            vec![0x2B, 0x21, 0xCA, 0x9A],
+        );
+        assert_encodes_as(
+            AsrRegToReg {
+                destination: X11,
+                reg1: X9,
+                reg2: X10,
+            },
+            vec![0x2B, 0x29, 0xCA, 0x9A],
+        );
+        assert_encodes_as(
+            LsrRegToReg {
+                destination: X11,
+                reg1: X9,
+                reg2: X10,
+            },
+            vec![0x2B, 0x25, 0xCA, 0x9A],
+        );
+    }
+
+    #[test]
+    fn can_encode_udiv() {
+        assert_encodes_as(
+            UdivRegToReg {
+                destination: X11,
+                reg1: X9,
+                reg2: X10,
+            },
+            vec![0x2B, 0x09, 0xCA, 0x9A],
+        );
+    }
+
+    #[test]
+    fn can_encode_bitfield_extracts() {
+        assert_encodes_as(
+            Ubfx {
+                destination: X11,
+                source: X9,
+                width: 8,
+            },
+            vec![0x2B, 0x1D, 0x40, 0xD3],
+        );
+        assert_encodes_as(
+            Ubfx {
+                destination: X11,
+                source: X9,
+                width: 32,
+            },
+            vec![0x2B, 0x7D, 0x40, 0xD3],
+        );
+        assert_encodes_as(
+            Sbfx {
+                destination: X11,
+                source: X9,
+                width: 32,
+            },
+            vec![0x2B, 0x7D, 0x40, 0x93],
+        );
+    }
+
+    #[test]
+    fn can_encode_msub() {
+        assert_encodes_as(
+            Msub {
+                destination: X11,
+                reg1: X11,
+                reg2: X10,
+                minuend: X9,
+            },
+            vec![0x6B, 0xA5, 0x0A, 0x9B],
+        );
+    }
+
+    #[test]
+    fn can_encode_cmp_immediate() {
+        assert_encodes_as(
+            CmpImm {
+                register: X10,
+                value: 64,
+            },
+            vec![0x5F, 0x01, 0x01, 0xF1],
+        );
+        assert_encodes_as(
+            CmpImm {
+                register: X10,
+                value: 63,
+            },
+            vec![0x5F, 0xFD, 0x00, 0xF1],
+        );
+    }
+
+    #[test]
+    fn can_encode_csel() {
+        assert_encodes_as(
+            Csel {
+                destination: X11,
+                reg1: X11,
+                reg2: Xzr,
+                condition: Condition::Lo,
+            },
+            vec![0x6B, 0x31, 0x9F, 0x9A],
+        );
+        assert_encodes_as(
+            Csel {
+                destination: X11,
+                reg1: X10,
+                reg2: X11,
+                condition: Condition::Ls,
+            },
+            vec![0x4B, 0x91, 0x8B, 0x9A],
+        );
+    }
+
+    #[test]
+    fn can_encode_cneg_and_bit_counts() {
+        assert_encodes_as(
+            Cneg {
+                destination: X9,
+                source: X10,
+                condition: Condition::Lt,
+            },
+            vec![0x49, 0xA5, 0x8A, 0xDA],
+        );
+        assert_encodes_as(
+            Cneg {
+                destination: X9,
+                source: X9,
+                condition: Condition::Hi,
+            },
+            vec![0x29, 0x95, 0x89, 0xDA],
+        );
+        assert_encodes_as(
+            Cnt {
+                source: D5,
+                destination: D3,
+            },
+            vec![0xA3, 0x58, 0x20, 0x0E],
+        );
+        assert_encodes_as(
+            Addv {
+                source: D7,
+                destination: D2,
+            },
+            vec![0xE2, 0xB8, 0x31, 0x0E],
+        );
+    }
+
+    #[test]
+    fn can_encode_float_arithmetic() {
+        assert_encodes_as(
+            Fadd {
+                destination: D0,
+                reg1: D1,
+                reg2: D2,
+            },
+            vec![0x20, 0x28, 0x62, 0x1E],
+        );
+        assert_encodes_as(
+            Fsub {
+                destination: D0,
+                reg1: D1,
+                reg2: D2,
+            },
+            vec![0x20, 0x38, 0x62, 0x1E],
+        );
+        assert_encodes_as(
+            Fmul {
+                destination: D0,
+                reg1: D1,
+                reg2: D2,
+            },
+            vec![0x20, 0x08, 0x62, 0x1E],
+        );
+        assert_encodes_as(
+            Fdiv {
+                destination: D31,
+                reg1: D16,
+                reg2: D17,
+            },
+            vec![0x1F, 0x1A, 0x71, 0x1E],
+        );
+        assert_encodes_as(Fcmp { reg1: D1, reg2: D2 }, vec![0x20, 0x20, 0x62, 0x1E]);
+        assert_encodes_as(
+            Cset {
+                destination: X0,
+                condition: Condition::Mi,
+            },
+            vec![0xE0, 0x57, 0x9F, 0x9A],
+        );
+    }
+
+    #[test]
+    fn can_encode_float_moves_and_conversions() {
+        assert_encodes_as(
+            Fmov {
+                source: D1,
+                destination: D0,
+            },
+            vec![0x20, 0x40, 0x60, 0x1E],
+        );
+        assert_encodes_as(
+            Fmov {
+                source: X1,
+                destination: D0,
+            },
+            vec![0x20, 0x00, 0x67, 0x9E],
+        );
+        assert_encodes_as(
+            Fmov {
+                source: D1,
+                destination: X0,
+            },
+            vec![0x20, 0x00, 0x66, 0x9E],
+        );
+        assert_encodes_as(
+            Scvtf {
+                source: X1,
+                destination: D0,
+            },
+            vec![0x20, 0x00, 0x62, 0x9E],
+        );
+        assert_encodes_as(
+            Fcvtzs {
+                source: D1,
+                destination: X0,
+            },
+            vec![0x20, 0x00, 0x78, 0x9E],
+        );
+        assert_encodes_as(
+            Str {
+                source: D0,
+                base: X29,
+                offset: 8,
+            },
+            vec![0xA0, 0x07, 0x00, 0xFD],
+        );
+        assert_encodes_as(
+            Ldr {
+                destination: D0,
+                base: X29,
+                offset: 8,
+            },
+            vec![0xA0, 0x07, 0x40, 0xFD],
+        );
+    }
+
+    #[test]
+    fn can_encode_b() {
+        assert_encodes_as(
+            B {
+                target: BlockId(0),
+                offset: -8,
+            },
+            vec![0xFE, 0xFF, 0xFF, 0x17],
+        );
+        assert_encodes_as(
+            B {
+                target: BlockId(0),
+                offset: 16,
+            },
+            vec![0x04, 0x00, 0x00, 0x14],
+        );
+    }
+
+    #[test]
+    fn can_encode_cbnz() {
+        assert_encodes_as(
+            Cbnz {
+                register: X16,
+                target: BlockId(0),
+                offset: 8,
+            },
+            vec![0x50, 0x00, 0x00, 0xB5],
+        );
+        assert_encodes_as(
+            Cbnz {
+                register: X1,
+                target: BlockId(0),
+                offset: -4,
+            },
+            vec![0xE1, 0xFF, 0xFF, 0xB5],
+        );
+    }
+
+    #[test]
+    fn can_encode_ldr_and_str_with_register_offset() {
+        assert_encodes_as(
+            LdrRegOffset {
+                destination: X9,
+                base: X16,
+                index: X17,
+            },
+            vec![0x09, 0x7A, 0x71, 0xF8],
+        );
+        assert_encodes_as(
+            StrRegOffset {
+                source: X10,
+                base: X16,
+                index: X17,
+            },
+            vec![0x0A, 0x7A, 0x31, 0xF8],
+        );
+    }
+
+    #[test]
+    fn can_encode_cbz() {
+        assert_encodes_as(
+            Cbz {
+                register: X9,
+                target: BlockId(0),
+                offset: 12,
+            },
+            vec![0x69, 0x00, 0x00, 0xB4],
+        );
+        assert_encodes_as(
+            Cbz {
+                register: X1,
+                target: BlockId(0),
+                offset: -4,
+            },
+            vec![0xE1, 0xFF, 0xFF, 0xB4],
+        );
+    }
+
+    #[test]
+    fn can_compile_trivial_function() {
+        let program = parse_program("fn main() { let a = 42; return a; }").unwrap();
+        let compiled = frontend::compile(program, &frontend::FrontendOptions::default())
+            .unwrap()
+            .functions;
         assert_eq!(compiled.len(), 1);
 
         let mut gen = Aarch64Generator::default();
         let machine_code = gen
             .generate_machine_code(
                 &compiled[0],
-                &Box::new(CompiledFunctionCatalog::new(&compiled)),
+                &Box::new(CompiledFunctionCatalog::new(&compiled, vec![])),
             )
             .unwrap();
         assert_eq!(
@@ -1162,14 +3949,16 @@ mod test {
     fn can_compile_math() {
         let program =
             parse_program("fn the_answer() { let a = 3; return a + 1 - 2 * 3 / -4; }").unwrap();
-        let compiled = frontend::compile(program).unwrap();
+        let compiled = frontend::compile(program, &frontend::FrontendOptions::default())
+            .unwrap()
+            .functions;
         assert_eq!(compiled.len(), 1);
 
         let mut gen = Aarch64Generator::default();
         let machine_code = gen
             .generate_machine_code(
                 &compiled[0],
-                &Box::new(CompiledFunctionCatalog::new(&compiled)),
+                &Box::new(CompiledFunctionCatalog::new(&compiled, vec![])),
             )
             .unwrap();
         assert_eq!(
@@ -1182,10 +3971,9 @@ mod test {
             |movz x10, 2
             |movz x9, 3
             |mul  x12, x10, x9
-            |movz x10, 4
-            |neg  x9, x10
-            |sdiv x10, x12, x9
-            |subs x12, x11, x10
+            |movz x10, -4
+            |sdiv x9, x12, x10
+            |subs x12, x11, x9
             |mov  x0, x12
             |ldp  x29, x30, [sp], #16
             |ret
@@ -1205,13 +3993,15 @@ mod test {
             ",
         )
         .unwrap();
-        let compiled = frontend::compile(program).unwrap();
+        let compiled = frontend::compile(program, &frontend::FrontendOptions::default())
+            .unwrap()
+            .functions;
         assert_eq!(compiled.len(), 2);
 
-        let function_catalog = Box::new(CompiledFunctionCatalog::new(&compiled));
+        let function_catalog = Box::new(CompiledFunctionCatalog::new(&compiled, vec![]));
         let fn_catalog_addr: usize =
             function_catalog.as_ref() as *const CompiledFunctionCatalog as usize;
-        let jit_call_trampoline_address: usize = jit_call_trampoline as usize;
+        let jit_call_trampoline_address: usize = jit_call_trampoline as *const () as usize;
 
         let mut gen = Aarch64Generator::default();
         let machine_code = gen
@@ -1241,12 +4031,68 @@ mod test {
             |ldr  x19, [x29, #32]
             |mov  x10, x0
             |ldr  x0, [x29, #24]
+            |movz x16, {}
+            |ldr  x16, [x16, #16]
+            |cbnz x16, .L2
             |add  x11, x9, x10
             |mov  x0, x11
             |ldp  x29, x30, [sp], #64
             |ret
+            |.L2:
+            |ldp  x29, x30, [sp], #64
+            |ret
+            |",
+                fn_catalog_addr,
+                jit_call_trampoline_address,
+                function_catalog.runtime_context_address()
+            )
+            .trim_margin()
+            .unwrap(),
+            machine_code.asm
+        );
+    }
+
+    #[test]
+    fn can_compile_tail_calls() {
+        let program = parse_program(
+            "fn f(n, x: f64) -> f64 { if (n == 0) { return x; } return f(n - 1, x); }",
+        )
+        .unwrap();
+        let compiled = frontend::compile(program, &frontend::FrontendOptions::default())
+            .unwrap()
+            .functions;
+
+        let function_catalog = Box::new(CompiledFunctionCatalog::new(&compiled, vec![]));
+        let mut gen = Aarch64Generator::default();
+        let machine_code = gen
+            .generate_machine_code(&compiled[0], &function_catalog)
+            .unwrap();
+        assert_eq!(
+            format!(
+                "
+            |stp  x29, x30, [sp, #-16]!
+            |mov  x29, sp
+            |mov  x9, x0
+            |fmov d16, d0
+            |movz x10, 0
+            |cmp  x9, x10
+            |cset x11, eq
+            |cbz  x11, .L2
+            |.L1:
+            |fmov d0, d16
+            |ldp  x29, x30, [sp], #16
+            |ret
+            |.L2:
+            |movz x11, 1
+            |subs x10, x9, x11
+            |mov  x0, x10
+            |fmov d0, d16
+            |movz x16, {}
+            |ldr  x16, [x16, #0]
+            |ldp  x29, x30, [sp], #16
+            |br   x16
             |",
-                fn_catalog_addr, jit_call_trampoline_address
+                function_catalog.function_pointer_address(FunctionId(0)),
             )
             .trim_margin()
             .unwrap(),
@@ -1254,6 +4100,1201 @@ mod test {
         );
     }
 
+    #[test]
+    fn can_compile_arguments_passed_on_the_stack() {
+        let program =
+            parse_program("fn f(a, b, c, d, e, f, g, h, x: f64, i, j) { return i - j; }").unwrap();
+        let compiled = frontend::compile(program, &frontend::FrontendOptions::default())
+            .unwrap()
+            .functions;
+
+        let function_catalog = Box::new(CompiledFunctionCatalog::new(&compiled, vec![]));
+        let mut gen = Aarch64Generator::default();
+        let machine_code = gen
+            .generate_machine_code(&compiled[0], &function_catalog)
+            .unwrap();
+        assert_eq!(
+            "
+            |stp  x29, x30, [sp, #-16]!
+            |mov  x29, sp
+            |mov  x9, x0
+            |mov  x9, x1
+            |mov  x9, x2
+            |mov  x9, x3
+            |mov  x9, x4
+            |mov  x9, x5
+            |mov  x9, x6
+            |mov  x9, x7
+            |fmov d16, d0
+            |ldr  x9, [x29, #16]
+            |ldr  x10, [x29, #24]
+            |subs x11, x9, x10
+            |mov  x0, x11
+            |ldp  x29, x30, [sp], #16
+            |ret
+            |"
+            .trim_margin()
+            .unwrap(),
+            machine_code.asm
+        );
+    }
+
+    #[test]
+    fn can_compile_spilled_values() {
+        let program =
+            parse_program("fn f(a, b, c, d, e, f, g, h) { return a + b + c + d + e + f + g + h; }")
+                .unwrap();
+        let compiled = frontend::compile(program, &frontend::FrontendOptions::default())
+            .unwrap()
+            .functions;
+
+        let function_catalog = Box::new(CompiledFunctionCatalog::new(&compiled, vec![]));
+        let mut gen = Aarch64Generator::default();
+        let machine_code = gen
+            .generate_machine_code(&compiled[0], &function_catalog)
+            .unwrap();
+        assert_eq!(
+            "
+            |stp  x29, x30, [sp, #-64]!
+            |mov  x29, sp
+            |str  x20, [x29, #32]
+            |str  x21, [x29, #40]
+            |str  x22, [x29, #48]
+            |mov  x9, x0
+            |mov  x10, x1
+            |mov  x11, x2
+            |mov  x12, x3
+            |mov  x13, x4
+            |mov  x14, x5
+            |mov  x15, x6
+            |str  x7, [x29, #16]
+            |add  x22, x9, x10
+            |str  x22, [x29, #24]
+            |ldr  x20, [x29, #24]
+            |add  x10, x20, x11
+            |add  x22, x10, x12
+            |str  x22, [x29, #24]
+            |ldr  x20, [x29, #24]
+            |add  x12, x20, x13
+            |add  x22, x12, x14
+            |str  x22, [x29, #24]
+            |ldr  x20, [x29, #24]
+            |add  x14, x20, x15
+            |ldr  x21, [x29, #16]
+            |add  x22, x14, x21
+            |str  x22, [x29, #24]
+            |ldr  x20, [x29, #24]
+            |mov  x0, x20
+            |ldr  x20, [x29, #32]
+            |ldr  x21, [x29, #40]
+            |ldr  x22, [x29, #48]
+            |ldp  x29, x30, [sp], #64
+            |ret
+            |"
+            .trim_margin()
+            .unwrap(),
+            machine_code.asm
+        );
+    }
+
+    #[test]
+    fn can_compile_spilled_arguments_passed_on_the_stack() {
+        let program = parse_program(
+            "fn f(a, b, c, d, e, f, g, h, i) { return a + b + c + d + e + f + g + h + i; }",
+        )
+        .unwrap();
+        let compiled = frontend::compile(program, &frontend::FrontendOptions::default())
+            .unwrap()
+            .functions;
+
+        let function_catalog = Box::new(CompiledFunctionCatalog::new(&compiled, vec![]));
+        let mut gen = Aarch64Generator::default();
+        let machine_code = gen
+            .generate_machine_code(&compiled[0], &function_catalog)
+            .unwrap();
+        assert_eq!(
+            "
+            |stp  x29, x30, [sp, #-64]!
+            |mov  x29, sp
+            |str  x20, [x29, #40]
+            |str  x21, [x29, #48]
+            |str  x22, [x29, #56]
+            |mov  x9, x0
+            |mov  x10, x1
+            |mov  x11, x2
+            |mov  x12, x3
+            |mov  x13, x4
+            |mov  x14, x5
+            |mov  x15, x6
+            |str  x7, [x29, #16]
+            |ldr  x22, [x29, #64]
+            |str  x22, [x29, #24]
+            |add  x22, x9, x10
+            |str  x22, [x29, #32]
+            |ldr  x20, [x29, #32]
+            |add  x10, x20, x11
+            |add  x22, x10, x12
+            |str  x22, [x29, #32]
+            |ldr  x20, [x29, #32]
+            |add  x12, x20, x13
+            |add  x22, x12, x14
+            |str  x22, [x29, #32]
+            |ldr  x20, [x29, #32]
+            |add  x14, x20, x15
+            |ldr  x21, [x29, #16]
+            |add  x22, x14, x21
+            |str  x22, [x29, #32]
+            |ldr  x20, [x29, #32]
+            |ldr  x21, [x29, #24]
+            |add  x22, x20, x21
+            |str  x22, [x29, #16]
+            |ldr  x20, [x29, #16]
+            |mov  x0, x20
+            |ldr  x20, [x29, #40]
+            |ldr  x21, [x29, #48]
+            |ldr  x22, [x29, #56]
+            |ldp  x29, x30, [sp], #64
+            |ret
+            |"
+            .trim_margin()
+            .unwrap(),
+            machine_code.asm
+        );
+    }
+
+    #[test]
+    fn can_compile_math_intrinsics() {
+        let program =
+            parse_program("fn f(a, b) { return max(abs(a), sign(b)) - popcount(a); }").unwrap();
+        let compiled = frontend::compile(program, &frontend::FrontendOptions::default())
+            .unwrap()
+            .functions;
+
+        let function_catalog = Box::new(CompiledFunctionCatalog::new(&compiled, vec![]));
+        let mut gen = Aarch64Generator::default();
+        let machine_code = gen
+            .generate_machine_code(&compiled[0], &function_catalog)
+            .unwrap();
+        assert_eq!(
+            "
+            |stp  x29, x30, [sp, #-16]!
+            |mov  x29, sp
+            |mov  x9, x0
+            |mov  x10, x1
+            |cmp  x9, #0
+            |cneg x11, x9, lt
+            |cmp  x10, #0
+            |cset x12, ne
+            |cneg x12, x12, lt
+            |cmp  x11, x12
+            |csel x10, x11, x12, gt
+            |fmov d0, x9
+            |cnt  v0.8b, v0.8b
+            |addv b0, v0.8b
+            |fmov x12, d0
+            |subs x9, x10, x12
+            |mov  x0, x9
+            |ldp  x29, x30, [sp], #16
+            |ret
+            |"
+            .trim_margin()
+            .unwrap(),
+            machine_code.asm
+        );
+    }
+
+    #[test]
+    fn can_compile_pow_and_gcd() {
+        let program = parse_program(
+            "
+            fn power(x, y) { return pow(x, y); }
+            fn divisor(x, y) { return gcd(x, y); }
+            ",
+        )
+        .unwrap();
+        let compiled = frontend::compile(program, &frontend::FrontendOptions::default())
+            .unwrap()
+            .functions;
+
+        let function_catalog = Box::new(CompiledFunctionCatalog::new(&compiled, vec![]));
+        let mut gen = Aarch64Generator::default();
+        let machine_code = gen
+            .generate_machine_code(&compiled[0], &function_catalog)
+            .unwrap();
+        assert_eq!(
+            "
+            |stp  x29, x30, [sp, #-64]!
+            |mov  x29, sp
+            |str  x20, [x29, #40]
+            |str  x21, [x29, #48]
+            |str  x22, [x29, #56]
+            |mov  x9, x0
+            |mov  x10, x1
+            |movz x11, 1
+            |movz x12, 0
+            |mov  x13, x11
+            |mov  x14, x9
+            |mov  x9, x10
+            |.L1:
+            |mov  x10, x13
+            |mov  x15, x14
+            |str  x9, [x29, #16]
+            |ldr  x20, [x29, #16]
+            |and  x22, x20, x11
+            |str  x22, [x29, #24]
+            |str  x10, [x29, #32]
+            |ldr  x20, [x29, #24]
+            |cbz  x20, .L3
+            |.L2:
+            |mul  x22, x10, x15
+            |str  x22, [x29, #24]
+            |ldr  x20, [x29, #24]
+            |str  x20, [x29, #32]
+            |.L3:
+            |ldr  x20, [x29, #32]
+            |str  x20, [x29, #24]
+            |ldr  x20, [x29, #16]
+            |lsr  x22, x20, x11
+            |cmp  x11, #64
+            |csel x22, x22, xzr, lo
+            |str  x22, [x29, #32]
+            |ldr  x20, [x29, #32]
+            |cmp  x20, x12
+            |cset x22, ne
+            |str  x22, [x29, #16]
+            |ldr  x20, [x29, #24]
+            |mov  x10, x20
+            |ldr  x20, [x29, #16]
+            |cbz  x20, .L5
+            |.L4:
+            |mul  x22, x15, x15
+            |str  x22, [x29, #16]
+            |ldr  x20, [x29, #24]
+            |mov  x13, x20
+            |ldr  x20, [x29, #16]
+            |mov  x14, x20
+            |ldr  x20, [x29, #32]
+            |mov  x9, x20
+            |b    .L1
+            |.L5:
+            |mov  x14, x10
+            |mov  x0, x14
+            |ldr  x20, [x29, #40]
+            |ldr  x21, [x29, #48]
+            |ldr  x22, [x29, #56]
+            |ldp  x29, x30, [sp], #64
+            |ret
+            |"
+            .trim_margin()
+            .unwrap(),
+            machine_code.asm
+        );
+
+        let machine_code = gen
+            .generate_machine_code(&compiled[1], &function_catalog)
+            .unwrap();
+        assert_eq!(
+            format!(
+                "
+            |stp  x29, x30, [sp, #-16]!
+            |mov  x29, sp
+            |mov  x9, x0
+            |mov  x10, x1
+            |cmp  x9, #0
+            |cneg x11, x9, lt
+            |cmp  x10, #0
+            |cneg x9, x10, lt
+            |movz x10, 0
+            |mov  x12, x11
+            |mov  x11, x9
+            |.L1:
+            |mov  x9, x12
+            |mov  x13, x11
+            |cmp  x13, x10
+            |cset x14, ne
+            |mov  x15, x9
+            |cbz  x14, .L3
+            |.L2:
+            |cbz  x13, .L7
+            |udiv x14, x9, x13
+            |msub x14, x14, x13, x9
+            |mov  x12, x13
+            |mov  x11, x14
+            |b    .L1
+            |.L3:
+            |mov  x12, x15
+            |mov  x0, x12
+            |ldp  x29, x30, [sp], #16
+            |ret
+            |.L7:
+            |movz x16, {}
+            |movz x8, 3
+            |str  x8, [x16, #16]
+            |.L5:
+            |ldp  x29, x30, [sp], #16
+            |ret
+            |",
+                function_catalog.runtime_context_address()
+            )
+            .trim_margin()
+            .unwrap(),
+            machine_code.asm
+        );
+    }
+
+    #[test]
+    fn can_compile_jump_tables() {
+        let program =
+            parse_program("fn f(x) { return match x { 1 => 5, 2 | 4 => 6, 3 => 7, _ => 8 }; }")
+                .unwrap();
+        let compiled = frontend::compile(program, &frontend::FrontendOptions::default())
+            .unwrap()
+            .functions;
+
+        let function_catalog = Box::new(CompiledFunctionCatalog::new(&compiled, vec![]));
+        let mut gen = Aarch64Generator::default();
+        let machine_code = gen
+            .generate_machine_code(&compiled[0], &function_catalog)
+            .unwrap();
+        assert_eq!(
+            "
+            |stp  x29, x30, [sp, #-16]!
+            |mov  x29, sp
+            |mov  x9, x0
+            |movz x16, 1
+            |subs x17, x9, x16
+            |movz x16, 4
+            |cmp  x17, x16
+            |cset x8, lo
+            |cbz  x8, .L4
+            |adr  x16, .L13
+            |ldr  x8, [x16, x17, lsl #3]
+            |add  x16, x16, x8
+            |br   x16
+            |.L1:
+            |movz x9, 5
+            |mov  x10, x9
+            |b    .L5
+            |.L2:
+            |movz x9, 6
+            |mov  x10, x9
+            |b    .L5
+            |.L3:
+            |movz x9, 7
+            |mov  x10, x9
+            |b    .L5
+            |.L4:
+            |movz x9, 8
+            |mov  x10, x9
+            |.L5:
+            |mov  x9, x10
+            |mov  x0, x9
+            |ldp  x29, x30, [sp], #16
+            |ret
+            |.L13:
+            |.quad .L1-.L13
+            |.quad .L2-.L13
+            |.quad .L3-.L13
+            |.quad .L2-.L13
+            |"
+            .trim_margin()
+            .unwrap(),
+            machine_code.asm
+        );
+
+        // The table follows the 28 instructions, and its entries are relative to its start
+        let table: Vec<i64> = machine_code.machine_code[28 * 4..]
+            .chunks(8)
+            .map(|entry| i64::from_le_bytes(entry.try_into().unwrap()))
+            .collect();
+        assert_eq!(
+            table,
+            vec![13 * 4 - 112, 16 * 4 - 112, 19 * 4 - 112, 16 * 4 - 112]
+        );
+    }
+
+    #[test]
+    fn can_compile_indirect_function_calls() {
+        let program = parse_program("fn apply(f, x) { return f(x) * x; }").unwrap();
+        let compiled = frontend::compile(program, &frontend::FrontendOptions::default())
+            .unwrap()
+            .functions;
+
+        let function_catalog = Box::new(CompiledFunctionCatalog::new(&compiled, vec![]));
+        let fn_catalog_addr: usize =
+            function_catalog.as_ref() as *const CompiledFunctionCatalog as usize;
+        let jit_call_trampoline_address: usize = jit_call_trampoline as *const () as usize;
+
+        let mut gen = Aarch64Generator::default();
+        let machine_code = gen
+            .generate_machine_code(&compiled[0], &function_catalog)
+            .unwrap();
+        assert_eq!(
+            format!(
+                "
+            |stp  x29, x30, [sp, #-80]!
+            |mov  x29, sp
+            |mov  x9, x0
+            |mov  x10, x1
+            |movz x16, 1
+            |cmp  x9, x16
+            |cset x8, lo
+            |cbz  x8, .L6
+            |movz x16, {0}
+            |ldr  x16, [x16, x9, lsl #3]
+            |movz x17, -1
+            |cmp  x16, x17
+            |cset x8, ne
+            |cbz  x8, .L6
+            |movz x17, 1
+            |cmp  x16, x17
+            |cset x8, eq
+            |cbz  x8, .L7
+            |str  x0, [x29, #24]
+            |str  x19, [x29, #32]
+            |str  x9, [x29, #40]
+            |str  x10, [x29, #48]
+            |str  x11, [x29, #56]
+            |str  x1, [x29, #64]
+            |movz x0, {1}
+            |mov  x1, x9
+            |mov  x2, x10
+            |movz x19, {2}
+            |blr x19
+            |ldr  x1, [x29, #64]
+            |ldr  x11, [x29, #56]
+            |ldr  x10, [x29, #48]
+            |ldr  x9, [x29, #40]
+            |ldr  x19, [x29, #32]
+            |mov  x11, x0
+            |ldr  x0, [x29, #24]
+            |movz x16, {3}
+            |ldr  x16, [x16, #16]
+            |cbnz x16, .L2
+            |mul  x9, x11, x10
+            |mov  x0, x9
+            |ldp  x29, x30, [sp], #80
+            |ret
+            |.L6:
+            |movz x16, {3}
+            |movz x8, 5
+            |str  x8, [x16, #16]
+            |b    .L2
+            |.L7:
+            |movz x16, {3}
+            |movz x8, 6
+            |str  x8, [x16, #16]
+            |.L2:
+            |ldp  x29, x30, [sp], #80
+            |ret
+            |",
+                function_catalog.arities_address(),
+                fn_catalog_addr,
+                jit_call_trampoline_address,
+                function_catalog.runtime_context_address()
+            )
+            .trim_margin()
+            .unwrap(),
+            machine_code.asm
+        );
+    }
+
+    #[test]
+    fn can_compile_indirect_tail_calls() {
+        let program = parse_program("fn apply(f, x) { return f(x); }").unwrap();
+        let compiled = frontend::compile(program, &frontend::FrontendOptions::default())
+            .unwrap()
+            .functions;
+
+        let function_catalog = Box::new(CompiledFunctionCatalog::new(&compiled, vec![]));
+        let mut gen = Aarch64Generator::default();
+        let machine_code = gen
+            .generate_machine_code(&compiled[0], &function_catalog)
+            .unwrap();
+        assert_eq!(
+            format!(
+                "
+            |stp  x29, x30, [sp, #-16]!
+            |mov  x29, sp
+            |mov  x9, x0
+            |mov  x10, x1
+            |movz x16, 1
+            |cmp  x9, x16
+            |cset x8, lo
+            |cbz  x8, .L6
+            |movz x16, {0}
+            |ldr  x16, [x16, x9, lsl #3]
+            |movz x17, -1
+            |cmp  x16, x17
+            |cset x8, ne
+            |cbz  x8, .L6
+            |movz x17, 1
+            |cmp  x16, x17
+            |cset x8, eq
+            |cbz  x8, .L7
+            |mov  x0, x10
+            |movz x16, {1}
+            |ldr  x16, [x16, x9, lsl #3]
+            |ldp  x29, x30, [sp], #16
+            |br   x16
+            |.L6:
+            |movz x16, {2}
+            |movz x8, 5
+            |str  x8, [x16, #16]
+            |b    .L2
+            |.L7:
+            |movz x16, {2}
+            |movz x8, 6
+            |str  x8, [x16, #16]
+            |.L2:
+            |ldp  x29, x30, [sp], #16
+            |ret
+            |",
+                function_catalog.arities_address(),
+                function_catalog.function_pointers_address(),
+                function_catalog.runtime_context_address()
+            )
+            .trim_margin()
+            .unwrap(),
+            machine_code.asm
+        );
+    }
+
+    #[test]
+    fn can_compile_float_function_calls() {
+        let mut program = parse_program(
+            "
+            fn f(n, x: f64) -> f64 { return g(x, n) * 2.5; }
+            fn g(x: f64, n) -> f64 { return x + n as f64; }
+            ",
+        )
+        .unwrap();
+        // The type checker records which values are floats
+        assert!(crate::frontend_types::check_types(
+            &mut program,
+            &frontend::FrontendOptions::default()
+        )
+        .is_empty());
+        let compiled = frontend::compile(program, &frontend::FrontendOptions::default())
+            .unwrap()
+            .functions;
+
+        let function_catalog = Box::new(CompiledFunctionCatalog::new(&compiled, vec![]));
+        let fn_catalog_addr: usize =
+            function_catalog.as_ref() as *const CompiledFunctionCatalog as usize;
+        let jit_call_trampoline_address: usize = jit_call_trampoline_float as *const () as usize;
+
+        let mut gen = Aarch64Generator::default();
+        let machine_code = gen
+            .generate_machine_code(&compiled[0], &function_catalog)
+            .unwrap();
+        assert_eq!(
+            format!(
+                "
+            |stp  x29, x30, [sp, #-80]!
+            |mov  x29, sp
+            |mov  x9, x0
+            |fmov d16, d0
+            |str  d0, [x29, #24]
+            |str  x19, [x29, #32]
+            |str  x9, [x29, #40]
+            |str  d16, [x29, #48]
+            |str  d17, [x29, #56]
+            |str  d18, [x29, #64]
+            |str  x0, [x29, #72]
+            |movz x0, {}
+            |movz x1, 1
+            |fmov d0, d16
+            |mov  x2, x9
+            |movz x19, {}
+            |blr x19
+            |ldr  x0, [x29, #72]
+            |ldr  d18, [x29, #64]
+            |ldr  d17, [x29, #56]
+            |ldr  d16, [x29, #48]
+            |ldr  x9, [x29, #40]
+            |ldr  x19, [x29, #32]
+            |fmov d17, d0
+            |ldr  d0, [x29, #24]
+            |movz x16, {}
+            |ldr  x16, [x16, #16]
+            |cbnz x16, .L2
+            |movz x16, 4612811918334230528
+            |fmov d16, x16
+            |fmul d18, d17, d16
+            |fmov d0, d18
+            |ldp  x29, x30, [sp], #80
+            |ret
+            |.L2:
+            |ldp  x29, x30, [sp], #80
+            |ret
+            |",
+                fn_catalog_addr,
+                jit_call_trampoline_address,
+                function_catalog.runtime_context_address()
+            )
+            .trim_margin()
+            .unwrap(),
+            machine_code.asm
+        );
+
+        let mut gen = Aarch64Generator::default();
+        let machine_code = gen
+            .generate_machine_code(&compiled[1], &function_catalog)
+            .unwrap();
+        assert_eq!(
+            "
+            |stp  x29, x30, [sp, #-16]!
+            |mov  x29, sp
+            |fmov d16, d0
+            |mov  x9, x0
+            |scvtf d17, x9
+            |fadd d18, d16, d17
+            |fmov d0, d18
+            |ldp  x29, x30, [sp], #16
+            |ret
+            |"
+            .trim_margin()
+            .unwrap(),
+            machine_code.asm
+        );
+    }
+
+    #[test]
+    fn can_compile_host_function_calls() {
+        let program = parse_program("extern fn h(x); fn f(x) { return h(x) + 1; }").unwrap();
+        let host_functions = [frontend::HostFunctionSignature {
+            name: "h".to_string(),
+            argument_classes: vec![RegisterClass::Integer],
+            return_class: RegisterClass::Integer,
+        }];
+        let compiled = frontend::compile_with_host_functions(
+            program,
+            &frontend::FrontendOptions::default(),
+            &host_functions,
+        )
+        .unwrap()
+        .functions;
+
+        // Host functions are called directly, without going through the trampoline
+        let function_catalog = Box::new(CompiledFunctionCatalog::new(&compiled, vec![0x1234]));
+        let mut gen = Aarch64Generator::default();
+        let machine_code = gen
+            .generate_machine_code(&compiled[0], &function_catalog)
+            .unwrap();
+        assert_eq!(
+            "
+            |stp  x29, x30, [sp, #-64]!
+            |mov  x29, sp
+            |mov  x9, x0
+            |str  x0, [x29, #24]
+            |str  x19, [x29, #32]
+            |str  x9, [x29, #40]
+            |str  x10, [x29, #48]
+            |str  x11, [x29, #56]
+            |mov  x0, x9
+            |movz x19, 4660
+            |blr x19
+            |ldr  x11, [x29, #56]
+            |ldr  x10, [x29, #48]
+            |ldr  x9, [x29, #40]
+            |ldr  x19, [x29, #32]
+            |mov  x10, x0
+            |ldr  x0, [x29, #24]
+            |movz x9, 1
+            |add  x11, x10, x9
+            |mov  x0, x11
+            |ldp  x29, x30, [sp], #64
+            |ret
+            |"
+            .trim_margin()
+            .unwrap(),
+            machine_code.asm
+        );
+    }
+
+    #[test]
+    fn can_compile_if_else() {
+        let program =
+            parse_program("fn f(x) { let a = 1; if (x) { a = 2; } else { a = 3; } return a; }")
+                .unwrap();
+        let compiled = frontend::compile(program, &frontend::FrontendOptions::default())
+            .unwrap()
+            .functions;
+        assert_eq!(compiled.len(), 1);
+
+        let mut gen = Aarch64Generator::default();
+        let machine_code = gen
+            .generate_machine_code(
+                &compiled[0],
+                &Box::new(CompiledFunctionCatalog::new(&compiled, vec![])),
+            )
+            .unwrap();
+        assert_eq!(
+            "
+            |stp  x29, x30, [sp, #-16]!
+            |mov  x29, sp
+            |mov  x9, x0
+            |movz x10, 1
+            |cbz  x9, .L2
+            |.L1:
+            |movz x9, 2
+            |mov  x10, x9
+            |b    .L3
+            |.L2:
+            |movz x9, 3
+            |mov  x10, x9
+            |.L3:
+            |mov  x9, x10
+            |mov  x0, x9
+            |ldp  x29, x30, [sp], #16
+            |ret
+            |"
+            .trim_margin()
+            .unwrap(),
+            machine_code.asm
+        );
+        assert_eq!(
+            vec![
+                0xFD, 0x7B, 0xBF, 0xA9, 0xFD, 0x03, 0x00, 0x91, 0xE9, 0x03, 0x00, 0xAA, 0x2A, 0x00,
+                0x80, 0xD2, 0x89, 0x00, 0x00, 0xB4, 0x49, 0x00, 0x80, 0xD2, 0xEA, 0x03, 0x09, 0xAA,
+                0x03, 0x00, 0x00, 0x14, 0x69, 0x00, 0x80, 0xD2, 0xEA, 0x03, 0x09, 0xAA, 0xE9, 0x03,
+                0x0A, 0xAA, 0xE0, 0x03, 0x09, 0xAA, 0xFD, 0x7B, 0xC1, 0xA8, 0xC0, 0x03, 0x5F, 0xD6
+            ],
+            machine_code.machine_code
+        );
+    }
+
+    #[test]
+    fn can_compile_compound_assignments() {
+        let source = "
+            fn f(n, x: u8) {
+                let product = 1;
+                while (n > 0) {
+                    product *= n;
+                    n--;
+                    if (n % 2 == 1) {
+                        x += 100;
+                    }
+                }
+                product <<= 1;
+                product |= x;
+                return product;
+            }
+        ";
+        let (asm, _) = compile_with_overflow_mode(source, OverflowMode::Wrapping);
+        assert_eq!(
+            "
+            |stp  x29, x30, [sp, #-64]!
+            |mov  x29, sp
+            |str  x20, [x29, #40]
+            |str  x21, [x29, #48]
+            |str  x22, [x29, #56]
+            |mov  x9, x0
+            |mov  x10, x1
+            |ubfx x11, x10, #0, #8
+            |movz x10, 1
+            |mov  x12, x9
+            |mov  x9, x10
+            |mov  x10, x11
+            |.L1:
+            |mov  x11, x12
+            |mov  x13, x9
+            |mov  x14, x10
+            |movz x15, 0
+            |cmp  x11, x15
+            |cset x22, gt
+            |str  x22, [x29, #16]
+            |ldr  x20, [x29, #16]
+            |cbz  x20, .L5
+            |.L2:
+            |mul  x22, x13, x11
+            |str  x22, [x29, #16]
+            |movz x15, 1
+            |subs x22, x11, x15
+            |str  x22, [x29, #24]
+            |movz x15, 2
+            |ldr  x20, [x29, #24]
+            |sdiv x11, x20, x15
+            |msub x11, x11, x15, x20
+            |movz x15, 1
+            |cmp  x11, x15
+            |cset x22, eq
+            |str  x22, [x29, #32]
+            |mov  x15, x14
+            |ldr  x20, [x29, #32]
+            |cbz  x20, .L4
+            |.L3:
+            |movz x22, 100
+            |str  x22, [x29, #32]
+            |ldr  x21, [x29, #32]
+            |add  x11, x14, x21
+            |ubfx x22, x11, #0, #8
+            |str  x22, [x29, #32]
+            |ldr  x20, [x29, #32]
+            |mov  x15, x20
+            |.L4:
+            |str  x15, [x29, #32]
+            |ldr  x20, [x29, #24]
+            |mov  x12, x20
+            |ldr  x20, [x29, #16]
+            |mov  x9, x20
+            |ldr  x20, [x29, #32]
+            |mov  x10, x20
+            |b    .L1
+            |.L5:
+            |movz x12, 1
+            |lsl  x10, x13, x12
+            |cmp  x12, #64
+            |csel x10, x10, xzr, lo
+            |orr  x13, x10, x14
+            |mov  x0, x13
+            |ldr  x20, [x29, #40]
+            |ldr  x21, [x29, #48]
+            |ldr  x22, [x29, #56]
+            |ldp  x29, x30, [sp], #64
+            |ret
+            |"
+            .trim_margin()
+            .unwrap(),
+            asm
+        );
+    }
+
+    #[test]
+    fn can_compile_bounds_checked_memory_accesses() {
+        let program = parse_program("fn f(a, i) { store(a, i, 3); return load(a, i); }").unwrap();
+        let compiled = frontend::compile(program, &frontend::FrontendOptions::default())
+            .unwrap()
+            .functions;
+
+        let function_catalog = Box::new(CompiledFunctionCatalog::new(&compiled, vec![]));
+        let context_address = function_catalog.runtime_context_address();
+        let mut gen = Aarch64Generator::default();
+        let machine_code = gen
+            .generate_machine_code(&compiled[0], &function_catalog)
+            .unwrap();
+        assert_eq!(
+            format!(
+                "
+            |stp  x29, x30, [sp, #-16]!
+            |mov  x29, sp
+            |mov  x9, x0
+            |mov  x10, x1
+            |movz x11, 3
+            |add  x17, x9, x10
+            |movz x16, {0}
+            |ldr  x8, [x16, #8]
+            |cmp  x17, x8
+            |cset x8, lo
+            |cbz  x8, .L1
+            |ldr  x16, [x16, #0]
+            |str  x11, [x16, x17, lsl #3]
+            |add  x17, x9, x10
+            |movz x16, {0}
+            |ldr  x8, [x16, #8]
+            |cmp  x17, x8
+            |cset x8, lo
+            |cbz  x8, .L1
+            |ldr  x16, [x16, #0]
+            |ldr  x11, [x16, x17, lsl #3]
+            |mov  x0, x11
+            |ldp  x29, x30, [sp], #16
+            |ret
+            |.L1:
+            |str  x17, [x16, #24]
+            |movz x8, 1
+            |str  x8, [x16, #16]
+            |.L2:
+            |ldp  x29, x30, [sp], #16
+            |ret
+            |",
+                context_address
+            )
+            .trim_margin()
+            .unwrap(),
+            machine_code.asm
+        );
+    }
+
+    #[test]
+    fn can_compile_memory_accesses_in_loops() {
+        let program = parse_program(
+            "
+            fn sum(base, n) {
+                let total = 0;
+                let i = 0;
+                while (i < n) {
+                    total = total + load(base, i);
+                    i = i + 1;
+                }
+                return total;
+            }
+            ",
+        )
+        .unwrap();
+        let compiled = frontend::compile(program, &frontend::FrontendOptions::default())
+            .unwrap()
+            .functions;
+
+        let function_catalog = Box::new(CompiledFunctionCatalog::new(&compiled, vec![]));
+        let context_address = function_catalog.runtime_context_address();
+        let mut gen = Aarch64Generator::default();
+        let machine_code = gen
+            .generate_machine_code(&compiled[0], &function_catalog)
+            .unwrap();
+        assert_eq!(
+            format!(
+                "
+            |stp  x29, x30, [sp, #-64]!
+            |mov  x29, sp
+            |str  x20, [x29, #32]
+            |str  x21, [x29, #40]
+            |str  x22, [x29, #48]
+            |mov  x9, x0
+            |mov  x10, x1
+            |movz x11, 0
+            |movz x12, 0
+            |mov  x13, x12
+            |mov  x12, x11
+            |.L1:
+            |mov  x11, x13
+            |mov  x14, x12
+            |cmp  x11, x10
+            |cset x15, lt
+            |cbz  x15, .L3
+            |.L2:
+            |add  x17, x9, x11
+            |movz x16, {0}
+            |ldr  x8, [x16, #8]
+            |cmp  x17, x8
+            |cset x8, lo
+            |cbz  x8, .L4
+            |ldr  x16, [x16, #0]
+            |ldr  x15, [x16, x17, lsl #3]
+            |add  x22, x14, x15
+            |str  x22, [x29, #16]
+            |movz x15, 1
+            |add  x22, x11, x15
+            |str  x22, [x29, #24]
+            |ldr  x20, [x29, #24]
+            |mov  x13, x20
+            |ldr  x20, [x29, #16]
+            |mov  x12, x20
+            |b    .L1
+            |.L3:
+            |mov  x0, x14
+            |ldr  x20, [x29, #32]
+            |ldr  x21, [x29, #40]
+            |ldr  x22, [x29, #48]
+            |ldp  x29, x30, [sp], #64
+            |ret
+            |.L4:
+            |str  x17, [x16, #24]
+            |movz x8, 1
+            |str  x8, [x16, #16]
+            |.L5:
+            |ldr  x20, [x29, #32]
+            |ldr  x21, [x29, #40]
+            |ldr  x22, [x29, #48]
+            |ldp  x29, x30, [sp], #64
+            |ret
+            |",
+                context_address
+            )
+            .trim_margin()
+            .unwrap(),
+            machine_code.asm
+        );
+    }
+
+    #[test]
+    fn can_encode_overflow_checks() {
+        assert_encodes_as(
+            AddsRegToReg {
+                destination: X9,
+                reg1: X10,
+                reg2: X11,
+            },
+            vec![0x49, 0x01, 0x0B, 0xAB],
+        );
+        assert_encodes_as(
+            Smulh {
+                destination: X16,
+                reg1: X9,
+                reg2: X10,
+            },
+            vec![0x30, 0x7D, 0x4A, 0x9B],
+        );
+        assert_encodes_as(
+            Umulh {
+                destination: X16,
+                reg1: X9,
+                reg2: X10,
+            },
+            vec![0x30, 0x7D, 0xCA, 0x9B],
+        );
+        assert_encodes_as(
+            AsrImm {
+                destination: X17,
+                source: X9,
+                amount: 63,
+            },
+            vec![0x31, 0xFD, 0x7F, 0x93],
+        );
+        assert_encodes_as(
+            Cset {
+                destination: X8,
+                condition: Condition::Vs,
+            },
+            vec![0xE8, 0x77, 0x9F, 0x9A],
+        );
+    }
+
+    /// Returns the assembly of the first function, and the address of the runtime context
+    fn compile_with_overflow_mode(source: &str, overflow_mode: OverflowMode) -> (String, usize) {
+        let mut program = parse_program(source).unwrap();
+        let options = frontend::FrontendOptions {
+            overflow_mode,
+            ..Default::default()
+        };
+        assert!(crate::frontend_types::check_types(&mut program, &options).is_empty());
+        let compiled = frontend::compile(program, &options).unwrap().functions;
+
+        let function_catalog = Box::new(CompiledFunctionCatalog::new(&compiled, vec![]));
+        let mut gen = Aarch64Generator::default();
+        let machine_code = gen
+            .generate_machine_code(&compiled[0], &function_catalog)
+            .unwrap();
+        (machine_code.asm, function_catalog.runtime_context_address())
+    }
+
+    #[test]
+    fn can_compile_checked_arithmetic() {
+        let source = "fn f(a, b) { return a * b; }";
+        let (asm, context_address) = compile_with_overflow_mode(source, OverflowMode::Checked);
+        assert_eq!(
+            format!(
+                "
+            |stp  x29, x30, [sp, #-16]!
+            |mov  x29, sp
+            |mov  x9, x0
+            |mov  x10, x1
+            |smulh x16, x9, x10
+            |mul  x11, x9, x10
+            |asr  x17, x11, #63
+            |cmp  x16, x17
+            |cset x8, ne
+            |cbnz x8, .L3
+            |mov  x0, x11
+            |ldp  x29, x30, [sp], #16
+            |ret
+            |.L3:
+            |movz x16, {}
+            |movz x8, 2
+            |str  x8, [x16, #16]
+            |.L2:
+            |ldp  x29, x30, [sp], #16
+            |ret
+            |",
+                context_address
+            )
+            .trim_margin()
+            .unwrap(),
+            asm
+        );
+    }
+
+    #[test]
+    fn can_compile_checked_division() {
+        let source = "fn f(a, b) { return a % b; }";
+        let (asm, context_address) = compile_with_overflow_mode(source, OverflowMode::Wrapping);
+        assert_eq!(
+            format!(
+                "
+            |stp  x29, x30, [sp, #-16]!
+            |mov  x29, sp
+            |mov  x9, x0
+            |mov  x10, x1
+            |cbz  x10, .L4
+            |movz x16, -9223372036854775808
+            |eor  x16, x16, x9
+            |mvn  x17, x10
+            |orr  x16, x16, x17
+            |cbz  x16, .L5
+            |sdiv x11, x9, x10
+            |msub x11, x11, x10, x9
+            |mov  x0, x11
+            |ldp  x29, x30, [sp], #16
+            |ret
+            |.L4:
+            |movz x16, {0}
+            |movz x8, 3
+            |str  x8, [x16, #16]
+            |b    .L2
+            |.L5:
+            |movz x16, {0}
+            |movz x8, 4
+            |str  x8, [x16, #16]
+            |.L2:
+            |ldp  x29, x30, [sp], #16
+            |ret
+            |",
+                context_address
+            )
+            .trim_margin()
+            .unwrap(),
+            asm
+        );
+
+        // Dividing by a constant other than 0 and -1 cannot fail
+        let source = "fn f(a) { return a / 3; }";
+        let (asm, _) = compile_with_overflow_mode(source, OverflowMode::Wrapping);
+        assert_eq!(
+            "
+            |stp  x29, x30, [sp, #-16]!
+            |mov  x29, sp
+            |mov  x9, x0
+            |movz x10, 3
+            |sdiv x11, x9, x10
+            |mov  x0, x11
+            |ldp  x29, x30, [sp], #16
+            |ret
+            |"
+            .trim_margin()
+            .unwrap(),
+            asm
+        );
+    }
+
+    #[test]
+    fn can_compile_saturating_arithmetic() {
+        let source = "fn f(a: i32, b: i32) -> i32 { return a + b; }";
+        assert_eq!(
+            "
+            |stp  x29, x30, [sp, #-16]!
+            |mov  x29, sp
+            |mov  x9, x0
+            |sbfx x10, x9, #0, #32
+            |mov  x9, x1
+            |sbfx x11, x9, #0, #32
+            |add  x9, x10, x11
+            |asr  x17, x9, #63
+            |movz x16, 2147483647
+            |eor  x17, x17, x16
+            |sbfx x16, x9, #0, #32
+            |cmp  x16, x9
+            |csel x9, x17, x16, ne
+            |mov  x0, x9
+            |ldp  x29, x30, [sp], #16
+            |ret
+            |"
+            .trim_margin()
+            .unwrap(),
+            compile_with_overflow_mode(source, OverflowMode::Saturating).0
+        );
+    }
+
     proptest! {
         #[test]
         fn mov_immediate_uses_one_instruction_for_16bit_values(n in 0..0xFFFF) {
@@ -0,0 +1,379 @@
+use crate::{
+    ir::{BasicBlock, BinOpOperator, BlockId, CompiledFunction, IrInstruction, IrRegister},
+    types::Type,
+};
+
+/// Replaces the `Pow` and `Gcd` operations, which have no machine instruction, with
+/// loops made of simpler ones. The block containing the operation is split: the
+/// instructions before it stay in the original block, which then jumps to the new
+/// blocks of the loop, and the ones after it move to a new block at the loop's exit,
+/// which defines the result with a phi. For example, `gcd` becomes:
+/// ```text
+/// b0:
+///   ...
+///   @r3 = abs r1
+///   @r4 = abs r2
+///   @r5 = mvi 0
+///   jmp b1
+/// b1:
+///   phi @r6, [b0: r3, b2: r7]
+///   phi @r8, [b0: r4, b2: r9]
+///   @r10 = ne r8, r5
+///   br r10, b2, b3
+/// b2:
+///   @r9 = rem(u64) r6, r8
+///   jmp b1
+/// b3:
+///   phi @r0, [b1: r6]
+///   ...
+/// ```
+///
+/// The new blocks are inserted right after the one they come from, so that the blocks
+/// are still in reverse post-order, and the other blocks are renumbered accordingly.
+pub fn expand_intrinsics<'a>(function: &CompiledFunction<'a>) -> CompiledFunction<'a> {
+    // Where each original block starts, and the block that ends up holding its
+    // terminator, which is what its successors see as predecessor
+    let mut entry_ids = Vec::with_capacity(function.blocks.len());
+    let mut exit_ids = Vec::with_capacity(function.blocks.len());
+    let mut num_blocks = 0;
+    for block in function.blocks.iter() {
+        entry_ids.push(BlockId(num_blocks));
+        num_blocks += 1 + block.body.iter().map(num_added_blocks).sum::<usize>();
+        exit_ids.push(BlockId(num_blocks - 1));
+    }
+
+    let mut expansion = Expansion {
+        blocks: Vec::with_capacity(num_blocks),
+        next_free_reg: IrRegister::new(function.num_used_registers),
+    };
+    for block in function.blocks.iter() {
+        expansion.new_block();
+        for instruction in block.body.iter() {
+            match instruction {
+                IrInstruction::BinOp {
+                    operator: BinOpOperator::Pow,
+                    ty,
+                    dest,
+                    op1,
+                    op2,
+                } => expansion.expand_pow(*ty, *dest, *op1, *op2),
+                IrInstruction::BinOp {
+                    operator: BinOpOperator::Gcd,
+                    ty,
+                    dest,
+                    op1,
+                    op2,
+                } => expansion.expand_gcd(*ty, *dest, *op1, *op2),
+                IrInstruction::Phi { dest, sources } => expansion.emit(IrInstruction::Phi {
+                    dest: *dest,
+                    sources: sources
+                        .iter()
+                        .map(|(predecessor, src)| (exit_ids[predecessor.0], *src))
+                        .collect(),
+                }),
+                _ => expansion.emit(instruction.map_blocks(|target| entry_ids[target.0])),
+            }
+        }
+    }
+
+    CompiledFunction {
+        name: function.name.clone(),
+        id: function.id,
+        arg_classes: function.arg_classes.clone(),
+        blocks: expansion.blocks,
+        num_used_registers: expansion.next_free_reg.0,
+        float_registers: function.float_registers.clone(),
+        overflow_mode: function.overflow_mode,
+    }
+}
+
+/// How many blocks the expansion of an instruction adds after the current one
+fn num_added_blocks(instruction: &IrInstruction) -> usize {
+    match instruction {
+        IrInstruction::BinOp {
+            operator: BinOpOperator::Pow,
+            ..
+        } => 5,
+        IrInstruction::BinOp {
+            operator: BinOpOperator::Gcd,
+            ..
+        } => 3,
+        _ => 0,
+    }
+}
+
+struct Expansion {
+    blocks: Vec<BasicBlock>,
+    next_free_reg: IrRegister,
+}
+
+impl Expansion {
+    /// Starts a new block, where the following instructions will be emitted
+    fn new_block(&mut self) -> BlockId {
+        let id = BlockId(self.blocks.len());
+        self.blocks.push(BasicBlock::new(id));
+        id
+    }
+
+    /// The id that the n-th block created after the current one will have
+    fn next_block(&self, n: usize) -> BlockId {
+        BlockId(self.blocks.len() + n)
+    }
+
+    fn current_block(&self) -> BlockId {
+        BlockId(self.blocks.len() - 1)
+    }
+
+    fn emit(&mut self, instruction: IrInstruction) {
+        self.blocks
+            .last_mut()
+            .expect("a block has been started")
+            .body
+            .push(instruction);
+    }
+
+    fn constant(&mut self, val: i64) -> IrRegister {
+        let dest = self.next_free_reg.inc();
+        self.emit(IrInstruction::Mvi { dest, val });
+        dest
+    }
+
+    fn binop(
+        &mut self,
+        operator: BinOpOperator,
+        ty: Type,
+        op1: IrRegister,
+        op2: IrRegister,
+    ) -> IrRegister {
+        let dest = self.next_free_reg.inc();
+        self.emit(IrInstruction::BinOp {
+            operator,
+            ty,
+            dest,
+            op1,
+            op2,
+        });
+        dest
+    }
+
+    /// Square-and-multiply: each iteration multiplies the result by the base if the
+    /// lowest bit of the exponent is set, then shifts the exponent and squares the
+    /// base, but only if there are bits left, so that the last square cannot report
+    /// an overflow that does not affect the result
+    fn expand_pow(&mut self, ty: Type, dest: IrRegister, base: IrRegister, exponent: IrRegister) {
+        let pre_id = self.current_block();
+        let loop_id = self.next_block(0);
+        let multiply_id = self.next_block(1);
+        let skip_id = self.next_block(2);
+        let square_id = self.next_block(3);
+        let exit_id = self.next_block(4);
+
+        let one = self.constant(1);
+        let zero = self.constant(0);
+        self.emit(IrInstruction::Jmp { target: loop_id });
+
+        let result = self.next_free_reg.inc();
+        let current_base = self.next_free_reg.inc();
+        let current_exponent = self.next_free_reg.inc();
+        let multiplied = self.next_free_reg.inc();
+        let new_result = self.next_free_reg.inc();
+        let new_exponent = self.next_free_reg.inc();
+        let squared = self.next_free_reg.inc();
+
+        self.new_block();
+        self.emit(IrInstruction::Phi {
+            dest: result,
+            sources: vec![(pre_id, one), (square_id, new_result)],
+        });
+        self.emit(IrInstruction::Phi {
+            dest: current_base,
+            sources: vec![(pre_id, base), (square_id, squared)],
+        });
+        self.emit(IrInstruction::Phi {
+            dest: current_exponent,
+            sources: vec![(pre_id, exponent), (square_id, new_exponent)],
+        });
+        let bit = self.binop(BinOpOperator::BitwiseAnd, Type::U64, current_exponent, one);
+        self.emit(IrInstruction::Br {
+            cond: bit,
+            if_true: multiply_id,
+            if_false: skip_id,
+        });
+
+        self.new_block();
+        self.emit(IrInstruction::BinOp {
+            operator: BinOpOperator::Mul,
+            ty,
+            dest: multiplied,
+            op1: result,
+            op2: current_base,
+        });
+        self.emit(IrInstruction::Jmp { target: skip_id });
+
+        self.new_block();
+        self.emit(IrInstruction::Phi {
+            dest: new_result,
+            sources: vec![(loop_id, result), (multiply_id, multiplied)],
+        });
+        self.emit(IrInstruction::BinOp {
+            operator: BinOpOperator::ShiftRight,
+            ty: Type::U64,
+            dest: new_exponent,
+            op1: current_exponent,
+            op2: one,
+        });
+        let more = self.binop(BinOpOperator::NotEqual, Type::U64, new_exponent, zero);
+        self.emit(IrInstruction::Br {
+            cond: more,
+            if_true: square_id,
+            if_false: exit_id,
+        });
+
+        self.new_block();
+        self.emit(IrInstruction::BinOp {
+            operator: BinOpOperator::Mul,
+            ty,
+            dest: squared,
+            op1: current_base,
+            op2: current_base,
+        });
+        self.emit(IrInstruction::Jmp { target: loop_id });
+
+        self.new_block();
+        self.emit(IrInstruction::Phi {
+            dest,
+            sources: vec![(skip_id, new_result)],
+        });
+    }
+
+    /// Euclid's algorithm on the absolute values, which are handled as `u64` so that
+    /// the one of `i64::MIN` is correct
+    fn expand_gcd(&mut self, ty: Type, dest: IrRegister, op1: IrRegister, op2: IrRegister) {
+        let pre_id = self.current_block();
+        let loop_id = self.next_block(0);
+        let body_id = self.next_block(1);
+        let exit_id = self.next_block(2);
+
+        let (a, b) = if ty.is_unsigned() {
+            (op1, op2)
+        } else {
+            let a = self.next_free_reg.inc();
+            self.emit(IrInstruction::Abs { dest: a, op: op1 });
+            let b = self.next_free_reg.inc();
+            self.emit(IrInstruction::Abs { dest: b, op: op2 });
+            (a, b)
+        };
+        let zero = self.constant(0);
+        self.emit(IrInstruction::Jmp { target: loop_id });
+
+        let x = self.next_free_reg.inc();
+        let y = self.next_free_reg.inc();
+        let remainder = self.next_free_reg.inc();
+
+        self.new_block();
+        self.emit(IrInstruction::Phi {
+            dest: x,
+            sources: vec![(pre_id, a), (body_id, y)],
+        });
+        self.emit(IrInstruction::Phi {
+            dest: y,
+            sources: vec![(pre_id, b), (body_id, remainder)],
+        });
+        let not_zero = self.binop(BinOpOperator::NotEqual, Type::U64, y, zero);
+        self.emit(IrInstruction::Br {
+            cond: not_zero,
+            if_true: body_id,
+            if_false: exit_id,
+        });
+
+        self.new_block();
+        self.emit(IrInstruction::BinOp {
+            operator: BinOpOperator::Rem,
+            ty: Type::U64,
+            dest: remainder,
+            op1: x,
+            op2: y,
+        });
+        self.emit(IrInstruction::Jmp { target: loop_id });
+
+        self.new_block();
+        self.emit(IrInstruction::Phi {
+            dest,
+            sources: vec![(loop_id, x)],
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::{
+        frontend::FunctionId,
+        ir::{
+            builders::{abs, block, br, jmp, mvarg, mvi, phi, ret, typed_binop},
+            OverflowMode, RegisterClass,
+        },
+    };
+
+    #[test]
+    fn gcd_is_expanded_to_a_loop_and_the_following_blocks_are_renumbered() {
+        let function = CompiledFunction {
+            name: "test".into(),
+            id: FunctionId(0),
+            arg_classes: vec![RegisterClass::Integer, RegisterClass::Integer],
+            blocks: vec![
+                block(
+                    0,
+                    vec![
+                        mvarg(0, 0),
+                        mvarg(1, 1),
+                        typed_binop(BinOpOperator::Gcd, Type::I64, 2, 0, 1),
+                        br(2, 1, 2),
+                    ],
+                ),
+                block(1, vec![jmp(2)]),
+                block(2, vec![phi(3, vec![(0, 0), (1, 1)]), ret(3)]),
+            ],
+            num_used_registers: 4,
+            float_registers: HashSet::new(),
+            overflow_mode: OverflowMode::Wrapping,
+        };
+
+        let function = expand_intrinsics(&function);
+        assert_eq!(
+            vec![
+                block(
+                    0,
+                    vec![
+                        mvarg(0, 0),
+                        mvarg(1, 1),
+                        abs(4, 0),
+                        abs(5, 1),
+                        mvi(6, 0),
+                        jmp(1)
+                    ]
+                ),
+                block(
+                    1,
+                    vec![
+                        phi(7, vec![(0, 4), (2, 8)]),
+                        phi(8, vec![(0, 5), (2, 9)]),
+                        typed_binop(BinOpOperator::NotEqual, Type::U64, 10, 8, 6),
+                        br(10, 2, 3),
+                    ]
+                ),
+                block(
+                    2,
+                    vec![typed_binop(BinOpOperator::Rem, Type::U64, 9, 7, 8), jmp(1)]
+                ),
+                block(3, vec![phi(2, vec![(1, 7)]), br(2, 4, 5)]),
+                block(4, vec![jmp(5)]),
+                block(5, vec![phi(3, vec![(3, 0), (4, 1)]), ret(3)]),
+            ],
+            function.blocks
+        );
+        assert_eq!(11, function.num_used_registers);
+    }
+}
@@ -0,0 +1,145 @@
+use crate::ir::{BasicBlock, CompiledFunction, IrInstruction, IrRegister};
+
+/// Replaces all the `Phi` instructions with plain copies, since there is no such thing
+/// in machine code. For each phi we allocate a new temporary register, that all the
+/// predecessors will set just before jumping, and that will be copied into the phi
+/// destination at the beginning of the block. For example:
+/// ```text
+/// b1:
+///   jmp b3
+/// b2:
+///   jmp b3
+/// b3:
+///   phi @r3, [b1: r1, b2: r2]
+/// ```
+///
+/// becomes
+///
+/// ```text
+/// b1:
+///   mv @r4, r1
+///   jmp b3
+/// b2:
+///   mv @r4, r2
+///   jmp b3
+/// b3:
+///   mv @r3, r4
+/// ```
+///
+/// The temporary is necessary because, in a loop, a phi can read the destination of
+/// another phi of the same block, which we must not overwrite too early. Since the copy
+/// into the temporary is done before the terminator, it will also be executed if the
+/// predecessor jumps to another block - but that is harmless, since the temporary is
+/// only read by the phi. The temporary has the same class as the phi destination.
+pub fn eliminate_phis<'a>(function: &CompiledFunction<'a>) -> CompiledFunction<'a> {
+    let mut next_free_reg = IrRegister::new(function.num_used_registers);
+    let mut float_registers = function.float_registers.clone();
+    let mut copies_at_end: Vec<Vec<IrInstruction>> = vec![Vec::new(); function.blocks.len()];
+
+    let mut blocks: Vec<BasicBlock> = function
+        .blocks
+        .iter()
+        .map(|block| BasicBlock {
+            id: block.id,
+            body: block
+                .body
+                .iter()
+                .map(|instruction| match instruction {
+                    IrInstruction::Phi { dest, sources } => {
+                        let temp = next_free_reg.inc();
+                        if function.float_registers.contains(dest) {
+                            float_registers.insert(temp);
+                        }
+                        for (predecessor, src) in sources.iter() {
+                            copies_at_end[predecessor.0].push(IrInstruction::Mv {
+                                dest: temp,
+                                src: *src,
+                            });
+                        }
+                        IrInstruction::Mv {
+                            dest: *dest,
+                            src: temp,
+                        }
+                    }
+                    _ => instruction.clone(),
+                })
+                .collect(),
+        })
+        .collect();
+
+    for (block, copies) in blocks.iter_mut().zip(copies_at_end) {
+        let insertion_point = if block.is_terminated() {
+            block.body.len() - 1
+        } else {
+            block.body.len()
+        };
+        block.body.splice(insertion_point..insertion_point, copies);
+    }
+
+    CompiledFunction {
+        name: function.name.clone(),
+        id: function.id,
+        arg_classes: function.arg_classes.clone(),
+        blocks,
+        num_used_registers: next_free_reg.0,
+        float_registers,
+        overflow_mode: function.overflow_mode,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::{
+        frontend::FunctionId,
+        ir::{
+            builders::{block, br, jmp, mv, mvarg, mvi, phi, ret},
+            OverflowMode, RegisterClass,
+        },
+    };
+
+    #[test]
+    fn phis_are_replaced_by_copies_through_a_temporary() {
+        let function = CompiledFunction {
+            name: "test".into(),
+            id: FunctionId(0),
+            arg_classes: vec![RegisterClass::Integer],
+            blocks: vec![
+                block(0, vec![mvarg(0, 0), mvi(1, 1), br(0, 1, 2)]),
+                block(1, vec![mvi(2, 2), jmp(2)]),
+                block(
+                    2,
+                    vec![
+                        phi(3, vec![(1, 2), (0, 1)]),
+                        phi(4, vec![(1, 2), (0, 0)]),
+                        ret(3),
+                    ],
+                ),
+            ],
+            num_used_registers: 5,
+            float_registers: HashSet::from([IrRegister::new(2), IrRegister::new(4)]),
+            overflow_mode: OverflowMode::Wrapping,
+        };
+
+        let function = eliminate_phis(&function);
+        assert_eq!(
+            vec![
+                block(
+                    0,
+                    vec![mvarg(0, 0), mvi(1, 1), mv(5, 1), mv(6, 0), br(0, 1, 2)]
+                ),
+                block(1, vec![mvi(2, 2), mv(5, 2), mv(6, 2), jmp(2)]),
+                block(2, vec![mv(3, 5), mv(4, 6), ret(3)]),
+            ],
+            function.blocks
+        );
+        assert_eq!(7, function.num_used_registers);
+        // The temporary of the second phi holds a float, like its destination
+        assert_eq!(
+            HashSet::from([IrRegister::new(2), IrRegister::new(4), IrRegister::new(6)]),
+            function.float_registers
+        );
+    }
+}
@@ -1,10 +1,10 @@
 use core::fmt;
-use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet};
 
 use tracing::debug;
 
 use crate::{
-    ir::{CompiledFunction, IrRegister},
+    ir::{CompiledFunction, IrRegister, RegisterClass},
     program_counter::ProgramCounter,
 };
 
@@ -14,24 +14,83 @@ pub enum AllocatedLocation<HardwareRegister> {
     Stack { offset: usize },
 }
 
-/// Computes `ir_reg_used_at`, mapping each ir_reg to the PCs where it is used
-/// Key: ir_reg, value: PCs where the register is used
-fn compute_ir_reg_used_at(function: &CompiledFunction) -> Vec<VecDeque<ProgramCounter>> {
-    let mut ir_reg_used_at = vec![VecDeque::new(); function.num_used_registers];
-    for (pc, instruction) in function.body.iter().enumerate() {
-        let pc = ProgramCounter(pc);
-        for ir_reg in instruction.operands() {
-            ir_reg_used_at[ir_reg.0].push_back(pc);
+/// Computes `ir_reg_live_until`, mapping each ir_reg to the last PC where it is alive
+/// Key: ir_reg, value: last PC where the register must be kept
+/// The PCs are computed by laying out the blocks one after the other, in reverse
+/// post-order. The last PC where a register is used is not enough, because of loops:
+/// a register defined before a loop and used inside it must stay alive until the end
+/// of the loop, since the next iteration will read it again. Therefore, we do a classic
+/// liveness analysis and we extend the range of all the registers that are alive at the
+/// end of a block up to its last instruction.
+/// Note that we do not need to extend the beginning of the range in the same way: since
+/// each register is defined in a block that dominates all its usages, and thus comes
+/// before them, the first time we encounter it is always its definition.
+fn compute_ir_reg_live_until(function: &CompiledFunction) -> Vec<ProgramCounter> {
+    let num_blocks = function.blocks.len();
+
+    // Registers read in the block before being written, and registers written in the block
+    let mut used_before_defined: Vec<HashSet<IrRegister>> = vec![HashSet::new(); num_blocks];
+    let mut defined: Vec<HashSet<IrRegister>> = vec![HashSet::new(); num_blocks];
+    for (block, (used, defined)) in function
+        .blocks
+        .iter()
+        .zip(used_before_defined.iter_mut().zip(defined.iter_mut()))
+    {
+        for instruction in block.body.iter() {
+            for ir_reg in instruction.uses() {
+                if !defined.contains(&ir_reg) {
+                    used.insert(ir_reg);
+                }
+            }
+            defined.extend(instruction.dest());
+        }
+    }
+
+    // Iterate until we reach a fixed point. Visiting the blocks backwards means that
+    // we need only one iteration more than the number of nested loops
+    let mut live_in: Vec<HashSet<IrRegister>> = vec![HashSet::new(); num_blocks];
+    let mut live_out: Vec<HashSet<IrRegister>> = vec![HashSet::new(); num_blocks];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (index, block) in function.blocks.iter().enumerate().rev() {
+            let out: HashSet<IrRegister> = block
+                .successors()
+                .iter()
+                .flat_map(|successor| live_in[successor.0].iter().copied())
+                .collect();
+            let mut alive: HashSet<IrRegister> = out.difference(&defined[index]).copied().collect();
+            alive.extend(used_before_defined[index].iter().copied());
+
+            if out != live_out[index] || alive != live_in[index] {
+                changed = true;
+                live_out[index] = out;
+                live_in[index] = alive;
+            }
+        }
+    }
+
+    let mut ir_reg_live_until = vec![ProgramCounter(0); function.num_used_registers];
+    let mut pc = 0;
+    for (block, live_out) in function.blocks.iter().zip(live_out.iter()) {
+        for instruction in block.body.iter() {
+            for ir_reg in instruction.operands() {
+                ir_reg_live_until[ir_reg.0] = ProgramCounter(pc);
+            }
+            pc += 1;
+        }
+        for ir_reg in live_out.iter() {
+            ir_reg_live_until[ir_reg.0] = ProgramCounter(pc - 1);
         }
     }
 
     // Debug
-    debug!("  computed usage:");
-    for (ir_reg, used_at) in ir_reg_used_at.iter().enumerate() {
-        debug!("    reg {} used at {:?}", ir_reg, used_at);
+    debug!("  computed liveness:");
+    for (ir_reg, live_until) in ir_reg_live_until.iter().enumerate() {
+        debug!("    reg {} alive until {:?}", ir_reg, live_until);
     }
 
-    ir_reg_used_at
+    ir_reg_live_until
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -45,24 +104,35 @@ impl fmt::Display for LogicalHwRegister {
 
 const NOT_ALLOCATED: LogicalHwRegister = LogicalHwRegister(usize::MAX);
 
+const FREE: IrRegister = IrRegister::new(usize::MAX);
+
+/// The logical hw registers of one register class
+#[derive(Default)]
+struct LogicalHwRegisters {
+    /// Key: logical_hw_reg, value: ir_reg
+    content: Vec<IrRegister>,
+    free: Vec<LogicalHwRegister>,
+}
+
 /// Allocates all ir registers to a logical hw register, reusing the hw registers
-/// when possible. Result key: ir_reg, value: logical_hw_reg
+/// when possible. Each register class has its own logical hw registers, numbered
+/// from zero. Result key: ir_reg, value: logical_hw_reg
 fn allocate_ir_regs_to_logical_hw_regs(
     function: &CompiledFunction,
-    mut ir_reg_used_at: Vec<VecDeque<ProgramCounter>>,
+    ir_reg_live_until: Vec<ProgramCounter>,
 ) -> Vec<LogicalHwRegister> {
     // Key: ir_reg, value: logical_hw_reg
     let mut ir_reg_allocation = vec![NOT_ALLOCATED; function.num_used_registers];
-    // Key: logical_hw_reg, value: ir_reg
-    let mut logical_hw_regs_content: Vec<IrRegister> = Vec::new();
-
-    const FREE: IrRegister = IrRegister::new(usize::MAX);
-    let mut free_logical_hw_registers: Vec<LogicalHwRegister> = Vec::new();
+    let mut classes: HashMap<RegisterClass, LogicalHwRegisters> = HashMap::new();
 
-    for (pc, instruction) in function.body.iter().enumerate() {
+    for (pc, instruction) in function.instructions().enumerate() {
         let pc = ProgramCounter(pc);
         debug!("  pc {:2}:  {}", pc.0, instruction);
         for ir_reg in instruction.operands() {
+            let LogicalHwRegisters {
+                content: logical_hw_regs_content,
+                free: free_logical_hw_registers,
+            } = classes.entry(function.register_class(ir_reg)).or_default();
             if ir_reg_allocation[ir_reg.0] != NOT_ALLOCATED {
                 // Already allocated
                 debug!(
@@ -91,20 +161,15 @@ fn allocate_ir_regs_to_logical_hw_regs(
         }
 
         // Can we free something?
-        for (hw_reg, ir_reg) in logical_hw_regs_content.iter_mut().enumerate() {
-            if *ir_reg != FREE {
-                let ir_reg_used_at_pcs = &mut ir_reg_used_at[ir_reg.0];
-                if !ir_reg_used_at_pcs.is_empty() && ir_reg_used_at_pcs[0] == pc {
-                    ir_reg_used_at_pcs.pop_front();
-                }
-
-                if ir_reg_used_at_pcs.is_empty() {
+        for (class, logical_hw_regs) in classes.iter_mut() {
+            for (hw_reg, ir_reg) in logical_hw_regs.content.iter_mut().enumerate() {
+                if *ir_reg != FREE && ir_reg_live_until[ir_reg.0] == pc {
                     debug!(
-                        "    freeing register {:?} which was assigned to {} because it was its last usage",
-                            hw_reg, *ir_reg
-                        );
+                        "    freeing {:?} register {:?} which was assigned to {} because it is not alive anymore",
+                        class, hw_reg, *ir_reg
+                    );
                     *ir_reg = FREE;
-                    free_logical_hw_registers.push(LogicalHwRegister(hw_reg));
+                    logical_hw_regs.free.push(LogicalHwRegister(hw_reg));
                 }
             }
         }
@@ -121,43 +186,66 @@ fn allocate_ir_regs_to_logical_hw_regs(
                 .collect::<Vec<_>>()
                 .join(", ")
         );
-        debug!(
-            "    logical_hw_regs: [{}]",
-            logical_hw_regs_content
-                .iter()
-                .map(|h| if *h == FREE {
-                    String::from("f")
-                } else {
-                    format!("{}", h)
-                })
-                .collect::<Vec<_>>()
-                .join(", ")
-        );
+        for (class, logical_hw_regs) in classes.iter() {
+            debug!(
+                "    logical_hw_regs {:?}: [{}]",
+                class,
+                logical_hw_regs
+                    .content
+                    .iter()
+                    .map(|h| if *h == FREE {
+                        String::from("f")
+                    } else {
+                        format!("{}", h)
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
     }
 
     ir_reg_allocation
 }
 
+/// Maps the logical hw registers of each class to the given hardware registers. The
+/// ones that do not fit are spilled on the stack: the integer slots come first, then
+/// the float ones.
 fn map_to_hw_register<HardwareRegister>(
+    function: &CompiledFunction,
     ir_reg_allocation: Vec<LogicalHwRegister>,
     hw_registers: Vec<HardwareRegister>,
+    float_hw_registers: Vec<HardwareRegister>,
 ) -> Vec<AllocatedLocation<HardwareRegister>>
 where
     HardwareRegister: Clone + fmt::Debug,
 {
-    let num_hw_regs = hw_registers.len();
+    let num_integer_spills = ir_reg_allocation
+        .iter()
+        .enumerate()
+        .filter(|(ir_reg, _)| {
+            function.register_class(IrRegister::new(*ir_reg)) == RegisterClass::Integer
+        })
+        .map(|(_, logical_hw_reg)| (logical_hw_reg.0 + 1).saturating_sub(hw_registers.len()))
+        .max()
+        .unwrap_or(0);
+
     let res: Vec<_> = ir_reg_allocation
         .iter()
-        .map(|logical_hw_reg| {
+        .enumerate()
+        .map(|(ir_reg, logical_hw_reg)| {
             assert!(*logical_hw_reg != NOT_ALLOCATED);
 
-            if logical_hw_reg.0 < num_hw_regs {
+            let (registers, first_slot) = match function.register_class(IrRegister::new(ir_reg)) {
+                RegisterClass::Integer => (&hw_registers, 0),
+                RegisterClass::Float => (&float_hw_registers, num_integer_spills),
+            };
+            if logical_hw_reg.0 < registers.len() {
                 AllocatedLocation::Register {
-                    register: hw_registers[logical_hw_reg.0].clone(),
+                    register: registers[logical_hw_reg.0].clone(),
                 }
             } else {
                 AllocatedLocation::Stack {
-                    offset: (logical_hw_reg.0 - num_hw_regs) * 8,
+                    offset: (first_slot + logical_hw_reg.0 - registers.len()) * 8,
                 }
             }
         })
@@ -171,37 +259,57 @@ where
     res
 }
 
+/// Allocates each ir register to either a hardware register or a stack slot. The
+/// registers holding floats get one of `float_hw_registers`, all the others one of
+/// `hw_registers`. The function must not contain any `Phi` instruction.
 pub fn allocate<HardwareRegister>(
     function: &CompiledFunction,
     hw_registers: Vec<HardwareRegister>,
+    float_hw_registers: Vec<HardwareRegister>,
 ) -> Vec<AllocatedLocation<HardwareRegister>>
 where
     HardwareRegister: Clone + fmt::Debug,
 {
     debug!("allocating registers");
-    let ir_reg_used_at = compute_ir_reg_used_at(function);
-    let ir_reg_allocation = allocate_ir_regs_to_logical_hw_regs(function, ir_reg_used_at);
-    map_to_hw_register(ir_reg_allocation, hw_registers)
+    let ir_reg_live_until = compute_ir_reg_live_until(function);
+    let ir_reg_allocation = allocate_ir_regs_to_logical_hw_regs(function, ir_reg_live_until);
+    map_to_hw_register(
+        function,
+        ir_reg_allocation,
+        hw_registers,
+        float_hw_registers,
+    )
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashSet;
+
     use crate::{
         backend_register_allocator::{allocate, AllocatedLocation},
         frontend::FunctionId,
         ir::{
-            builders::{add, mvi},
-            CompiledFunction, IrInstruction,
+            builders::{add, block, br, ftoi, jmp, mvi, ret},
+            BasicBlock, CompiledFunction, IrInstruction, IrRegister, OverflowMode,
         },
     };
 
     fn fun(body: Vec<IrInstruction>, num_used_registers: usize) -> CompiledFunction<'static> {
+        fun_with_blocks(vec![block(0, body)], num_used_registers)
+    }
+
+    fn fun_with_blocks(
+        blocks: Vec<BasicBlock>,
+        num_used_registers: usize,
+    ) -> CompiledFunction<'static> {
         CompiledFunction {
-            name: "test",
+            name: "test".into(),
             id: FunctionId(0),
-            num_args: 0,
-            body,
+            arg_classes: vec![],
+            blocks,
             num_used_registers,
+            float_registers: HashSet::new(),
+            overflow_mode: OverflowMode::Wrapping,
         }
     }
 
@@ -210,6 +318,7 @@ mod tests {
         let allocations = allocate(
             &fun(vec![mvi(0, 0), mvi(1, 1), add(2, 0, 1)], 3),
             vec!["h0"],
+            vec![],
         );
 
         assert_eq!(
@@ -231,6 +340,7 @@ mod tests {
                 4,
             ),
             vec!["h0", "h1", "h2"],
+            vec![],
         );
 
         assert_eq!(
@@ -243,4 +353,63 @@ mod tests {
             ]
         )
     }
+
+    #[test]
+    fn registers_used_in_a_loop_are_kept_alive_until_its_end() {
+        let allocations = allocate(
+            &fun_with_blocks(
+                vec![
+                    block(0, vec![mvi(0, 10), jmp(1)]),
+                    // r0 is last read here, but the loop will jump back to this block
+                    block(1, vec![br(0, 2, 3)]),
+                    // so r1 cannot reuse the same hw register
+                    block(2, vec![mvi(1, 1), jmp(1)]),
+                    block(3, vec![mvi(2, 2), ret(2)]),
+                ],
+                3,
+            ),
+            vec!["h0", "h1"],
+            vec![],
+        );
+
+        assert_eq!(
+            allocations,
+            vec![
+                AllocatedLocation::Register { register: "h0" },
+                AllocatedLocation::Register { register: "h1" },
+                AllocatedLocation::Register { register: "h0" },
+            ]
+        )
+    }
+
+    #[test]
+    fn floats_are_allocated_separately() {
+        let mut function = fun(
+            vec![
+                mvi(0, 0),
+                mvi(1, 1),
+                add(2, 0, 1),
+                ftoi(3, 2),
+                mvi(4, 4),
+                add(5, 3, 4),
+                ret(5),
+            ],
+            6,
+        );
+        function.float_registers = HashSet::from([0, 1, 2].map(IrRegister::new));
+
+        let allocations = allocate(&function, vec!["x0"], vec!["d0", "d1"]);
+        assert_eq!(
+            allocations,
+            vec![
+                AllocatedLocation::Register { register: "d0" },
+                AllocatedLocation::Register { register: "d1" },
+                // The float spills come after the integer ones
+                AllocatedLocation::Stack { offset: 16 },
+                AllocatedLocation::Register { register: "x0" },
+                AllocatedLocation::Stack { offset: 0 },
+                AllocatedLocation::Stack { offset: 8 },
+            ]
+        );
+    }
 }
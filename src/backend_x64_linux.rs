@@ -1,9 +1,20 @@
 use std::fmt::{Display, Write};
 
 use crate::{
-    backend::{BackendError, CompiledFunctionCatalog, GeneratedMachineCode, MachineCodeGenerator},
+    backend::{
+        self, BackendError, Callee, CompiledFunctionCatalog, DivisorChecks, GeneratedMachineCode,
+        JumpTables, MachineCodeGenerator, RuntimeContext, TrapCode, TrapLabels,
+        NOT_CALLABLE_INDIRECTLY,
+    },
+    backend_intrinsic_expansion::expand_intrinsics,
+    backend_phi_elimination::eliminate_phis,
     backend_register_allocator::{self, AllocatedLocation},
-    ir::{BinOpOperator::*, CompiledFunction, IrInstruction, IrRegister},
+    ir::{
+        ArgumentIndex, BinOpOperator, BinOpOperator::*, BlockId, CompiledFunction, IrInstruction,
+        IrRegister, OverflowMode, RegisterClass,
+    },
+    jit::{jit_call_trampoline, jit_call_trampoline_float},
+    types::Type,
 };
 use Register::*;
 use X64Instruction::*;
@@ -19,7 +30,31 @@ enum Register {
     Rsp,
     Rbp,
     Rsi,
+    Rdi,
+    R8,
+    R9,
+    R10,
     R11,
+    R12,
+    R13,
+    R14,
+    R15,
+    Xmm0,
+    Xmm1,
+    Xmm2,
+    Xmm3,
+    Xmm4,
+    Xmm5,
+    Xmm6,
+    Xmm7,
+    Xmm8,
+    Xmm9,
+    Xmm10,
+    Xmm11,
+    Xmm12,
+    Xmm13,
+    Xmm14,
+    Xmm15,
 }
 
 impl Register {
@@ -32,9 +67,65 @@ impl Register {
             Rsp => 4,
             Rbp => 5,
             Rsi => 6,
+            Rdi => 7,
+            R8 => 8,
+            R9 => 9,
+            R10 => 10,
             R11 => 11,
+            R12 => 12,
+            R13 => 13,
+            R14 => 14,
+            R15 => 15,
+            Xmm0 => 0,
+            Xmm1 => 1,
+            Xmm2 => 2,
+            Xmm3 => 3,
+            Xmm4 => 4,
+            Xmm5 => 5,
+            Xmm6 => 6,
+            Xmm7 => 7,
+            Xmm8 => 8,
+            Xmm9 => 9,
+            Xmm10 => 10,
+            Xmm11 => 11,
+            Xmm12 => 12,
+            Xmm13 => 13,
+            Xmm14 => 14,
+            Xmm15 => 15,
         }
     }
+
+    /// Whether this is one of the SSE registers, which hold the floats
+    fn is_float(&self) -> bool {
+        matches!(
+            self,
+            Xmm0 | Xmm1
+                | Xmm2
+                | Xmm3
+                | Xmm4
+                | Xmm5
+                | Xmm6
+                | Xmm7
+                | Xmm8
+                | Xmm9
+                | Xmm10
+                | Xmm11
+                | Xmm12
+                | Xmm13
+                | Xmm14
+                | Xmm15
+        )
+    }
+
+    /// The lowest three bits of the index, used in the ModR/M byte or in the opcode
+    fn low_bits(&self) -> u8 {
+        self.index() & 0b111
+    }
+
+    /// The fourth bit of the index, which goes into one of the REX prefix fields
+    fn high_bit(&self) -> u8 {
+        self.index() >> 3
+    }
 }
 
 impl Display for Register {
@@ -47,12 +138,117 @@ impl Display for Register {
             Rsp => write!(f, "rsp"),
             Rbp => write!(f, "rbp"),
             Rsi => write!(f, "rsi"),
+            Rdi => write!(f, "rdi"),
+            R8 => write!(f, "r8"),
+            R9 => write!(f, "r9"),
+            R10 => write!(f, "r10"),
             R11 => write!(f, "r11"),
+            R12 => write!(f, "r12"),
+            R13 => write!(f, "r13"),
+            R14 => write!(f, "r14"),
+            R15 => write!(f, "r15"),
+            _ => write!(f, "xmm{}", self.index()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Condition {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    /// Unsigned greater
+    Above,
+    /// Unsigned greater or equal
+    AboveOrEqual,
+    /// Unsigned less
+    Below,
+    /// Unsigned less or equal
+    BelowOrEqual,
+    /// After comparing floats, set if either one was NaN
+    Parity,
+    /// Signed overflow
+    Overflow,
+}
+
+impl Condition {
+    /// The condition code, as used in the `jcc` and `setcc` families of instructions
+    fn code(&self) -> u8 {
+        match self {
+            Condition::Equal => 0x4,
+            Condition::NotEqual => 0x5,
+            Condition::Less => 0xC,
+            Condition::LessOrEqual => 0xE,
+            Condition::Greater => 0xF,
+            Condition::GreaterOrEqual => 0xD,
+            Condition::Above => 0x7,
+            Condition::AboveOrEqual => 0x3,
+            Condition::Below => 0x2,
+            Condition::BelowOrEqual => 0x6,
+            Condition::Parity => 0xA,
+            Condition::Overflow => 0x0,
+        }
+    }
+}
+
+impl Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Condition::Equal => write!(f, "e"),
+            Condition::NotEqual => write!(f, "ne"),
+            Condition::Less => write!(f, "l"),
+            Condition::LessOrEqual => write!(f, "le"),
+            Condition::Greater => write!(f, "g"),
+            Condition::GreaterOrEqual => write!(f, "ge"),
+            Condition::Above => write!(f, "a"),
+            Condition::AboveOrEqual => write!(f, "ae"),
+            Condition::Below => write!(f, "b"),
+            Condition::BelowOrEqual => write!(f, "be"),
+            Condition::Parity => write!(f, "p"),
+            Condition::Overflow => write!(f, "o"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FloatOperation {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl FloatOperation {
+    /// The opcode of the scalar double-precision instruction, after `F2 0F`
+    fn opcode(&self) -> u8 {
+        match self {
+            FloatOperation::Add => 0x58,
+            FloatOperation::Sub => 0x5C,
+            FloatOperation::Mul => 0x59,
+            FloatOperation::Div => 0x5E,
+        }
+    }
+}
+
+impl Display for FloatOperation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FloatOperation::Add => write!(f, "addsd"),
+            FloatOperation::Sub => write!(f, "subsd"),
+            FloatOperation::Mul => write!(f, "mulsd"),
+            FloatOperation::Div => write!(f, "divsd"),
         }
     }
 }
 
 enum X64Instruction {
+    /// Not a real instruction: marks the beginning of a block, and generates no code
+    Label {
+        block: BlockId,
+    },
     Push {
         register: Register,
     },
@@ -68,23 +264,215 @@ enum X64Instruction {
         source: Register,
         destination: Register,
     },
-    AddRegToRax {
-        register: Register,
+    /// mov destination, [base + offset]
+    Load {
+        destination: Register,
+        base: Register,
+        offset: i32,
+    },
+    /// mov [base + offset], source
+    Store {
+        source: Register,
+        base: Register,
+        offset: i32,
+    },
+    /// mov destination, [base + index * 8]. The index cannot be rsp
+    LoadIndexed {
+        destination: Register,
+        base: Register,
+        index: Register,
+    },
+    /// mov [base + index * 8], source. The index cannot be rsp
+    StoreIndexed {
+        source: Register,
+        base: Register,
+        index: Register,
+    },
+    AddRegToReg {
+        source: Register,
+        destination: Register,
+    },
+    SubRegFromReg {
+        source: Register,
+        destination: Register,
+    },
+    MulRegToReg {
+        source: Register,
+        destination: Register,
+    },
+    AndRegToReg {
+        source: Register,
+        destination: Register,
+    },
+    OrRegToReg {
+        source: Register,
+        destination: Register,
+    },
+    XorRegToReg {
+        source: Register,
+        destination: Register,
     },
-    SubRegFromRax {
+    AddImmToReg {
         register: Register,
+        value: i32,
     },
-    MulRegToRax {
+    SubImmFromReg {
         register: Register,
+        value: i32,
     },
+    /// Sign-extends rax into rdx:rax, which is the dividend of `idiv`
+    Cqo,
+    /// Divides rdx:rax by the given register, storing the quotient in rax
+    /// and the remainder in rdx
     DivRegFromRax {
         register: Register,
     },
+    /// Like `DivRegFromRax`, but unsigned. The dividend in rdx:rax must be zero-extended
+    UnsignedDivRegFromRax {
+        register: Register,
+    },
+    /// Multiplies rax by the given register, as unsigned values, storing the result in
+    /// rdx:rax. Sets the carry flag if the upper half in rdx is not zero
+    UnsignedMulRegToRax {
+        register: Register,
+    },
+    Neg {
+        register: Register,
+    },
+    Not {
+        register: Register,
+    },
+    /// Counts the bits set in the register, replacing its value
+    Popcnt {
+        register: Register,
+    },
+    /// Shifts the register left by the amount in `cl`, modulo 64
+    ShlCl {
+        register: Register,
+    },
+    /// Shifts the register right, preserving the sign, by the amount in `cl`, modulo 64
+    SarCl {
+        register: Register,
+    },
+    /// Shifts the register right, filling it with zeros, by the amount in `cl`, modulo 64
+    ShrCl {
+        register: Register,
+    },
+    /// Moves source into destination only if the condition holds
+    Cmov {
+        condition: Condition,
+        source: Register,
+        destination: Register,
+    },
+    /// Sets the flags according to `register & register`, i.e. checks if it is zero
+    Test {
+        register: Register,
+    },
+    /// Sets the flags according to `left - right`
+    CmpRegToReg {
+        left: Register,
+        right: Register,
+    },
+    /// Sets `al` to 1 if the condition holds, to 0 otherwise
+    SetCcAl {
+        condition: Condition,
+    },
+    /// Zero-extends `al` into the whole `rax`
+    MovzxAlToRax,
+    /// Sign-extends `eax` into the whole `rax`
+    MovsxdEaxToRax,
+    /// Zero-extends `eax` into the whole `rax`, since writing a 32-bit register always
+    /// clears the upper half
+    MovEaxToEax,
+    Call {
+        register: Register,
+    },
+    /// Jumps to the address contained in the register
+    JmpReg {
+        register: Register,
+    },
+    /// Unconditional jump. The offset, relative to the next instruction, is computed
+    /// once all the code has been generated
+    Jmp {
+        target: BlockId,
+        offset: i32,
+    },
+    /// Conditional jump
+    Jcc {
+        condition: Condition,
+        target: BlockId,
+        offset: i32,
+    },
+    /// lea destination, [rip + offset], loading the address of a label. Like for the
+    /// jumps, the offset is relative to the next instruction
+    LeaRipRelative {
+        destination: Register,
+        target: BlockId,
+        offset: i32,
+    },
+    /// Not a real instruction: the data of a jump table, placed after the code. Every
+    /// entry is the offset of a target from `label`, which marks the start of the table
+    JumpTable {
+        label: BlockId,
+        targets: Vec<BlockId>,
+        offsets: Vec<i64>,
+    },
+    /// Copies the bits of a general purpose register into an SSE register
+    MovqToXmm {
+        source: Register,
+        destination: Register,
+    },
+    /// Copies the bits of an SSE register into a general purpose register
+    MovqFromXmm {
+        source: Register,
+        destination: Register,
+    },
+    /// Copies the float between two SSE registers
+    MovsdRegToReg {
+        source: Register,
+        destination: Register,
+    },
+    /// movsd destination, [base + offset]
+    LoadFloat {
+        destination: Register,
+        base: Register,
+        offset: i32,
+    },
+    /// movsd [base + offset], source
+    StoreFloat {
+        source: Register,
+        base: Register,
+        offset: i32,
+    },
+    /// destination = destination (operation) source, between floats
+    FloatArithmetic {
+        operation: FloatOperation,
+        source: Register,
+        destination: Register,
+    },
+    /// Compares two floats, setting the flags like an unsigned comparison. If either
+    /// is NaN, the result is "unordered", which sets the zero, parity and carry flags
+    Ucomisd {
+        left: Register,
+        right: Register,
+    },
+    /// Converts the signed integer in `source` to a float
+    Cvtsi2sd {
+        source: Register,
+        destination: Register,
+    },
+    /// Converts the float in `source` to a signed integer, rounding towards zero. Values
+    /// out of range and NaN become `i64::MIN`
+    Cvttsd2si {
+        source: Register,
+        destination: Register,
+    },
 }
 
 impl Display for X64Instruction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Label { block } => write!(f, ".L{}:", block.0),
             Push { register: reg } => write!(f, "push {}", reg),
             Pop { register: reg } => write!(f, "pop  {}", reg),
             Retn => write!(f, "retn"),
@@ -95,78 +483,455 @@ impl Display for X64Instruction {
                 source,
                 destination,
             } => write!(f, "mov  {}, {}", destination, source),
-            AddRegToRax { register } => write!(f, "add  rax, {}", register),
-            SubRegFromRax { register } => write!(f, "sub  rax, {}", register),
-            MulRegToRax { register } => write!(f, "add  rax, {}", register),
-            DivRegFromRax { register } => write!(f, "div  {}", register),
+            Load {
+                destination,
+                base,
+                offset,
+            } => write!(f, "mov  {}, [{}{:+}]", destination, base, offset),
+            Store {
+                source,
+                base,
+                offset,
+            } => write!(f, "mov  [{}{:+}], {}", base, offset, source),
+            LoadIndexed {
+                destination,
+                base,
+                index,
+            } => write!(f, "mov  {}, [{}+{}*8]", destination, base, index),
+            StoreIndexed {
+                source,
+                base,
+                index,
+            } => write!(f, "mov  [{}+{}*8], {}", base, index, source),
+            AddRegToReg {
+                source,
+                destination,
+            } => write!(f, "add  {}, {}", destination, source),
+            SubRegFromReg {
+                source,
+                destination,
+            } => write!(f, "sub  {}, {}", destination, source),
+            MulRegToReg {
+                source,
+                destination,
+            } => write!(f, "imul {}, {}", destination, source),
+            AndRegToReg {
+                source,
+                destination,
+            } => write!(f, "and  {}, {}", destination, source),
+            OrRegToReg {
+                source,
+                destination,
+            } => write!(f, "or   {}, {}", destination, source),
+            XorRegToReg {
+                source,
+                destination,
+            } => write!(f, "xor  {}, {}", destination, source),
+            AddImmToReg { register, value } => write!(f, "add  {}, {}", register, value),
+            SubImmFromReg { register, value } => write!(f, "sub  {}, {}", register, value),
+            Cqo => write!(f, "cqo"),
+            DivRegFromRax { register } => write!(f, "idiv {}", register),
+            UnsignedDivRegFromRax { register } => write!(f, "div  {}", register),
+            UnsignedMulRegToRax { register } => write!(f, "mul  {}", register),
+            Neg { register } => write!(f, "neg  {}", register),
+            Not { register } => write!(f, "not  {}", register),
+            Popcnt { register } => write!(f, "popcnt {}, {}", register, register),
+            ShlCl { register } => write!(f, "shl  {}, cl", register),
+            SarCl { register } => write!(f, "sar  {}, cl", register),
+            ShrCl { register } => write!(f, "shr  {}, cl", register),
+            Cmov {
+                condition,
+                source,
+                destination,
+            } => write!(
+                f,
+                "{:<4} {}, {}",
+                format!("cmov{}", condition),
+                destination,
+                source
+            ),
+            Test { register } => write!(f, "test {}, {}", register, register),
+            CmpRegToReg { left, right } => write!(f, "cmp  {}, {}", left, right),
+            SetCcAl { condition } => write!(f, "{:<4} al", format!("set{}", condition)),
+            MovzxAlToRax => write!(f, "movzx rax, al"),
+            MovsxdEaxToRax => write!(f, "movsxd rax, eax"),
+            MovEaxToEax => write!(f, "mov  eax, eax"),
+            Call { register } => write!(f, "call {}", register),
+            JmpReg { register } => write!(f, "jmp  {}", register),
+            Jmp { target, .. } => write!(f, "jmp  .L{}", target.0),
+            Jcc {
+                condition, target, ..
+            } => write!(f, "{:<4} .L{}", format!("j{}", condition), target.0),
+            LeaRipRelative {
+                destination,
+                target,
+                ..
+            } => write!(f, "lea  {}, [rip+.L{}]", destination, target.0),
+            JumpTable { label, targets, .. } => {
+                let entries: Vec<String> = targets
+                    .iter()
+                    .map(|target| format!(".quad .L{}-.L{}", target.0, label.0))
+                    .collect();
+                write!(f, "{}", entries.join("\n"))
+            }
+            MovqToXmm {
+                source,
+                destination,
+            }
+            | MovqFromXmm {
+                source,
+                destination,
+            } => write!(f, "movq {}, {}", destination, source),
+            MovsdRegToReg {
+                source,
+                destination,
+            } => write!(f, "movsd {}, {}", destination, source),
+            LoadFloat {
+                destination,
+                base,
+                offset,
+            } => write!(f, "movsd {}, [{}{:+}]", destination, base, offset),
+            StoreFloat {
+                source,
+                base,
+                offset,
+            } => write!(f, "movsd [{}{:+}], {}", base, offset, source),
+            FloatArithmetic {
+                operation,
+                source,
+                destination,
+            } => write!(f, "{} {}, {}", operation, destination, source),
+            Ucomisd { left, right } => write!(f, "ucomisd {}, {}", left, right),
+            Cvtsi2sd {
+                source,
+                destination,
+            } => write!(f, "cvtsi2sd {}, {}", destination, source),
+            Cvttsd2si {
+                source,
+                destination,
+            } => write!(f, "cvttsd2si {}, {}", destination, source),
         }
     }
 }
 
 impl X64Instruction {
-    fn make_machine_code(&self) -> Result<Vec<u8>, BackendError> {
-        Ok(match self {
+    const REX_W: u8 = 0x48;
+    const REX_B: u8 = 0x41;
+
+    fn make_machine_code(&self) -> Vec<u8> {
+        match self {
+            Label { .. } => vec![],
             Retn => vec![0xC3],
-            Push { register } => vec![0x50 + register.index()],
-            Pop { register } => vec![0x58 + register.index()],
+            Push { register } => Self::with_optional_rex_b(*register, 0x50),
+            Pop { register } => Self::with_optional_rex_b(*register, 0x58),
             MovImmToReg { register, value } => {
-                let mut vec = vec![0x48, 0xB8 + register.index()];
+                let mut vec = vec![
+                    Self::REX_W | register.high_bit(),
+                    0xB8 + register.low_bits(),
+                ];
                 vec.extend_from_slice(&(*value).to_le_bytes());
                 vec
             }
             MovRegToReg {
                 source,
                 destination,
-            } => vec![0x48, 0x89, self.lookup_reg_reg(*source, *destination)?],
-            AddRegToRax { register } => {
-                vec![0x48, 0x01, self.lookup_reg_reg(*register, Rax)?]
+            } => Self::encode_reg_reg(&[0x89], *source, *destination),
+            Load {
+                destination,
+                base,
+                offset,
+            } => Self::encode_reg_mem(&[0x8B], *destination, *base, *offset),
+            Store {
+                source,
+                base,
+                offset,
+            } => Self::encode_reg_mem(&[0x89], *source, *base, *offset),
+            LoadIndexed {
+                destination,
+                base,
+                index,
+            } => Self::encode_reg_mem_indexed(&[0x8B], *destination, *base, *index),
+            StoreIndexed {
+                source,
+                base,
+                index,
+            } => Self::encode_reg_mem_indexed(&[0x89], *source, *base, *index),
+            AddRegToReg {
+                source,
+                destination,
+            } => Self::encode_reg_reg(&[0x01], *source, *destination),
+            SubRegFromReg {
+                source,
+                destination,
+            } => Self::encode_reg_reg(&[0x29], *source, *destination),
+            MulRegToReg {
+                source,
+                destination,
+            } => Self::encode_reg_reg(&[0x0F, 0xAF], *destination, *source),
+            AndRegToReg {
+                source,
+                destination,
+            } => Self::encode_reg_reg(&[0x21], *source, *destination),
+            OrRegToReg {
+                source,
+                destination,
+            } => Self::encode_reg_reg(&[0x09], *source, *destination),
+            XorRegToReg {
+                source,
+                destination,
+            } => Self::encode_reg_reg(&[0x31], *source, *destination),
+            AddImmToReg { register, value } => Self::encode_imm(0, *register, *value),
+            SubImmFromReg { register, value } => Self::encode_imm(5, *register, *value),
+            Cqo => vec![Self::REX_W, 0x99],
+            DivRegFromRax { register } => Self::encode_extended_opcode(0xF7, 7, *register),
+            UnsignedDivRegFromRax { register } => Self::encode_extended_opcode(0xF7, 6, *register),
+            UnsignedMulRegToRax { register } => Self::encode_extended_opcode(0xF7, 4, *register),
+            Neg { register } => Self::encode_extended_opcode(0xF7, 3, *register),
+            Not { register } => Self::encode_extended_opcode(0xF7, 2, *register),
+            Popcnt { register } => {
+                // The mandatory prefix must come before the REX one
+                let mut vec = vec![0xF3];
+                vec.extend(Self::encode_reg_reg(&[0x0F, 0xB8], *register, *register));
+                vec
+            }
+            ShlCl { register } => Self::encode_extended_opcode(0xD3, 4, *register),
+            SarCl { register } => Self::encode_extended_opcode(0xD3, 7, *register),
+            ShrCl { register } => Self::encode_extended_opcode(0xD3, 5, *register),
+            Cmov {
+                condition,
+                source,
+                destination,
+            } => Self::encode_reg_reg(&[0x0F, 0x40 + condition.code()], *destination, *source),
+            Test { register } => Self::encode_reg_reg(&[0x85], *register, *register),
+            CmpRegToReg { left, right } => Self::encode_reg_reg(&[0x39], *right, *left),
+            SetCcAl { condition } => vec![0x0F, 0x90 + condition.code(), 0xC0],
+            MovzxAlToRax => vec![Self::REX_W, 0x0F, 0xB6, 0xC0],
+            MovsxdEaxToRax => vec![Self::REX_W, 0x63, 0xC0],
+            MovEaxToEax => vec![0x89, 0xC0],
+            Call { register } => {
+                let mut vec = Self::with_optional_rex_b(*register, 0xFF);
+                vec.push(0xD0 + register.low_bits());
+                vec
+            }
+            JmpReg { register } => {
+                let mut vec = if register.high_bit() != 0 {
+                    vec![Self::REX_B]
+                } else {
+                    vec![]
+                };
+                vec.extend_from_slice(&[0xFF, 0xE0 + register.low_bits()]);
+                vec
             }
-            SubRegFromRax { register } => {
-                vec![0x48, 0x29, self.lookup_reg_reg(*register, Rax)?]
+            Jmp { offset, .. } => {
+                let mut vec = vec![0xE9];
+                vec.extend_from_slice(&offset.to_le_bytes());
+                vec
             }
-            MulRegToRax { register } => {
-                vec![0x48, 0xF7, 0xE0 + register.index()]
+            Jcc {
+                condition, offset, ..
+            } => {
+                let mut vec = vec![0x0F, 0x80 + condition.code()];
+                vec.extend_from_slice(&offset.to_le_bytes());
+                vec
             }
-            DivRegFromRax { register } => {
-                vec![0x48, 0xF7, 0xF0 + register.index()]
+            LeaRipRelative {
+                destination,
+                offset,
+                ..
+            } => {
+                // Mod 00 and r/m 101 mean [rip + disp32]
+                let mut vec = vec![
+                    Self::REX_W | (destination.high_bit() << 2),
+                    0x8D,
+                    (destination.low_bits() << 3) | 0b101,
+                ];
+                vec.extend_from_slice(&offset.to_le_bytes());
+                vec
             }
-        })
+            JumpTable { offsets, .. } => offsets
+                .iter()
+                .flat_map(|offset| offset.to_le_bytes())
+                .collect(),
+            MovqToXmm {
+                source,
+                destination,
+            } => Self::encode_sse(0x66, 0x6E, *destination, *source, true),
+            MovqFromXmm {
+                source,
+                destination,
+            } => Self::encode_sse(0x66, 0x7E, *source, *destination, true),
+            MovsdRegToReg {
+                source,
+                destination,
+            } => Self::encode_sse(0xF2, 0x10, *destination, *source, false),
+            LoadFloat {
+                destination,
+                base,
+                offset,
+            } => Self::encode_sse_mem(0x10, *destination, *base, *offset),
+            StoreFloat {
+                source,
+                base,
+                offset,
+            } => Self::encode_sse_mem(0x11, *source, *base, *offset),
+            FloatArithmetic {
+                operation,
+                source,
+                destination,
+            } => Self::encode_sse(0xF2, operation.opcode(), *destination, *source, false),
+            Ucomisd { left, right } => Self::encode_sse(0x66, 0x2E, *left, *right, false),
+            Cvtsi2sd {
+                source,
+                destination,
+            } => Self::encode_sse(0xF2, 0x2A, *destination, *source, true),
+            Cvttsd2si {
+                source,
+                destination,
+            } => Self::encode_sse(0xF2, 0x2C, *destination, *source, true),
+        }
+    }
+
+    /// Encodes an SSE instruction with two register operands: a mandatory prefix, an
+    /// optional REX prefix, and the opcode after `0F`. The REX prefix is only needed
+    /// for 64-bit integer operands (`wide`) or for the registers with index 8-15
+    fn encode_sse(prefix: u8, opcode: u8, reg: Register, rm: Register, wide: bool) -> Vec<u8> {
+        let mut vec = vec![prefix];
+        let rex = if wide { Self::REX_W } else { 0x40 } | (reg.high_bit() << 2) | rm.high_bit();
+        if rex != 0x40 {
+            vec.push(rex);
+        }
+        vec.extend_from_slice(&[0x0F, opcode, 0xC0 | (reg.low_bits() << 3) | rm.low_bits()]);
+        vec
+    }
+
+    /// Encodes `movsd` between an SSE register and a memory operand in the form
+    /// [base + offset], always using a 32-bit displacement
+    fn encode_sse_mem(opcode: u8, reg: Register, base: Register, offset: i32) -> Vec<u8> {
+        let mut vec = vec![0xF2];
+        let rex = 0x40 | (reg.high_bit() << 2) | base.high_bit();
+        if rex != 0x40 {
+            vec.push(rex);
+        }
+        vec.extend_from_slice(&[0x0F, opcode, 0x80 | (reg.low_bits() << 3) | base.low_bits()]);
+        if base.low_bits() == Rsp.low_bits() {
+            vec.push(0x24);
+        }
+        vec.extend_from_slice(&offset.to_le_bytes());
+        vec
+    }
+
+    /// Encodes an instruction that does not need a REX prefix, except when
+    /// using one of the registers r8-r15
+    fn with_optional_rex_b(register: Register, opcode: u8) -> Vec<u8> {
+        if register.high_bit() != 0 {
+            vec![Self::REX_B, opcode + register.low_bits()]
+        } else {
+            vec![opcode + register.low_bits()]
+        }
+    }
+
+    fn rex_w(reg: Register, rm: Register) -> u8 {
+        Self::REX_W | (reg.high_bit() << 2) | rm.high_bit()
+    }
+
+    /// Encodes a 64-bit instruction with two register operands, where `reg`
+    /// goes in the ModR/M reg field and `rm` in the r/m field
+    fn encode_reg_reg(opcode: &[u8], reg: Register, rm: Register) -> Vec<u8> {
+        let mut vec = vec![Self::rex_w(reg, rm)];
+        vec.extend_from_slice(opcode);
+        vec.push(0xC0 | (reg.low_bits() << 3) | rm.low_bits());
+        vec
+    }
+
+    /// Encodes a 64-bit instruction with a register and a memory operand in
+    /// the form [base + offset], always using a 32-bit displacement
+    fn encode_reg_mem(opcode: &[u8], reg: Register, base: Register, offset: i32) -> Vec<u8> {
+        let mut vec = vec![Self::rex_w(reg, base)];
+        vec.extend_from_slice(opcode);
+        vec.push(0x80 | (reg.low_bits() << 3) | base.low_bits());
+        if base.low_bits() == Rsp.low_bits() {
+            // rsp and r12 as base require a SIB byte
+            vec.push(0x24);
+        }
+        vec.extend_from_slice(&offset.to_le_bytes());
+        vec
     }
 
-    // TODO: I am not clear how to encode this in a generalized way, so I have built this hardcoded table
-    fn lookup_reg_reg(&self, source: Register, destination: Register) -> Result<u8, BackendError> {
-        match (source, destination) {
-            (Rax, Rbx) => Ok(0xC3),
-            (Rax, Rcx) => Ok(0xC1),
-            (Rax, Rdx) => Ok(0xC2),
-            (Rbx, Rax) => Ok(0xD8),
-            (Rcx, Rax) => Ok(0xC8),
-            (Rdx, Rax) => Ok(0xD0),
-            (Rsp, Rbp) => Ok(0xE5),
-            (Rbp, Rsp) => Ok(0xEC),
-            (Rax, Rsi) => Ok(0xC6),
-            (Rsi, Rax) => Ok(0xF0),
-            (R11, Rdx) => Ok(0xDA),
-            (Rdx, R11) => Ok(0xD3),
-            _ => Err(BackendError::NotImplemented(format!(
-                "encoding of move from reg {source} to reg {destination}",
-            ))),
+    /// Encodes a 64-bit instruction with a register and a memory operand in the form
+    /// [base + index * 8], which requires a SIB byte
+    fn encode_reg_mem_indexed(
+        opcode: &[u8],
+        reg: Register,
+        base: Register,
+        index: Register,
+    ) -> Vec<u8> {
+        assert!(index != Rsp, "rsp cannot be used as an index");
+        let mut vec = vec![Self::rex_w(reg, base) | (index.high_bit() << 1)];
+        vec.extend_from_slice(opcode);
+        let sib = (0b11 << 6) | (index.low_bits() << 3) | base.low_bits();
+        if base.low_bits() == Rbp.low_bits() {
+            // Without a displacement, rbp and r13 as base mean "no base at all",
+            // so we need to add an explicit zero displacement
+            vec.extend_from_slice(&[0x44 | (reg.low_bits() << 3), sib, 0x00]);
+        } else {
+            vec.extend_from_slice(&[0x04 | (reg.low_bits() << 3), sib]);
         }
+        vec
+    }
+
+    /// Encodes a 64-bit instruction with a single register operand, for which the
+    /// ModR/M reg field contains an extension of the opcode
+    fn encode_extended_opcode(opcode: u8, extension: u8, register: Register) -> Vec<u8> {
+        vec![
+            Self::REX_W | register.high_bit(),
+            opcode,
+            0xC0 | (extension << 3) | register.low_bits(),
+        ]
+    }
+
+    /// Encodes an arithmetic operation between a register and a 32-bit immediate
+    fn encode_imm(extension: u8, register: Register, value: i32) -> Vec<u8> {
+        let mut vec = Self::encode_extended_opcode(0x81, extension, register);
+        vec.extend_from_slice(&value.to_le_bytes());
+        vec
     }
 }
 
+/// Registers used to pass the arguments, according to the System V calling convention
+const ARGUMENT_REGISTERS: [Register; 6] = [Rdi, Rsi, Rdx, Rcx, R8, R9];
+
+/// Registers used to pass the float arguments, which are numbered independently of the
+/// integer ones. The float result is returned in xmm0.
+const FLOAT_ARGUMENT_REGISTERS: [Register; 8] = [Xmm0, Xmm1, Xmm2, Xmm3, Xmm4, Xmm5, Xmm6, Xmm7];
+
 #[derive(Default)]
 pub struct X64LinuxGenerator {
     locations: Vec<AllocatedLocation<Register>>,
+    /// Callee-saved registers that we use, and must restore before returning
+    saved_registers: Vec<Register>,
+    /// The SSE registers that we use. They are all caller-saved, so we store them
+    /// in the stack frame around every call, after the spilled values
+    float_registers: Vec<Register>,
+    num_spilled_values: usize,
+    /// Size of the stack area for spilled values and saved SSE registers, including
+    /// the padding necessary to keep the stack 16-byte aligned
+    spill_area_size: i32,
+    /// Labels of the code, placed after all the blocks, that handles the traps
+    trap_labels: TrapLabels,
+    /// The jump tables used by the function, which are placed after all the code
+    jump_tables: JumpTables,
 }
 
 impl MachineCodeGenerator for X64LinuxGenerator {
     fn generate_machine_code(
         &mut self,
         function: &CompiledFunction,
-        _function_catalog: &CompiledFunctionCatalog,
+        function_catalog: &CompiledFunctionCatalog,
     ) -> Result<GeneratedMachineCode, BackendError> {
+        let function = &eliminate_phis(&expand_intrinsics(function));
         self.allocate_registers(function);
+        self.trap_labels = TrapLabels::new(function.blocks.len());
+        self.jump_tables = JumpTables::new(&self.trap_labels);
 
         let mut instructions = Vec::new();
 
@@ -175,108 +940,539 @@ impl MachineCodeGenerator for X64LinuxGenerator {
             source: Rsp,
             destination: Rbp,
         });
+        for register in self.saved_registers.iter() {
+            instructions.push(Push {
+                register: *register,
+            });
+        }
+        if self.spill_area_size > 0 {
+            instructions.push(SubImmFromReg {
+                register: Rsp,
+                value: self.spill_area_size,
+            });
+        }
 
-        for instruction in function.body.iter() {
-            match instruction {
-                IrInstruction::Mvi { dest, val } => {
-                    let AllocatedLocation::Register { register } = self.locations[dest.0] else {
-                        return Err(BackendError::NotImplemented(
-                            "move immediate to stack".to_string(),
-                        ));
-                    };
-                    instructions.push(MovImmToReg {
-                        register,
-                        value: *val,
-                    })
-                }
+        for (block_index, block) in function.blocks.iter().enumerate() {
+            // The entry block is never the target of a jump
+            if block_index > 0 {
+                instructions.push(Label { block: block.id });
+            }
+            let next_block = function.blocks.get(block_index + 1).map(|b| b.id);
 
-                IrInstruction::Ret { reg } => {
-                    self.move_to_accumulator(reg, &mut instructions)?;
+            for (index, instruction) in block.body.iter().enumerate() {
+                match instruction {
+                    IrInstruction::Mvi { dest, val } => match self.locations[dest.0] {
+                        AllocatedLocation::Register { register } if !register.is_float() => {
+                            instructions.push(MovImmToReg {
+                                register,
+                                value: *val,
+                            })
+                        }
+                        // Floats are moved as their bits
+                        _ => {
+                            instructions.push(MovImmToReg {
+                                register: Rax,
+                                value: *val,
+                            });
+                            self.store(&mut instructions, Rax, dest);
+                        }
+                    },
 
-                    // Epilogue and then return
-                    instructions.push(Pop { register: Rbp });
-                    instructions.push(Retn);
-                }
+                    IrInstruction::MvArg { dest, arg } => {
+                        match Self::get_argument_location(function, *arg) {
+                            AllocatedLocation::Register { register } => {
+                                self.store(&mut instructions, register, dest);
+                            }
+                            AllocatedLocation::Stack { offset } => {
+                                // The arguments are moved in order, so xmm0 has already
+                                // been read when we get to the ones on the stack
+                                let register = Self::scratch_register(function, dest);
+                                Self::load_stack_argument(&mut instructions, offset, register);
+                                self.store(&mut instructions, register, dest);
+                            }
+                        }
+                    }
 
-                IrInstruction::BinOp {
-                    operator,
-                    dest,
-                    op1,
-                    op2,
-                } => {
-                    self.move_to_accumulator(op1, &mut instructions)?;
+                    IrInstruction::Mv { dest, src } => {
+                        let scratch = Self::scratch_register(function, src);
+                        let source = self.operand_register(&mut instructions, src, scratch);
+                        self.store(&mut instructions, source, dest);
+                    }
 
-                    match self.locations[op2.0] {
-                        AllocatedLocation::Stack { .. } => {
-                            return Err(BackendError::NotImplemented(
-                                "binop when operand 2 is on the stack".to_string(),
-                            ))
-                        }
-                        AllocatedLocation::Register { register } => match operator {
-                            Add => instructions.push(AddRegToRax { register }),
-                            Sub => instructions.push(SubRegFromRax { register }),
-                            Mul => instructions.push(MulRegToRax { register }),
-                            Div => {
-                                // DIV is different from most other instructions: it will forcibly
-                                // divide rdx:rax by the given register. For the accumulator we
-                                // are fine, but we need to set rdx to zero, and to do so we backup
-                                // its value. Furthermore, we might have that the divisor is actually
-                                // in rdx. In that case, we move the divisor to r11 (which we know we
-                                // have never allocated) and use `div r11`.
-                                if register == Rdx {
-                                    instructions.push(MovRegToReg {
-                                        source: Rdx,
-                                        destination: R11,
-                                    });
-                                    instructions.push(MovImmToReg {
-                                        register: Rdx,
-                                        value: 0,
-                                    });
-                                    instructions.push(DivRegFromRax { register: R11 });
-                                    instructions.push(MovRegToReg {
-                                        source: R11,
-                                        destination: Rdx,
-                                    });
-                                } else {
-                                    instructions.push(Push { register: Rdx });
-                                    instructions.push(MovImmToReg {
-                                        register: Rdx,
-                                        value: 0,
-                                    });
-                                    instructions.push(DivRegFromRax { register });
-                                    instructions.push(Pop { register: Rdx });
-                                }
-                            }
-                        },
+                    IrInstruction::Phi { .. } => {
+                        unreachable!("phi instructions should have been eliminated")
+                    }
+
+                    IrInstruction::Ret { reg } => {
+                        self.load(&mut instructions, reg, Self::result_register(function, reg));
+                        self.epilogue(&mut instructions);
                     }
 
-                    match self.locations[dest.0] {
-                        AllocatedLocation::Register { register } => {
-                            instructions.push(MovRegToReg {
-                                source: Rax,
-                                destination: register,
+                    IrInstruction::Jmp { target } => {
+                        if Some(*target) != next_block {
+                            instructions.push(Jmp {
+                                target: *target,
+                                offset: 0,
                             });
                         }
-                        AllocatedLocation::Stack { .. } => {
-                            return Err(BackendError::NotImplemented(
-                                "binop when destination is on the stack".to_string(),
-                            ));
-                        }
                     }
-                }
 
-                IrInstruction::MvArg { .. } => {
-                    return Err(BackendError::NotImplemented(
-                        "accessing function arguments".to_string(),
-                    ))
-                }
-                IrInstruction::Call { .. } => {
-                    return Err(BackendError::NotImplemented("function calls".to_string()))
+                    IrInstruction::Br {
+                        cond,
+                        if_true,
+                        if_false,
+                    } => {
+                        let register = self.operand_register(&mut instructions, cond, Rax);
+                        instructions.push(Test { register });
+                        instructions.push(Jcc {
+                            condition: Condition::Equal,
+                            target: *if_false,
+                            offset: 0,
+                        });
+                        if Some(*if_true) != next_block {
+                            instructions.push(Jmp {
+                                target: *if_true,
+                                offset: 0,
+                            });
+                        }
+                    }
+
+                    IrInstruction::Switch {
+                        value,
+                        first,
+                        targets,
+                        default,
+                    } => {
+                        // Values below `first` wrap around to huge unsigned indexes, so a
+                        // single comparison catches both the ones before and after the table
+                        self.load(&mut instructions, value, Rax);
+                        if *first != 0 {
+                            instructions.push(MovImmToReg {
+                                register: R11,
+                                value: *first,
+                            });
+                            instructions.push(SubRegFromReg {
+                                source: R11,
+                                destination: Rax,
+                            });
+                        }
+                        instructions.push(MovImmToReg {
+                            register: R11,
+                            value: targets.len() as i64,
+                        });
+                        instructions.push(CmpRegToReg {
+                            left: Rax,
+                            right: R11,
+                        });
+                        instructions.push(Jcc {
+                            condition: Condition::AboveOrEqual,
+                            target: *default,
+                            offset: 0,
+                        });
+                        let table = self.jump_tables.add(targets);
+                        instructions.push(LeaRipRelative {
+                            destination: R11,
+                            target: table,
+                            offset: 0,
+                        });
+                        instructions.push(LoadIndexed {
+                            destination: Rax,
+                            base: R11,
+                            index: Rax,
+                        });
+                        instructions.push(AddRegToReg {
+                            source: R11,
+                            destination: Rax,
+                        });
+                        instructions.push(JmpReg { register: Rax });
+                    }
+
+                    IrInstruction::BinOp {
+                        operator,
+                        ty: Type::F64,
+                        dest,
+                        op1,
+                        op2,
+                    } => {
+                        self.load(&mut instructions, op1, Xmm0);
+                        let register = self.operand_register(&mut instructions, op2, Xmm1);
+                        let operation = match operator {
+                            Add => Some(FloatOperation::Add),
+                            Sub => Some(FloatOperation::Sub),
+                            Mul => Some(FloatOperation::Mul),
+                            Div => Some(FloatOperation::Div),
+                            _ => None,
+                        };
+                        if let Some(operation) = operation {
+                            instructions.push(FloatArithmetic {
+                                operation,
+                                source: register,
+                                destination: Xmm0,
+                            });
+                            self.store(&mut instructions, Xmm0, dest);
+                        } else {
+                            Self::compare_floats(&mut instructions, *operator, register)?;
+                            self.store(&mut instructions, Rax, dest);
+                        }
+                    }
+
+                    IrInstruction::BinOp {
+                        operator,
+                        ty,
+                        dest,
+                        op1,
+                        op2,
+                    } => {
+                        self.load(&mut instructions, op1, Rax);
+                        let register = self.operand_register(&mut instructions, op2, R11);
+                        let unsigned = ty.is_unsigned();
+                        let divisor_checks = DivisorChecks::new(function, *ty, *op2);
+                        match operator {
+                            Add | Sub | Mul if function.overflow_mode != OverflowMode::Wrapping => {
+                                self.arithmetic_with_overflow(
+                                    &mut instructions,
+                                    *operator,
+                                    *ty,
+                                    function.overflow_mode,
+                                    register,
+                                )
+                            }
+                            Add | Sub | Mul => {
+                                Self::wrapping_arithmetic(&mut instructions, *operator, register)
+                            }
+                            Div | Rem if unsigned => {
+                                self.check_divisor(&mut instructions, register, divisor_checks);
+                                // For unsigned divisions, the upper half of the dividend
+                                // is simply zero
+                                instructions.push(XorRegToReg {
+                                    source: Rdx,
+                                    destination: Rdx,
+                                });
+                                instructions.push(UnsignedDivRegFromRax { register });
+                                if *operator == Rem {
+                                    instructions.push(MovRegToReg {
+                                        source: Rdx,
+                                        destination: Rax,
+                                    });
+                                }
+                            }
+                            Div => {
+                                // IDIV is different from most other instructions: it will
+                                // forcibly divide rdx:rax by the given register. Since rdx
+                                // is never allocated, we can simply sign-extend rax into it.
+                                self.check_divisor(&mut instructions, register, divisor_checks);
+                                instructions.push(Cqo);
+                                instructions.push(DivRegFromRax { register });
+                            }
+                            Rem => {
+                                // Same as the division, but the remainder ends up in rdx
+                                self.check_divisor(&mut instructions, register, divisor_checks);
+                                instructions.push(Cqo);
+                                instructions.push(DivRegFromRax { register });
+                                instructions.push(MovRegToReg {
+                                    source: Rdx,
+                                    destination: Rax,
+                                });
+                            }
+                            BitwiseAnd => instructions.push(AndRegToReg {
+                                source: register,
+                                destination: Rax,
+                            }),
+                            BitwiseOr => instructions.push(OrRegToReg {
+                                source: register,
+                                destination: Rax,
+                            }),
+                            BitwiseXor => instructions.push(XorRegToReg {
+                                source: register,
+                                destination: Rax,
+                            }),
+                            ShiftLeft => Self::shift_logical(
+                                &mut instructions,
+                                register,
+                                ShlCl { register: Rax },
+                            ),
+                            ShiftRight if unsigned => Self::shift_logical(
+                                &mut instructions,
+                                register,
+                                ShrCl { register: Rax },
+                            ),
+                            ShiftRight => Self::shift_right(&mut instructions, register),
+                            Equal => Self::compare(&mut instructions, register, Condition::Equal),
+                            NotEqual => {
+                                Self::compare(&mut instructions, register, Condition::NotEqual)
+                            }
+                            LessThan if unsigned => {
+                                Self::compare(&mut instructions, register, Condition::Below)
+                            }
+                            LessThan => Self::compare(&mut instructions, register, Condition::Less),
+                            LessThanOrEqual if unsigned => {
+                                Self::compare(&mut instructions, register, Condition::BelowOrEqual)
+                            }
+                            LessThanOrEqual => {
+                                Self::compare(&mut instructions, register, Condition::LessOrEqual)
+                            }
+                            GreaterThan if unsigned => {
+                                Self::compare(&mut instructions, register, Condition::Above)
+                            }
+                            GreaterThan => {
+                                Self::compare(&mut instructions, register, Condition::Greater)
+                            }
+                            GreaterThanOrEqual if unsigned => {
+                                Self::compare(&mut instructions, register, Condition::AboveOrEqual)
+                            }
+                            GreaterThanOrEqual => Self::compare(
+                                &mut instructions,
+                                register,
+                                Condition::GreaterOrEqual,
+                            ),
+                            Min | Max => {
+                                // Replace the first operand with the second one if it
+                                // is the wrong side of it
+                                let condition = match (operator, unsigned) {
+                                    (Min, false) => Condition::Greater,
+                                    (Min, true) => Condition::Above,
+                                    (_, false) => Condition::Less,
+                                    (_, true) => Condition::Below,
+                                };
+                                instructions.push(CmpRegToReg {
+                                    left: Rax,
+                                    right: register,
+                                });
+                                instructions.push(Cmov {
+                                    condition,
+                                    source: register,
+                                    destination: Rax,
+                                });
+                            }
+                            Pow | Gcd => unreachable!("expanded before code generation"),
+                        }
+                        self.store(&mut instructions, Rax, dest);
+                    }
+
+                    IrInstruction::Neg { dest, op } => {
+                        self.load(&mut instructions, op, Rax);
+                        instructions.push(Neg { register: Rax });
+                        self.store(&mut instructions, Rax, dest);
+                    }
+
+                    IrInstruction::BitwiseNot { dest, op } => {
+                        self.load(&mut instructions, op, Rax);
+                        instructions.push(Not { register: Rax });
+                        self.store(&mut instructions, Rax, dest);
+                    }
+
+                    IrInstruction::Abs { dest, op } => {
+                        // neg sets the flags as `0 - rax`, so the negation is less than
+                        // zero exactly when the original value was positive
+                        self.load(&mut instructions, op, Rax);
+                        instructions.push(MovRegToReg {
+                            source: Rax,
+                            destination: Rcx,
+                        });
+                        instructions.push(Neg { register: Rax });
+                        instructions.push(Cmov {
+                            condition: Condition::Less,
+                            source: Rcx,
+                            destination: Rax,
+                        });
+                        self.store(&mut instructions, Rax, dest);
+                    }
+
+                    IrInstruction::Sign { dest, op } => {
+                        self.load(&mut instructions, op, Rax);
+                        Self::sign(&mut instructions);
+                        self.store(&mut instructions, Rax, dest);
+                    }
+
+                    IrInstruction::Popcount { dest, op } => {
+                        self.load(&mut instructions, op, Rax);
+                        instructions.push(Popcnt { register: Rax });
+                        self.store(&mut instructions, Rax, dest);
+                    }
+
+                    IrInstruction::Cast { dest, op, ty } => {
+                        self.load(&mut instructions, op, Rax);
+                        Self::normalize(&mut instructions, *ty);
+                        self.store(&mut instructions, Rax, dest);
+                    }
+
+                    IrInstruction::IntToFloat { dest, op } => {
+                        self.load(&mut instructions, op, Rax);
+                        instructions.push(Cvtsi2sd {
+                            source: Rax,
+                            destination: Xmm0,
+                        });
+                        self.store(&mut instructions, Xmm0, dest);
+                    }
+
+                    IrInstruction::FloatToInt { dest, op } => {
+                        self.load(&mut instructions, op, Xmm0);
+                        Self::float_to_int(&mut instructions);
+                        self.store(&mut instructions, Rax, dest);
+                    }
+
+                    IrInstruction::Call {
+                        function_id, args, ..
+                    } if backend::is_tail_call(block, index) => {
+                        self.tail_call(
+                            &mut instructions,
+                            function,
+                            function_catalog,
+                            Callee::Function(*function_id),
+                            args,
+                        )?;
+                        // The callee returns directly to our caller, so we skip the `ret`
+                        break;
+                    }
+
+                    IrInstruction::CallIndirect { handle, args, .. }
+                        if backend::is_tail_call(block, index) =>
+                    {
+                        self.check_function_handle(
+                            &mut instructions,
+                            handle,
+                            args.len(),
+                            function_catalog,
+                        );
+                        self.tail_call(
+                            &mut instructions,
+                            function,
+                            function_catalog,
+                            Callee::Handle(*handle),
+                            args,
+                        )?;
+                        break;
+                    }
+
+                    IrInstruction::Call {
+                        dest,
+                        name: _,
+                        function_id,
+                        args,
+                    } => self.call_through_trampoline(
+                        &mut instructions,
+                        function,
+                        function_catalog,
+                        Callee::Function(*function_id),
+                        args,
+                        dest,
+                    )?,
+
+                    IrInstruction::CallIndirect { dest, handle, args } => {
+                        self.check_function_handle(
+                            &mut instructions,
+                            handle,
+                            args.len(),
+                            function_catalog,
+                        );
+                        self.call_through_trampoline(
+                            &mut instructions,
+                            function,
+                            function_catalog,
+                            Callee::Handle(*handle),
+                            args,
+                            dest,
+                        )?
+                    }
+
+                    IrInstruction::Load { dest, base, index } => {
+                        self.check_bounds(&mut instructions, base, index, function_catalog);
+                        instructions.push(LoadIndexed {
+                            destination: Rax,
+                            base: R11,
+                            index: Rax,
+                        });
+                        self.store(&mut instructions, Rax, dest);
+                    }
+
+                    IrInstruction::Store { base, index, value } => {
+                        self.check_bounds(&mut instructions, base, index, function_catalog);
+                        let source = self.operand_register(&mut instructions, value, Rcx);
+                        instructions.push(StoreIndexed {
+                            source,
+                            base: R11,
+                            index: Rax,
+                        });
+                    }
+
+                    IrInstruction::CallHost {
+                        dest,
+                        name: _,
+                        host_function_id,
+                        args: call_args,
+                    } => {
+                        let (int_args, float_args) = Self::split_arguments(function, call_args);
+                        if int_args.len() > ARGUMENT_REGISTERS.len()
+                            || float_args.len() > FLOAT_ARGUMENT_REGISTERS.len()
+                        {
+                            return Err(BackendError::NotImplemented(
+                                "host functions with more than 6 integer or 8 float arguments"
+                                    .to_string(),
+                            ));
+                        }
+
+                        // Host functions are called directly. Since we only allocate
+                        // callee-saved general purpose registers, only the SSE ones must
+                        // be saved around the call, and the stack is already 16-byte aligned.
+                        self.save_float_registers(&mut instructions);
+                        for (arg, register) in int_args.iter().zip(&ARGUMENT_REGISTERS) {
+                            self.load(&mut instructions, arg, *register);
+                        }
+                        for (arg, register) in float_args.iter().zip(&FLOAT_ARGUMENT_REGISTERS) {
+                            self.load(&mut instructions, arg, *register);
+                        }
+                        instructions.push(MovImmToReg {
+                            register: Rax,
+                            value: function_catalog.get_host_function_address(*host_function_id)
+                                as i64,
+                        });
+                        instructions.push(Call { register: Rax });
+                        self.restore_float_registers(&mut instructions);
+
+                        let result = Self::result_register(function, dest);
+                        self.store(&mut instructions, result, dest);
+                    }
                 }
+            }
+        }
+
+        self.generate_trap_handlers(&mut instructions, function_catalog);
+        let num_labels = self.jump_tables.num_labels();
+        self.generate_jump_tables(&mut instructions);
 
-                IrInstruction::Neg { .. } => {
-                    return Err(BackendError::NotImplemented("negate".to_string()))
+        // Now that the position of every block is known, we can compute the jump offsets
+        let mut block_offsets = vec![0; num_labels];
+        let mut offset = 0;
+        for instruction in instructions.iter() {
+            if let Label { block } = instruction {
+                block_offsets[block.0] = offset;
+            }
+            offset += instruction.make_machine_code().len() as i32;
+        }
+        let mut offset = 0;
+        for instruction in instructions.iter_mut() {
+            offset += instruction.make_machine_code().len() as i32;
+            match instruction {
+                Jmp {
+                    target,
+                    offset: jump_offset,
+                }
+                | Jcc {
+                    target,
+                    offset: jump_offset,
+                    ..
                 }
+                | LeaRipRelative {
+                    target,
+                    offset: jump_offset,
+                    ..
+                } => *jump_offset = block_offsets[target.0] - offset,
+                JumpTable {
+                    label,
+                    targets,
+                    offsets,
+                } => *offsets = JumpTables::offsets(*label, targets, &block_offsets),
+                _ => {}
             }
         }
 
@@ -285,7 +1481,7 @@ impl MachineCodeGenerator for X64LinuxGenerator {
 
         for instruction in instructions {
             let _ = writeln!(&mut asm, "{}", instruction);
-            machine_code.extend(instruction.make_machine_code()?);
+            machine_code.extend(instruction.make_machine_code());
         }
 
         Ok(GeneratedMachineCode { asm, machine_code })
@@ -294,28 +1490,981 @@ impl MachineCodeGenerator for X64LinuxGenerator {
 
 impl X64LinuxGenerator {
     fn allocate_registers(&mut self, function: &CompiledFunction) {
-        let allocations = backend_register_allocator::allocate(function, vec![Rcx, Rdx, Rbx, Rsi]);
-        self.locations.extend(allocations);
+        // We only use callee-saved registers, so that we do not need to save them
+        // around calls. rax, r11 and the argument registers are used as scratch.
+        // There are no callee-saved SSE registers, so for floats we use the ones that
+        // are not used for the arguments, and xmm0 and xmm1 as scratch.
+        self.locations = backend_register_allocator::allocate(
+            function,
+            vec![Rbx, R12, R13, R14, R15],
+            vec![Xmm8, Xmm9, Xmm10, Xmm11, Xmm12, Xmm13, Xmm14, Xmm15],
+        );
+
+        self.saved_registers.clear();
+        self.float_registers.clear();
+        self.num_spilled_values = 0;
+        for location in self.locations.iter() {
+            match location {
+                AllocatedLocation::Register { register } if register.is_float() => {
+                    if !self.float_registers.contains(register) {
+                        self.float_registers.push(*register);
+                    }
+                }
+                AllocatedLocation::Register { register } => {
+                    if !self.saved_registers.contains(register) {
+                        self.saved_registers.push(*register);
+                    }
+                }
+                AllocatedLocation::Stack { offset } => {
+                    self.num_spilled_values = self.num_spilled_values.max(offset / NUM_SIZE + 1);
+                }
+            }
+        }
+
+        // At the entry, the stack is 16-byte aligned, plus the return address. We then
+        // push rbp and the saved registers, and we need to end up aligned again.
+        let pushed_size = (1 + 1 + self.saved_registers.len()) * NUM_SIZE;
+        let spill_size = (self.num_spilled_values + self.float_registers.len()) * NUM_SIZE;
+        let padding = (16 - (pushed_size + spill_size) % 16) % 16;
+        self.spill_area_size = (spill_size + padding) as i32;
+    }
+
+    /// Restores the stack and the callee-saved registers, and returns to the caller
+    fn epilogue(&self, instructions: &mut Vec<X64Instruction>) {
+        self.leave_frame(instructions);
+        instructions.push(Retn);
+    }
+
+    /// Restores the stack and the callee-saved registers, leaving the return address
+    /// on the top of the stack
+    fn leave_frame(&self, instructions: &mut Vec<X64Instruction>) {
+        if self.spill_area_size > 0 {
+            instructions.push(AddImmToReg {
+                register: Rsp,
+                value: self.spill_area_size,
+            });
+        }
+        for register in self.saved_registers.iter().rev() {
+            instructions.push(Pop {
+                register: *register,
+            });
+        }
+        instructions.push(Pop { register: Rbp });
     }
 
-    fn move_to_accumulator(
+    /// Calls one of our functions whose result we return right away, by jumping to it
+    /// after having torn down our frame. The arguments are passed in the same registers
+    /// used by a normal call, but we do not go through `jit_call_trampoline`
+    fn tail_call(
         &mut self,
-        reg: &IrRegister,
         instructions: &mut Vec<X64Instruction>,
+        function: &CompiledFunction,
+        function_catalog: &CompiledFunctionCatalog,
+        callee: Callee,
+        call_args: &[IrRegister],
     ) -> Result<(), BackendError> {
-        match self.locations[reg.0] {
-            AllocatedLocation::Register { register } => {
+        let (int_args, float_args) = Self::split_arguments(function, call_args);
+        if int_args.len() > ARGUMENT_REGISTERS.len()
+            || float_args.len() > FLOAT_ARGUMENT_REGISTERS.len()
+        {
+            return Err(BackendError::NotImplemented(
+                "functions with more than 6 integer or 8 float arguments".to_string(),
+            ));
+        }
+
+        // The argument registers are never allocated, so loading them cannot overwrite
+        // the values of the other arguments
+        for (arg, register) in int_args.iter().zip(&ARGUMENT_REGISTERS) {
+            self.load(instructions, arg, *register);
+        }
+        for (arg, register) in float_args.iter().zip(&FLOAT_ARGUMENT_REGISTERS) {
+            self.load(instructions, arg, *register);
+        }
+
+        // The callee might not have been compiled yet, so we read its address from the
+        // catalog when doing the call
+        match callee {
+            Callee::Function(id) => {
+                instructions.push(MovImmToReg {
+                    register: Rax,
+                    value: function_catalog.function_pointer_address(id) as i64,
+                });
+                instructions.push(Load {
+                    destination: Rax,
+                    base: Rax,
+                    offset: 0,
+                });
+            }
+            Callee::Handle(handle) => {
+                self.load(instructions, &handle, Rax);
+                instructions.push(MovImmToReg {
+                    register: R11,
+                    value: function_catalog.function_pointers_address() as i64,
+                });
+                instructions.push(LoadIndexed {
+                    destination: Rax,
+                    base: R11,
+                    index: Rax,
+                });
+            }
+        }
+        self.leave_frame(instructions);
+        instructions.push(JmpReg { register: Rax });
+        Ok(())
+    }
+
+    /// Calls one of our functions through `jit_call_trampoline`, stopping if it traps
+    fn call_through_trampoline(
+        &mut self,
+        instructions: &mut Vec<X64Instruction>,
+        function: &CompiledFunction,
+        function_catalog: &CompiledFunctionCatalog,
+        callee: Callee,
+        call_args: &[IrRegister],
+        dest: &IrRegister,
+    ) -> Result<(), BackendError> {
+        let (int_args, float_args) = Self::split_arguments(function, call_args);
+        if int_args.len() > 6 || float_args.len() > FLOAT_ARGUMENT_REGISTERS.len() {
+            return Err(BackendError::NotImplemented(
+                "functions with more than 6 integer or 8 float arguments".to_string(),
+            ));
+        }
+        self.save_float_registers(instructions);
+
+        // We call jit_call_trampoline(function_catalog_ptr, called_function_index, args).
+        // Its last two integer arguments are passed on the stack, so we always push two
+        // values - which also keeps the stack 16-byte aligned. The values of
+        // the arguments that were not passed do not matter.
+        for stack_arg in (4..6).rev() {
+            if let Some(arg) = int_args.get(stack_arg) {
+                self.load(instructions, arg, Rax);
+            }
+            instructions.push(Push { register: Rax });
+        }
+        for (arg, register) in int_args.iter().zip(&ARGUMENT_REGISTERS[2..]) {
+            self.load(instructions, arg, *register);
+        }
+        for (arg, register) in float_args.iter().zip(&FLOAT_ARGUMENT_REGISTERS) {
+            self.load(instructions, arg, *register);
+        }
+
+        let fn_catalog_addr: usize = function_catalog as *const CompiledFunctionCatalog as usize;
+        let jit_call_trampoline_address: usize = match function.register_class(*dest) {
+            RegisterClass::Integer => jit_call_trampoline as *const () as usize,
+            RegisterClass::Float => jit_call_trampoline_float as *const () as usize,
+        };
+        instructions.push(MovImmToReg {
+            register: Rdi,
+            value: fn_catalog_addr as i64,
+        });
+        match callee {
+            Callee::Function(id) => instructions.push(MovImmToReg {
+                register: Rsi,
+                value: id.0 as i64,
+            }),
+            Callee::Handle(handle) => self.load(instructions, &handle, Rsi),
+        }
+        instructions.push(MovImmToReg {
+            register: Rax,
+            value: jit_call_trampoline_address as i64,
+        });
+        instructions.push(Call { register: Rax });
+        instructions.push(AddImmToReg {
+            register: Rsp,
+            value: 2 * NUM_SIZE as i32,
+        });
+        self.restore_float_registers(instructions);
+
+        // If the callee trapped, we must stop too
+        instructions.push(MovImmToReg {
+            register: R11,
+            value: function_catalog.runtime_context_address() as i64,
+        });
+        instructions.push(Load {
+            destination: R11,
+            base: R11,
+            offset: RuntimeContext::TRAP_OFFSET,
+        });
+        instructions.push(Test { register: R11 });
+        instructions.push(Jcc {
+            condition: Condition::NotEqual,
+            target: self.trap_labels.exit,
+            offset: 0,
+        });
+        self.trap_labels.exit_used = true;
+
+        let result = Self::result_register(function, dest);
+        self.store(instructions, result, dest);
+        Ok(())
+    }
+
+    /// Traps if the handle of an indirect call does not refer to a function, or if the
+    /// function does not take `num_args` arguments
+    fn check_function_handle(
+        &mut self,
+        instructions: &mut Vec<X64Instruction>,
+        handle: &IrRegister,
+        num_args: usize,
+        function_catalog: &CompiledFunctionCatalog,
+    ) {
+        // Negative handles are very large unsigned values, so one comparison is enough
+        self.load(instructions, handle, Rax);
+        instructions.push(MovImmToReg {
+            register: R11,
+            value: function_catalog.num_functions() as i64,
+        });
+        instructions.push(CmpRegToReg {
+            left: Rax,
+            right: R11,
+        });
+        self.trap_if(
+            instructions,
+            Condition::AboveOrEqual,
+            TrapCode::InvalidFunctionHandle,
+        );
+        instructions.push(MovImmToReg {
+            register: R11,
+            value: function_catalog.arities_address() as i64,
+        });
+        instructions.push(LoadIndexed {
+            destination: R11,
+            base: R11,
+            index: Rax,
+        });
+        instructions.push(MovImmToReg {
+            register: Rcx,
+            value: NOT_CALLABLE_INDIRECTLY,
+        });
+        instructions.push(CmpRegToReg {
+            left: R11,
+            right: Rcx,
+        });
+        self.trap_if(
+            instructions,
+            Condition::Equal,
+            TrapCode::InvalidFunctionHandle,
+        );
+        instructions.push(MovImmToReg {
+            register: Rcx,
+            value: num_args as i64,
+        });
+        instructions.push(CmpRegToReg {
+            left: R11,
+            right: Rcx,
+        });
+        self.trap_if(instructions, Condition::NotEqual, TrapCode::ArityMismatch);
+    }
+
+    /// Computes the index of the accessed element `base + index` in rax, and jumps to
+    /// the out-of-bounds handler if it is not smaller than the length of the memory.
+    /// Treating the index as unsigned means negative values are rejected as well.
+    /// Afterwards, r11 contains the address of the memory.
+    fn check_bounds(
+        &mut self,
+        instructions: &mut Vec<X64Instruction>,
+        base: &IrRegister,
+        index: &IrRegister,
+        function_catalog: &CompiledFunctionCatalog,
+    ) {
+        self.load(instructions, base, Rax);
+        let index = self.operand_register(instructions, index, R11);
+        instructions.push(AddRegToReg {
+            source: index,
+            destination: Rax,
+        });
+        instructions.push(MovImmToReg {
+            register: R11,
+            value: function_catalog.runtime_context_address() as i64,
+        });
+        instructions.push(Load {
+            destination: Rcx,
+            base: R11,
+            offset: RuntimeContext::MEMORY_LENGTH_OFFSET,
+        });
+        instructions.push(CmpRegToReg {
+            left: Rax,
+            right: Rcx,
+        });
+        instructions.push(Jcc {
+            condition: Condition::AboveOrEqual,
+            target: self.trap_labels.out_of_bounds,
+            offset: 0,
+        });
+        instructions.push(Load {
+            destination: R11,
+            base: R11,
+            offset: RuntimeContext::MEMORY_OFFSET,
+        });
+        self.trap_labels.out_of_bounds_used = true;
+    }
+
+    /// Computes `rax (operator) register` for `+`, `-` and `*`, wrapping around on overflow
+    fn wrapping_arithmetic(
+        instructions: &mut Vec<X64Instruction>,
+        operator: BinOpOperator,
+        register: Register,
+    ) {
+        instructions.push(match operator {
+            Add => AddRegToReg {
+                source: register,
+                destination: Rax,
+            },
+            Sub => SubRegFromReg {
+                source: register,
+                destination: Rax,
+            },
+            _ => MulRegToReg {
+                source: register,
+                destination: Rax,
+            },
+        });
+    }
+
+    /// Computes `rax (operator) register` for `+`, `-` and `*` in checked or saturating
+    /// mode, leaving in rax a result already normalized to `ty`. Clobbers rcx, rdx and,
+    /// once the operation is done, r11.
+    fn arithmetic_with_overflow(
+        &mut self,
+        instructions: &mut Vec<X64Instruction>,
+        operator: BinOpOperator,
+        ty: Type,
+        mode: OverflowMode,
+        register: Register,
+    ) {
+        let saturating = mode == OverflowMode::Saturating;
+        let (min, max) = ty.bounds();
+
+        if ty.is_narrow() {
+            // The operands are normalized, so the 64-bit result is exact, and it does not
+            // fit in the type if normalizing it changes it
+            Self::wrapping_arithmetic(instructions, operator, register);
+            if saturating {
+                // For signed types, the sign of the exact result tells which bound we
+                // went past. For unsigned ones, only a subtraction can go below zero.
+                if ty.is_unsigned() {
+                    instructions.push(MovImmToReg {
+                        register: R11,
+                        value: if operator == Sub { min } else { max } as i64,
+                    });
+                } else {
+                    instructions.push(Cqo);
+                    instructions.push(MovImmToReg {
+                        register: R11,
+                        value: max as i64,
+                    });
+                    instructions.push(XorRegToReg {
+                        source: Rdx,
+                        destination: R11,
+                    });
+                }
+            }
+            instructions.push(MovRegToReg {
+                source: Rax,
+                destination: Rcx,
+            });
+            Self::normalize(instructions, ty);
+            instructions.push(CmpRegToReg {
+                left: Rax,
+                right: Rcx,
+            });
+            self.handle_overflow(instructions, mode, Condition::NotEqual, R11);
+            return;
+        }
+
+        if ty.is_unsigned() {
+            // The carry flag tells whether the result went past either bound
+            if operator == Mul {
+                instructions.push(UnsignedMulRegToRax { register });
+            } else {
+                Self::wrapping_arithmetic(instructions, operator, register);
+            }
+            if saturating {
+                // A mov does not change the flags
+                instructions.push(MovImmToReg {
+                    register: Rcx,
+                    value: if operator == Sub { min } else { max } as i64,
+                });
+            }
+            self.handle_overflow(instructions, mode, Condition::Below, Rcx);
+            return;
+        }
+
+        if saturating {
+            // The result saturates to i64::MAX or i64::MIN depending on the sign of the
+            // first operand, or of the product of the signs for a multiplication. We
+            // compute it before the operation, since it destroys the operands.
+            if operator == Mul {
                 instructions.push(MovRegToReg {
+                    source: Rax,
+                    destination: Rcx,
+                });
+                instructions.push(XorRegToReg {
                     source: register,
                     destination: Rax,
                 });
-                Ok(())
+                instructions.push(Cqo);
+                instructions.push(MovRegToReg {
+                    source: Rcx,
+                    destination: Rax,
+                });
+            } else {
+                instructions.push(Cqo);
+            }
+            instructions.push(MovImmToReg {
+                register: Rcx,
+                value: i64::MAX,
+            });
+            instructions.push(XorRegToReg {
+                source: Rdx,
+                destination: Rcx,
+            });
+        }
+        Self::wrapping_arithmetic(instructions, operator, register);
+        self.handle_overflow(instructions, mode, Condition::Overflow, Rcx);
+    }
+
+    /// After an operation that sets the flags, jumps to the overflow trap if the
+    /// condition holds, or replaces rax with the saturated value in `saturated`
+    fn handle_overflow(
+        &mut self,
+        instructions: &mut Vec<X64Instruction>,
+        mode: OverflowMode,
+        condition: Condition,
+        saturated: Register,
+    ) {
+        if mode == OverflowMode::Saturating {
+            instructions.push(Cmov {
+                condition,
+                source: saturated,
+                destination: Rax,
+            });
+        } else {
+            self.trap_if(instructions, condition, TrapCode::Overflow);
+        }
+    }
+
+    /// Jumps to the code that records the given trap if the condition holds
+    fn trap_if(
+        &mut self,
+        instructions: &mut Vec<X64Instruction>,
+        condition: Condition,
+        code: TrapCode,
+    ) {
+        instructions.push(Jcc {
+            condition,
+            target: self.trap_labels.label(code),
+            offset: 0,
+        });
+        self.trap_labels.use_trap(code);
+    }
+
+    /// Traps if the divisor in `register` is zero, or if we are computing `MIN / -1`,
+    /// for which `idiv` would raise an exception with i64. The dividend is in rax
+    fn check_divisor(
+        &mut self,
+        instructions: &mut Vec<X64Instruction>,
+        register: Register,
+        checks: DivisorChecks,
+    ) {
+        if checks.zero {
+            instructions.push(Test { register });
+            self.trap_if(instructions, Condition::Equal, TrapCode::DivisionByZero);
+        }
+        if let Some(min) = checks.overflow {
+            // (divisor + 1) | (dividend ^ MIN) is zero only if both are zero
+            instructions.push(MovRegToReg {
+                source: register,
+                destination: Rcx,
+            });
+            instructions.push(AddImmToReg {
+                register: Rcx,
+                value: 1,
+            });
+            instructions.push(MovImmToReg {
+                register: Rdx,
+                value: min,
+            });
+            instructions.push(XorRegToReg {
+                source: Rax,
+                destination: Rdx,
+            });
+            instructions.push(OrRegToReg {
+                source: Rcx,
+                destination: Rdx,
+            });
+            self.trap_if(instructions, Condition::Equal, TrapCode::DivisionOverflow);
+        }
+    }
+
+    /// Truncates rax to the given type, and then sign- or zero-extends it back to 64 bits
+    fn normalize(instructions: &mut Vec<X64Instruction>, ty: Type) {
+        match ty {
+            Type::I64 | Type::U64 | Type::F64 => {}
+            Type::I32 => instructions.push(MovsxdEaxToRax),
+            Type::U32 => instructions.push(MovEaxToEax),
+            Type::U8 => instructions.push(MovzxAlToRax),
+            Type::Bool => {
+                instructions.push(Test { register: Rax });
+                instructions.push(SetCcAl {
+                    condition: Condition::NotEqual,
+                });
+                instructions.push(MovzxAlToRax);
+            }
+        }
+    }
+
+    /// Generates the code that the checks jump to, after all the blocks of the function.
+    /// The out-of-bounds handler expects the index in rax, and the address of the
+    /// runtime context in r11
+    fn generate_trap_handlers(
+        &mut self,
+        instructions: &mut Vec<X64Instruction>,
+        function_catalog: &CompiledFunctionCatalog,
+    ) {
+        for (code, jump_to_exit) in self.trap_labels.take_handlers() {
+            instructions.push(Label {
+                block: self.trap_labels.label(code),
+            });
+            instructions.push(MovImmToReg {
+                register: R11,
+                value: function_catalog.runtime_context_address() as i64,
+            });
+            instructions.push(MovImmToReg {
+                register: Rax,
+                value: code as i64,
+            });
+            instructions.push(Store {
+                source: Rax,
+                base: R11,
+                offset: RuntimeContext::TRAP_OFFSET,
+            });
+            if jump_to_exit {
+                instructions.push(Jmp {
+                    target: self.trap_labels.exit,
+                    offset: 0,
+                });
             }
-            AllocatedLocation::Stack { .. } => Err(BackendError::NotImplemented(
-                "move to accumulator from stack".to_string(),
-            )),
+        }
+        if self.trap_labels.out_of_bounds_used {
+            instructions.push(Label {
+                block: self.trap_labels.out_of_bounds,
+            });
+            instructions.push(Store {
+                source: Rax,
+                base: R11,
+                offset: RuntimeContext::TRAP_VALUE_OFFSET,
+            });
+            instructions.push(MovImmToReg {
+                register: Rax,
+                value: TrapCode::OutOfBounds as i64,
+            });
+            instructions.push(Store {
+                source: Rax,
+                base: R11,
+                offset: RuntimeContext::TRAP_OFFSET,
+            });
+        }
+        if self.trap_labels.exit_used {
+            instructions.push(Label {
+                block: self.trap_labels.exit,
+            });
+            self.epilogue(instructions);
+        }
+    }
+
+    /// Places the jump tables after the code. Their entries are filled once the
+    /// offsets of the blocks are known
+    fn generate_jump_tables(&mut self, instructions: &mut Vec<X64Instruction>) {
+        for (label, targets) in self.jump_tables.take() {
+            instructions.push(Label { block: label });
+            instructions.push(JumpTable {
+                label,
+                offsets: vec![0; targets.len()],
+                targets,
+            });
         }
     }
+
+    /// Offset, relative to rbp, of a value spilled to the stack
+    fn stack_offset(&self, offset: usize) -> i32 {
+        -(((self.saved_registers.len() + 1) * NUM_SIZE + offset) as i32)
+    }
+
+    /// Copies a hardware register into another one, of either kind
+    fn copy(source: Register, destination: Register) -> X64Instruction {
+        match (source.is_float(), destination.is_float()) {
+            (false, false) => MovRegToReg {
+                source,
+                destination,
+            },
+            (true, true) => MovsdRegToReg {
+                source,
+                destination,
+            },
+            (false, true) => MovqToXmm {
+                source,
+                destination,
+            },
+            (true, false) => MovqFromXmm {
+                source,
+                destination,
+            },
+        }
+    }
+
+    /// Copies the value of the given ir register into the hardware register `destination`
+    fn load(
+        &self,
+        instructions: &mut Vec<X64Instruction>,
+        reg: &IrRegister,
+        destination: Register,
+    ) {
+        match self.locations[reg.0] {
+            AllocatedLocation::Register { register } => {
+                if register != destination {
+                    instructions.push(Self::copy(register, destination));
+                }
+            }
+            AllocatedLocation::Stack { offset } => {
+                let offset = self.stack_offset(offset);
+                instructions.push(if destination.is_float() {
+                    LoadFloat {
+                        destination,
+                        base: Rbp,
+                        offset,
+                    }
+                } else {
+                    Load {
+                        destination,
+                        base: Rbp,
+                        offset,
+                    }
+                })
+            }
+        }
+    }
+
+    /// Copies the hardware register `source` into the location of the given ir register
+    fn store(&self, instructions: &mut Vec<X64Instruction>, source: Register, reg: &IrRegister) {
+        match self.locations[reg.0] {
+            AllocatedLocation::Register { register } => {
+                if register != source {
+                    instructions.push(Self::copy(source, register));
+                }
+            }
+            AllocatedLocation::Stack { offset } => {
+                let offset = self.stack_offset(offset);
+                instructions.push(if source.is_float() {
+                    StoreFloat {
+                        source,
+                        base: Rbp,
+                        offset,
+                    }
+                } else {
+                    Store {
+                        source,
+                        base: Rbp,
+                        offset,
+                    }
+                })
+            }
+        }
+    }
+
+    /// Stores the SSE registers that we use in the frame, before a call can clobber them
+    fn save_float_registers(&self, instructions: &mut Vec<X64Instruction>) {
+        for (index, register) in self.float_registers.iter().enumerate() {
+            instructions.push(StoreFloat {
+                source: *register,
+                base: Rbp,
+                offset: self.stack_offset((self.num_spilled_values + index) * NUM_SIZE),
+            });
+        }
+    }
+
+    /// Reloads the SSE registers stored by `save_float_registers`
+    fn restore_float_registers(&self, instructions: &mut Vec<X64Instruction>) {
+        for (index, register) in self.float_registers.iter().enumerate() {
+            instructions.push(LoadFloat {
+                destination: *register,
+                base: Rbp,
+                offset: self.stack_offset((self.num_spilled_values + index) * NUM_SIZE),
+            });
+        }
+    }
+
+    /// The register used to load the given ir register when it has been spilled
+    fn scratch_register(function: &CompiledFunction, reg: &IrRegister) -> Register {
+        match function.register_class(*reg) {
+            RegisterClass::Integer => Rax,
+            RegisterClass::Float => Xmm0,
+        }
+    }
+
+    /// The register where functions return the value of the class of `reg`. It is
+    /// also the scratch register of that class
+    fn result_register(function: &CompiledFunction, reg: &IrRegister) -> Register {
+        Self::scratch_register(function, reg)
+    }
+
+    /// Splits the arguments of a call into the integer and the float ones, which are
+    /// passed in different registers
+    fn split_arguments(
+        function: &CompiledFunction,
+        args: &[IrRegister],
+    ) -> (Vec<IrRegister>, Vec<IrRegister>) {
+        args.iter()
+            .partition(|arg| function.register_class(**arg) == RegisterClass::Integer)
+    }
+
+    /// Returns the hardware register containing the given ir register. If it has been
+    /// spilled to the stack, it will be loaded in the `scratch` register
+    fn operand_register(
+        &self,
+        instructions: &mut Vec<X64Instruction>,
+        reg: &IrRegister,
+        scratch: Register,
+    ) -> Register {
+        match self.locations[reg.0] {
+            AllocatedLocation::Register { register } => register,
+            AllocatedLocation::Stack { .. } => {
+                self.load(instructions, reg, scratch);
+                scratch
+            }
+        }
+    }
+
+    /// Compares rax with the given register, and sets rax to 1 if the condition
+    /// holds or to 0 otherwise
+    fn compare(instructions: &mut Vec<X64Instruction>, register: Register, condition: Condition) {
+        instructions.push(CmpRegToReg {
+            left: Rax,
+            right: register,
+        });
+        instructions.push(SetCcAl { condition });
+        instructions.push(MovzxAlToRax);
+    }
+
+    /// Replaces rax with -1, 0 or 1 depending on its sign. The moves of immediates do
+    /// not touch the flags, so we can test the value just once
+    fn sign(instructions: &mut Vec<X64Instruction>) {
+        instructions.push(Test { register: Rax });
+        instructions.push(MovImmToReg {
+            register: Rax,
+            value: 1,
+        });
+        instructions.push(MovImmToReg {
+            register: Rcx,
+            value: -1,
+        });
+        instructions.push(Cmov {
+            condition: Condition::Less,
+            source: Rcx,
+            destination: Rax,
+        });
+        instructions.push(MovImmToReg {
+            register: Rcx,
+            value: 0,
+        });
+        instructions.push(Cmov {
+            condition: Condition::Equal,
+            source: Rcx,
+            destination: Rax,
+        });
+    }
+
+    /// Shifts rax left, or right filling it with zeros, by the given register. Since the
+    /// hardware only considers the lowest 6 bits of the amount, we need to explicitly
+    /// clear the result when the amount is not in the range 0..64 (negative amounts look
+    /// huge when unsigned). The shift amount must be in cl: rcx is never allocated, and
+    /// we only read the arguments from it at the very beginning of the function.
+    fn shift_logical(
+        instructions: &mut Vec<X64Instruction>,
+        register: Register,
+        shift: X64Instruction,
+    ) {
+        instructions.push(MovRegToReg {
+            source: register,
+            destination: Rcx,
+        });
+        instructions.push(shift);
+        instructions.push(MovImmToReg {
+            register: R11,
+            value: 64,
+        });
+        instructions.push(CmpRegToReg {
+            left: Rcx,
+            right: R11,
+        });
+        instructions.push(MovImmToReg {
+            register: R11,
+            value: 0,
+        });
+        instructions.push(Cmov {
+            condition: Condition::AboveOrEqual,
+            source: R11,
+            destination: Rax,
+        });
+    }
+
+    /// Arithmetic shift of rax right by the given register. Shifting by 63 already
+    /// fills the register with the sign bit, so we clamp the amount to 63.
+    fn shift_right(instructions: &mut Vec<X64Instruction>, register: Register) {
+        instructions.push(MovRegToReg {
+            source: register,
+            destination: Rcx,
+        });
+        instructions.push(MovImmToReg {
+            register: R11,
+            value: 63,
+        });
+        instructions.push(CmpRegToReg {
+            left: Rcx,
+            right: R11,
+        });
+        instructions.push(Cmov {
+            condition: Condition::Above,
+            source: R11,
+            destination: Rcx,
+        });
+        instructions.push(SarCl { register: Rax });
+    }
+
+    /// Compares the float in xmm0 with the given register, and sets rax to 1 if the
+    /// comparison holds or to 0 otherwise. All comparisons with NaN are false, except
+    /// for `!=`. Since `ucomisd` sets the flags like an unsigned comparison, and the
+    /// "unordered" result looks like "below", we only test for "above", swapping the
+    /// operands of `<` and `<=`.
+    fn compare_floats(
+        instructions: &mut Vec<X64Instruction>,
+        operator: BinOpOperator,
+        register: Register,
+    ) -> Result<(), BackendError> {
+        let (left, right, condition) = match operator {
+            Equal => (Xmm0, register, Condition::Equal),
+            NotEqual => (Xmm0, register, Condition::NotEqual),
+            LessThan => (register, Xmm0, Condition::Above),
+            LessThanOrEqual => (register, Xmm0, Condition::AboveOrEqual),
+            GreaterThan => (Xmm0, register, Condition::Above),
+            GreaterThanOrEqual => (Xmm0, register, Condition::AboveOrEqual),
+            _ => {
+                return Err(BackendError::NotImplemented(format!(
+                    "operator {} for floats",
+                    operator
+                )))
+            }
+        };
+        instructions.push(Ucomisd { left, right });
+        instructions.push(SetCcAl { condition });
+        instructions.push(MovzxAlToRax);
+        // The zero flag is also set for unordered values, which we detect via the
+        // parity flag
+        if let Equal | NotEqual = operator {
+            instructions.push(MovImmToReg {
+                register: R11,
+                value: (operator == NotEqual) as i64,
+            });
+            instructions.push(Cmov {
+                condition: Condition::Parity,
+                source: R11,
+                destination: Rax,
+            });
+        }
+        Ok(())
+    }
+
+    /// Converts the float in xmm0 to an integer in rax. `cvttsd2si` returns `i64::MIN`
+    /// for all the values it cannot convert, so we fix the result for the values that
+    /// are too big and for NaN, to match the `as` of Rust.
+    fn float_to_int(instructions: &mut Vec<X64Instruction>) {
+        instructions.push(Cvttsd2si {
+            source: Xmm0,
+            destination: Rax,
+        });
+        instructions.push(MovImmToReg {
+            register: R11,
+            value: (i64::MAX as f64).to_bits() as i64,
+        });
+        instructions.push(MovqToXmm {
+            source: R11,
+            destination: Xmm1,
+        });
+        instructions.push(Ucomisd {
+            left: Xmm0,
+            right: Xmm1,
+        });
+        instructions.push(MovImmToReg {
+            register: R11,
+            value: i64::MAX,
+        });
+        instructions.push(Cmov {
+            condition: Condition::AboveOrEqual,
+            source: R11,
+            destination: Rax,
+        });
+        instructions.push(Ucomisd {
+            left: Xmm0,
+            right: Xmm0,
+        });
+        instructions.push(MovImmToReg {
+            register: R11,
+            value: 0,
+        });
+        instructions.push(Cmov {
+            condition: Condition::Parity,
+            source: R11,
+            destination: Rax,
+        });
+    }
+
+    /// Where the given argument is passed: the register chosen by its class and by its
+    /// position among the arguments of the same class or, if there are not enough of
+    /// them, its position among the ones that the caller pushes on the stack
+    fn get_argument_location(
+        function: &CompiledFunction,
+        arg: ArgumentIndex,
+    ) -> AllocatedLocation<Register> {
+        let position = function.position_in_class(arg);
+        let registers: &[Register] = match function.arg_classes[usize::from(arg)] {
+            RegisterClass::Integer => &ARGUMENT_REGISTERS,
+            RegisterClass::Float => &FLOAT_ARGUMENT_REGISTERS,
+        };
+        match registers.get(position) {
+            Some(register) => AllocatedLocation::Register {
+                register: *register,
+            },
+            None => AllocatedLocation::Stack {
+                offset: function.position_on_stack(
+                    arg,
+                    ARGUMENT_REGISTERS.len(),
+                    FLOAT_ARGUMENT_REGISTERS.len(),
+                ),
+            },
+        }
+    }
+
+    /// Loads the n-th argument passed on the stack, which our caller pushed right
+    /// before the return address and the rbp that we saved
+    fn load_stack_argument(
+        instructions: &mut Vec<X64Instruction>,
+        position: usize,
+        destination: Register,
+    ) {
+        let offset = ((2 + position) * NUM_SIZE) as i32;
+        instructions.push(if destination.is_float() {
+            LoadFloat {
+                destination,
+                base: Rbp,
+                offset,
+            }
+        } else {
+            Load {
+                destination,
+                base: Rbp,
+                offset,
+            }
+        });
+    }
 }
 
 #[cfg(test)]
@@ -325,67 +2474,118 @@ mod test {
     use super::*;
     use crate::{backend::CompiledFunctionCatalog, frontend, parser::*};
 
-    #[test]
-    fn can_compile_trivial_function() {
-        let program = parse_program("fn the_answer() { return 1; }").unwrap();
-        let compiled = frontend::compile(program).unwrap();
+    fn compile_single_function(source: &str) -> GeneratedMachineCode {
+        let program = parse_program(source).unwrap();
+        let compiled = frontend::compile(program, &frontend::FrontendOptions::default())
+            .unwrap()
+            .functions;
         assert_eq!(compiled.len(), 1);
 
         let mut gen = X64LinuxGenerator::default();
-        let machine_code = gen
-            .generate_machine_code(
-                &compiled[0],
-                &Box::new(CompiledFunctionCatalog::new(&compiled)),
-            )
-            .unwrap();
+        gen.generate_machine_code(
+            &compiled[0],
+            &Box::new(CompiledFunctionCatalog::new(&compiled, vec![])),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn can_compile_trivial_function() {
+        let machine_code = compile_single_function("fn the_answer() { return 1; }");
         assert_eq!(
             machine_code.machine_code,
             vec![
-                0x55, 0x48, 0x89, 0xE5, 0x48, 0xB9, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-                0x48, 0x89, 0xC8, 0x5D, 0xC3
+                0x55, 0x48, 0x89, 0xE5, 0x53, 0x48, 0x81, 0xEC, 0x08, 0x00, 0x00, 0x00, 0x48, 0xBB,
+                0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x48, 0x89, 0xD8, 0x48, 0x81, 0xC4,
+                0x08, 0x00, 0x00, 0x00, 0x5B, 0x5D, 0xC3
             ]
         )
     }
 
     #[test]
     fn can_compile_math() {
-        let program =
-            parse_program("fn the_answer() { let a = 3; return a + 1 - 2 * 3 / 4; }").unwrap();
-        let compiled = frontend::compile(program).unwrap();
-        assert_eq!(compiled.len(), 1);
-
-        let mut gen = X64LinuxGenerator::default();
-        let machine_code = gen
-            .generate_machine_code(
-                &compiled[0],
-                &Box::new(CompiledFunctionCatalog::new(&compiled)),
-            )
-            .unwrap();
+        let machine_code =
+            compile_single_function("fn the_answer() { let a = 3; return a + 1 - 2 * 3 / 4; }");
         assert_eq!(
             "
             |push rbp
             |mov  rbp, rsp
-            |mov  rcx, 3
-            |mov  rdx, 1
-            |mov  rax, rcx
-            |add  rax, rdx
+            |push rbx
+            |push r12
+            |push r13
+            |push r14
+            |mov  rbx, 3
+            |mov  r12, 1
+            |mov  rax, rbx
+            |add  rax, r12
+            |mov  r13, rax
+            |mov  r12, 2
+            |mov  rbx, 3
+            |mov  rax, r12
+            |imul rax, rbx
+            |mov  r14, rax
+            |mov  r12, 4
+            |mov  rax, r14
+            |cqo
+            |idiv r12
             |mov  rbx, rax
-            |mov  rdx, 2
-            |mov  rcx, 3
-            |mov  rax, rdx
-            |add  rax, rcx
-            |mov  rsi, rax
-            |mov  rdx, 4
-            |mov  rax, rsi
-            |mov  r11, rdx
-            |mov  rdx, 0
-            |div  r11
-            |mov  rdx, r11
-            |mov  rcx, rax
+            |mov  rax, r13
+            |sub  rax, rbx
+            |mov  r14, rax
+            |mov  rax, r14
+            |pop  r14
+            |pop  r13
+            |pop  r12
+            |pop  rbx
+            |pop  rbp
+            |retn
+            |"
+            .trim_margin()
+            .unwrap(),
+            machine_code.asm
+        );
+        assert_eq!(
+            vec![
+                0x55, 0x48, 0x89, 0xE5, 0x53, 0x41, 0x54, 0x41, 0x55, 0x41, 0x56, 0x48, 0xBB, 0x03,
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x49, 0xBC, 0x01, 0x00, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x48, 0x89, 0xD8, 0x4C, 0x01, 0xE0, 0x49, 0x89, 0xC5, 0x49, 0xBC,
+                0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x48, 0xBB, 0x03, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x00, 0x4C, 0x89, 0xE0, 0x48, 0x0F, 0xAF, 0xC3, 0x49, 0x89, 0xC6,
+                0x49, 0xBC, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x4C, 0x89, 0xF0, 0x48,
+                0x99, 0x49, 0xF7, 0xFC, 0x48, 0x89, 0xC3, 0x4C, 0x89, 0xE8, 0x48, 0x29, 0xD8, 0x49,
+                0x89, 0xC6, 0x4C, 0x89, 0xF0, 0x41, 0x5E, 0x41, 0x5D, 0x41, 0x5C, 0x5B, 0x5D, 0xC3
+            ],
+            machine_code.machine_code
+        );
+    }
+
+    #[test]
+    fn can_compile_if_else() {
+        let machine_code = compile_single_function(
+            "fn f(x) { let a = 1; if (x) { a = 2; } else { a = 3; } return a; }",
+        );
+        assert_eq!(
+            "
+            |push rbp
+            |mov  rbp, rsp
+            |push rbx
+            |push r12
+            |mov  rbx, rdi
+            |mov  r12, 1
+            |test rbx, rbx
+            |je   .L2
+            |.L1:
+            |mov  rbx, 2
+            |mov  r12, rbx
+            |jmp  .L3
+            |.L2:
+            |mov  rbx, 3
+            |mov  r12, rbx
+            |.L3:
+            |mov  rbx, r12
             |mov  rax, rbx
-            |sub  rax, rcx
-            |mov  rsi, rax
-            |mov  rax, rsi
+            |pop  r12
+            |pop  rbx
             |pop  rbp
             |retn
             |"
@@ -395,16 +2595,324 @@ mod test {
         );
         assert_eq!(
             vec![
-                0x55, 0x48, 0x89, 0xE5, 0x48, 0xB9, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-                0x48, 0xBA, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x48, 0x89, 0xC8, 0x48,
-                0x01, 0xD0, 0x48, 0x89, 0xC3, 0x48, 0xBA, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-                0x00, 0x48, 0xB9, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x48, 0x89, 0xD0,
-                0x48, 0xF7, 0xE1, 0x48, 0x89, 0xC6, 0x48, 0xBA, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00,
-                0x00, 0x00, 0x48, 0x89, 0xF0, 0x48, 0x89, 0xD3, 0x48, 0xBA, 0x00, 0x00, 0x00, 0x00,
-                0x00, 0x00, 0x00, 0x00, 0x48, 0xF7, 0xFB, 0x48, 0x89, 0xDA, 0x48, 0x89, 0xC1, 0x48,
-                0x89, 0xD8, 0x48, 0x29, 0xC8, 0x48, 0x89, 0xC6, 0x48, 0x89, 0xF0, 0x5D, 0xC3
+                0x55, 0x48, 0x89, 0xE5, 0x53, 0x41, 0x54, 0x48, 0x89, 0xFB, 0x49, 0xBC, 0x01, 0x00,
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x48, 0x85, 0xDB, 0x0F, 0x84, 0x12, 0x00, 0x00,
+                0x00, 0x48, 0xBB, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x49, 0x89, 0xDC,
+                0xE9, 0x0D, 0x00, 0x00, 0x00, 0x48, 0xBB, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                0x00, 0x49, 0x89, 0xDC, 0x4C, 0x89, 0xE3, 0x48, 0x89, 0xD8, 0x41, 0x5C, 0x5B, 0x5D,
+                0xC3
             ],
             machine_code.machine_code
         );
     }
+
+    #[test]
+    fn can_encode_indexed_memory_accesses() {
+        let cases = [
+            (
+                LoadIndexed {
+                    destination: Rax,
+                    base: R11,
+                    index: Rax,
+                },
+                vec![0x49, 0x8B, 0x04, 0xC3],
+            ),
+            (
+                StoreIndexed {
+                    source: Rcx,
+                    base: R11,
+                    index: Rax,
+                },
+                vec![0x49, 0x89, 0x0C, 0xC3],
+            ),
+            (
+                LoadIndexed {
+                    destination: Rbx,
+                    base: R13,
+                    index: R9,
+                },
+                vec![0x4B, 0x8B, 0x5C, 0xCD, 0x00],
+            ),
+            (
+                StoreIndexed {
+                    source: R15,
+                    base: Rbp,
+                    index: R12,
+                },
+                vec![0x4E, 0x89, 0x7C, 0xE5, 0x00],
+            ),
+        ];
+        for (instruction, expected) in cases {
+            assert_eq!(expected, instruction.make_machine_code(), "{}", instruction);
+        }
+    }
+
+    #[test]
+    fn can_encode_unsigned_operations_and_casts() {
+        let cases = [
+            (
+                UnsignedDivRegFromRax { register: R11 },
+                vec![0x49, 0xF7, 0xF3],
+            ),
+            (ShrCl { register: Rbx }, vec![0x48, 0xD3, 0xEB]),
+            (MovsxdEaxToRax, vec![0x48, 0x63, 0xC0]),
+            (MovEaxToEax, vec![0x89, 0xC0]),
+            (
+                SetCcAl {
+                    condition: Condition::Below,
+                },
+                vec![0x0F, 0x92, 0xC0],
+            ),
+            (
+                SetCcAl {
+                    condition: Condition::BelowOrEqual,
+                },
+                vec![0x0F, 0x96, 0xC0],
+            ),
+        ];
+        for (instruction, expected) in cases {
+            assert_eq!(expected, instruction.make_machine_code(), "{}", instruction);
+        }
+    }
+
+    #[test]
+    fn can_encode_intrinsics() {
+        let cases = [
+            (Popcnt { register: Rax }, vec![0xF3, 0x48, 0x0F, 0xB8, 0xC0]),
+            (Popcnt { register: R12 }, vec![0xF3, 0x4D, 0x0F, 0xB8, 0xE4]),
+            (
+                Cmov {
+                    condition: Condition::Greater,
+                    source: Rbx,
+                    destination: Rax,
+                },
+                vec![0x48, 0x0F, 0x4F, 0xC3],
+            ),
+            (
+                Cmov {
+                    condition: Condition::Above,
+                    source: R12,
+                    destination: Rax,
+                },
+                vec![0x49, 0x0F, 0x47, 0xC4],
+            ),
+        ];
+        for (instruction, expected) in cases {
+            assert_eq!(expected, instruction.make_machine_code(), "{}", instruction);
+        }
+    }
+
+    #[test]
+    fn can_encode_float_instructions() {
+        let cases = [
+            (
+                MovqToXmm {
+                    source: Rax,
+                    destination: Xmm8,
+                },
+                vec![0x66, 0x4C, 0x0F, 0x6E, 0xC0],
+            ),
+            (
+                MovqFromXmm {
+                    source: Xmm9,
+                    destination: R11,
+                },
+                vec![0x66, 0x4D, 0x0F, 0x7E, 0xCB],
+            ),
+            (
+                MovsdRegToReg {
+                    source: Xmm8,
+                    destination: Xmm0,
+                },
+                vec![0xF2, 0x41, 0x0F, 0x10, 0xC0],
+            ),
+            (
+                LoadFloat {
+                    destination: Xmm0,
+                    base: Rbp,
+                    offset: -56,
+                },
+                vec![0xF2, 0x0F, 0x10, 0x85, 0xC8, 0xFF, 0xFF, 0xFF],
+            ),
+            (
+                StoreFloat {
+                    source: Xmm10,
+                    base: Rsp,
+                    offset: 8,
+                },
+                vec![0xF2, 0x44, 0x0F, 0x11, 0x94, 0x24, 0x08, 0x00, 0x00, 0x00],
+            ),
+            (
+                FloatArithmetic {
+                    operation: FloatOperation::Add,
+                    source: Xmm9,
+                    destination: Xmm0,
+                },
+                vec![0xF2, 0x41, 0x0F, 0x58, 0xC1],
+            ),
+            (
+                FloatArithmetic {
+                    operation: FloatOperation::Sub,
+                    source: Xmm1,
+                    destination: Xmm0,
+                },
+                vec![0xF2, 0x0F, 0x5C, 0xC1],
+            ),
+            (
+                FloatArithmetic {
+                    operation: FloatOperation::Mul,
+                    source: Xmm0,
+                    destination: Xmm8,
+                },
+                vec![0xF2, 0x44, 0x0F, 0x59, 0xC0],
+            ),
+            (
+                FloatArithmetic {
+                    operation: FloatOperation::Div,
+                    source: Xmm15,
+                    destination: Xmm0,
+                },
+                vec![0xF2, 0x41, 0x0F, 0x5E, 0xC7],
+            ),
+            (
+                Ucomisd {
+                    left: Xmm11,
+                    right: Xmm0,
+                },
+                vec![0x66, 0x44, 0x0F, 0x2E, 0xD8],
+            ),
+            (
+                Cvtsi2sd {
+                    source: R12,
+                    destination: Xmm9,
+                },
+                vec![0xF2, 0x4D, 0x0F, 0x2A, 0xCC],
+            ),
+            (
+                Cvttsd2si {
+                    source: Xmm10,
+                    destination: R13,
+                },
+                vec![0xF2, 0x4D, 0x0F, 0x2C, 0xEA],
+            ),
+            (
+                Cmov {
+                    condition: Condition::Parity,
+                    source: R11,
+                    destination: Rax,
+                },
+                vec![0x49, 0x0F, 0x4A, 0xC3],
+            ),
+        ];
+        for (instruction, expected) in cases {
+            assert_eq!(expected, instruction.make_machine_code(), "{}", instruction);
+        }
+    }
+
+    #[test]
+    fn can_encode_indirect_jumps() {
+        let cases = [
+            (JmpReg { register: Rax }, vec![0xFF, 0xE0]),
+            (JmpReg { register: R11 }, vec![0x41, 0xFF, 0xE3]),
+            (
+                LeaRipRelative {
+                    destination: R11,
+                    target: BlockId(0),
+                    offset: 0x10,
+                },
+                vec![0x4C, 0x8D, 0x1D, 0x10, 0x00, 0x00, 0x00],
+            ),
+            (
+                LeaRipRelative {
+                    destination: Rax,
+                    target: BlockId(0),
+                    offset: -4,
+                },
+                vec![0x48, 0x8D, 0x05, 0xFC, 0xFF, 0xFF, 0xFF],
+            ),
+        ];
+        for (instruction, expected) in cases {
+            assert_eq!(expected, instruction.make_machine_code(), "{}", instruction);
+        }
+    }
+
+    #[test]
+    fn can_encode_overflow_checks() {
+        let cases = [
+            (
+                UnsignedMulRegToRax { register: R11 },
+                vec![0x49, 0xF7, 0xE3],
+            ),
+            (
+                Cmov {
+                    condition: Condition::Overflow,
+                    source: Rcx,
+                    destination: Rax,
+                },
+                vec![0x48, 0x0F, 0x40, 0xC1],
+            ),
+        ];
+        for (instruction, expected) in cases {
+            assert_eq!(expected, instruction.make_machine_code(), "{}", instruction);
+        }
+    }
+
+    #[test]
+    fn memory_accesses_are_bounds_checked() {
+        let program = parse_program("fn f(a) { return load(a, 1); }").unwrap();
+        let compiled = frontend::compile(program, &frontend::FrontendOptions::default())
+            .unwrap()
+            .functions;
+        let function_catalog = Box::new(CompiledFunctionCatalog::new(&compiled, vec![]));
+
+        let mut gen = X64LinuxGenerator::default();
+        let machine_code = gen
+            .generate_machine_code(&compiled[0], &function_catalog)
+            .unwrap();
+        assert_eq!(
+            format!(
+                "
+            |push rbp
+            |mov  rbp, rsp
+            |push rbx
+            |push r12
+            |push r13
+            |sub  rsp, 8
+            |mov  rbx, rdi
+            |mov  r12, 1
+            |mov  rax, rbx
+            |add  rax, r12
+            |mov  r11, {}
+            |mov  rcx, [r11+8]
+            |cmp  rax, rcx
+            |jae  .L1
+            |mov  r11, [r11+0]
+            |mov  rax, [r11+rax*8]
+            |mov  r13, rax
+            |mov  rax, r13
+            |add  rsp, 8
+            |pop  r13
+            |pop  r12
+            |pop  rbx
+            |pop  rbp
+            |retn
+            |.L1:
+            |mov  [r11+24], rax
+            |mov  rax, 1
+            |mov  [r11+16], rax
+            |.L2:
+            |add  rsp, 8
+            |pop  r13
+            |pop  r12
+            |pop  rbx
+            |pop  rbp
+            |retn
+            |",
+                function_catalog.runtime_context_address()
+            )
+            .trim_margin()
+            .unwrap(),
+            machine_code.asm
+        );
+    }
 }
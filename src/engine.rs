@@ -0,0 +1,458 @@
+use tracing::{debug, info, warn, Level};
+use tracing_subscriber::FmtSubscriber;
+
+use crate::{
+    ast::Module,
+    backend::{CompiledFunctionCatalog, GeneratedMachineCode, MachineCodeGenerator},
+    backend_aarch64::Aarch64Generator,
+    backend_x64_linux::X64LinuxGenerator,
+    frontend::{self, FrontendOptions, FrontendOutput, FrontendWarning},
+    frontend_types,
+    ir::{CompiledFunction, OverflowMode},
+    jit::{
+        frontend_errors_to_jit_error, to_function_pointer, HostFunctions, JitError, JitProgram,
+        ANONYMOUS_SOURCE_PATH,
+    },
+    modules::{InMemoryFiles, SourceFiles},
+    optimization, parser,
+};
+
+/// How much the IR is optimized before generating machine code
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OptimizationLevel {
+    /// The machine code is generated from the IR as the frontend produced it, which
+    /// makes it easier to follow
+    None,
+    /// Constant propagation, deduplication of constants and dead store elimination
+    #[default]
+    Full,
+}
+
+/// The architecture to generate machine code for. Only the code generated for the
+/// machine running the compiler can be executed, but the one of any backend can be
+/// inspected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Aarch64,
+    X64Linux,
+}
+
+impl Backend {
+    /// The backend of the machine running the compiler
+    pub const fn native() -> Backend {
+        #[cfg(target_arch = "aarch64")]
+        return Backend::Aarch64;
+        #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+        return Backend::X64Linux;
+    }
+
+    fn generator(&self) -> Box<dyn MachineCodeGenerator> {
+        match self {
+            Backend::Aarch64 => Box::<Aarch64Generator>::default(),
+            Backend::X64Linux => Box::<X64LinuxGenerator>::default(),
+        }
+    }
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Backend::native()
+    }
+}
+
+/// Compiles programs with the same options. It is created with a builder:
+/// ```
+/// # use emjay::{Engine, OptimizationLevel};
+/// let engine = Engine::builder()
+///     .optimization_level(OptimizationLevel::None)
+///     .build();
+/// let program = engine.compile("fn double(x) { return x * 2; }").unwrap();
+/// println!("{}", program.function("double").unwrap().machine_code.asm);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Engine {
+    options: FrontendOptions,
+    optimization_level: OptimizationLevel,
+    backend: Backend,
+    host_functions: HostFunctions,
+    log_level: Option<Level>,
+}
+
+/// Sets the options of an `Engine`. The ones that are not set keep their default
+#[derive(Debug, Default)]
+pub struct EngineBuilder {
+    engine: Engine,
+}
+
+impl EngineBuilder {
+    pub fn frontend_options(&mut self, options: FrontendOptions) -> &mut Self {
+        self.engine.options = options;
+        self
+    }
+
+    pub fn overflow_mode(&mut self, overflow_mode: OverflowMode) -> &mut Self {
+        self.engine.options.overflow_mode = overflow_mode;
+        self
+    }
+
+    pub fn optimization_level(&mut self, optimization_level: OptimizationLevel) -> &mut Self {
+        self.engine.optimization_level = optimization_level;
+        self
+    }
+
+    pub fn backend(&mut self, backend: Backend) -> &mut Self {
+        self.engine.backend = backend;
+        self
+    }
+
+    /// The functions of the host that the programs can declare with `extern fn`
+    pub fn host_functions(&mut self, host_functions: HostFunctions) -> &mut Self {
+        self.engine.host_functions = host_functions;
+        self
+    }
+
+    /// Prints the sources, the IR and the machine code of the programs, as well as the
+    /// warnings, up to the given level. Without it, they are sent to the `tracing`
+    /// subscriber of the host program, if any
+    pub fn log_level(&mut self, level: Level) -> &mut Self {
+        self.engine.log_level = Some(level);
+        self
+    }
+
+    pub fn build(&self) -> Engine {
+        self.engine.clone()
+    }
+}
+
+/// A function of a compiled program, with the artifacts of each step of its compilation
+#[derive(Debug)]
+pub struct ProgramFunction<'a> {
+    /// The IR generated by the frontend
+    pub ir: CompiledFunction<'a>,
+    /// The IR that the machine code was generated from, which is `ir` after the
+    /// optimizations of the engine's level
+    pub optimized_ir: CompiledFunction<'a>,
+    pub machine_code: GeneratedMachineCode,
+}
+
+/// A compiled program. It borrows the source code, which its AST and IR refer to
+#[derive(Debug)]
+pub struct Program<'a> {
+    /// The AST of each file, starting from the root one, after the type checking
+    pub modules: Vec<Module<'a>>,
+    /// Indexed by `FunctionId`
+    pub functions: Vec<ProgramFunction<'a>>,
+    pub warnings: Vec<FrontendWarning>,
+    pub backend: Backend,
+    function_catalog: Box<CompiledFunctionCatalog>,
+}
+
+impl<'a> Program<'a> {
+    /// Finds a function of any module by name. The ones of imported modules are found by
+    /// their qualified name, such as `finance::npv`
+    pub fn function(&self, name: &str) -> Option<&ProgramFunction<'a>> {
+        self.functions
+            .iter()
+            .find(|function| function.ir.name == name)
+    }
+
+    /// Whether the machine code has been loaded in memory, which happens when it was
+    /// generated for the machine running the compiler
+    pub fn is_executable(&self) -> bool {
+        self.backend == Backend::native()
+    }
+
+    /// Keeps just what is needed to run the program, starting from the given function
+    pub fn into_jit_program(self, main_function_name: &str) -> Result<JitProgram, JitError> {
+        if !self.is_executable() {
+            return Err(JitError::ForeignBackend(self.backend));
+        }
+        let Some(main_function) = self
+            .function(main_function_name)
+            .map(|function| function.ir.id)
+        else {
+            return Err(JitError::MainFunctionNotFound(
+                main_function_name.to_string(),
+            ));
+        };
+        Ok(JitProgram {
+            function_catalog: self.function_catalog,
+            main_function,
+            warnings: self.warnings,
+        })
+    }
+}
+
+impl Engine {
+    pub fn builder() -> EngineBuilder {
+        EngineBuilder::default()
+    }
+
+    /// Compiles a program made of just one source, which cannot import other files
+    pub fn compile<'a>(&self, source: &'a str) -> Result<Program<'a>, JitError> {
+        self.with_logging(|| {
+            // Resolving the imports reports any of them as a missing file
+            let mut provider = InMemoryFiles::default();
+            provider.add(ANONYMOUS_SOURCE_PATH, source);
+            let files = SourceFiles::resolve(ANONYMOUS_SOURCE_PATH, &provider)?;
+            let program = parser::parse_program(source)
+                .map_err(|err| Box::new(err.with_path(ANONYMOUS_SOURCE_PATH)))?;
+            let module = Module {
+                path: ANONYMOUS_SOURCE_PATH,
+                program,
+            };
+            self.compile_modules(vec![module], &files)
+        })
+    }
+
+    /// Compiles a program made of several files, found by `SourceFiles::resolve`
+    pub fn compile_files<'a>(&self, files: &'a SourceFiles) -> Result<Program<'a>, JitError> {
+        self.with_logging(|| self.compile_modules(files.parse()?, files))
+    }
+
+    fn with_logging<T>(&self, compile: impl FnOnce() -> T) -> T {
+        match self.log_level {
+            Some(level) => {
+                let subscriber = FmtSubscriber::builder().with_max_level(level).finish();
+                tracing::subscriber::with_default(subscriber, compile)
+            }
+            None => compile(),
+        }
+    }
+
+    fn compile_modules<'a>(
+        &self,
+        mut modules: Vec<Module<'a>>,
+        files: &SourceFiles,
+    ) -> Result<Program<'a>, JitError> {
+        for file in files.files.iter() {
+            info!("source of {}: \n{}", file.path, file.source);
+        }
+
+        // Even if the types are wrong, we still run the frontend to report all the errors
+        let type_errors = frontend_types::check_module_types(&mut modules, &self.options);
+        let frontend_output =
+            frontend::compile_modules(&modules, &self.options, &self.host_functions.signatures());
+        let FrontendOutput {
+            functions: compiled_functions,
+            warnings,
+        } = match frontend_output {
+            Ok(output) if type_errors.is_empty() => output,
+            Ok(_) => return Err(frontend_errors_to_jit_error(type_errors, files)),
+            Err(mut errors) => {
+                if !type_errors.is_empty() {
+                    errors.extend(type_errors);
+                    errors.sort_by_key(|error| error.span().start);
+                }
+                return Err(frontend_errors_to_jit_error(errors, files));
+            }
+        };
+        for warning in warnings.iter() {
+            warn!("{}", warning.format_with_sources(files));
+        }
+
+        // Create the function catalog and stores it in a box, to ensure that it will be at a fixed
+        // address and not be de-allocated
+        let mut function_catalog = Box::new(CompiledFunctionCatalog::new(
+            &compiled_functions,
+            self.host_functions.addresses(),
+        ));
+        let function_catalog_ptr: *const CompiledFunctionCatalog = &*function_catalog;
+        debug!("function catalog: {:0X}", function_catalog_ptr as usize);
+
+        let mut gen = self.backend.generator();
+        let mut functions = Vec::with_capacity(compiled_functions.len());
+        for function in compiled_functions {
+            debug!("compiling function: {}", function.name);
+            debug!("base ir:\n{}", function);
+
+            let optimized_function = match self.optimization_level {
+                OptimizationLevel::None => function.clone(),
+                OptimizationLevel::Full => optimization::optimize_fun(&function),
+            };
+            debug!("optimized ir:\n{}", optimized_function);
+
+            let machine_code = gen.generate_machine_code(&optimized_function, &function_catalog)?;
+            debug!("asm:\n{}", machine_code.asm);
+
+            let machine_code_for_debug: String = machine_code
+                .machine_code
+                .iter()
+                .enumerate()
+                .map(|(index, byte)| {
+                    if index % 4 == 3 {
+                        format!("{:02X}\n", byte)
+                    } else {
+                        format!("{:02X} ", byte)
+                    }
+                })
+                .collect();
+            debug!("Machine code:\n{}", machine_code_for_debug);
+
+            if self.backend == Backend::native() {
+                let fun_ptr = unsafe { to_function_pointer(&machine_code.machine_code)? };
+                function_catalog.store_function_pointer(function.id, fun_ptr);
+            }
+            functions.push(ProgramFunction {
+                ir: function,
+                optimized_ir: optimized_function,
+                machine_code,
+            });
+        }
+
+        Ok(Program {
+            modules,
+            functions,
+            warnings,
+            backend: self.backend,
+            function_catalog,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::Level;
+
+    use crate::{
+        engine::{Backend, Engine, OptimizationLevel},
+        ir::{
+            builders::{block, call, mul, mvarg, mvi, ret},
+            OverflowMode,
+        },
+        jit::{CallError, JitError, RuntimeError},
+        modules::{InMemoryFiles, SourceFiles},
+    };
+
+    const SOURCE: &str = "fn f(x) { return g(x) * 2; }\nfn g(x) { return x + 1 * 2; }";
+
+    #[test]
+    fn programs_expose_the_artifacts_of_each_function() {
+        let engine = Engine::builder().log_level(Level::TRACE).build();
+        let program = engine.compile(SOURCE).expect("program should compile");
+        assert_eq!(
+            vec!["f", "g"],
+            program.modules[0]
+                .program
+                .functions
+                .iter()
+                .map(|function| function.name)
+                .collect::<Vec<_>>()
+        );
+        assert!(program.warnings.is_empty());
+        assert!(program.function("h").is_none());
+
+        let f = program.function("f").expect("f should exist");
+        assert_eq!(
+            vec![block(
+                0,
+                vec![
+                    mvarg(0, 0),
+                    call(1, "g", 1, vec![0]),
+                    mvi(2, 2),
+                    mul(3, 1, 2),
+                    ret(3)
+                ]
+            )],
+            f.ir.blocks
+        );
+        assert!(!f.machine_code.asm.is_empty());
+        assert!(!f.machine_code.machine_code.is_empty());
+        assert_eq!(Ok(10), program.into_jit_program("f").unwrap().run(&[3]));
+    }
+
+    #[test]
+    fn the_optimization_level_decides_whether_the_ir_is_optimized() {
+        let optimized = Engine::builder().build();
+        let program = optimized.compile(SOURCE).unwrap();
+        let g = program.function("g").unwrap();
+        assert_ne!(g.ir.blocks, g.optimized_ir.blocks);
+
+        let unoptimized = Engine::builder()
+            .optimization_level(OptimizationLevel::None)
+            .build();
+        let program = unoptimized.compile(SOURCE).unwrap();
+        let g = program.function("g").unwrap();
+        assert_eq!(g.ir.blocks, g.optimized_ir.blocks);
+        assert_eq!(Ok(10), program.into_jit_program("f").unwrap().run(&[3]));
+    }
+
+    #[test]
+    fn unreachable_code_compiles_without_optimizations() {
+        let engine = Engine::builder()
+            .optimization_level(OptimizationLevel::None)
+            .build();
+        let program = engine
+            .compile("fn f(x) { return 1; let a = 2; return a; }")
+            .expect("program should compile");
+        let f = program.function("f").unwrap();
+        assert_eq!(
+            vec![block(0, vec![mvarg(0, 0), mvi(1, 1), ret(1)])],
+            f.ir.blocks
+        );
+        assert_eq!(2, f.ir.num_used_registers);
+        assert_eq!(Ok(1), program.into_jit_program("f").unwrap().run(&[3]));
+    }
+
+    #[test]
+    fn code_for_other_backends_can_be_inspected_but_not_run() {
+        let other = match Backend::native() {
+            Backend::Aarch64 => Backend::X64Linux,
+            Backend::X64Linux => Backend::Aarch64,
+        };
+        let engine = Engine::builder().backend(other).build();
+        let program = engine.compile(SOURCE).expect("program should compile");
+        assert!(!program.is_executable());
+        assert!(!program.function("g").unwrap().machine_code.asm.is_empty());
+        assert!(matches!(
+            program.into_jit_program("f"),
+            Err(JitError::ForeignBackend(backend)) if backend == other
+        ));
+    }
+
+    #[test]
+    fn engines_apply_their_options_to_every_program() {
+        let engine = Engine::builder()
+            .overflow_mode(OverflowMode::Checked)
+            .build();
+        let source = "fn f(x) { return x * 2; }";
+        for _ in 0..2 {
+            let program = engine
+                .compile(source)
+                .unwrap()
+                .into_jit_program("f")
+                .unwrap();
+            assert_eq!(
+                Err(CallError::Runtime(RuntimeError::Overflow)),
+                program.run(&[i64::MAX])
+            );
+        }
+
+        let error = engine.compile("import \"a.mj\";\nfn f() { return 0; }");
+        assert!(matches!(error, Err(JitError::Module(_))));
+    }
+
+    #[test]
+    fn can_compile_several_files() {
+        let mut provider = InMemoryFiles::default();
+        provider
+            .add(
+                "main.mj",
+                "import \"util.mj\";\nfn main(x) { return util::g(x); }",
+            )
+            .add("util.mj", "fn g(x) { return x + 1; }");
+        let files = SourceFiles::resolve("main.mj", &provider).unwrap();
+        let program = Engine::builder().build().compile_files(&files).unwrap();
+        assert_eq!(
+            vec!["main.mj", "util.mj"],
+            program
+                .modules
+                .iter()
+                .map(|module| module.path)
+                .collect::<Vec<_>>()
+        );
+        assert!(program.function("g").is_none());
+        assert!(program.function("util::g").is_some());
+        assert_eq!(Ok(2), program.into_jit_program("main").unwrap().run(&[1]));
+    }
+}
@@ -1,25 +1,41 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, HashSet},
+    rc::Rc,
+};
 
 use thiserror::Error;
 
 use crate::{
-    ast::{Block, BlockElement, Expression, Function, Program},
-    ir::{BinOpOperator::*, CompiledFunction, IrInstruction, IrRegister},
+    ast::{
+        Block, BlockElementKind, Expression, ExpressionKind, Function, FunctionCall, MatchArm,
+        Module, Program, Span, Type,
+    },
+    frontend_constants::evaluate_constants,
+    ir::{
+        BasicBlock, BinOpOperator, BinOpOperator::*, BlockId, CompiledFunction, IrInstruction,
+        IrRegister, OverflowMode, RegisterClass,
+    },
+    modules::{module_name, SourceFiles},
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct FunctionId(pub usize);
 
+/// Identifies a function provided by the host program
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HostFunctionId(pub usize);
+
 #[derive(Debug, Error)]
 pub enum FrontendError {
     #[error("variable \"{name}\" not defined")]
-    VariableNotDefined { name: String },
+    VariableNotDefined { name: String, span: Span },
     #[error("variable \"{name}\" already defined")]
-    VariableAlreadyDefined { name: String },
+    VariableAlreadyDefined { name: String, span: Span },
     #[error("variable \"{name}\" cannot shadow function argument with the same name")]
-    VariableCannotShadowArgument { name: String },
+    VariableCannotShadowArgument { name: String, span: Span },
     #[error("unknown function \"{name}\" called")]
-    UnknownFunctionCalled { name: String },
+    UnknownFunctionCalled { name: String, span: Span },
     #[error(
         "function \"{function_name}\" requires {expected} argument(s) but was called with {actual}"
     )]
@@ -27,37 +43,583 @@ pub enum FrontendError {
         function_name: String,
         expected: usize,
         actual: usize,
+        span: Span,
+    },
+    #[error("break statement outside of a loop")]
+    BreakOutsideLoop { span: Span },
+    #[error("continue statement outside of a loop")]
+    ContinueOutsideLoop { span: Span },
+    #[error("function \"{function_name}\" does not return a value in all paths")]
+    MissingReturn { function_name: String, span: Span },
+    #[error("function \"{name}\" already defined")]
+    FunctionAlreadyDefined {
+        name: String,
+        span: Span,
+        previous_definition: Span,
+    },
+    #[error("function argument \"{name}\" already defined")]
+    ArgumentAlreadyDefined {
+        name: String,
+        span: Span,
+        previous_definition: Span,
+    },
+    #[error("constant \"{name}\" already defined")]
+    ConstantAlreadyDefined {
+        name: String,
+        span: Span,
+        previous_definition: Span,
+    },
+    #[error("constant \"{name}\" depends on itself: {cycle}")]
+    ConstantDependsOnItself {
+        name: String,
+        cycle: String,
+        span: Span,
+    },
+    #[error("constant \"{name}\" not defined")]
+    ConstantNotDefined { name: String, span: Span },
+    #[error("functions cannot be called in the value of a constant")]
+    FunctionCallInConstant { span: Span },
+    #[error("division by zero or overflow in the value of a constant")]
+    InvalidConstantOperation { span: Span },
+    #[error("cannot assign to constant \"{name}\"")]
+    CannotAssignToConstant { name: String, span: Span },
+    #[error("extern function \"{name}\" is not provided by the host")]
+    ExternFunctionNotProvided { name: String, span: Span },
+    #[error(
+        "extern function \"{name}\" is declared with {declared} argument(s) but the host provides it with {provided}"
+    )]
+    ExternFunctionArgumentsMismatch {
+        name: String,
+        declared: usize,
+        provided: usize,
+        span: Span,
+    },
+    #[error("mismatched types: expected {expected}, found {found}")]
+    TypeMismatch {
+        expected: Type,
+        found: Type,
+        span: Span,
+    },
+    #[error("literal {value} does not fit in type {ty}")]
+    LiteralOutOfRange { value: i128, ty: Type, span: Span },
+    #[error("cannot negate a value of unsigned type {ty}")]
+    CannotNegateUnsigned { ty: Type, span: Span },
+    #[error("operator {operator} cannot be applied to values of type {ty}")]
+    UnsupportedOperator {
+        operator: &'static str,
+        ty: Type,
+        span: Span,
+    },
+    #[error("cannot cast {from} to {to}, f64 can only be converted to and from i64")]
+    InvalidCast { from: Type, to: Type, span: Span },
+    #[error("floating-point values cannot be used in the value of a constant")]
+    FloatInConstant { span: Span },
+    #[error(
+        "extern function \"{name}\" is declared as {declared} but the host provides it as {provided}"
+    )]
+    ExternFunctionTypesMismatch {
+        name: String,
+        declared: String,
+        provided: String,
+        span: Span,
+    },
+    #[error("division by zero")]
+    DivisionByZero { span: Span },
+    #[error("unknown function \"{name}\" referenced")]
+    UnknownFunctionReferenced { name: String, span: Span },
+    #[error(
+        "extern function \"{name}\" cannot be referenced, only the functions of the program can"
+    )]
+    ExternFunctionReferenced { name: String, span: Span },
+    #[error("function \"{name}\" cannot be referenced, since only functions taking and returning i64 can be called indirectly")]
+    FunctionNotCallableIndirectly { name: String, span: Span },
+    #[error("function handles cannot be used in the value of a constant")]
+    FunctionHandleInConstant { span: Span },
+    #[error("division overflow: the minimum of a signed type cannot be divided by -1")]
+    DivisionOverflow { span: Span },
+    #[error("match must have a `_` arm, for the values not matched by the other arms")]
+    MatchWithoutWildcard { span: Span },
+    #[error("value {value} is matched by more than one arm")]
+    DuplicateMatchValue {
+        value: i64,
+        span: Span,
+        previous_definition: Span,
     },
+    #[error("function \"{name}\" cannot be defined, since it is a built-in function")]
+    IntrinsicRedefined { name: String, span: Span },
+}
+
+impl FrontendError {
+    /// The position in the source code where the error was found
+    pub fn span(&self) -> Span {
+        match self {
+            FrontendError::VariableNotDefined { span, .. }
+            | FrontendError::VariableAlreadyDefined { span, .. }
+            | FrontendError::VariableCannotShadowArgument { span, .. }
+            | FrontendError::UnknownFunctionCalled { span, .. }
+            | FrontendError::InvalidArgumentsToFunctionCall { span, .. }
+            | FrontendError::BreakOutsideLoop { span }
+            | FrontendError::ContinueOutsideLoop { span }
+            | FrontendError::MissingReturn { span, .. }
+            | FrontendError::FunctionAlreadyDefined { span, .. }
+            | FrontendError::ArgumentAlreadyDefined { span, .. }
+            | FrontendError::ConstantAlreadyDefined { span, .. }
+            | FrontendError::ConstantDependsOnItself { span, .. }
+            | FrontendError::ConstantNotDefined { span, .. }
+            | FrontendError::FunctionCallInConstant { span }
+            | FrontendError::InvalidConstantOperation { span }
+            | FrontendError::CannotAssignToConstant { span, .. }
+            | FrontendError::ExternFunctionNotProvided { span, .. }
+            | FrontendError::ExternFunctionArgumentsMismatch { span, .. }
+            | FrontendError::TypeMismatch { span, .. }
+            | FrontendError::LiteralOutOfRange { span, .. }
+            | FrontendError::CannotNegateUnsigned { span, .. }
+            | FrontendError::UnsupportedOperator { span, .. }
+            | FrontendError::InvalidCast { span, .. }
+            | FrontendError::FloatInConstant { span }
+            | FrontendError::ExternFunctionTypesMismatch { span, .. }
+            | FrontendError::DivisionByZero { span }
+            | FrontendError::DivisionOverflow { span }
+            | FrontendError::UnknownFunctionReferenced { span, .. }
+            | FrontendError::ExternFunctionReferenced { span, .. }
+            | FrontendError::FunctionNotCallableIndirectly { span, .. }
+            | FrontendError::FunctionHandleInConstant { span }
+            | FrontendError::MatchWithoutWildcard { span }
+            | FrontendError::DuplicateMatchValue { span, .. }
+            | FrontendError::IntrinsicRedefined { span, .. } => *span,
+        }
+    }
+
+    /// Another position in the source code that is relevant to understand the error,
+    /// with a short description of it
+    pub fn related_span(&self) -> Option<(Span, &'static str)> {
+        match self {
+            FrontendError::FunctionAlreadyDefined {
+                previous_definition,
+                ..
+            }
+            | FrontendError::ArgumentAlreadyDefined {
+                previous_definition,
+                ..
+            }
+            | FrontendError::ConstantAlreadyDefined {
+                previous_definition,
+                ..
+            } => Some((*previous_definition, "previously defined here")),
+            FrontendError::DuplicateMatchValue {
+                previous_definition,
+                ..
+            } => Some((*previous_definition, "previously matched here")),
+            _ => None,
+        }
+    }
+
+    /// Formats the error as `path:line:col`, followed by the line of the source
+    /// containing it, with the offending code underlined. If the error has a related
+    /// span, it is shown in the same way after the error.
+    pub fn format_with_sources(&self, files: &SourceFiles) -> String {
+        let mut formatted = files.format_error_at(self.span(), &self.to_string());
+        if let Some((span, description)) = self.related_span() {
+            formatted.push('\n');
+            formatted.push_str(&files.format_error_at(span, description));
+        }
+        formatted
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum FrontendWarning {
+    #[error("unreachable statement")]
+    UnreachableStatement { span: Span },
+    #[error("unreachable match arm, since the `_` arm before it matches every value")]
+    UnreachableMatchArm { span: Span },
+}
+
+impl FrontendWarning {
+    /// The position in the source code where the warning was found
+    pub fn span(&self) -> Span {
+        match self {
+            FrontendWarning::UnreachableStatement { span }
+            | FrontendWarning::UnreachableMatchArm { span } => *span,
+        }
+    }
+
+    /// Formats the warning in the same way as `FrontendError::format_with_sources`
+    pub fn format_with_sources(&self, files: &SourceFiles) -> String {
+        files.format_error_at(self.span(), &self.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrontendOptions {
+    /// By default, a function where some path can reach the end of the body without
+    /// a `return` is an error. When this is true, an implicit `return 0` is added instead.
+    pub implicit_return_zero: bool,
+    /// What happens when integer arithmetic overflows, both in constants and at runtime
+    pub overflow_mode: OverflowMode,
+}
+
+#[derive(Debug)]
+pub struct FrontendOutput<'input> {
+    pub functions: Vec<CompiledFunction<'input>>,
+    pub warnings: Vec<FrontendWarning>,
+}
+
+/// A function provided by the host program, which the source can use after declaring
+/// it with `extern fn`. The host only tells whether each value is an integer or a
+/// float, so an `extern fn` can declare any integer type in place of `i64`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostFunctionSignature {
+    pub name: String,
+    pub argument_classes: Vec<RegisterClass>,
+    pub return_class: RegisterClass,
+}
+
+impl HostFunctionSignature {
+    /// Formats the signature like `fn(int, float) -> float`
+    pub(crate) fn describe(
+        argument_classes: &[RegisterClass],
+        return_class: RegisterClass,
+    ) -> String {
+        let class_name = |class: &RegisterClass| match class {
+            RegisterClass::Integer => "int",
+            RegisterClass::Float => "float",
+        };
+        format!(
+            "fn({}) -> {}",
+            argument_classes
+                .iter()
+                .map(class_name)
+                .collect::<Vec<_>>()
+                .join(", "),
+            class_name(&return_class)
+        )
+    }
+}
+
+/// Compiles all the functions of the program. Compilation does not stop at the first
+/// error: all the errors found in all the functions and constants are returned.
+pub fn compile<'input>(
+    program: Program<'input>,
+    options: &FrontendOptions,
+) -> Result<FrontendOutput<'input>, Vec<FrontendError>> {
+    compile_with_host_functions(program, options, &[])
+}
+
+/// Like `compile`, but the `extern fn` declarations of the program can refer to the given
+/// host functions. Each one is identified by its position in `host_functions`.
+pub fn compile_with_host_functions<'input>(
+    program: Program<'input>,
+    options: &FrontendOptions,
+    host_functions: &[HostFunctionSignature],
+) -> Result<FrontendOutput<'input>, Vec<FrontendError>> {
+    compile_modules(&[Module { path: "", program }], options, host_functions)
+}
+
+/// Compiles a program made of several modules. Constants and extern functions are
+/// private to their module, while functions can be called from the modules importing
+/// theirs with a qualified name, such as `finance::npv`. Functions are numbered across
+/// all the modules, in order. The ones of the first module, which is the root of the
+/// program, keep their name, while the others are named with the qualified one.
+pub fn compile_modules<'input>(
+    modules: &[Module<'input>],
+    options: &FrontendOptions,
+    host_functions: &[HostFunctionSignature],
+) -> Result<FrontendOutput<'input>, Vec<FrontendError>> {
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+
+    let mut symbol_tables = Vec::with_capacity(modules.len());
+    let mut first_ids = Vec::with_capacity(modules.len());
+    let mut next_id = 0;
+    for module in modules.iter() {
+        first_ids.push(next_id);
+        symbol_tables.push(declare_module_symbols(
+            &module.program,
+            next_id,
+            options,
+            host_functions,
+            &mut errors,
+        ));
+        next_id += module.program.functions.len();
+    }
+
+    // Imports of files that are not part of the program are ignored, so calling their
+    // functions will be reported as calls to unknown functions
+    for (module, symbol_table) in modules.iter().zip(symbol_tables.iter()) {
+        for import in module.program.imports.iter() {
+            if let Some(index) = modules.iter().position(|other| other.path == import.path) {
+                symbol_table
+                    .borrow_mut()
+                    .import(module_name(import.path), symbol_tables[index].clone());
+            }
+        }
+    }
+
+    // Then do a second pass to actually compile each function
+    let mut functions = Vec::new();
+    for (module_index, ((module, symbol_table), first_id)) in
+        modules.iter().zip(symbol_tables).zip(first_ids).enumerate()
+    {
+        for (index, function) in module.program.functions.iter().enumerate() {
+            let mut compiler = FunctionCompiler {
+                options: *options,
+                ..Default::default()
+            };
+            let mut compiled_function = compiler.compile_function(
+                function,
+                FunctionId(first_id + index),
+                symbol_table.clone(),
+            );
+            if module_index > 0 {
+                let qualified_name = format!("{}::{}", module_name(module.path), function.name);
+                compiled_function.name = qualified_name.into();
+            }
+            functions.push(compiled_function);
+            errors.append(&mut compiler.errors);
+            warnings.append(&mut compiler.warnings);
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(FrontendOutput {
+            functions,
+            warnings,
+        })
+    } else {
+        Err(errors)
+    }
 }
 
-pub fn compile(program: Program) -> Result<Vec<CompiledFunction>, FrontendError> {
+/// Creates the global symbol table of a module, with its constants, extern functions
+/// and functions, whose ids start from `first_id`
+fn declare_module_symbols<'input>(
+    program: &Program<'input>,
+    first_id: usize,
+    options: &FrontendOptions,
+    host_functions: &[HostFunctionSignature],
+    errors: &mut Vec<FrontendError>,
+) -> SymbolTableRef<'input> {
     let global_symbol_table = SymbolTable::new();
+    let definition_span = |symbol| definition_span(program, first_id, symbol);
+
+    // Constants are evaluated first, and are used in the functions as if their value
+    // had been written in place of their name
+    let values = evaluate_constants(&program.constants, options.overflow_mode, errors);
+    for (index, constant) in program.constants.iter().enumerate() {
+        let existing_symbol = global_symbol_table.borrow().lookup(constant.name);
+        if let Some(previous_definition) = definition_span(existing_symbol) {
+            errors.push(FrontendError::ConstantAlreadyDefined {
+                name: constant.name.to_string(),
+                span: constant.name_span,
+                previous_definition,
+            });
+            continue;
+        }
+
+        global_symbol_table.borrow_mut().put(Symbol::Constant {
+            index,
+            name: constant.name,
+            value: values[index],
+        });
+    }
 
-    // Do one quick pass to store all functions by name
-    program.iter().enumerate().for_each(|(index, function)| {
+    // Extern functions must match one of the functions provided by the host
+    for (index, extern_function) in program.extern_functions.iter().enumerate() {
+        if Intrinsic::from_name(extern_function.name).is_some() {
+            errors.push(FrontendError::IntrinsicRedefined {
+                name: extern_function.name.to_string(),
+                span: extern_function.name_span,
+            });
+            continue;
+        }
+        let existing_symbol = global_symbol_table.borrow().lookup(extern_function.name);
+        if let Some(previous_definition) = definition_span(existing_symbol) {
+            errors.push(FrontendError::FunctionAlreadyDefined {
+                name: extern_function.name.to_string(),
+                span: extern_function.name_span,
+                previous_definition,
+            });
+            continue;
+        }
+
+        let Some(id) = host_functions
+            .iter()
+            .position(|host_function| host_function.name == extern_function.name)
+        else {
+            errors.push(FrontendError::ExternFunctionNotProvided {
+                name: extern_function.name.to_string(),
+                span: extern_function.name_span,
+            });
+            continue;
+        };
+        let provided = &host_functions[id];
+        let declared_classes = extern_function
+            .args
+            .iter()
+            .map(|arg| RegisterClass::from(arg.ty))
+            .collect::<Vec<_>>();
+        let declared_return_class = RegisterClass::from(extern_function.return_type);
+        if provided.argument_classes.len() != extern_function.args.len() {
+            errors.push(FrontendError::ExternFunctionArgumentsMismatch {
+                name: extern_function.name.to_string(),
+                declared: extern_function.args.len(),
+                provided: provided.argument_classes.len(),
+                span: extern_function.span,
+            });
+        } else if provided.argument_classes != declared_classes
+            || provided.return_class != declared_return_class
+        {
+            errors.push(FrontendError::ExternFunctionTypesMismatch {
+                name: extern_function.name.to_string(),
+                declared: HostFunctionSignature::describe(&declared_classes, declared_return_class),
+                provided: HostFunctionSignature::describe(
+                    &provided.argument_classes,
+                    provided.return_class,
+                ),
+                span: extern_function.span,
+            });
+        }
+
+        global_symbol_table.borrow_mut().put(Symbol::HostFunction {
+            id: HostFunctionId(id),
+            declaration_index: index,
+            name: extern_function.name,
+            signature: FunctionSignature {
+                num_arguments: extern_function.args.len(),
+                return_type: extern_function.return_type,
+            },
+        });
+    }
+
+    // Do one quick pass to store all functions by name. If a function is defined
+    // twice, calls will refer to the first definition. Other modules can reuse the
+    // name, since their functions are only visible with a qualified one
+    for (index, function) in program.functions.iter().enumerate() {
+        if Intrinsic::from_name(function.name).is_some() {
+            errors.push(FrontendError::IntrinsicRedefined {
+                name: function.name.to_string(),
+                span: function.name_span,
+            });
+            continue;
+        }
+        let existing_symbol = global_symbol_table.borrow().lookup(function.name);
+        if let Some(previous_definition) = definition_span(existing_symbol) {
+            errors.push(FrontendError::FunctionAlreadyDefined {
+                name: function.name.to_string(),
+                span: function.name_span,
+                previous_definition,
+            });
+            continue;
+        }
         global_symbol_table.borrow_mut().put(Symbol::Function {
-            id: FunctionId(index),
+            id: FunctionId(first_id + index),
             name: function.name,
             signature: FunctionSignature {
                 num_arguments: function.args.len(),
+                return_type: function.return_type,
             },
         });
-    });
+    }
 
-    // Then do a second pass to actually compile each function
-    program
-        .iter()
-        .enumerate()
-        .map(|(index, function)| {
-            let mut compiler = FunctionCompiler::default();
-            compiler.compile_function(function, FunctionId(index), global_symbol_table.clone())
-        })
-        .collect()
+    global_symbol_table
+}
+
+/// Where the given global symbol of a module was defined, used to report duplicate
+/// definitions. The ids of the functions of the module start from `first_id`
+fn definition_span(program: &Program, first_id: usize, symbol: Option<Symbol>) -> Option<Span> {
+    match symbol? {
+        Symbol::Function { id, .. } => Some(program.functions[id.0 - first_id].name_span),
+        Symbol::HostFunction {
+            declaration_index, ..
+        } => Some(program.extern_functions[declaration_index].name_span),
+        Symbol::Constant { index, .. } => Some(program.constants[index].name_span),
+        Symbol::Variable { .. } | Symbol::Argument { .. } => None,
+    }
+}
+
+/// Checks that a `match` has a `_` arm, and that no value is matched by two arms. Arms
+/// following the `_` one can never be reached, but they are not an error.
+pub fn check_match_arms(arms: &[MatchArm], span: Span) -> Vec<FrontendError> {
+    let mut errors = Vec::new();
+    if arms.iter().all(|arm| arm.patterns.is_some()) {
+        errors.push(FrontendError::MatchWithoutWildcard { span });
+    }
+    let mut matched: HashMap<i64, Span> = HashMap::new();
+    for pattern in arms.iter().flat_map(|arm| arm.patterns.iter().flatten()) {
+        if let Some(previous_definition) = matched.insert(pattern.value, pattern.span) {
+            errors.push(FrontendError::DuplicateMatchValue {
+                value: pattern.value,
+                span: pattern.span,
+                previous_definition,
+            });
+            matched.insert(pattern.value, previous_definition);
+        }
+    }
+    errors
+}
+
+/// Functions built into the language. They are resolved before any user-defined symbol,
+/// so a program cannot define functions with the same names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Intrinsic {
+    /// `load(base, index)` reads the element `base + index` of the memory supplied
+    /// by the host
+    Load,
+    /// `store(base, index, value)` writes `value` into the element `base + index` of
+    /// the memory supplied by the host, and evaluates to `value`
+    Store,
+    /// `abs(x)`, which overflows for `i64::MIN` like `-x` does
+    Abs,
+    Min,
+    Max,
+    /// `clamp(x, low, high)`, i.e. `min(max(x, low), high)`. If `low > high`, the
+    /// result is always `high`
+    Clamp,
+    /// `pow(base, exponent)`, where the exponent is treated as unsigned, so that a
+    /// negative one is a huge exponent. It overflows like a chain of `*`
+    Pow,
+    /// `gcd(a, b)`, the greatest common divisor of the absolute values. It is 0 only if
+    /// both are 0, and `gcd(i64::MIN, 0)` wraps around to `i64::MIN`
+    Gcd,
+    /// `sign(x)` is -1, 0 or 1
+    Sign,
+    /// `popcount(x)`, the number of bits set
+    Popcount,
+}
+
+impl Intrinsic {
+    pub fn from_name(name: &str) -> Option<Intrinsic> {
+        match name {
+            "load" => Some(Intrinsic::Load),
+            "store" => Some(Intrinsic::Store),
+            "abs" => Some(Intrinsic::Abs),
+            "min" => Some(Intrinsic::Min),
+            "max" => Some(Intrinsic::Max),
+            "clamp" => Some(Intrinsic::Clamp),
+            "pow" => Some(Intrinsic::Pow),
+            "gcd" => Some(Intrinsic::Gcd),
+            "sign" => Some(Intrinsic::Sign),
+            "popcount" => Some(Intrinsic::Popcount),
+            _ => None,
+        }
+    }
+
+    fn num_arguments(&self) -> usize {
+        match self {
+            Intrinsic::Abs | Intrinsic::Sign | Intrinsic::Popcount => 1,
+            Intrinsic::Load | Intrinsic::Min | Intrinsic::Max | Intrinsic::Pow | Intrinsic::Gcd => {
+                2
+            }
+            Intrinsic::Store | Intrinsic::Clamp => 3,
+        }
+    }
 }
 
 #[derive(Clone)]
 struct FunctionSignature {
     num_arguments: usize,
+    return_type: Type,
 }
 
 #[derive(Clone)]
@@ -67,13 +629,26 @@ enum Symbol<'input> {
         name: &'input str,
         signature: FunctionSignature,
     },
+    HostFunction {
+        id: HostFunctionId,
+        /// The position of the `extern fn` in `Program::extern_functions`
+        declaration_index: usize,
+        name: &'input str,
+        signature: FunctionSignature,
+    },
     Variable {
         name: &'input str,
         allocated_register: IrRegister,
     },
     Argument {
         name: &'input str,
+        allocated_register: IrRegister,
+    },
+    Constant {
+        /// The position of the constant in `Program::constants`
         index: usize,
+        name: &'input str,
+        value: i64,
     },
 }
 
@@ -81,8 +656,10 @@ impl<'input> Symbol<'input> {
     fn name(&self) -> &'input str {
         match self {
             Symbol::Function { name, .. } => name,
+            Symbol::HostFunction { name, .. } => name,
             Symbol::Variable { name, .. } => name,
             Symbol::Argument { name, .. } => name,
+            Symbol::Constant { name, .. } => name,
         }
     }
 }
@@ -91,6 +668,8 @@ impl<'input> Symbol<'input> {
 struct SymbolTable<'input> {
     parent: Option<Rc<RefCell<SymbolTable<'input>>>>,
     names_to_symbols: HashMap<&'input str, Symbol<'input>>,
+    /// The global symbol tables of the modules imported by this one, by module name
+    imported_modules: HashMap<&'input str, SymbolTableRef<'input>>,
 }
 
 type SymbolTableRef<'input> = Rc<RefCell<SymbolTable<'input>>>;
@@ -103,16 +682,33 @@ impl<'input> SymbolTable<'input> {
     fn with_parent(parent: SymbolTableRef<'input>) -> SymbolTableRef<'input> {
         Rc::new(RefCell::new(Self {
             parent: Some(parent),
-            names_to_symbols: HashMap::new(),
+            ..Default::default()
         }))
     }
 
+    /// Finds the symbol with the given name, in this table or in its ancestors. A name
+    /// such as `module::name` refers to a function of an imported module: its constants
+    /// and extern functions are not visible.
     fn lookup(&self, name: &str) -> Option<Symbol<'input>> {
-        self.names_to_symbols.get(name).cloned().or_else(|| {
-            self.parent
-                .as_ref()
-                .and_then(|parent| parent.borrow().lookup(name))
-        })
+        if let Some(symbol) = self.names_to_symbols.get(name) {
+            return Some(symbol.clone());
+        }
+        if let Some((module, function)) = name.split_once("::") {
+            if let Some(imported) = self.imported_modules.get(module) {
+                return match imported.borrow().names_to_symbols.get(function) {
+                    Some(symbol @ Symbol::Function { .. }) => Some(symbol.clone()),
+                    _ => None,
+                };
+            }
+        }
+        self.parent
+            .as_ref()
+            .and_then(|parent| parent.borrow().lookup(name))
+    }
+
+    /// Makes the functions of another module visible as `name::function`
+    fn import(&mut self, name: &'input str, module: SymbolTableRef<'input>) {
+        self.imported_modules.insert(name, module);
     }
 
     fn put(&mut self, symbol: Symbol<'input>) {
@@ -122,7 +718,7 @@ impl<'input> SymbolTable<'input> {
 
     /// Updates the location of the given name. It's important that this happens in the
     /// declaring scope of the value, because if we have something like:
-    /// ```
+    /// ```text
     /// let a = 1;
     /// { a = 2; }
     /// return a
@@ -137,262 +733,1424 @@ impl<'input> SymbolTable<'input> {
                     parent.borrow_mut().update_location(name, register);
                 }
             },
-            Some(Symbol::Function { .. }) => panic!("cannot assign location of function {}", name),
-            Some(Symbol::Argument { .. }) => panic!("cannot assign location of arguments {}", name),
+            Some(Symbol::Function { .. }) | Some(Symbol::HostFunction { .. }) => {
+                panic!("cannot assign location of function {}", name)
+            }
+            Some(Symbol::Constant { .. }) => panic!("cannot assign location of constant {}", name),
             Some(Symbol::Variable {
                 allocated_register, ..
+            })
+            | Some(Symbol::Argument {
+                allocated_register, ..
             }) => *allocated_register = register,
         };
     }
+
+    /// Returns the current location of all the variables and arguments visible from
+    /// this scope, sorted by name
+    fn variables(&self) -> Vec<(&'input str, IrRegister)> {
+        let mut variables = BTreeMap::new();
+        self.collect_variables(&mut variables);
+        variables.into_iter().collect()
+    }
+
+    fn collect_variables(&self, variables: &mut BTreeMap<&'input str, IrRegister>) {
+        for symbol in self.names_to_symbols.values() {
+            if let Symbol::Variable {
+                name,
+                allocated_register,
+            }
+            | Symbol::Argument {
+                name,
+                allocated_register,
+            } = symbol
+            {
+                variables.insert(name, *allocated_register);
+            }
+        }
+        if let Some(parent) = &self.parent {
+            parent.borrow().collect_variables(variables);
+        }
+    }
+
+    /// Restores the location of the given variables, as returned by `variables`
+    fn update_locations(&mut self, variables: &[(&'input str, IrRegister)]) {
+        for (name, register) in variables {
+            self.update_location(name, *register);
+        }
+    }
 }
 
 #[derive(Default)]
-struct FunctionCompiler {
+struct FunctionCompiler<'input> {
     next_free_reg: IrRegister,
+    /// The registers allocated for `f64` values
+    float_registers: HashSet<IrRegister>,
+    blocks: Vec<BasicBlock>,
+    current_block: BlockId,
+    /// Whether the code being compiled can actually be executed, i.e. it does
+    /// not follow a `return` in all the paths leading to it
+    reachable: bool,
+    /// The loops enclosing the code being compiled, innermost last
+    loops: Vec<Loop<'input>>,
+    /// The errors found so far. After an error we keep compiling, to report as many
+    /// errors as possible, but the generated code is meaningless.
+    errors: Vec<FrontendError>,
+    warnings: Vec<FrontendWarning>,
+    options: FrontendOptions,
+}
+
+/// The registers holding each variable at the end of a block that flows into a merge point
+type IncomingVariables<'input> = (BlockId, Vec<(&'input str, IrRegister)>);
+
+struct Loop<'input> {
+    header: BlockId,
+    exit: BlockId,
+    /// The scope containing the loop. The variables declared inside the loop body are
+    /// not visible after it, so they never need to be merged.
+    symbol_table: SymbolTableRef<'input>,
+    /// Edges going back to the header, i.e. `continue` and the end of the body
+    back_edges: Vec<IncomingVariables<'input>>,
+    /// Edges going to the exit, i.e. `break` and the condition being false
+    exits: Vec<IncomingVariables<'input>>,
+}
+
+#[derive(Clone, Copy)]
+enum LoopEdge {
+    BackEdge,
+    Exit,
 }
 
-impl<'input> FunctionCompiler {
+impl<'input> FunctionCompiler<'input> {
     fn compile_function(
         &mut self,
         function: &Function<'input>,
         id: FunctionId,
         parent_symbol_table: SymbolTableRef<'input>,
-    ) -> Result<CompiledFunction<'input>, FrontendError> {
+    ) -> CompiledFunction<'input> {
         let symbol_table = SymbolTable::with_parent(parent_symbol_table);
-        let mut body: Vec<IrInstruction> = Vec::new();
-        Self::define_args(function, symbol_table.clone());
-        self.compile_block(&mut body, &function.block, symbol_table)?;
-        Ok(CompiledFunction {
-            name: function.name,
+        self.current_block = self.new_block();
+        self.reachable = true;
+        self.define_args(function, symbol_table.clone());
+        self.compile_block(&function.block, symbol_table);
+
+        // We do not try to figure out whether a loop condition is always true, so
+        // a function ending with an infinite loop still needs a `return` after it
+        if self.reachable {
+            if self.options.implicit_return_zero {
+                let reg = if function.return_type.is_float() {
+                    self.compile_float_constant(0.0)
+                } else {
+                    self.compile_constant(0)
+                };
+                self.emit(IrInstruction::Ret { reg });
+            } else {
+                // Point to the closing brace of the function
+                self.errors.push(FrontendError::MissingReturn {
+                    function_name: function.name.to_string(),
+                    span: Span::new(function.span.end - 1, function.span.end),
+                });
+            }
+        }
+
+        let blocks = Self::sort_blocks_in_reverse_post_order(std::mem::take(&mut self.blocks));
+        let (blocks, num_used_registers, float_registers) = Self::compact_registers(
+            blocks,
+            self.next_free_reg.0,
+            &std::mem::take(&mut self.float_registers),
+        );
+        CompiledFunction {
+            name: function.name.into(),
             id,
-            num_args: function.args.len(),
-            num_used_registers: self.next_free_reg.0,
-            body,
-        })
+            arg_classes: function
+                .args
+                .iter()
+                .map(|arg| RegisterClass::from(arg.ty))
+                .collect(),
+            num_used_registers,
+            blocks,
+            float_registers,
+            overflow_mode: self.options.overflow_mode,
+        }
     }
 
-    fn define_args(f: &Function<'input>, symbol_table: SymbolTableRef<'input>) {
+    /// Copies all arguments into registers at the beginning of the function. Doing it
+    /// eagerly means that arguments behave exactly like variables, even when they are
+    /// reassigned in only some branches.
+    fn define_args(&mut self, f: &Function<'input>, symbol_table: SymbolTableRef<'input>) {
         for (index, arg) in f.args.iter().enumerate() {
-            symbol_table
-                .borrow_mut()
-                .put(Symbol::Argument { name: arg, index });
+            if let Some(previous) = f.args[..index].iter().find(|prev| prev.name == arg.name) {
+                self.errors.push(FrontendError::ArgumentAlreadyDefined {
+                    name: arg.name.to_string(),
+                    span: arg.span,
+                    previous_definition: previous.span,
+                });
+            }
+
+            let reg = self.allocate_reg_for(arg.ty);
+            self.emit(IrInstruction::MvArg {
+                dest: reg,
+                arg: index.into(),
+            });
+            // The caller might be the host, so we cannot assume that the value fits
+            let reg = self.normalize(reg, arg.ty);
+            symbol_table.borrow_mut().put(Symbol::Argument {
+                name: arg.name,
+                allocated_register: reg,
+            });
         }
     }
 
     fn compile_block(
         &mut self,
-        body: &mut Vec<IrInstruction>,
         block: &Block<'input>,
         parent_symbol_table: SymbolTableRef<'input>,
-    ) -> Result<(), FrontendError> {
+    ) {
         let symbol_table = SymbolTable::with_parent(parent_symbol_table);
+        // We warn only about the first statement that cannot be reached, and only if
+        // the block itself can be reached, to avoid repeating the same warning
+        let mut should_warn_if_unreachable = self.reachable;
         for element in block.iter() {
-            match element {
-                BlockElement::NestedBlock(nested) => {
-                    self.compile_block(body, nested, symbol_table.clone())?
+            if should_warn_if_unreachable && !self.reachable {
+                self.warnings
+                    .push(FrontendWarning::UnreachableStatement { span: element.span });
+                should_warn_if_unreachable = false;
+            }
+
+            match &element.kind {
+                BlockElementKind::NestedBlock(nested) => {
+                    self.compile_block(nested, symbol_table.clone())
                 }
-                BlockElement::LetStatement { name, expression } => {
-                    match symbol_table.borrow().lookup(name) {
+                BlockElementKind::LetStatement {
+                    name, expression, ..
+                } => {
+                    let reg = self.compile_expression(expression, symbol_table.clone());
+                    let existing_symbol = symbol_table.borrow().lookup(name);
+                    match existing_symbol {
                         Some(Symbol::Variable { .. }) => {
-                            return Err(FrontendError::VariableAlreadyDefined {
+                            self.errors.push(FrontendError::VariableAlreadyDefined {
                                 name: name.to_string(),
+                                span: element.span,
                             });
                         }
                         Some(Symbol::Argument { .. }) => {
-                            return Err(FrontendError::VariableCannotShadowArgument {
-                                name: name.to_string(),
+                            self.errors
+                                .push(FrontendError::VariableCannotShadowArgument {
+                                    name: name.to_string(),
+                                    span: element.span,
+                                });
+                        }
+                        _ => {
+                            symbol_table.borrow_mut().put(Symbol::Variable {
+                                name,
+                                allocated_register: reg,
                             });
                         }
-                        _ => (),
                     }
-                    let reg = self.compile_expression(body, expression, symbol_table.clone())?;
-                    symbol_table.borrow_mut().put(Symbol::Variable {
-                        name,
-                        allocated_register: reg,
-                    });
                 }
-                BlockElement::AssignmentStatement { name, expression } => {
+                BlockElementKind::AssignmentStatement { name, expression } => {
+                    let num_previous_errors = self.errors.len();
+                    let reg = self.compile_expression(expression, symbol_table.clone());
                     let existing_symbol = symbol_table.borrow().lookup(name);
+                    // A compound assignment such as `x += 1` also reads the variable, so
+                    // it might have been reported as not defined already
+                    let already_reported =
+                        self.errors[num_previous_errors..]
+                            .iter()
+                            .any(|error| match error {
+                                FrontendError::VariableNotDefined { name: read, .. } => {
+                                    read == name
+                                }
+                                _ => false,
+                            });
                     match existing_symbol {
-                        Some(Symbol::Variable { .. }) => {
-                            let reg =
-                                self.compile_expression(body, expression, symbol_table.clone())?;
+                        Some(Symbol::Variable { .. }) | Some(Symbol::Argument { .. }) => {
                             symbol_table.borrow_mut().update_location(name, reg);
                         }
-                        Some(Symbol::Argument { name, index }) => {
-                            let reg = self.allocate_reg();
-                            body.push(IrInstruction::MvArg {
-                                dest: reg,
-                                arg: index.into(),
-                            });
-
-                            // Overwrite the entry in the symbol table so that future lookups will not need
-                            // to copy again the argument into a register
-                            symbol_table.borrow_mut().put(Symbol::Variable {
-                                name,
-                                allocated_register: reg,
+                        Some(Symbol::Constant { .. }) => {
+                            self.errors.push(FrontendError::CannotAssignToConstant {
+                                name: name.to_string(),
+                                span: element.span,
                             });
-
-                            let reg =
-                                self.compile_expression(body, expression, symbol_table.clone())?;
-                            symbol_table.borrow_mut().update_location(name, reg);
                         }
+                        _ if already_reported => {}
                         _ => {
-                            return Err(FrontendError::VariableNotDefined {
+                            self.errors.push(FrontendError::VariableNotDefined {
                                 name: name.to_string(),
+                                span: element.span,
                             });
                         }
                     }
                 }
-                BlockElement::ReturnStatement(expression) => {
-                    let reg = self.compile_expression(body, expression, symbol_table.clone())?;
-                    body.push(IrInstruction::Ret { reg });
+                BlockElementKind::ExpressionStatement(expression) => {
+                    self.compile_expression(expression, symbol_table.clone());
+                }
+                BlockElementKind::ReturnStatement(expression) => {
+                    let reg = self.compile_expression(expression, symbol_table.clone());
+                    self.emit(IrInstruction::Ret { reg });
+                    self.reachable = false;
+                }
+                BlockElementKind::IfStatement {
+                    condition,
+                    then_block,
+                    else_block,
+                } => self.compile_if(
+                    condition,
+                    then_block,
+                    else_block.as_ref(),
+                    symbol_table.clone(),
+                ),
+                BlockElementKind::WhileStatement { condition, body } => {
+                    self.compile_while(condition, body, symbol_table.clone())
+                }
+                BlockElementKind::BreakStatement => {
+                    if self.loops.is_empty() {
+                        self.errors
+                            .push(FrontendError::BreakOutsideLoop { span: element.span });
+                    } else {
+                        self.jump_out_of_loop(LoopEdge::Exit);
+                    }
+                }
+                BlockElementKind::ContinueStatement => {
+                    if self.loops.is_empty() {
+                        self.errors
+                            .push(FrontendError::ContinueOutsideLoop { span: element.span });
+                    } else {
+                        self.jump_out_of_loop(LoopEdge::BackEdge);
+                    }
+                }
+            }
+        }
+    }
+
+    fn compile_if(
+        &mut self,
+        condition: &Expression,
+        then_block: &Block<'input>,
+        else_block: Option<&Block<'input>>,
+        symbol_table: SymbolTableRef<'input>,
+    ) {
+        let cond = self.compile_expression(condition, symbol_table.clone());
+        let reachable = self.reachable;
+        let variables_before = symbol_table.borrow().variables();
+
+        let then_target = self.new_block();
+        let merge_target = self.new_block();
+        let else_target = match else_block {
+            Some(_) => self.new_block(),
+            None => merge_target,
+        };
+        self.emit(IrInstruction::Br {
+            cond,
+            if_true: then_target,
+            if_false: else_target,
+        });
+        let branch_block = self.current_block;
+
+        let mut incoming = Vec::new();
+        self.compile_branch(
+            then_target,
+            merge_target,
+            then_block,
+            reachable,
+            symbol_table.clone(),
+            &mut incoming,
+        );
+        symbol_table
+            .borrow_mut()
+            .update_locations(&variables_before);
+
+        match else_block {
+            Some(else_block) => {
+                self.compile_branch(
+                    else_target,
+                    merge_target,
+                    else_block,
+                    reachable,
+                    symbol_table.clone(),
+                    &mut incoming,
+                );
+                symbol_table
+                    .borrow_mut()
+                    .update_locations(&variables_before);
+            }
+            None if reachable => incoming.push((branch_block, variables_before)),
+            None => {}
+        }
+
+        self.current_block = merge_target;
+        self.reachable = !incoming.is_empty();
+        self.merge_variables(&incoming, symbol_table);
+    }
+
+    /// Compiles one arm of a conditional, starting in the block `target` and jumping
+    /// to `merge_target` at the end. If the end of the arm is reachable, the location
+    /// of all variables is stored in `incoming`
+    fn compile_branch(
+        &mut self,
+        target: BlockId,
+        merge_target: BlockId,
+        block: &Block<'input>,
+        reachable: bool,
+        symbol_table: SymbolTableRef<'input>,
+        incoming: &mut Vec<IncomingVariables<'input>>,
+    ) {
+        self.current_block = target;
+        self.reachable = reachable;
+        self.compile_block(block, symbol_table.clone());
+        if self.reachable {
+            self.emit(IrInstruction::Jmp {
+                target: merge_target,
+            });
+            incoming.push((self.current_block, symbol_table.borrow().variables()));
+        }
+    }
+
+    /// Compiles a `while` loop. The variables that are reassigned in the body get a `Phi`
+    /// in the loop header, whose sources coming from the back-edges are only filled once
+    /// the whole body has been compiled:
+    /// ```text
+    /// pre_header:
+    ///   jmp header
+    /// header:
+    ///   phi for all reassigned variables
+    ///   br cond, body, exit
+    /// body:
+    ///   ...
+    ///   jmp header
+    /// exit:
+    ///   phi merging the header and all the `break`
+    /// ```
+    fn compile_while(
+        &mut self,
+        condition: &Expression,
+        body: &Block<'input>,
+        symbol_table: SymbolTableRef<'input>,
+    ) {
+        let reachable = self.reachable;
+        let header = self.new_block();
+        let body_target = self.new_block();
+        let exit = self.new_block();
+        self.emit(IrInstruction::Jmp { target: header });
+        let pre_header = self.current_block;
+
+        self.current_block = header;
+        let mut phis = Vec::new();
+        if reachable {
+            let reassigned = reassigned_variables(body);
+            let variables_before = symbol_table.borrow().variables();
+            for (name, reg) in variables_before {
+                if reassigned.contains(name) {
+                    let dest = self.allocate_reg_like(reg);
+                    self.emit(IrInstruction::Phi {
+                        dest,
+                        sources: vec![(pre_header, reg)],
+                    });
+                    phis.push((name, dest));
+                }
+            }
+            symbol_table.borrow_mut().update_locations(&phis);
+        }
+        let variables_in_header = symbol_table.borrow().variables();
+
+        let cond = self.compile_expression(condition, symbol_table.clone());
+        self.emit(IrInstruction::Br {
+            cond,
+            if_true: body_target,
+            if_false: exit,
+        });
+        let mut exits = Vec::new();
+        if reachable {
+            exits.push((self.current_block, variables_in_header.clone()));
+        }
+
+        self.loops.push(Loop {
+            header,
+            exit,
+            symbol_table: symbol_table.clone(),
+            back_edges: Vec::new(),
+            exits,
+        });
+        self.current_block = body_target;
+        self.compile_block(body, symbol_table.clone());
+        self.jump_out_of_loop(LoopEdge::BackEdge);
+        let current_loop = self.loops.pop().unwrap();
+
+        // Now that we know all the back-edges, we can complete the phis in the header
+        for instruction in self.blocks[header.0].body.iter_mut() {
+            if let IrInstruction::Phi { dest, sources } = instruction {
+                let (name, _) = phis.iter().find(|(_, reg)| reg == dest).unwrap();
+                for (block, variables) in current_loop.back_edges.iter() {
+                    let (_, reg) = variables.iter().find(|(n, _)| n == name).unwrap();
+                    sources.push((*block, *reg));
                 }
             }
         }
-        Ok(())
+
+        symbol_table
+            .borrow_mut()
+            .update_locations(&variables_in_header);
+        self.current_block = exit;
+        self.reachable = !current_loop.exits.is_empty();
+        self.merge_variables(&current_loop.exits, symbol_table);
+    }
+
+    /// Jumps from the current position to the header or to the exit of the innermost
+    /// loop. If the current position is reachable, the location of all variables is
+    /// recorded, so that they can be merged at the target
+    fn jump_out_of_loop(&mut self, edge: LoopEdge) {
+        if !self.reachable {
+            return;
+        }
+        let current_loop = self.loops.last().unwrap();
+        let target = match edge {
+            LoopEdge::BackEdge => current_loop.header,
+            LoopEdge::Exit => current_loop.exit,
+        };
+        self.emit(IrInstruction::Jmp { target });
+
+        let current_block = self.current_block;
+        let current_loop = self.loops.last_mut().unwrap();
+        let variables = current_loop.symbol_table.borrow().variables();
+        match edge {
+            LoopEdge::BackEdge => current_loop.back_edges.push((current_block, variables)),
+            LoopEdge::Exit => current_loop.exits.push((current_block, variables)),
+        }
+        self.reachable = false;
+    }
+
+    /// Updates the location of all variables at a merge point, generating a `Phi` for
+    /// all the variables that were assigned differently in the incoming blocks
+    fn merge_variables(
+        &mut self,
+        incoming: &[IncomingVariables<'input>],
+        symbol_table: SymbolTableRef<'input>,
+    ) {
+        let Some((_, first_variables)) = incoming.first() else {
+            return;
+        };
+
+        for (i, (name, reg)) in first_variables.iter().enumerate() {
+            let reg = if incoming.iter().all(|(_, variables)| variables[i].1 == *reg) {
+                *reg
+            } else {
+                let dest = self.allocate_reg_like(*reg);
+                self.emit(IrInstruction::Phi {
+                    dest,
+                    sources: incoming
+                        .iter()
+                        .map(|(block, variables)| (*block, variables[i].1))
+                        .collect(),
+                });
+                dest
+            };
+            symbol_table.borrow_mut().update_location(name, reg);
+        }
     }
 
     fn compile_expression(
         &mut self,
-        body: &mut Vec<IrInstruction>,
         expression: &Expression,
         symbol_table: SymbolTableRef<'input>,
-    ) -> Result<IrRegister, FrontendError> {
-        match expression {
-            Expression::Identifier(name) => {
+    ) -> IrRegister {
+        match &expression.kind {
+            ExpressionKind::Identifier(name) => {
                 let symbol = symbol_table.borrow().lookup(name);
                 match symbol {
                     Some(Symbol::Variable {
                         allocated_register, ..
-                    }) => Ok(allocated_register),
-                    Some(Symbol::Argument { name, index }) => {
-                        let reg = self.allocate_reg();
-                        body.push(IrInstruction::MvArg {
-                            dest: reg,
-                            arg: index.into(),
-                        });
-
-                        // Overwrite the entry in the symbol table so that future lookups will not need
-                        // to copy again the argument into a register
-                        symbol_table.borrow_mut().put(Symbol::Variable {
-                            name,
-                            allocated_register: reg,
-                        });
-
-                        Ok(reg)
+                    })
+                    | Some(Symbol::Argument {
+                        allocated_register, ..
+                    }) => allocated_register,
+                    // The optimizer will propagate the value to wherever it is used
+                    Some(Symbol::Constant { value, .. }) => {
+                        self.compile_literal(value, expression.ty)
                     }
-                    _ => Err(FrontendError::VariableNotDefined {
+                    _ => self.invalid_expression(FrontendError::VariableNotDefined {
                         name: name.to_string(),
+                        span: expression.span,
                     }),
                 }
             }
-            Expression::Number(n) => {
-                let reg = self.allocate_reg();
-                body.push(IrInstruction::Mvi { dest: reg, val: *n });
-                Ok(reg)
+            ExpressionKind::Number(n) | ExpressionKind::UnsignedNumber(n) => {
+                self.compile_literal(*n, expression.ty)
             }
-            Expression::FunctionCall(call) => {
-                let Some(Symbol::Function {
-                    id: function_id,
-                    signature,
+            ExpressionKind::Float(value) => self.compile_float_constant(*value),
+            ExpressionKind::FunctionCall(call) => {
+                if let Some(intrinsic) = Intrinsic::from_name(call.name) {
+                    return self.compile_intrinsic_call(
+                        intrinsic,
+                        call,
+                        expression.span,
+                        symbol_table,
+                    );
+                }
+
+                let dest = self.allocate_reg();
+                let args = call
+                    .args
+                    .iter()
+                    .map(|arg| self.compile_expression(arg, symbol_table.clone()))
+                    .collect::<Vec<_>>();
+
+                let symbol = symbol_table.borrow().lookup(call.name);
+                // The number of arguments of an indirect call can only be checked at runtime
+                if let Some(Symbol::Variable {
+                    allocated_register: handle,
+                    ..
+                })
+                | Some(Symbol::Argument {
+                    allocated_register: handle,
                     ..
-                }) = symbol_table.borrow().lookup(call.name)
+                }) = symbol
+                {
+                    self.emit(IrInstruction::CallIndirect { dest, handle, args });
+                    return dest;
+                }
+                let (Some(Symbol::Function { signature, .. })
+                | Some(Symbol::HostFunction { signature, .. })) = &symbol
                 else {
-                    return Err(FrontendError::UnknownFunctionCalled {
+                    return self.invalid_expression(FrontendError::UnknownFunctionCalled {
                         name: call.name.to_string(),
+                        span: expression.span,
                     });
                 };
 
                 if call.args.len() != signature.num_arguments {
-                    return Err(FrontendError::InvalidArgumentsToFunctionCall {
-                        function_name: call.name.to_string(),
-                        expected: signature.num_arguments,
-                        actual: call.args.len(),
-                    });
+                    return self.invalid_expression(
+                        FrontendError::InvalidArgumentsToFunctionCall {
+                            function_name: call.name.to_string(),
+                            expected: signature.num_arguments,
+                            actual: call.args.len(),
+                            span: expression.span,
+                        },
+                    );
                 }
 
-                let dest = self.allocate_reg();
-                let args = call
-                    .args
-                    .iter()
-                    .map(|arg| self.compile_expression(body, arg, symbol_table.clone()))
-                    .collect::<Result<Vec<_>, _>>()?;
-                body.push(IrInstruction::Call {
-                    dest,
-                    name: call.name.to_string(),
-                    function_id,
-                    args,
-                });
-                Ok(dest)
+                match symbol {
+                    Some(Symbol::HostFunction { id, signature, .. }) => {
+                        self.set_type(dest, signature.return_type);
+                        self.emit(IrInstruction::CallHost {
+                            dest,
+                            name: call.name.to_string(),
+                            host_function_id: id,
+                            args,
+                        });
+                        // Unlike our functions, the host might return values that do
+                        // not fit in the declared type
+                        self.normalize(dest, signature.return_type)
+                    }
+                    Some(Symbol::Function { id, signature, .. }) => {
+                        self.set_type(dest, signature.return_type);
+                        self.emit(IrInstruction::Call {
+                            dest,
+                            name: call.name.to_string(),
+                            function_id: id,
+                            args,
+                        });
+                        dest
+                    }
+                    _ => unreachable!(),
+                }
             }
-            Expression::Negate(expr) => {
-                let op = self.compile_expression(body, expr, symbol_table.clone())?;
-                let dest = self.allocate_reg();
-                body.push(IrInstruction::Neg { dest, op });
-                Ok(dest)
+            ExpressionKind::FunctionHandle(name) => {
+                let symbol = symbol_table.borrow().lookup(name);
+                match symbol {
+                    Some(Symbol::Function { id, .. }) => self.compile_constant(id.0 as i64),
+                    Some(Symbol::HostFunction { .. }) => {
+                        self.invalid_expression(FrontendError::ExternFunctionReferenced {
+                            name: name.to_string(),
+                            span: expression.span,
+                        })
+                    }
+                    _ => self.invalid_expression(FrontendError::UnknownFunctionReferenced {
+                        name: name.to_string(),
+                        span: expression.span,
+                    }),
+                }
             }
-            Expression::Add(left, right) => {
-                let op1 = self.compile_expression(body, left, symbol_table.clone())?;
-                let op2 = self.compile_expression(body, right, symbol_table)?;
-                let dest = self.allocate_reg();
-                body.push(IrInstruction::BinOp {
-                    operator: Add,
+            ExpressionKind::Boolean(b) => self.compile_constant(*b as i64),
+            ExpressionKind::Cast(expr, ty) => {
+                let op = self.compile_expression(expr, symbol_table);
+                if expr.ty.is_float() != ty.is_float() {
+                    let dest = self.allocate_reg_for(*ty);
+                    self.emit(if ty.is_float() {
+                        IrInstruction::IntToFloat { dest, op }
+                    } else {
+                        IrInstruction::FloatToInt { dest, op }
+                    });
+                    dest
+                } else if expr.ty.can_widen_to(*ty) {
+                    // Values are always normalized, so widening them is free
+                    op
+                } else {
+                    self.normalize(op, *ty)
+                }
+            }
+            ExpressionKind::Negate(expr) if expression.ty.is_float() => {
+                // Multiplying by -1 only flips the sign, which is exactly a negation
+                let op1 = self.compile_expression(expr, symbol_table.clone());
+                let op2 = self.compile_float_constant(-1.0);
+                let dest = self.allocate_reg_for(Type::F64);
+                self.emit(IrInstruction::BinOp {
+                    operator: Mul,
+                    ty: Type::F64,
                     dest,
                     op1,
                     op2,
                 });
-                Ok(dest)
+                dest
             }
-            Expression::Sub(left, right) => {
-                let op1 = self.compile_expression(body, left, symbol_table.clone())?;
-                let op2 = self.compile_expression(body, right, symbol_table)?;
+            ExpressionKind::Negate(expr)
+                if self.options.overflow_mode != OverflowMode::Wrapping =>
+            {
+                // `0 - x` detects the overflow of `-i64::MIN`, or of any unsigned value
+                let op1 = self.compile_constant(0);
+                let op2 = self.compile_expression(expr, symbol_table.clone());
                 let dest = self.allocate_reg();
-                body.push(IrInstruction::BinOp {
+                self.emit(IrInstruction::BinOp {
                     operator: Sub,
+                    ty: expression.ty,
                     dest,
                     op1,
                     op2,
                 });
-                Ok(dest)
+                dest
             }
-            Expression::Mul(left, right) => {
-                let op1 = self.compile_expression(body, left, symbol_table.clone())?;
-                let op2 = self.compile_expression(body, right, symbol_table)?;
+            ExpressionKind::Negate(expr) => {
+                let op = self.compile_expression(expr, symbol_table.clone());
                 let dest = self.allocate_reg();
-                body.push(IrInstruction::BinOp {
-                    operator: Mul,
+                self.emit(IrInstruction::Neg { dest, op });
+                self.normalize(dest, expression.ty)
+            }
+            ExpressionKind::Add(left, right) => self.compile_binop(Add, left, right, symbol_table),
+            ExpressionKind::Sub(left, right) => self.compile_binop(Sub, left, right, symbol_table),
+            ExpressionKind::Mul(left, right) => self.compile_binop(Mul, left, right, symbol_table),
+            ExpressionKind::Div(left, right) => self.compile_binop(Div, left, right, symbol_table),
+            ExpressionKind::Rem(left, right) => self.compile_binop(Rem, left, right, symbol_table),
+            ExpressionKind::BitwiseAnd(left, right) => {
+                self.compile_binop(BitwiseAnd, left, right, symbol_table)
+            }
+            ExpressionKind::BitwiseOr(left, right) => {
+                self.compile_binop(BitwiseOr, left, right, symbol_table)
+            }
+            ExpressionKind::BitwiseXor(left, right) => {
+                self.compile_binop(BitwiseXor, left, right, symbol_table)
+            }
+            ExpressionKind::ShiftLeft(left, right) => {
+                self.compile_binop(ShiftLeft, left, right, symbol_table)
+            }
+            ExpressionKind::ShiftRight(left, right) => {
+                self.compile_binop(ShiftRight, left, right, symbol_table)
+            }
+            ExpressionKind::BitwiseNot(expr) => {
+                let op = self.compile_expression(expr, symbol_table.clone());
+                let dest = self.allocate_reg();
+                self.emit(IrInstruction::BitwiseNot { dest, op });
+                self.normalize(dest, expression.ty)
+            }
+            ExpressionKind::Equal(left, right) => {
+                self.compile_binop(Equal, left, right, symbol_table)
+            }
+            ExpressionKind::NotEqual(left, right) => {
+                self.compile_binop(NotEqual, left, right, symbol_table)
+            }
+            ExpressionKind::LessThan(left, right) => {
+                self.compile_binop(LessThan, left, right, symbol_table)
+            }
+            ExpressionKind::LessThanOrEqual(left, right) => {
+                self.compile_binop(LessThanOrEqual, left, right, symbol_table)
+            }
+            ExpressionKind::GreaterThan(left, right) => {
+                self.compile_binop(GreaterThan, left, right, symbol_table)
+            }
+            ExpressionKind::GreaterThanOrEqual(left, right) => {
+                self.compile_binop(GreaterThanOrEqual, left, right, symbol_table)
+            }
+            ExpressionKind::LogicalNot(expr) => {
+                // !x is simply x == 0
+                let op1 = self.compile_expression(expr, symbol_table);
+                let op2 = self.compile_constant(0);
+                let dest = self.allocate_reg();
+                self.emit(IrInstruction::BinOp {
+                    operator: Equal,
+                    ty: Type::I64,
                     dest,
                     op1,
                     op2,
                 });
-                Ok(dest)
+                dest
+            }
+            ExpressionKind::LogicalAnd(left, right) => {
+                self.compile_short_circuit(false, left, right, symbol_table)
+            }
+            ExpressionKind::LogicalOr(left, right) => {
+                self.compile_short_circuit(true, left, right, symbol_table)
+            }
+            ExpressionKind::Match { scrutinee, arms } => {
+                self.compile_match(scrutinee, arms, expression, symbol_table)
             }
-            Expression::Div(left, right) => {
-                let op1 = self.compile_expression(body, left, symbol_table.clone())?;
-                let op2 = self.compile_expression(body, right, symbol_table)?;
+        }
+    }
+
+    fn compile_intrinsic_call(
+        &mut self,
+        intrinsic: Intrinsic,
+        call: &FunctionCall,
+        span: Span,
+        symbol_table: SymbolTableRef<'input>,
+    ) -> IrRegister {
+        let args = call
+            .args
+            .iter()
+            .map(|arg| self.compile_expression(arg, symbol_table.clone()))
+            .collect::<Vec<_>>();
+        if args.len() != intrinsic.num_arguments() {
+            return self.invalid_expression(FrontendError::InvalidArgumentsToFunctionCall {
+                function_name: call.name.to_string(),
+                expected: intrinsic.num_arguments(),
+                actual: args.len(),
+                span,
+            });
+        }
+
+        match intrinsic {
+            Intrinsic::Load => {
                 let dest = self.allocate_reg();
-                body.push(IrInstruction::BinOp {
-                    operator: Div,
+                self.emit(IrInstruction::Load {
                     dest,
-                    op1,
-                    op2,
+                    base: args[0],
+                    index: args[1],
+                });
+                dest
+            }
+            Intrinsic::Store => {
+                self.emit(IrInstruction::Store {
+                    base: args[0],
+                    index: args[1],
+                    value: args[2],
+                });
+                args[2]
+            }
+            Intrinsic::Abs if self.options.overflow_mode != OverflowMode::Wrapping => {
+                // Like for negations, `0 - x` detects the overflow of `-i64::MIN`
+                let zero = self.compile_constant(0);
+                let negated = self.emit_binop(Sub, zero, args[0]);
+                self.emit_binop(Max, args[0], negated)
+            }
+            Intrinsic::Abs => {
+                let dest = self.allocate_reg();
+                self.emit(IrInstruction::Abs { dest, op: args[0] });
+                dest
+            }
+            Intrinsic::Min => self.emit_binop(Min, args[0], args[1]),
+            Intrinsic::Max => self.emit_binop(Max, args[0], args[1]),
+            Intrinsic::Clamp => {
+                let at_least_low = self.emit_binop(Max, args[0], args[1]);
+                self.emit_binop(Min, at_least_low, args[2])
+            }
+            Intrinsic::Pow => self.emit_binop(Pow, args[0], args[1]),
+            Intrinsic::Gcd => self.emit_binop(Gcd, args[0], args[1]),
+            Intrinsic::Sign => {
+                let dest = self.allocate_reg();
+                self.emit(IrInstruction::Sign { dest, op: args[0] });
+                dest
+            }
+            Intrinsic::Popcount => {
+                let dest = self.allocate_reg();
+                self.emit(IrInstruction::Popcount { dest, op: args[0] });
+                dest
+            }
+        }
+    }
+
+    /// Emits an operation between two `i64`, which is the type of the arguments and of
+    /// the result of all intrinsics
+    fn emit_binop(
+        &mut self,
+        operator: BinOpOperator,
+        op1: IrRegister,
+        op2: IrRegister,
+    ) -> IrRegister {
+        let dest = self.allocate_reg();
+        self.emit(IrInstruction::BinOp {
+            operator,
+            ty: Type::I64,
+            dest,
+            op1,
+            op2,
+        });
+        dest
+    }
+
+    /// Compiles `&&` and `||`, which evaluate their right operand only if the left one
+    /// does not already determine the result. For `&&`, `short_circuit_value` is false,
+    /// and the generated code looks like:
+    /// ```text
+    /// left:
+    ///   r1 = <left operand>
+    ///   r2 = 0
+    ///   br r1, right, merge
+    /// right:
+    ///   r3 = <right operand>
+    ///   r4 = 0
+    ///   r5 = ne r3, r4
+    ///   jmp merge
+    /// merge:
+    ///   r6 = phi [left: r2, right: r5]
+    /// ```
+    /// For `||` the targets of the branch are swapped, and r2 is 1. In both cases, the
+    /// result is always 0 or 1.
+    fn compile_short_circuit(
+        &mut self,
+        short_circuit_value: bool,
+        left: &Expression,
+        right: &Expression,
+        symbol_table: SymbolTableRef<'input>,
+    ) -> IrRegister {
+        let cond = self.compile_expression(left, symbol_table.clone());
+        let short_circuit_result = self.compile_constant(short_circuit_value as i64);
+        let right_target = self.new_block();
+        let merge_target = self.new_block();
+        self.emit(if short_circuit_value {
+            IrInstruction::Br {
+                cond,
+                if_true: merge_target,
+                if_false: right_target,
+            }
+        } else {
+            IrInstruction::Br {
+                cond,
+                if_true: right_target,
+                if_false: merge_target,
+            }
+        });
+        let left_block = self.current_block;
+
+        self.current_block = right_target;
+        let op1 = self.compile_expression(right, symbol_table);
+        let op2 = self.compile_constant(0);
+        let right_result = self.allocate_reg();
+        self.emit(IrInstruction::BinOp {
+            operator: NotEqual,
+            ty: Type::I64,
+            dest: right_result,
+            op1,
+            op2,
+        });
+        self.emit(IrInstruction::Jmp {
+            target: merge_target,
+        });
+        let right_block = self.current_block;
+
+        self.current_block = merge_target;
+        let dest = self.allocate_reg();
+        self.emit(IrInstruction::Phi {
+            dest,
+            sources: vec![
+                (left_block, short_circuit_result),
+                (right_block, right_result),
+            ],
+        });
+        dest
+    }
+
+    /// Compiles a `match` by giving each arm a block that computes its value and then
+    /// jumps to a common block, where a phi selects the result:
+    /// ```text
+    /// entry:
+    ///   r1 = <scrutinee>
+    ///   <dispatch of r1 to arm0, arm1 or arm2>
+    /// arm0:
+    ///   r2 = <value of the first arm>
+    ///   jmp merge
+    /// ...
+    /// merge:
+    ///   r5 = phi [arm0: r2, arm1: r3, arm2: r4]
+    /// ```
+    fn compile_match(
+        &mut self,
+        scrutinee: &Expression,
+        arms: &[MatchArm],
+        expression: &Expression,
+        symbol_table: SymbolTableRef<'input>,
+    ) -> IrRegister {
+        let value = self.compile_expression(scrutinee, symbol_table.clone());
+        let errors = check_match_arms(arms, expression.span);
+        if !errors.is_empty() {
+            self.errors.extend(errors);
+            return self.compile_constant(0);
+        }
+
+        // Values are matched by the first arm listing them, and the arms following the
+        // `_` one are never compiled
+        let mut cases: Vec<(i64, BlockId)> = Vec::new();
+        let mut arm_blocks = Vec::new();
+        let mut default = None;
+        for arm in arms {
+            if default.is_some() {
+                self.warnings
+                    .push(FrontendWarning::UnreachableMatchArm { span: arm.span });
+                continue;
+            }
+            let block = self.new_block();
+            match &arm.patterns {
+                Some(patterns) => {
+                    cases.extend(patterns.iter().map(|pattern| (pattern.value, block)));
+                }
+                None => default = Some(block),
+            }
+            arm_blocks.push((arm, block));
+        }
+        let default = default.expect("match should have a wildcard arm");
+        cases.sort_by_key(|(value, _)| *value);
+        self.compile_match_dispatch(value, scrutinee.ty, &cases, default);
+
+        let merge_target = self.new_block();
+        let mut sources = Vec::with_capacity(arm_blocks.len());
+        for (arm, block) in arm_blocks {
+            self.current_block = block;
+            let result = self.compile_expression(&arm.expression, symbol_table.clone());
+            // The arm might have created more blocks, e.g. for a nested `match`
+            sources.push((self.current_block, result));
+            self.emit(IrInstruction::Jmp {
+                target: merge_target,
+            });
+        }
+
+        self.current_block = merge_target;
+        let dest = self.allocate_reg_for(expression.ty);
+        self.emit(IrInstruction::Phi { dest, sources });
+        dest
+    }
+
+    /// Jumps to the block of the case with the same value as `value`, or to `default`
+    /// if there is none. The cases must be sorted by value. If their values are dense,
+    /// we use a jump table; otherwise, we do a binary search, which ends by comparing
+    /// `value` with a few cases one at a time.
+    fn compile_match_dispatch(
+        &mut self,
+        value: IrRegister,
+        ty: Type,
+        cases: &[(i64, BlockId)],
+        default: BlockId,
+    ) {
+        const MIN_JUMP_TABLE_CASES: usize = 4;
+        const MAX_LINEAR_COMPARISONS: usize = 3;
+
+        if let (Some((first, _)), Some((last, _))) = (cases.first(), cases.last()) {
+            // A table with at most two entries per case, so that it is not mostly holes
+            let num_entries = *last as i128 - *first as i128 + 1;
+            if cases.len() >= MIN_JUMP_TABLE_CASES && num_entries <= 2 * cases.len() as i128 {
+                let mut targets = vec![default; num_entries as usize];
+                for (case_value, target) in cases {
+                    targets[(*case_value as i128 - *first as i128) as usize] = *target;
+                }
+                self.emit(IrInstruction::Switch {
+                    value,
+                    first: *first,
+                    targets,
+                    default,
+                });
+                return;
+            }
+        }
+
+        if cases.len() > MAX_LINEAR_COMPARISONS {
+            let (lower, upper) = cases.split_at(cases.len() / 2);
+            let cond = self.compile_comparison(LessThan, value, upper[0].0, ty);
+            let lower_target = self.new_block();
+            let upper_target = self.new_block();
+            self.emit(IrInstruction::Br {
+                cond,
+                if_true: lower_target,
+                if_false: upper_target,
+            });
+            self.current_block = lower_target;
+            self.compile_match_dispatch(value, ty, lower, default);
+            self.current_block = upper_target;
+            self.compile_match_dispatch(value, ty, upper, default);
+            return;
+        }
+
+        for (index, (case_value, target)) in cases.iter().enumerate() {
+            let cond = self.compile_comparison(Equal, value, *case_value, ty);
+            let next = if index + 1 < cases.len() {
+                self.new_block()
+            } else {
+                default
+            };
+            self.emit(IrInstruction::Br {
+                cond,
+                if_true: *target,
+                if_false: next,
+            });
+            self.current_block = next;
+        }
+        if cases.is_empty() {
+            self.emit(IrInstruction::Jmp { target: default });
+        }
+    }
+
+    /// Compares `value`, of type `ty`, with a constant
+    fn compile_comparison(
+        &mut self,
+        operator: BinOpOperator,
+        value: IrRegister,
+        constant: i64,
+        ty: Type,
+    ) -> IrRegister {
+        let op2 = self.compile_constant(constant);
+        let dest = self.allocate_reg();
+        self.emit(IrInstruction::BinOp {
+            operator,
+            ty,
+            dest,
+            op1: value,
+            op2,
+        });
+        dest
+    }
+
+    fn compile_constant(&mut self, val: i64) -> IrRegister {
+        let dest = self.allocate_reg();
+        self.emit(IrInstruction::Mvi { dest, val });
+        dest
+    }
+
+    /// Float constants are moved into registers as their bits
+    fn compile_float_constant(&mut self, val: f64) -> IrRegister {
+        let dest = self.allocate_reg_for(Type::F64);
+        self.emit(IrInstruction::Mvi {
+            dest,
+            val: val.to_bits() as i64,
+        });
+        dest
+    }
+
+    /// Compiles an integer literal, which the type checker might have given type `f64`
+    fn compile_literal(&mut self, val: i64, ty: Type) -> IrRegister {
+        if ty.is_float() {
+            self.compile_float_constant(val as f64)
+        } else {
+            self.compile_constant(val)
+        }
+    }
+
+    fn compile_binop(
+        &mut self,
+        operator: BinOpOperator,
+        left: &Expression,
+        right: &Expression,
+        symbol_table: SymbolTableRef<'input>,
+    ) -> IrRegister {
+        // Both operands have the same type, except for shifts, where the amount can
+        // have any type and the result has the type of the left operand
+        let ty = left.ty;
+        if matches!(operator, Div | Rem) && !ty.is_float() {
+            self.check_constant_division(left, right, &symbol_table.borrow());
+        }
+        let op1 = self.compile_expression(left, symbol_table.clone());
+        let op2 = self.compile_expression(right, symbol_table);
+        let dest = match operator {
+            Add | Sub | Mul | Div => self.allocate_reg_for(ty),
+            _ => self.allocate_reg(),
+        };
+        self.emit(IrInstruction::BinOp {
+            operator,
+            ty,
+            dest,
+            op1,
+            op2,
+        });
+        match operator {
+            // The backends already keep the checked and saturating results in range
+            Add | Sub | Mul if self.options.overflow_mode != OverflowMode::Wrapping => dest,
+            // Narrower values are divided in 64 bits, so `MIN / -1` is just above the
+            // maximum of the type, and it is enough to clamp it there
+            Div if self.options.overflow_mode == OverflowMode::Saturating
+                && ty.is_narrow()
+                && !ty.is_unsigned() =>
+            {
+                let max = self.compile_constant(ty.bounds().1 as i64);
+                let clamped = self.allocate_reg_for(ty);
+                self.emit(IrInstruction::BinOp {
+                    operator: Min,
+                    ty,
+                    dest: clamped,
+                    op1: dest,
+                    op2: max,
                 });
-                Ok(dest)
+                clamped
+            }
+            Add | Sub | Mul | Div | ShiftLeft => self.normalize(dest, ty),
+            _ => dest,
+        }
+    }
+
+    /// Reports the divisions that would always trap at runtime, because their operands
+    /// are known at compile time
+    fn check_constant_division(
+        &mut self,
+        left: &Expression,
+        right: &Expression,
+        symbol_table: &SymbolTable,
+    ) {
+        match self.constant_value(right, symbol_table) {
+            Some(0) => self
+                .errors
+                .push(FrontendError::DivisionByZero { span: right.span }),
+            Some(-1)
+                if (left.ty == Type::I64
+                    || (left.ty.is_narrow()
+                        && self.options.overflow_mode == OverflowMode::Checked))
+                    && !left.ty.is_unsigned()
+                    && self.constant_value(left, symbol_table)
+                        == Some(left.ty.bounds().0 as i64) =>
+            {
+                self.errors.push(FrontendError::DivisionOverflow {
+                    span: left.span.to(right.span),
+                })
+            }
+            _ => {}
+        }
+    }
+
+    /// The value of an integer expression made only of literals and constants, if it
+    /// can be computed without errors. The optimizer will fold it in the same way.
+    fn constant_value(&self, expression: &Expression, symbol_table: &SymbolTable) -> Option<i64> {
+        let compute = |operator: BinOpOperator, left: Option<i64>, right: Option<i64>| {
+            let ty = expression.ty;
+            operator
+                .evaluate_with_overflow(ty, self.options.overflow_mode, left?, right?)
+                .map(|value| ty.normalize(value))
+        };
+        let binop = |operator: BinOpOperator, left: &Expression, right: &Expression| {
+            compute(
+                operator,
+                self.constant_value(left, symbol_table),
+                self.constant_value(right, symbol_table),
+            )
+        };
+        match &expression.kind {
+            ExpressionKind::Number(n) | ExpressionKind::UnsignedNumber(n) => Some(*n),
+            ExpressionKind::Boolean(b) => Some(*b as i64),
+            ExpressionKind::Identifier(name) => match symbol_table.lookup(name) {
+                Some(Symbol::Constant { value, .. }) => Some(value),
+                _ => None,
+            },
+            ExpressionKind::Cast(op, ty) if !ty.is_float() && !op.ty.is_float() => {
+                Some(ty.normalize(self.constant_value(op, symbol_table)?))
+            }
+            ExpressionKind::Negate(op) if !expression.ty.is_float() => {
+                compute(Sub, Some(0), self.constant_value(op, symbol_table))
             }
+            ExpressionKind::Add(left, right) => binop(Add, left, right),
+            ExpressionKind::Sub(left, right) => binop(Sub, left, right),
+            ExpressionKind::Mul(left, right) => binop(Mul, left, right),
+            _ => None,
         }
     }
 
+    /// Converts the result of an operation that might not fit in the given type,
+    /// truncating it and then sign- or zero-extending it back to 64 bits
+    fn normalize(&mut self, op: IrRegister, ty: Type) -> IrRegister {
+        if !ty.is_narrow() {
+            return op;
+        }
+        let dest = self.allocate_reg();
+        self.emit(IrInstruction::Cast { dest, op, ty });
+        dest
+    }
+
+    /// Records an error found while compiling an expression. The expression is replaced
+    /// by a constant, so that we can keep compiling the code that uses it.
+    fn invalid_expression(&mut self, error: FrontendError) -> IrRegister {
+        self.errors.push(error);
+        self.compile_constant(0)
+    }
+
     fn allocate_reg(&mut self) -> IrRegister {
         self.next_free_reg.inc()
     }
+
+    /// Allocates a register for a value of the given type
+    fn allocate_reg_for(&mut self, ty: Type) -> IrRegister {
+        let reg = self.allocate_reg();
+        self.set_type(reg, ty);
+        reg
+    }
+
+    /// Allocates a register of the same class as `other`, e.g. for a phi
+    fn allocate_reg_like(&mut self, other: IrRegister) -> IrRegister {
+        let reg = self.allocate_reg();
+        if self.float_registers.contains(&other) {
+            self.float_registers.insert(reg);
+        }
+        reg
+    }
+
+    fn set_type(&mut self, reg: IrRegister, ty: Type) {
+        if ty.is_float() {
+            self.float_registers.insert(reg);
+        }
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        let id = BlockId(self.blocks.len());
+        self.blocks.push(BasicBlock::new(id));
+        id
+    }
+
+    fn emit(&mut self, instruction: IrInstruction) {
+        if self.blocks[self.current_block.0].is_terminated() {
+            // Code following a `return` can never be executed, but we still compile it
+            // to report errors. We put it in a new block that no one jumps to.
+            self.current_block = self.new_block();
+        }
+        self.blocks[self.current_block.0].body.push(instruction);
+    }
+
+    /// Sorts the blocks in reverse post-order, so that every block comes after all of
+    /// its predecessors, and renumbers them accordingly. Blocks that are not reachable
+    /// from the entry block are dropped.
+    fn sort_blocks_in_reverse_post_order(blocks: Vec<BasicBlock>) -> Vec<BasicBlock> {
+        let mut visited = vec![false; blocks.len()];
+        let mut post_order = Vec::with_capacity(blocks.len());
+
+        // Iterative DFS: each stack entry is a block and its successors still to visit.
+        // Successors are visited in reverse, so that the "true" branch comes first
+        let mut stack = vec![(BlockId(0), blocks[0].successors())];
+        visited[0] = true;
+        while let Some((block, successors)) = stack.last_mut() {
+            if let Some(successor) = successors.pop() {
+                if !visited[successor.0] {
+                    visited[successor.0] = true;
+                    stack.push((successor, blocks[successor.0].successors()));
+                }
+            } else {
+                post_order.push(*block);
+                stack.pop();
+            }
+        }
+
+        let mut new_ids = vec![BlockId(usize::MAX); blocks.len()];
+        for (new_id, old_id) in post_order.iter().rev().enumerate() {
+            new_ids[old_id.0] = BlockId(new_id);
+        }
+
+        let mut blocks: Vec<Option<BasicBlock>> = blocks.into_iter().map(Some).collect();
+        post_order
+            .iter()
+            .rev()
+            .map(|old_id| {
+                let block = blocks[old_id.0].take().unwrap();
+                BasicBlock {
+                    id: new_ids[old_id.0],
+                    body: block
+                        .body
+                        .iter()
+                        .map(|instruction| instruction.map_blocks(|id| new_ids[id.0]))
+                        .collect(),
+                }
+            })
+            .collect()
+    }
+
+    /// Renumbers the registers, so that the ones used only by the unreachable blocks that
+    /// were dropped do not leave holes, which the register allocator does not expect.
+    /// The others keep their order, so nothing changes if no block was dropped.
+    fn compact_registers(
+        blocks: Vec<BasicBlock>,
+        num_used_registers: usize,
+        float_registers: &HashSet<IrRegister>,
+    ) -> (Vec<BasicBlock>, usize, HashSet<IrRegister>) {
+        let mut used = vec![false; num_used_registers];
+        for instruction in blocks.iter().flat_map(|block| block.body.iter()) {
+            instruction.map_registers(|reg| {
+                used[reg.0] = true;
+                reg
+            });
+        }
+
+        let mut new_registers = vec![IrRegister::new(usize::MAX); num_used_registers];
+        let mut next_free_reg = IrRegister::new(0);
+        for (reg, used) in used.iter().enumerate() {
+            if *used {
+                new_registers[reg] = next_free_reg.inc();
+            }
+        }
+        if next_free_reg.0 == num_used_registers {
+            return (blocks, num_used_registers, float_registers.clone());
+        }
+
+        let blocks = blocks
+            .into_iter()
+            .map(|block| BasicBlock {
+                id: block.id,
+                body: block
+                    .body
+                    .iter()
+                    .map(|instruction| instruction.map_registers(|reg| new_registers[reg.0]))
+                    .collect(),
+            })
+            .collect();
+        let float_registers = float_registers
+            .iter()
+            .filter(|reg| used[reg.0])
+            .map(|reg| new_registers[reg.0])
+            .collect();
+        (blocks, next_free_reg.0, float_registers)
+    }
+}
+
+/// Returns the names of all the variables that are assigned in the given block,
+/// including its nested blocks
+fn reassigned_variables<'input>(block: &Block<'input>) -> HashSet<&'input str> {
+    let mut names = HashSet::new();
+    collect_reassigned_variables(block, &mut names);
+    names
+}
+
+fn collect_reassigned_variables<'input>(block: &Block<'input>, names: &mut HashSet<&'input str>) {
+    for element in block.iter() {
+        match &element.kind {
+            BlockElementKind::AssignmentStatement { name, .. } => {
+                names.insert(name);
+            }
+            BlockElementKind::NestedBlock(nested) => collect_reassigned_variables(nested, names),
+            BlockElementKind::IfStatement {
+                then_block,
+                else_block,
+                ..
+            } => {
+                collect_reassigned_variables(then_block, names);
+                if let Some(else_block) = else_block {
+                    collect_reassigned_variables(else_block, names);
+                }
+            }
+            BlockElementKind::WhileStatement { body, .. } => {
+                collect_reassigned_variables(body, names)
+            }
+            BlockElementKind::LetStatement { .. }
+            | BlockElementKind::ReturnStatement(_)
+            | BlockElementKind::ExpressionStatement(_)
+            | BlockElementKind::BreakStatement
+            | BlockElementKind::ContinueStatement => {}
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::{
-        ir::builders::{add, call, div, mul, mvarg, mvi, neg, ret, sub},
+        ir::builders::{
+            abs, add, binop, block, br, call, call_host, call_indirect, cast, div, ftoi, jmp, load,
+            mul, mvarg, mvi, neg, phi, popcount, ret, sign, store, sub, switch, typed_binop,
+        },
+        modules::InMemoryFiles,
         parser::*,
     };
 
@@ -409,7 +2167,9 @@ mod test {
             ",
         )
         .unwrap();
-        let compiled = compile(program).unwrap();
+        let compiled = compile(program, &FrontendOptions::default())
+            .unwrap()
+            .functions;
         assert_eq!(compiled.len(), 2);
 
         let f = &compiled[0];
@@ -417,22 +2177,25 @@ mod test {
         assert_eq!(f.id, FunctionId(0));
         assert_eq!(f.num_used_registers, 12);
         assert_eq!(
-            vec![
-                mvi(0, 3),
-                mvarg(1, 0),
-                neg(2, 1),
-                mvi(3, 1),
-                add(4, 2, 3),
-                add(5, 0, 4),
-                mvi(6, 2),
-                mvi(7, 3),
-                mul(8, 6, 7),
-                call(9, "f", 1, vec![0, 4]),
-                div(10, 8, 9),
-                sub(11, 5, 10),
-                ret(11),
-            ],
-            f.body,
+            vec![block(
+                0,
+                vec![
+                    mvarg(0, 0),
+                    mvi(1, 3),
+                    neg(2, 0),
+                    mvi(3, 1),
+                    add(4, 2, 3),
+                    add(5, 1, 4),
+                    mvi(6, 2),
+                    mvi(7, 3),
+                    mul(8, 6, 7),
+                    call(9, "f", 1, vec![1, 4]),
+                    div(10, 8, 9),
+                    sub(11, 5, 10),
+                    ret(11),
+                ]
+            )],
+            f.blocks,
         );
     }
 
@@ -448,96 +2211,687 @@ mod test {
             }",
         )
         .unwrap();
-        let compiled = compile(program).unwrap();
+        let compiled = compile(program, &FrontendOptions::default())
+            .unwrap()
+            .functions;
         assert_eq!(compiled.len(), 1);
 
         let f = &compiled[0];
         assert_eq!(f.name, "the_answer");
         assert_eq!(f.num_used_registers, 2);
-        assert_eq!(f.body, vec![mvi(0, 1), mvi(1, 2), ret(1)]);
+        assert_eq!(f.blocks, vec![block(0, vec![mvi(0, 1), mvi(1, 2), ret(1)])]);
     }
 
     #[test]
-    fn can_refer_to_outside_variable_from_nested_block() {
-        let program = parse_program(
-            r"fn the_answer() {
-                let a = 1;
-                {
-                    return a;
-                }
-            }",
-        )
-        .unwrap();
-        let compiled = compile(program).unwrap();
-        assert_eq!(compiled.len(), 1);
+    fn can_compile_compound_assignments_to_arguments() {
+        let program = parse_program("fn f(x) { x += 2; x *= x; return x; }").unwrap();
+        let compiled = compile(program, &FrontendOptions::default())
+            .unwrap()
+            .functions;
 
         let f = &compiled[0];
-        assert_eq!(f.name, "the_answer");
-        assert_eq!(f.num_used_registers, 1);
-        assert_eq!(f.body, vec![mvi(0, 1), ret(0)]);
+        assert_eq!(
+            f.blocks,
+            vec![block(
+                0,
+                vec![mvarg(0, 0), mvi(1, 2), add(2, 0, 1), mul(3, 2, 2), ret(3)]
+            )]
+        );
     }
 
     #[test]
-    fn compile_error_return_undeclared_variable() {
-        let program = parse_program("fn f() { return a; }").unwrap();
-        let error = compile(program).unwrap_err();
-        assert_eq!(error.to_string(), "variable \"a\" not defined");
+    fn can_compile_dense_match_to_switch() {
+        let program =
+            parse_program("fn f(x) { return match x { 1 => 10, 2 | 3 => 20, 4 => 30, _ => 0 }; }")
+                .unwrap();
+        let compiled = compile(program, &FrontendOptions::default())
+            .unwrap()
+            .functions;
+
+        let f = &compiled[0];
+        assert_eq!(
+            f.blocks,
+            vec![
+                block(0, vec![mvarg(0, 0), switch(0, 1, vec![1, 2, 2, 3], 4)]),
+                block(1, vec![mvi(1, 10), jmp(5)]),
+                block(2, vec![mvi(2, 20), jmp(5)]),
+                block(3, vec![mvi(3, 30), jmp(5)]),
+                block(4, vec![mvi(4, 0), jmp(5)]),
+                block(
+                    5,
+                    vec![phi(5, vec![(1, 1), (2, 2), (3, 3), (4, 4)]), ret(5)]
+                ),
+            ]
+        );
     }
 
     #[test]
-    fn compile_error_assign_to_undeclared_variable() {
-        let program = parse_program("fn f() { a = 1; }").unwrap();
-        let error = compile(program).unwrap_err();
-        assert_eq!(error.to_string(), "variable \"a\" not defined");
+    fn can_compile_sparse_match_to_comparisons() {
+        let program =
+            parse_program("fn f(x) { return match x { 1 => 10, 100 | -5 => 20, _ => x }; }")
+                .unwrap();
+        let compiled = compile(program, &FrontendOptions::default())
+            .unwrap()
+            .functions;
+
+        // The values are compared in order, and the last comparison falls through to `_`
+        let f = &compiled[0];
+        assert_eq!(
+            f.blocks,
+            vec![
+                block(
+                    0,
+                    vec![mvarg(0, 0), mvi(1, -5), binop(Equal, 2, 0, 1), br(2, 4, 1)]
+                ),
+                block(1, vec![mvi(3, 1), binop(Equal, 4, 0, 3), br(4, 2, 3)]),
+                block(2, vec![mvi(7, 10), jmp(6)]),
+                block(3, vec![mvi(5, 100), binop(Equal, 6, 0, 5), br(6, 4, 5)]),
+                block(4, vec![mvi(8, 20), jmp(6)]),
+                block(5, vec![jmp(6)]),
+                block(6, vec![phi(9, vec![(2, 7), (4, 8), (5, 0)]), ret(9)]),
+            ]
+        );
     }
 
     #[test]
-    fn compile_error_double_variable_declaration() {
-        let program = parse_program("fn f() { let a = 1; let a = 2; }").unwrap();
-        let error = compile(program).unwrap_err();
-        assert_eq!(error.to_string(), "variable \"a\" already defined");
+    fn compile_error_invalid_match() {
+        let source = "fn f(x) { return match x { 1 => 2 }; }";
+        let error = compile_single_error(parse_program(source).unwrap());
+        assert_eq!(
+            error.to_string(),
+            "match must have a `_` arm, for the values not matched by the other arms"
+        );
+        assert_eq!(error.span(), Span::new(17, 35));
+
+        let source = "fn f(x) { return match x { 1 | 2 => 2, 3 | 1 => 4, _ => 0 }; }";
+        let error = compile_single_error(parse_program(source).unwrap());
+        assert_eq!(error.to_string(), "value 1 is matched by more than one arm");
+        assert_eq!(error.span(), Span::new(43, 44));
+        assert_eq!(
+            error.related_span(),
+            Some((Span::new(27, 28), "previously matched here"))
+        );
     }
 
     #[test]
-    fn compile_error_variable_declared_in_nested_block() {
+    fn arms_after_the_wildcard_are_unreachable() {
+        let source = "fn f(x) { return match x { 1 => 2, _ => 3, 4 => 5 }; }";
+        let program = parse_program(source).unwrap();
+        let warnings = compile(program, &FrontendOptions::default())
+            .unwrap()
+            .warnings;
+        let warned_arms: Vec<&str> = warnings
+            .iter()
+            .map(|warning| &source[warning.span().start..warning.span().end])
+            .collect();
+        assert_eq!(warned_arms, vec!["4 => 5"]);
+    }
+
+    #[test]
+    fn can_refer_to_outside_variable_from_nested_block() {
         let program = parse_program(
-            r"fn f() {
+            r"fn the_answer() {
+                let a = 1;
                 {
-                    let a = 1;
+                    return a;
                 }
-                return a;
             }",
         )
         .unwrap();
-        let error = compile(program).unwrap_err();
-        assert_eq!(error.to_string(), "variable \"a\" not defined");
+        let compiled = compile(program, &FrontendOptions::default())
+            .unwrap()
+            .functions;
+        assert_eq!(compiled.len(), 1);
+
+        let f = &compiled[0];
+        assert_eq!(f.name, "the_answer");
+        assert_eq!(f.num_used_registers, 1);
+        assert_eq!(f.blocks, vec![block(0, vec![mvi(0, 1), ret(0)])]);
     }
 
     #[test]
-    fn compile_error_variable_cannot_be_shadowed_in_nested_block() {
+    fn can_compile_if_else_with_phi() {
         let program = parse_program(
-            r"fn f() {
+            r"fn f(x) {
                 let a = 1;
-                {
-                    let a = 2;
+                if (x) {
+                    a = 2;
+                } else {
+                    a = 3;
                 }
                 return a;
             }",
         )
         .unwrap();
-        let error = compile(program).unwrap_err();
-        assert_eq!(error.to_string(), "variable \"a\" already defined");
-    }
+        let compiled = compile(program, &FrontendOptions::default())
+            .unwrap()
+            .functions;
+
+        let f = &compiled[0];
+        assert_eq!(f.num_used_registers, 5);
+        assert_eq!(
+            f.blocks,
+            vec![
+                block(0, vec![mvarg(0, 0), mvi(1, 1), br(0, 1, 2)]),
+                block(1, vec![mvi(2, 2), jmp(3)]),
+                block(2, vec![mvi(3, 3), jmp(3)]),
+                block(3, vec![phi(4, vec![(1, 2), (2, 3)]), ret(4)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn can_compile_while_with_phis_in_header() {
+        let program = parse_program(
+            r"fn f(n) {
+                let sum = 0;
+                while (n) {
+                    sum = sum + n;
+                    n = n - 1;
+                }
+                return sum;
+            }",
+        )
+        .unwrap();
+        let compiled = compile(program, &FrontendOptions::default())
+            .unwrap()
+            .functions;
+
+        let f = &compiled[0];
+        assert_eq!(f.num_used_registers, 7);
+        assert_eq!(
+            f.blocks,
+            vec![
+                block(0, vec![mvarg(0, 0), mvi(1, 0), jmp(1)]),
+                block(
+                    1,
+                    vec![
+                        phi(2, vec![(0, 0), (2, 6)]),
+                        phi(3, vec![(0, 1), (2, 4)]),
+                        br(2, 2, 3)
+                    ]
+                ),
+                block(2, vec![add(4, 3, 2), mvi(5, 1), sub(6, 2, 5), jmp(1)]),
+                block(3, vec![ret(3)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn can_compile_break_and_continue() {
+        let program = parse_program(
+            r"fn f(n) {
+                while (1) {
+                    n = n - 1;
+                    if (n) {
+                        continue;
+                    }
+                    break;
+                }
+                return n;
+            }",
+        )
+        .unwrap();
+        let compiled = compile(program, &FrontendOptions::default())
+            .unwrap()
+            .functions;
+
+        let f = &compiled[0];
+        assert_eq!(
+            f.blocks,
+            vec![
+                block(0, vec![mvarg(0, 0), jmp(1)]),
+                block(
+                    1,
+                    vec![phi(1, vec![(0, 0), (3, 4)]), mvi(2, 1), br(2, 2, 5)]
+                ),
+                block(2, vec![mvi(3, 1), sub(4, 1, 3), br(4, 3, 4)]),
+                // continue
+                block(3, vec![jmp(1)]),
+                // break
+                block(4, vec![jmp(5)]),
+                block(5, vec![phi(5, vec![(1, 1), (4, 4)]), ret(5)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn can_compile_short_circuit_operators() {
+        let program = parse_program(r"fn f(a, b) { return a && !b; }").unwrap();
+        let compiled = compile(program, &FrontendOptions::default())
+            .unwrap()
+            .functions;
+
+        let f = &compiled[0];
+        assert_eq!(
+            f.blocks,
+            vec![
+                block(0, vec![mvarg(0, 0), mvarg(1, 1), mvi(2, 0), br(0, 1, 2)]),
+                block(
+                    1,
+                    vec![
+                        mvi(3, 0),
+                        binop(Equal, 4, 1, 3),
+                        mvi(5, 0),
+                        binop(NotEqual, 6, 4, 5),
+                        jmp(2)
+                    ]
+                ),
+                block(2, vec![phi(7, vec![(0, 2), (1, 6)]), ret(7)]),
+            ]
+        );
+
+        let program = parse_program(r"fn f(a, b) { return a || b; }").unwrap();
+        let compiled = compile(program, &FrontendOptions::default())
+            .unwrap()
+            .functions;
+
+        let f = &compiled[0];
+        assert_eq!(
+            f.blocks,
+            vec![
+                block(0, vec![mvarg(0, 0), mvarg(1, 1), mvi(2, 1), br(0, 2, 1)]),
+                block(1, vec![mvi(3, 0), binop(NotEqual, 4, 1, 3), jmp(2)]),
+                block(2, vec![phi(5, vec![(0, 2), (1, 4)]), ret(5)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn narrow_values_are_normalized() {
+        let mut program = parse_program(r"fn f(x: u8, y: u8) -> u8 { return x / y + 1; }").unwrap();
+        assert!(
+            crate::frontend_types::check_types(&mut program, &FrontendOptions::default())
+                .is_empty()
+        );
+        let compiled = compile(program, &FrontendOptions::default())
+            .unwrap()
+            .functions;
+
+        let f = &compiled[0];
+        assert_eq!(
+            f.blocks,
+            vec![block(
+                0,
+                vec![
+                    mvarg(0, 0),
+                    cast(1, 0, Type::U8),
+                    mvarg(2, 1),
+                    cast(3, 2, Type::U8),
+                    typed_binop(BinOpOperator::Div, Type::U8, 4, 1, 3),
+                    cast(5, 4, Type::U8),
+                    mvi(6, 1),
+                    typed_binop(BinOpOperator::Add, Type::U8, 7, 5, 6),
+                    cast(8, 7, Type::U8),
+                    ret(8),
+                ]
+            )]
+        );
+    }
+
+    #[test]
+    fn checked_arithmetic_is_not_normalized() {
+        let mut program =
+            parse_program(r"fn f(x: i32, y: i32) -> i32 { return -(x * y) / 2; }").unwrap();
+        let options = FrontendOptions {
+            overflow_mode: OverflowMode::Checked,
+            ..Default::default()
+        };
+        assert!(crate::frontend_types::check_types(&mut program, &options).is_empty());
+        let compiled = compile(program, &options).unwrap().functions;
+
+        // The backend produces results that are in range, or traps, while negation
+        // becomes a subtraction from zero, which can overflow
+        let f = &compiled[0];
+        assert_eq!(f.overflow_mode, OverflowMode::Checked);
+        assert_eq!(
+            f.blocks,
+            vec![block(
+                0,
+                vec![
+                    mvarg(0, 0),
+                    cast(1, 0, Type::I32),
+                    mvarg(2, 1),
+                    cast(3, 2, Type::I32),
+                    mvi(4, 0),
+                    typed_binop(BinOpOperator::Mul, Type::I32, 5, 1, 3),
+                    typed_binop(BinOpOperator::Sub, Type::I32, 6, 4, 5),
+                    mvi(7, 2),
+                    typed_binop(BinOpOperator::Div, Type::I32, 8, 6, 7),
+                    cast(9, 8, Type::I32),
+                    ret(9),
+                ]
+            )]
+        );
+    }
+
+    #[test]
+    fn floats_use_the_float_registers() {
+        let mut program =
+            parse_program(r"fn f(n, x: f64) -> i64 { return (-x * 0.5) as i64 + n; }").unwrap();
+        assert!(
+            crate::frontend_types::check_types(&mut program, &FrontendOptions::default())
+                .is_empty()
+        );
+        let compiled = compile(program, &FrontendOptions::default())
+            .unwrap()
+            .functions;
+
+        let f = &compiled[0];
+        assert_eq!(
+            f.arg_classes,
+            vec![RegisterClass::Integer, RegisterClass::Float]
+        );
+        assert_eq!(
+            f.blocks,
+            vec![block(
+                0,
+                vec![
+                    mvarg(0, 0),
+                    mvarg(1, 1),
+                    mvi(2, (-1.0f64).to_bits() as i64),
+                    typed_binop(BinOpOperator::Mul, Type::F64, 3, 1, 2),
+                    mvi(4, 0.5f64.to_bits() as i64),
+                    typed_binop(BinOpOperator::Mul, Type::F64, 5, 3, 4),
+                    ftoi(6, 5),
+                    binop(BinOpOperator::Add, 7, 6, 0),
+                    ret(7),
+                ]
+            )]
+        );
+        assert_eq!(
+            f.float_registers,
+            HashSet::from([
+                IrRegister(1),
+                IrRegister(2),
+                IrRegister(3),
+                IrRegister(4),
+                IrRegister(5)
+            ])
+        );
+    }
+
+    fn compile_single_error(program: Program) -> FrontendError {
+        let mut errors = compile(program, &FrontendOptions::default()).unwrap_err();
+        assert_eq!(errors.len(), 1, "expected exactly one error: {:?}", errors);
+        errors.remove(0)
+    }
+
+    #[test]
+    fn compile_error_break_outside_loop() {
+        let program = parse_program(r"fn f() { break; return 0; }").unwrap();
+        let error = compile_single_error(program);
+        assert!(matches!(error, FrontendError::BreakOutsideLoop { .. }));
+    }
+
+    #[test]
+    fn compile_error_continue_outside_loop() {
+        let program = parse_program(r"fn f() { { continue; } return 0; }").unwrap();
+        let error = compile_single_error(program);
+        assert!(matches!(error, FrontendError::ContinueOutsideLoop { .. }));
+    }
+
+    #[test]
+    fn can_compile_if_without_else() {
+        let program = parse_program(
+            r"fn f(x) {
+                let a = 1;
+                if (x) {
+                    a = 2;
+                    x = a;
+                }
+                return a + x;
+            }",
+        )
+        .unwrap();
+        let compiled = compile(program, &FrontendOptions::default())
+            .unwrap()
+            .functions;
+
+        let f = &compiled[0];
+        assert_eq!(
+            f.blocks,
+            vec![
+                block(0, vec![mvarg(0, 0), mvi(1, 1), br(0, 1, 2)]),
+                block(1, vec![mvi(2, 2), jmp(2)]),
+                block(
+                    2,
+                    vec![
+                        phi(3, vec![(1, 2), (0, 1)]),
+                        phi(4, vec![(1, 2), (0, 0)]),
+                        add(5, 3, 4),
+                        ret(5)
+                    ]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn branches_that_return_do_not_reach_the_merge_block() {
+        let program = parse_program(
+            r"fn f(x) {
+                let a = 1;
+                if (x) {
+                    return a;
+                } else if (x - 1) {
+                    a = 2;
+                }
+                return a;
+            }",
+        )
+        .unwrap();
+        let compiled = compile(program, &FrontendOptions::default())
+            .unwrap()
+            .functions;
+
+        let f = &compiled[0];
+        assert_eq!(
+            f.blocks,
+            vec![
+                block(0, vec![mvarg(0, 0), mvi(1, 1), br(0, 1, 2)]),
+                block(1, vec![ret(1)]),
+                block(2, vec![mvi(2, 1), sub(3, 0, 2), br(3, 3, 4)]),
+                block(3, vec![mvi(4, 2), jmp(4)]),
+                block(4, vec![phi(5, vec![(3, 4), (2, 1)]), jmp(5)]),
+                block(5, vec![ret(5)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn unreachable_code_is_dropped() {
+        let program = parse_program(
+            r"fn f(x) {
+                if (x) {
+                    return 1;
+                } else {
+                    return 2;
+                }
+                return 3;
+            }",
+        )
+        .unwrap();
+        let compiled = compile(program, &FrontendOptions::default())
+            .unwrap()
+            .functions;
+
+        let f = &compiled[0];
+        assert_eq!(
+            f.blocks,
+            vec![
+                block(0, vec![mvarg(0, 0), br(0, 1, 2)]),
+                block(1, vec![mvi(1, 1), ret(1)]),
+                block(2, vec![mvi(2, 2), ret(2)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn statements_after_return_are_reported_once() {
+        let source = r"fn f(x) {
+                while (x) {
+                    break;
+                    x = x - 1;
+                }
+                return 1;
+                let a = 2;
+                if (x) {
+                    a = 3;
+                }
+                return a;
+            }";
+        let program = parse_program(source).unwrap();
+        let warnings = compile(program, &FrontendOptions::default())
+            .unwrap()
+            .warnings;
+        let warned_statements: Vec<&str> = warnings
+            .iter()
+            .map(|warning| &source[warning.span().start..warning.span().end])
+            .collect();
+        assert_eq!(warned_statements, vec!["x = x - 1", "let a = 2"]);
+    }
+
+    #[test]
+    fn compile_error_missing_return() {
+        let source = r"fn f(x) {
+                if (x) {
+                    return 1;
+                }
+            }";
+        let program = parse_program(source).unwrap();
+        let error = compile_single_error(program);
+        assert_eq!(
+            error.to_string(),
+            "function \"f\" does not return a value in all paths"
+        );
+        assert_eq!(error.span(), Span::new(source.len() - 1, source.len()));
+    }
+
+    #[test]
+    fn compile_error_missing_return_after_infinite_loop() {
+        let program = parse_program("fn f() { while (1) {} }").unwrap();
+        let error = compile_single_error(program);
+        assert!(matches!(error, FrontendError::MissingReturn { .. }));
+    }
+
+    #[test]
+    fn implicit_return_zero_can_be_enabled() {
+        let mut program = parse_program(
+            "fn f(x) { if (x) { return 1; } }\nfn g(x) -> f64 { if (x) { return 1.5; } }",
+        )
+        .unwrap();
+        let options = FrontendOptions {
+            implicit_return_zero: true,
+            ..Default::default()
+        };
+        assert!(crate::frontend_types::check_types(&mut program, &options).is_empty());
+        let compiled = compile(program, &options).unwrap().functions;
+
+        let f = &compiled[0];
+        assert_eq!(
+            f.blocks,
+            vec![
+                block(0, vec![mvarg(0, 0), br(0, 1, 2)]),
+                block(1, vec![mvi(1, 1), ret(1)]),
+                block(2, vec![mvi(2, 0), ret(2)]),
+            ]
+        );
+
+        // The zero returned by a function returning `f64` is a float
+        let g = &compiled[1];
+        assert_eq!(
+            g.blocks,
+            vec![
+                block(0, vec![mvarg(0, 0), br(0, 1, 2)]),
+                block(1, vec![mvi(1, 1.5f64.to_bits() as i64), ret(1)]),
+                block(2, vec![mvi(2, 0.0f64.to_bits() as i64), ret(2)]),
+            ]
+        );
+        assert_eq!(
+            g.float_registers,
+            HashSet::from([IrRegister(1), IrRegister(2)])
+        );
+    }
+
+    #[test]
+    fn compile_error_in_unreachable_code() {
+        let program = parse_program("fn f() { return 1; return a; }").unwrap();
+        let error = compile_single_error(program);
+        assert_eq!(error.to_string(), "variable \"a\" not defined");
+    }
+
+    #[test]
+    fn compile_error_return_undeclared_variable() {
+        let program = parse_program("fn f() { return a; }").unwrap();
+        let error = compile_single_error(program);
+        assert_eq!(error.to_string(), "variable \"a\" not defined");
+    }
+
+    #[test]
+    fn compile_errors_record_where_they_happened() {
+        let source = "fn f(x) { let y = x; return g(y, 1); }";
+        let program = parse_program(source).unwrap();
+        let error = compile_single_error(program);
+        let span = error.span();
+        assert_eq!(&source[span.start..span.end], "g(y, 1)");
+    }
+
+    #[test]
+    fn compile_error_assign_to_undeclared_variable() {
+        let program = parse_program("fn f() { a = 1; return 0; }").unwrap();
+        let error = compile_single_error(program);
+        assert_eq!(error.to_string(), "variable \"a\" not defined");
+    }
+
+    #[test]
+    fn compile_error_double_variable_declaration() {
+        let program = parse_program("fn f() { let a = 1; let a = 2; return a; }").unwrap();
+        let error = compile_single_error(program);
+        assert_eq!(error.to_string(), "variable \"a\" already defined");
+    }
+
+    #[test]
+    fn compile_error_variable_declared_in_nested_block() {
+        let program = parse_program(
+            r"fn f() {
+                {
+                    let a = 1;
+                }
+                return a;
+            }",
+        )
+        .unwrap();
+        let error = compile_single_error(program);
+        assert_eq!(error.to_string(), "variable \"a\" not defined");
+    }
+
+    #[test]
+    fn compile_error_variable_cannot_be_shadowed_in_nested_block() {
+        let program = parse_program(
+            r"fn f() {
+                let a = 1;
+                {
+                    let a = 2;
+                }
+                return a;
+            }",
+        )
+        .unwrap();
+        let error = compile_single_error(program);
+        assert_eq!(error.to_string(), "variable \"a\" already defined");
+    }
 
     #[test]
     fn compile_error_fn_arg_cannot_be_shadowed() {
         let program = parse_program(
             r"fn f(x) {
                 let x = 1;
+                return x;
             }",
         )
         .unwrap();
-        let error = compile(program).unwrap_err();
+        let error = compile_single_error(program);
         assert_eq!(
             error.to_string(),
             "variable \"x\" cannot shadow function argument with the same name"
@@ -547,7 +2901,7 @@ mod test {
     #[test]
     fn unknown_function_called() {
         let program = parse_program(r"fn f(x) { return g(); }").unwrap();
-        let error = compile(program).unwrap_err();
+        let error = compile_single_error(program);
         assert_eq!(error.to_string(), "unknown function \"g\" called");
     }
 
@@ -560,10 +2914,674 @@ mod test {
             ",
         )
         .unwrap();
-        let error = compile(program).unwrap_err();
+        let error = compile_single_error(program);
         assert_eq!(
             error.to_string(),
             "function \"g\" requires 1 argument(s) but was called with 0"
         );
     }
+
+    #[test]
+    fn all_errors_are_reported() {
+        let program = parse_program(
+            r"
+            fn f(x) {
+                let a = b + c;
+                continue;
+                return g(a, x);
+            }
+            fn g(y) { return z; }
+            ",
+        )
+        .unwrap();
+        let errors = compile(program, &FrontendOptions::default()).unwrap_err();
+        assert_eq!(
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            vec![
+                "variable \"b\" not defined",
+                "variable \"c\" not defined",
+                "continue statement outside of a loop",
+                "function \"g\" requires 1 argument(s) but was called with 2",
+                "variable \"z\" not defined",
+            ]
+        );
+    }
+
+    #[test]
+    fn undefined_variables_in_compound_assignments_are_reported_once() {
+        let source = "fn f() { y += 1; z++; w = 2; return 0; }";
+        let program = parse_program(source).unwrap();
+        let errors = compile(program, &FrontendOptions::default()).unwrap_err();
+        let spans = errors
+            .iter()
+            .map(|error| {
+                (
+                    error.to_string(),
+                    &source[error.span().start..error.span().end],
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            spans,
+            vec![
+                ("variable \"y\" not defined".to_string(), "y"),
+                ("variable \"z\" not defined".to_string(), "z"),
+                ("variable \"w\" not defined".to_string(), "w = 2"),
+            ]
+        );
+    }
+
+    #[test]
+    fn variables_with_invalid_initializers_are_still_defined() {
+        // Only the undefined `y` is reported, not every usage of `x` after it
+        let program = parse_program("fn f() { let x = y; let z = x + 1; return x * z; }").unwrap();
+        let error = compile_single_error(program);
+        assert_eq!(error.to_string(), "variable \"y\" not defined");
+    }
+
+    #[test]
+    fn compile_error_function_defined_twice() {
+        let source = "fn f() { return 1; }\nfn g() { return f(); }\nfn f(x) { return x; }";
+        let program = parse_program(source).unwrap();
+        let error = compile_single_error(program);
+        assert_eq!(error.to_string(), "function \"f\" already defined");
+        assert_eq!(error.span(), Span::new(47, 48));
+        assert_eq!(
+            error.related_span(),
+            Some((Span::new(3, 4), "previously defined here"))
+        );
+    }
+
+    #[test]
+    fn compile_error_argument_defined_twice() {
+        let source = "fn f(x, y, x) { return x; }";
+        let program = parse_program(source).unwrap();
+        let error = compile_single_error(program);
+        assert_eq!(error.to_string(), "function argument \"x\" already defined");
+        assert_eq!(error.span(), Span::new(11, 12));
+        assert_eq!(
+            error.related_span(),
+            Some((Span::new(5, 6), "previously defined here"))
+        );
+    }
+
+    #[test]
+    fn constants_are_compiled_as_immediate_values() {
+        let program = parse_program(
+            r"
+            fn f(x) { return x * SCALE; }
+            const SCALE = 1000 * OFFSET;
+            const OFFSET = -(1 << 2) + 7;
+            ",
+        )
+        .unwrap();
+        let compiled = compile(program, &FrontendOptions::default())
+            .unwrap()
+            .functions;
+
+        let f = &compiled[0];
+        assert_eq!(
+            f.blocks,
+            vec![block(
+                0,
+                vec![mvarg(0, 0), mvi(1, 3000), mul(2, 0, 1), ret(2)]
+            )]
+        );
+    }
+
+    #[test]
+    fn constants_can_use_match() {
+        // Only the selected arm is evaluated
+        let source = "const KIND = 2; const SIZE = match KIND { 1 => 1 / 0, 2 | 3 => 16, _ => 0 };
+            fn f() { return SIZE; }";
+        let program = parse_program(source).unwrap();
+        let compiled = compile(program, &FrontendOptions::default())
+            .unwrap()
+            .functions;
+        assert_eq!(compiled[0].blocks, vec![block(0, vec![mvi(0, 16), ret(0)])]);
+
+        let source = "const SIZE = match 1 { 1 => 2 }; fn f() { return SIZE; }";
+        let error = compile_single_error(parse_program(source).unwrap());
+        assert!(matches!(error, FrontendError::MatchWithoutWildcard { .. }));
+    }
+
+    #[test]
+    fn constants_follow_the_overflow_mode() {
+        let source = "const A = -(-9223372036854775807 - 1); const B = A * 2; fn f() { return B; }";
+        let program = parse_program(source).unwrap();
+        let compiled = compile(program, &FrontendOptions::default())
+            .unwrap()
+            .functions;
+        assert_eq!(compiled[0].blocks, vec![block(0, vec![mvi(0, 0), ret(0)])]);
+
+        let options = FrontendOptions {
+            overflow_mode: OverflowMode::Saturating,
+            ..Default::default()
+        };
+        let program = parse_program(source).unwrap();
+        let compiled = compile(program, &options).unwrap().functions;
+        assert_eq!(
+            compiled[0].blocks,
+            vec![block(0, vec![mvi(0, i64::MAX), ret(0)])]
+        );
+
+        let options = FrontendOptions {
+            overflow_mode: OverflowMode::Checked,
+            ..Default::default()
+        };
+        let program = parse_program(source).unwrap();
+        let errors = compile(program, &options).unwrap_err();
+        assert!(matches!(
+            errors.as_slice(),
+            [FrontendError::InvalidConstantOperation { span }] if *span == Span::new(10, 37)
+        ));
+    }
+
+    #[test]
+    fn compile_error_constant_divisions() {
+        let source = "const ZERO = 0; const MIN = -9223372036854775807 - 1;
+            fn f(x) { let a = x / (ZERO * 2); let b = MIN % -1; return 1 / (MIN / -1); }";
+        let program = parse_program(source).unwrap();
+        let errors = compile(program, &FrontendOptions::default()).unwrap_err();
+        let spans = errors
+            .iter()
+            .map(|error| {
+                (
+                    error.to_string(),
+                    &source[error.span().start..error.span().end],
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            spans,
+            vec![
+                ("division by zero".to_string(), "(ZERO * 2)"),
+                (
+                    "division overflow: the minimum of a signed type cannot be divided by -1"
+                        .to_string(),
+                    "MIN % -1"
+                ),
+                (
+                    "division overflow: the minimum of a signed type cannot be divided by -1"
+                        .to_string(),
+                    "MIN / -1"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn constants_can_be_shadowed_by_variables() {
+        let program = parse_program("const A = 1; fn f() { let A = 2; return A; }").unwrap();
+        let compiled = compile(program, &FrontendOptions::default())
+            .unwrap()
+            .functions;
+        assert_eq!(compiled[0].blocks, vec![block(0, vec![mvi(0, 2), ret(0)])]);
+    }
+
+    #[test]
+    fn compile_error_constants_depending_on_each_other() {
+        let source = "const A = B + 1;\nconst B = C * 2;\nconst C = A;\nfn f() { return A; }";
+        let program = parse_program(source).unwrap();
+        let error = compile_single_error(program);
+        assert_eq!(
+            error.to_string(),
+            "constant \"A\" depends on itself: A -> B -> C -> A"
+        );
+        assert_eq!(error.span(), Span::new(6, 7));
+
+        let program = parse_program("const A = A; fn f() { return 0; }").unwrap();
+        let error = compile_single_error(program);
+        assert_eq!(
+            error.to_string(),
+            "constant \"A\" depends on itself: A -> A"
+        );
+    }
+
+    #[test]
+    fn compile_error_invalid_constants() {
+        let program = parse_program(
+            r"
+            const A = x;
+            const B = f();
+            const C = 1 / (A - A);
+            const D = 1 || 1 / 0;
+            const E = 1.5;
+            const F = &f;
+            fn f() { return 0; }
+            ",
+        )
+        .unwrap();
+        let errors = compile(program, &FrontendOptions::default()).unwrap_err();
+        assert_eq!(
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            vec![
+                "constant \"x\" not defined",
+                "functions cannot be called in the value of a constant",
+                "division by zero or overflow in the value of a constant",
+                "floating-point values cannot be used in the value of a constant",
+                "function handles cannot be used in the value of a constant",
+            ]
+        );
+    }
+
+    #[test]
+    fn compile_error_assign_to_constant() {
+        let program = parse_program("const A = 1; fn f() { A = 2; return A; }").unwrap();
+        let error = compile_single_error(program);
+        assert_eq!(error.to_string(), "cannot assign to constant \"A\"");
+    }
+
+    #[test]
+    fn compile_error_constant_defined_twice() {
+        let source = "const A = 1;\nconst A = 2;\nfn A() { return 0; }";
+        let program = parse_program(source).unwrap();
+        let errors = compile(program, &FrontendOptions::default()).unwrap_err();
+        assert_eq!(
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            vec![
+                "constant \"A\" already defined",
+                "function \"A\" already defined"
+            ]
+        );
+        for error in errors {
+            assert_eq!(
+                error.related_span(),
+                Some((Span::new(6, 7), "previously defined here"))
+            );
+        }
+    }
+
+    #[test]
+    fn variables_can_be_called_as_function_handles() {
+        let program = parse_program(
+            r"
+            fn apply(f, x) { return f(x, 1); }
+            fn g() { let h = &h; return apply(h, 2); }
+            fn h(a, b) { return a; }
+            ",
+        )
+        .unwrap();
+        let compiled = compile(program, &FrontendOptions::default())
+            .unwrap()
+            .functions;
+
+        assert_eq!(
+            compiled[0].blocks,
+            vec![block(
+                0,
+                vec![
+                    mvarg(0, 0),
+                    mvarg(1, 1),
+                    mvi(3, 1),
+                    call_indirect(2, 0, vec![1, 3]),
+                    ret(2)
+                ]
+            )]
+        );
+        assert_eq!(
+            compiled[1].blocks,
+            vec![block(
+                0,
+                vec![
+                    mvi(0, 2),
+                    mvi(2, 2),
+                    call(1, "apply", 0, vec![0, 2]),
+                    ret(1)
+                ]
+            )]
+        );
+    }
+
+    #[test]
+    fn compile_error_invalid_function_handles() {
+        let program = parse_program(
+            r"
+            extern fn lookup(key);
+            const ANSWER = 42;
+            fn f(x) { return x(&lookup) + &ANSWER + &g + &load; }
+            ",
+        )
+        .unwrap();
+        let host_functions = [HostFunctionSignature {
+            name: "lookup".to_string(),
+            argument_classes: vec![RegisterClass::Integer; 1],
+            return_class: RegisterClass::Integer,
+        }];
+        let errors =
+            compile_with_host_functions(program, &FrontendOptions::default(), &host_functions)
+                .unwrap_err();
+        assert_eq!(
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            vec![
+                "extern function \"lookup\" cannot be referenced, only the functions of the program can",
+                "unknown function \"ANSWER\" referenced",
+                "unknown function \"g\" referenced",
+                "unknown function \"load\" referenced",
+            ]
+        );
+    }
+
+    #[test]
+    fn extern_functions_are_called_directly() {
+        let program = parse_program(
+            r"
+            extern fn lookup(key);
+            fn f(x) { return lookup(x) + g(); }
+            fn g() { return 1; }
+            ",
+        )
+        .unwrap();
+        let host_functions = [
+            HostFunctionSignature {
+                name: "unused".to_string(),
+                argument_classes: vec![],
+                return_class: RegisterClass::Integer,
+            },
+            HostFunctionSignature {
+                name: "lookup".to_string(),
+                argument_classes: vec![RegisterClass::Integer; 1],
+                return_class: RegisterClass::Integer,
+            },
+        ];
+        let compiled =
+            compile_with_host_functions(program, &FrontendOptions::default(), &host_functions)
+                .unwrap()
+                .functions;
+
+        let f = &compiled[0];
+        assert_eq!(
+            f.blocks,
+            vec![block(
+                0,
+                vec![
+                    mvarg(0, 0),
+                    call_host(1, "lookup", 1, vec![0]),
+                    call(2, "g", 1, vec![]),
+                    add(3, 1, 2),
+                    ret(3),
+                ]
+            )]
+        );
+    }
+
+    #[test]
+    fn compile_error_extern_function_clashes_with_function() {
+        let source = "extern fn f();\nfn f() { return 1; }\nfn g() { return f(1); }";
+        let program = parse_program(source).unwrap();
+        let host_functions = [HostFunctionSignature {
+            name: "f".to_string(),
+            argument_classes: vec![],
+            return_class: RegisterClass::Integer,
+        }];
+        let errors =
+            compile_with_host_functions(program, &FrontendOptions::default(), &host_functions)
+                .unwrap_err();
+        assert_eq!(
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            vec![
+                "function \"f\" already defined",
+                "function \"f\" requires 0 argument(s) but was called with 1",
+            ]
+        );
+        assert_eq!(
+            errors[0].related_span(),
+            Some((Span::new(10, 11), "previously defined here"))
+        );
+    }
+
+    #[test]
+    fn compile_error_extern_function_with_different_types() {
+        let source = "extern fn sqrt(x: f64) -> f64;\nfn f() { return 1; }";
+        let program = parse_program(source).unwrap();
+        let host_functions = [HostFunctionSignature {
+            name: "sqrt".to_string(),
+            argument_classes: vec![RegisterClass::Integer],
+            return_class: RegisterClass::Float,
+        }];
+        let errors =
+            compile_with_host_functions(program, &FrontendOptions::default(), &host_functions)
+                .unwrap_err();
+        assert_eq!(
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            vec![
+                "extern function \"sqrt\" is declared as fn(float) -> float but the host provides it as fn(int) -> float",
+            ]
+        );
+    }
+
+    #[test]
+    fn can_compile_load_and_store() {
+        let program = parse_program(
+            r"fn f(a, i) {
+                store(a, i, load(a, i + 1));
+                return load(a, 0);
+            }",
+        )
+        .unwrap();
+        let compiled = compile(program, &FrontendOptions::default())
+            .unwrap()
+            .functions;
+
+        let f = &compiled[0];
+        assert_eq!(
+            f.blocks,
+            vec![block(
+                0,
+                vec![
+                    mvarg(0, 0),
+                    mvarg(1, 1),
+                    mvi(2, 1),
+                    add(3, 1, 2),
+                    load(4, 0, 3),
+                    store(0, 1, 4),
+                    mvi(5, 0),
+                    load(6, 0, 5),
+                    ret(6),
+                ]
+            )]
+        );
+    }
+
+    #[test]
+    fn can_compile_math_intrinsics() {
+        let source = r"fn f(a, b) {
+            return clamp(abs(a), b, 10) + pow(a, sign(b)) + popcount(gcd(a, b));
+        }";
+        let compiled = compile(parse_program(source).unwrap(), &FrontendOptions::default())
+            .unwrap()
+            .functions;
+        assert_eq!(
+            compiled[0].blocks,
+            vec![block(
+                0,
+                vec![
+                    mvarg(0, 0),
+                    mvarg(1, 1),
+                    abs(2, 0),
+                    mvi(3, 10),
+                    binop(Max, 4, 2, 1),
+                    binop(Min, 5, 4, 3),
+                    sign(6, 1),
+                    binop(Pow, 7, 0, 6),
+                    add(8, 5, 7),
+                    binop(Gcd, 9, 0, 1),
+                    popcount(10, 9),
+                    add(11, 8, 10),
+                    ret(11),
+                ]
+            )]
+        );
+
+        // abs of i64::MIN must be detected like a negation
+        let options = FrontendOptions {
+            overflow_mode: OverflowMode::Checked,
+            ..Default::default()
+        };
+        let compiled = compile(
+            parse_program("fn f(a) { return abs(a); }").unwrap(),
+            &options,
+        )
+        .unwrap()
+        .functions;
+        assert_eq!(
+            compiled[0].blocks,
+            vec![block(
+                0,
+                vec![
+                    mvarg(0, 0),
+                    mvi(1, 0),
+                    sub(2, 1, 0),
+                    binop(Max, 3, 0, 2),
+                    ret(3)
+                ]
+            )]
+        );
+    }
+
+    #[test]
+    fn compile_error_intrinsics_are_resolved_before_functions() {
+        let source =
+            "fn load(a) { return a; }\nfn f() { return load(1) + store(1, 2) + clamp(1, 2); }";
+        let program = parse_program(source).unwrap();
+        let errors = compile(program, &FrontendOptions::default()).unwrap_err();
+        assert_eq!(
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            vec![
+                "function \"load\" cannot be defined, since it is a built-in function",
+                "function \"load\" requires 2 argument(s) but was called with 1",
+                "function \"store\" requires 3 argument(s) but was called with 2",
+                "function \"clamp\" requires 3 argument(s) but was called with 2",
+            ]
+        );
+    }
+
+    #[test]
+    fn compile_error_function_named_like_an_intrinsic() {
+        let source = "fn abs(x) { return x; }\nfn f() { return abs(-1); }";
+        let program = parse_program(source).unwrap();
+        let error = compile_single_error(program);
+        assert_eq!(
+            error.to_string(),
+            "function \"abs\" cannot be defined, since it is a built-in function"
+        );
+        assert_eq!(error.span(), Span::new(3, 6));
+
+        let source = "extern fn pow(x: f64, y: f64) -> f64;\nfn f() { return 1; }";
+        let program = parse_program(source).unwrap();
+        let error = compile_single_error(program);
+        assert!(matches!(error, FrontendError::IntrinsicRedefined { .. }));
+    }
+
+    fn resolve_files(files: &[(&str, &str)]) -> SourceFiles {
+        let mut provider = InMemoryFiles::default();
+        for (path, source) in files {
+            provider.add(path, source);
+        }
+        SourceFiles::resolve(files[0].0, &provider).unwrap()
+    }
+
+    #[test]
+    fn can_compile_calls_to_imported_modules() {
+        let files = resolve_files(&[
+            (
+                "main.mj",
+                "import \"lib/finance.mj\";\nfn main(x) { let f = &finance::twice; return finance::twice(x) + f(helper()); }\nfn helper() { return 1; }",
+            ),
+            ("lib/finance.mj", "fn twice(x) { return x * 2; }"),
+        ]);
+        let compiled = compile_modules(&files.parse().unwrap(), &FrontendOptions::default(), &[])
+            .unwrap()
+            .functions;
+        assert_eq!(
+            vec![
+                ("main", FunctionId(0)),
+                ("helper", FunctionId(1)),
+                ("finance::twice", FunctionId(2))
+            ],
+            compiled
+                .iter()
+                .map(|function| (function.name.as_ref(), function.id))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![block(
+                0,
+                vec![
+                    mvarg(0, 0),
+                    mvi(1, 2),
+                    call(2, "finance::twice", 2, vec![0]),
+                    call(4, "helper", 1, vec![]),
+                    call_indirect(3, 1, vec![4]),
+                    add(5, 2, 3),
+                    ret(5),
+                ]
+            )],
+            compiled[0].blocks
+        );
+    }
+
+    #[test]
+    fn modules_can_define_functions_with_the_same_name() {
+        let files = resolve_files(&[
+            (
+                "main.mj",
+                "import \"a.mj\";\nimport \"b.mj\";\nfn g() { return a::g() + b::g(); }",
+            ),
+            ("a.mj", "fn g() { return 1; }"),
+            ("b.mj", "fn g() { return 2; }"),
+        ]);
+        let compiled = compile_modules(&files.parse().unwrap(), &FrontendOptions::default(), &[])
+            .unwrap()
+            .functions;
+        assert_eq!(
+            vec![
+                ("g", FunctionId(0)),
+                ("a::g", FunctionId(1)),
+                ("b::g", FunctionId(2))
+            ],
+            compiled
+                .iter()
+                .map(|function| (function.name.as_ref(), function.id))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![block(
+                0,
+                vec![
+                    call(0, "a::g", 1, vec![]),
+                    call(1, "b::g", 2, vec![]),
+                    add(2, 0, 1),
+                    ret(2),
+                ]
+            )],
+            compiled[0].blocks
+        );
+    }
+
+    #[test]
+    fn compile_error_only_functions_of_directly_imported_modules_are_visible() {
+        let files = resolve_files(&[
+            (
+                "main.mj",
+                "import \"a.mj\";\nfn main() { return a::f() + a::lookup(1) + a::b_only() + b::g(); }",
+            ),
+            (
+                "a.mj",
+                "import \"b.mj\";\nextern fn lookup(key);\nfn f() { return b::g(); }",
+            ),
+            ("b.mj", "fn g() { return 1; }"),
+        ]);
+        let errors =
+            compile_modules(&files.parse().unwrap(), &FrontendOptions::default(), &[]).unwrap_err();
+        assert_eq!(
+            vec![
+                "extern function \"lookup\" is not provided by the host",
+                "unknown function \"a::lookup\" called",
+                "unknown function \"a::b_only\" called",
+                "unknown function \"b::g\" called",
+            ],
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>()
+        );
+    }
 }
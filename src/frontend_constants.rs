@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+
+use crate::{
+    ast::{Constant, Expression, ExpressionKind, Span, Type},
+    frontend::{check_match_arms, FrontendError},
+    ir::{
+        BinOpOperator::{self, *},
+        OverflowMode,
+    },
+};
+
+#[derive(Clone, Copy)]
+enum ConstantState {
+    NotStarted,
+    /// The constant is being evaluated, so finding it again means there is a cycle
+    InProgress,
+    Done(i64),
+}
+
+/// Evaluates all the module-level constants at compile time, returning their values in
+/// the same order as `constants`. Constants can refer to each other regardless of the
+/// order in which they are declared, but not to functions or to themselves.
+///
+/// A constant whose value cannot be computed gets the value 0, and the reason is added
+/// to `errors`, so that the rest of the program can still be compiled. Overflows
+/// follow `overflow_mode`, like at runtime, and are thus errors in checked mode.
+pub fn evaluate_constants(
+    constants: &[Constant],
+    overflow_mode: OverflowMode,
+    errors: &mut Vec<FrontendError>,
+) -> Vec<i64> {
+    let mut evaluator = ConstantEvaluator {
+        constants,
+        overflow_mode,
+        indexes_by_name: HashMap::new(),
+        states: vec![ConstantState::NotStarted; constants.len()],
+        stack: Vec::new(),
+        errors,
+    };
+    // If a constant is defined twice, references will point to the first definition
+    for (index, constant) in constants.iter().enumerate() {
+        evaluator
+            .indexes_by_name
+            .entry(constant.name)
+            .or_insert(index);
+    }
+
+    (0..constants.len())
+        .map(|index| evaluator.evaluate_constant(index))
+        .collect()
+}
+
+struct ConstantEvaluator<'a, 'input> {
+    constants: &'a [Constant<'input>],
+    overflow_mode: OverflowMode,
+    indexes_by_name: HashMap<&'input str, usize>,
+    states: Vec<ConstantState>,
+    /// The constants currently being evaluated, outermost first
+    stack: Vec<usize>,
+    errors: &'a mut Vec<FrontendError>,
+}
+
+impl ConstantEvaluator<'_, '_> {
+    fn evaluate_constant(&mut self, index: usize) -> i64 {
+        match self.states[index] {
+            ConstantState::Done(value) => return value,
+            ConstantState::InProgress => {
+                self.report_cycle(index);
+                return 0;
+            }
+            ConstantState::NotStarted => {}
+        }
+
+        self.states[index] = ConstantState::InProgress;
+        self.stack.push(index);
+        let value = self
+            .evaluate(&self.constants[index].expression)
+            .unwrap_or(0);
+        self.stack.pop();
+        self.states[index] = ConstantState::Done(value);
+        value
+    }
+
+    /// Reports that the constant at `index` ends up depending on itself, listing all
+    /// the constants involved, e.g. `A -> B -> A`
+    fn report_cycle(&mut self, index: usize) {
+        let start = self.stack.iter().position(|i| *i == index).unwrap();
+        let cycle = self.stack[start..]
+            .iter()
+            .chain(std::iter::once(&index))
+            .map(|i| self.constants[*i].name)
+            .collect::<Vec<_>>()
+            .join(" -> ");
+        let constant = &self.constants[index];
+        self.errors.push(FrontendError::ConstantDependsOnItself {
+            name: constant.name.to_string(),
+            cycle,
+            span: constant.name_span,
+        });
+    }
+
+    /// Returns `None` if the expression cannot be evaluated. The error has already been
+    /// reported in that case.
+    fn evaluate(&mut self, expression: &Expression) -> Option<i64> {
+        match &expression.kind {
+            ExpressionKind::Number(n) => Some(*n),
+            // Constants are evaluated as `i64`, which cannot hold these
+            ExpressionKind::UnsignedNumber(n) => self.error(FrontendError::LiteralOutOfRange {
+                value: (*n as u64).into(),
+                ty: Type::I64,
+                span: expression.span,
+            }),
+            ExpressionKind::Boolean(b) => Some(*b as i64),
+            // Constants are always integers
+            ExpressionKind::Float(_) | ExpressionKind::Cast(_, Type::F64) => {
+                self.error(FrontendError::FloatInConstant {
+                    span: expression.span,
+                })
+            }
+            ExpressionKind::Cast(op, ty) => Some(ty.normalize(self.evaluate(op)?)),
+            ExpressionKind::Identifier(name) => match self.indexes_by_name.get(name) {
+                Some(index) => Some(self.evaluate_constant(*index)),
+                None => self.error(FrontendError::ConstantNotDefined {
+                    name: name.to_string(),
+                    span: expression.span,
+                }),
+            },
+            ExpressionKind::FunctionCall(_) => self.error(FrontendError::FunctionCallInConstant {
+                span: expression.span,
+            }),
+            ExpressionKind::FunctionHandle(_) => {
+                self.error(FrontendError::FunctionHandleInConstant {
+                    span: expression.span,
+                })
+            }
+            ExpressionKind::Negate(op) => {
+                let value = self.evaluate(op)?;
+                self.compute(Sub, 0, value, expression.span)
+            }
+            ExpressionKind::BitwiseNot(op) => Some(!self.evaluate(op)?),
+            ExpressionKind::LogicalNot(op) => Some((self.evaluate(op)? == 0) as i64),
+            // Like at runtime, the right operand is evaluated only when needed
+            ExpressionKind::LogicalAnd(left, right) => match self.evaluate(left)? {
+                0 => Some(0),
+                _ => Some((self.evaluate(right)? != 0) as i64),
+            },
+            ExpressionKind::LogicalOr(left, right) => match self.evaluate(left)? {
+                0 => Some((self.evaluate(right)? != 0) as i64),
+                _ => Some(1),
+            },
+            ExpressionKind::Add(left, right) => self.evaluate_binop(Add, left, right),
+            ExpressionKind::Sub(left, right) => self.evaluate_binop(Sub, left, right),
+            ExpressionKind::Mul(left, right) => self.evaluate_binop(Mul, left, right),
+            ExpressionKind::Div(left, right) => self.evaluate_binop(Div, left, right),
+            ExpressionKind::Rem(left, right) => self.evaluate_binop(Rem, left, right),
+            ExpressionKind::BitwiseAnd(left, right) => self.evaluate_binop(BitwiseAnd, left, right),
+            ExpressionKind::BitwiseOr(left, right) => self.evaluate_binop(BitwiseOr, left, right),
+            ExpressionKind::BitwiseXor(left, right) => self.evaluate_binop(BitwiseXor, left, right),
+            ExpressionKind::ShiftLeft(left, right) => self.evaluate_binop(ShiftLeft, left, right),
+            ExpressionKind::ShiftRight(left, right) => self.evaluate_binop(ShiftRight, left, right),
+            ExpressionKind::Equal(left, right) => self.evaluate_binop(Equal, left, right),
+            ExpressionKind::NotEqual(left, right) => self.evaluate_binop(NotEqual, left, right),
+            ExpressionKind::LessThan(left, right) => self.evaluate_binop(LessThan, left, right),
+            ExpressionKind::LessThanOrEqual(left, right) => {
+                self.evaluate_binop(LessThanOrEqual, left, right)
+            }
+            ExpressionKind::GreaterThan(left, right) => {
+                self.evaluate_binop(GreaterThan, left, right)
+            }
+            ExpressionKind::GreaterThanOrEqual(left, right) => {
+                self.evaluate_binop(GreaterThanOrEqual, left, right)
+            }
+            // Only the arm that is selected is evaluated
+            ExpressionKind::Match { scrutinee, arms } => {
+                let errors = check_match_arms(arms, expression.span);
+                if !errors.is_empty() {
+                    self.errors.extend(errors);
+                    return None;
+                }
+                let value = self.evaluate(scrutinee)?;
+                let arm = arms.iter().find(|arm| match &arm.patterns {
+                    Some(patterns) => patterns.iter().any(|pattern| pattern.value == value),
+                    None => true,
+                })?;
+                self.evaluate(&arm.expression)
+            }
+        }
+    }
+
+    fn evaluate_binop(
+        &mut self,
+        operator: BinOpOperator,
+        left: &Expression,
+        right: &Expression,
+    ) -> Option<i64> {
+        // Evaluate both sides before giving up, to report all the errors they contain
+        let left_value = self.evaluate(left);
+        let right_value = self.evaluate(right);
+        let (left_value, right_value) = left_value.zip(right_value)?;
+        self.compute(operator, left_value, right_value, left.span.to(right.span))
+    }
+
+    fn compute(
+        &mut self,
+        operator: BinOpOperator,
+        left_value: i64,
+        right_value: i64,
+        span: Span,
+    ) -> Option<i64> {
+        // Constants have no declared type, so they are always computed as `i64`
+        match operator.evaluate_with_overflow(
+            Type::I64,
+            self.overflow_mode,
+            left_value,
+            right_value,
+        ) {
+            Some(value) => Some(value),
+            None => self.error(FrontendError::InvalidConstantOperation { span }),
+        }
+    }
+
+    fn error(&mut self, error: FrontendError) -> Option<i64> {
+        self.errors.push(error);
+        None
+    }
+}
@@ -0,0 +1,844 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{
+    ast::{
+        Block, BlockElementKind, Expression, ExpressionKind, FunctionCall, MatchArm, Module,
+        Program, Span, Type,
+    },
+    frontend::{FrontendError, FrontendOptions, Intrinsic},
+    frontend_constants::evaluate_constants,
+    modules::module_name,
+};
+
+/// The types of the arguments and of the result of a function
+struct Signature {
+    arguments: Vec<Type>,
+    return_type: Type,
+    is_extern: bool,
+}
+
+impl Signature {
+    /// Indirect calls pass and return only `i64` values
+    fn is_callable_indirectly(&self) -> bool {
+        self.return_type == Type::I64 && self.arguments.iter().all(|ty| *ty == Type::I64)
+    }
+}
+
+/// Infers the type of every expression of the program, checking that values are only
+/// converted implicitly when no information can be lost, i.e. from a type to a wider one
+/// with the same signedness (or from an unsigned type to a wider signed one). Every
+/// implicit conversion is made explicit by wrapping the expression in a cast.
+///
+/// Integer literals and constants do not have a type of their own: they take the one
+/// required by the context, if their value fits in it, or `i64` otherwise. This includes
+/// `f64`, so that `x * 2` works for a float `x`.
+///
+/// Floats only support arithmetic and comparisons, cannot be used as conditions and can
+/// only be converted explicitly to and from `i64`.
+///
+/// Undefined variables and functions are simply assumed to be `i64`, since they are
+/// reported by the frontend.
+pub fn check_types(program: &mut Program, options: &FrontendOptions) -> Vec<FrontendError> {
+    let functions = Rc::new(function_signatures(program));
+    check_program_types(program, functions, HashMap::new(), options)
+}
+
+/// Like `check_types`, for all the modules of a program. The functions of the imported
+/// modules are called with their qualified name
+pub fn check_module_types(modules: &mut [Module], options: &FrontendOptions) -> Vec<FrontendError> {
+    let paths = modules.iter().map(|module| module.path).collect::<Vec<_>>();
+    let signatures = modules
+        .iter()
+        .map(|module| Rc::new(function_signatures(&module.program)))
+        .collect::<Vec<_>>();
+
+    let mut errors = Vec::new();
+    for (module, functions) in modules.iter_mut().zip(signatures.iter()) {
+        let imported_functions = module
+            .program
+            .imports
+            .iter()
+            .filter_map(|import| {
+                let index = paths.iter().position(|path| *path == import.path)?;
+                Some((module_name(import.path), signatures[index].clone()))
+            })
+            .collect();
+        errors.extend(check_program_types(
+            &mut module.program,
+            functions.clone(),
+            imported_functions,
+            options,
+        ));
+    }
+    errors
+}
+
+/// The signatures of the functions and extern functions of a program, by name
+fn function_signatures<'input>(program: &Program<'input>) -> HashMap<&'input str, Signature> {
+    let mut functions = HashMap::new();
+    for extern_function in program.extern_functions.iter() {
+        functions.entry(extern_function.name).or_insert(Signature {
+            arguments: extern_function.args.iter().map(|arg| arg.ty).collect(),
+            return_type: extern_function.return_type,
+            is_extern: true,
+        });
+    }
+    for function in program.functions.iter() {
+        functions.entry(function.name).or_insert(Signature {
+            arguments: function.args.iter().map(|arg| arg.ty).collect(),
+            return_type: function.return_type,
+            is_extern: false,
+        });
+    }
+    functions
+}
+
+fn check_program_types<'input>(
+    program: &mut Program<'input>,
+    functions: Rc<HashMap<&'input str, Signature>>,
+    imported_functions: HashMap<&'input str, Rc<HashMap<&'input str, Signature>>>,
+    options: &FrontendOptions,
+) -> Vec<FrontendError> {
+    // Invalid constants are reported by the frontend as well
+    let values = evaluate_constants(&program.constants, options.overflow_mode, &mut Vec::new());
+    let mut checker = TypeChecker {
+        functions,
+        imported_functions,
+        ..Default::default()
+    };
+    for (constant, value) in program.constants.iter().zip(values) {
+        checker.constants.entry(constant.name).or_insert(value);
+    }
+
+    for function in program.functions.iter_mut() {
+        checker.return_type = function.return_type;
+        checker.scopes = vec![function.args.iter().map(|arg| (arg.name, arg.ty)).collect()];
+        checker.check_block(&mut function.block);
+    }
+    checker.errors
+}
+
+#[derive(Default)]
+struct TypeChecker<'input> {
+    constants: HashMap<&'input str, i64>,
+    functions: Rc<HashMap<&'input str, Signature>>,
+    /// The functions of the imported modules, by module name
+    imported_functions: HashMap<&'input str, Rc<HashMap<&'input str, Signature>>>,
+    /// The types of the variables and arguments visible in the current position, with
+    /// the innermost scope last
+    scopes: Vec<HashMap<&'input str, Type>>,
+    /// The return type of the function being checked
+    return_type: Type,
+    errors: Vec<FrontendError>,
+}
+
+impl<'input> TypeChecker<'input> {
+    /// Finds the signature of a function of this module or, for a qualified name, of an
+    /// imported one, whose extern functions are not visible
+    fn lookup_function(&self, name: &str) -> Option<&Signature> {
+        match name.split_once("::") {
+            Some((module, function)) => self
+                .imported_functions
+                .get(module)?
+                .get(function)
+                .filter(|signature| !signature.is_extern),
+            None => self.functions.get(name),
+        }
+    }
+
+    fn check_block(&mut self, block: &mut Block<'input>) {
+        self.scopes.push(HashMap::new());
+        for element in block.iter_mut() {
+            match &mut element.kind {
+                BlockElementKind::LetStatement {
+                    name,
+                    ty,
+                    expression,
+                } => {
+                    let ty = match ty {
+                        Some(ty) => {
+                            self.check_expression_as(expression, *ty);
+                            *ty
+                        }
+                        None => self.check_expression(expression, None),
+                    };
+                    self.scopes.last_mut().unwrap().insert(name, ty);
+                }
+                BlockElementKind::AssignmentStatement { name, expression } => {
+                    match self.lookup_variable(name) {
+                        Some(ty) => self.check_expression_as(expression, ty),
+                        None => {
+                            self.check_expression(expression, None);
+                        }
+                    }
+                }
+                BlockElementKind::ReturnStatement(expression) => {
+                    self.check_expression_as(expression, self.return_type)
+                }
+                BlockElementKind::IfStatement {
+                    condition,
+                    then_block,
+                    else_block,
+                } => {
+                    // Like in C, any integer can be used as a condition
+                    self.check_condition(condition);
+                    self.check_block(then_block);
+                    if let Some(else_block) = else_block {
+                        self.check_block(else_block);
+                    }
+                }
+                BlockElementKind::WhileStatement { condition, body } => {
+                    self.check_condition(condition);
+                    self.check_block(body);
+                }
+                BlockElementKind::ExpressionStatement(expression) => {
+                    self.check_expression(expression, None);
+                }
+                BlockElementKind::NestedBlock(nested) => self.check_block(nested),
+                BlockElementKind::BreakStatement | BlockElementKind::ContinueStatement => {}
+            }
+        }
+        self.scopes.pop();
+    }
+
+    fn lookup_variable(&self, name: &str) -> Option<Type> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).copied())
+    }
+
+    /// Checks that the expression has type `ty`, or one that can be implicitly converted
+    /// to it. In the latter case, the expression is wrapped in a cast.
+    fn check_expression_as(&mut self, expression: &mut Expression<'input>, ty: Type) {
+        let found = self.check_expression(expression, Some(ty));
+        if found == ty {
+            return;
+        }
+        if !found.can_widen_to(ty) {
+            self.errors.push(FrontendError::TypeMismatch {
+                expected: ty,
+                found,
+                span: expression.span,
+            });
+            return;
+        }
+
+        let span = expression.span;
+        let placeholder = Expression {
+            kind: ExpressionKind::Boolean(false),
+            span,
+            ty: found,
+        };
+        let inner = std::mem::replace(expression, placeholder);
+        *expression = Expression {
+            kind: ExpressionKind::Cast(Box::new(inner), ty),
+            span,
+            ty,
+        };
+    }
+
+    /// Checks an expression used as a condition, which can have any type except `f64`
+    fn check_condition(&mut self, expression: &mut Expression<'input>) {
+        let found = self.check_expression(expression, None);
+        if found.is_float() {
+            self.errors.push(FrontendError::TypeMismatch {
+                expected: Type::Bool,
+                found,
+                span: expression.span,
+            });
+        }
+    }
+
+    fn check_not_float(&mut self, ty: Type, operator: &'static str, span: crate::ast::Span) {
+        if ty.is_float() {
+            self.errors
+                .push(FrontendError::UnsupportedOperator { operator, ty, span });
+        }
+    }
+
+    /// Computes and records the type of the expression. The type `expected` by the
+    /// context is only used for literals and constants.
+    fn check_expression(
+        &mut self,
+        expression: &mut Expression<'input>,
+        expected: Option<Type>,
+    ) -> Type {
+        let span = expression.span;
+        let ty = match &mut expression.kind {
+            ExpressionKind::Number(value) => self.literal_type(*value, expected, expression.span),
+            ExpressionKind::UnsignedNumber(value) => {
+                self.unsigned_literal_type(*value as u64, expected, expression.span)
+            }
+            ExpressionKind::Float(_) => Type::F64,
+            ExpressionKind::Boolean(_) => Type::Bool,
+            ExpressionKind::Identifier(name) => match self.lookup_variable(name) {
+                Some(ty) => ty,
+                None => match self.constants.get(name) {
+                    Some(value) => self.literal_type(*value, expected, span),
+                    None => Type::I64,
+                },
+            },
+            ExpressionKind::Negate(op) => {
+                let ty = self.check_arithmetic_operand(op, expected);
+                if ty.is_unsigned() {
+                    self.errors
+                        .push(FrontendError::CannotNegateUnsigned { ty, span });
+                }
+                ty
+            }
+            ExpressionKind::BitwiseNot(op) => {
+                let ty = self.check_arithmetic_operand(op, expected);
+                self.check_not_float(ty, "~", span);
+                ty
+            }
+            ExpressionKind::Add(left, right)
+            | ExpressionKind::Sub(left, right)
+            | ExpressionKind::Mul(left, right)
+            | ExpressionKind::Div(left, right)
+            | ExpressionKind::Rem(left, right) => {
+                let ty = promote_bool(self.operands_type(left, right, expected));
+                self.check_expression_as(left, ty);
+                self.check_expression_as(right, ty);
+                if matches!(expression.kind, ExpressionKind::Rem(_, _)) {
+                    self.check_not_float(ty, "%", span);
+                }
+                ty
+            }
+            // Bitwise operations between two bools produce a bool
+            ExpressionKind::BitwiseAnd(left, right)
+            | ExpressionKind::BitwiseOr(left, right)
+            | ExpressionKind::BitwiseXor(left, right) => {
+                let ty = self.operands_type(left, right, expected);
+                self.check_expression_as(left, ty);
+                self.check_expression_as(right, ty);
+                let operator = match expression.kind {
+                    ExpressionKind::BitwiseAnd(_, _) => "&",
+                    ExpressionKind::BitwiseOr(_, _) => "|",
+                    _ => "^",
+                };
+                self.check_not_float(ty, operator, span);
+                ty
+            }
+            // The amount of a shift can have any integer type
+            ExpressionKind::ShiftLeft(left, right) | ExpressionKind::ShiftRight(left, right) => {
+                let ty = self.check_arithmetic_operand(left, expected);
+                let amount_ty = self.check_expression(right, None);
+                let operator = match expression.kind {
+                    ExpressionKind::ShiftLeft(_, _) => "<<",
+                    _ => ">>",
+                };
+                self.check_not_float(ty, operator, span);
+                self.check_not_float(amount_ty, operator, span);
+                ty
+            }
+            ExpressionKind::Equal(left, right)
+            | ExpressionKind::NotEqual(left, right)
+            | ExpressionKind::LessThan(left, right)
+            | ExpressionKind::LessThanOrEqual(left, right)
+            | ExpressionKind::GreaterThan(left, right)
+            | ExpressionKind::GreaterThanOrEqual(left, right) => {
+                let ty = self.operands_type(left, right, None);
+                self.check_expression_as(left, ty);
+                self.check_expression_as(right, ty);
+                Type::Bool
+            }
+            ExpressionKind::LogicalNot(op) => {
+                self.check_condition(op);
+                Type::Bool
+            }
+            ExpressionKind::LogicalAnd(left, right) | ExpressionKind::LogicalOr(left, right) => {
+                self.check_condition(left);
+                self.check_condition(right);
+                Type::Bool
+            }
+            ExpressionKind::FunctionCall(call) => self.check_call(call, span),
+            // Unknown and extern functions are reported by the frontend
+            ExpressionKind::FunctionHandle(name) => {
+                if let Some(signature) = self.lookup_function(name) {
+                    if !signature.is_extern && !signature.is_callable_indirectly() {
+                        self.errors
+                            .push(FrontendError::FunctionNotCallableIndirectly {
+                                name: name.to_string(),
+                                span,
+                            });
+                    }
+                }
+                Type::I64
+            }
+            // An explicit cast can convert between any two integer types
+            ExpressionKind::Cast(op, ty) => {
+                let from = self.check_expression(op, None);
+                let to = *ty;
+                let converts_float =
+                    |from: Type, to: Type| from.is_float() && !matches!(to, Type::I64 | Type::F64);
+                if converts_float(from, to) || converts_float(to, from) {
+                    self.errors
+                        .push(FrontendError::InvalidCast { from, to, span });
+                }
+                to
+            }
+            // The patterns are integer literals, so they must fit in the type of the
+            // scrutinee, and the arms are all converted to a common type
+            ExpressionKind::Match { scrutinee, arms } => {
+                let scrutinee_ty = self.check_arithmetic_operand(scrutinee, None);
+                self.check_not_float(scrutinee_ty, "match", scrutinee.span);
+                for pattern in arms.iter().flat_map(|arm| arm.patterns.iter().flatten()) {
+                    if !scrutinee_ty.is_float() && !scrutinee_ty.can_represent(pattern.value) {
+                        self.errors.push(FrontendError::LiteralOutOfRange {
+                            value: pattern.value.into(),
+                            ty: scrutinee_ty,
+                            span: pattern.span,
+                        });
+                    }
+                }
+                let ty = self
+                    .natural_match_type(arms)
+                    .unwrap_or(literal_context_type(expected));
+                for arm in arms.iter_mut() {
+                    self.check_expression_as(&mut arm.expression, ty);
+                }
+                ty
+            }
+        };
+        expression.ty = ty;
+        ty
+    }
+
+    /// Checks the operand of an arithmetic operation, which is converted to `i64` if
+    /// it is a bool
+    fn check_arithmetic_operand(
+        &mut self,
+        op: &mut Expression<'input>,
+        expected: Option<Type>,
+    ) -> Type {
+        let ty = promote_bool(
+            self.natural_type(op)
+                .unwrap_or(literal_context_type(expected)),
+        );
+        self.check_expression_as(op, ty);
+        ty
+    }
+
+    fn check_call(&mut self, call: &mut FunctionCall<'input>, span: Span) -> Type {
+        // Intrinsics only work with i64, and are resolved before any other function
+        if Intrinsic::from_name(call.name).is_some() {
+            for arg in call.args.iter_mut() {
+                self.check_expression_as(arg, Type::I64);
+            }
+            return Type::I64;
+        }
+        // Like for any other name, a variable shadows a function. It must contain the
+        // handle of a function, which always takes and returns i64
+        if let Some(found) = self.lookup_variable(call.name) {
+            if found != Type::I64 {
+                self.errors.push(FrontendError::TypeMismatch {
+                    expected: Type::I64,
+                    found,
+                    span,
+                });
+            }
+            for arg in call.args.iter_mut() {
+                self.check_expression_as(arg, Type::I64);
+            }
+            return Type::I64;
+        }
+
+        let Some(signature) = self.lookup_function(call.name) else {
+            for arg in call.args.iter_mut() {
+                self.check_expression(arg, None);
+            }
+            return Type::I64;
+        };
+        // A wrong number of arguments is reported by the frontend
+        let arguments = signature.arguments.clone();
+        let return_type = signature.return_type;
+        for (index, arg) in call.args.iter_mut().enumerate() {
+            match arguments.get(index) {
+                Some(ty) => self.check_expression_as(arg, *ty),
+                None => {
+                    self.check_expression(arg, None);
+                }
+            }
+        }
+        return_type
+    }
+
+    /// The type that a literal gets in a context where `expected` is required. If the
+    /// literal does not fit in it, an error is reported
+    fn literal_type(&mut self, value: i64, expected: Option<Type>, span: crate::ast::Span) -> Type {
+        let ty = literal_context_type(expected);
+        if !ty.can_represent(value) {
+            self.errors.push(FrontendError::LiteralOutOfRange {
+                value: value.into(),
+                ty,
+                span,
+            });
+        }
+        ty
+    }
+
+    /// Like `literal_type`, for the literals above `i64::MAX`
+    fn unsigned_literal_type(
+        &mut self,
+        value: u64,
+        expected: Option<Type>,
+        span: crate::ast::Span,
+    ) -> Type {
+        let ty = literal_context_type(expected);
+        if ty != Type::U64 {
+            self.errors.push(FrontendError::LiteralOutOfRange {
+                value: value.into(),
+                ty,
+                span,
+            });
+        }
+        ty
+    }
+
+    /// The common type of the two operands of a binary operation, where both must have
+    /// the same type. If it is not determined by the operands, i.e. if they are both
+    /// literals, it is the one `expected` by the context
+    fn operands_type(
+        &self,
+        left: &Expression<'input>,
+        right: &Expression<'input>,
+        expected: Option<Type>,
+    ) -> Type {
+        self.natural_operands_type(left, right)
+            .unwrap_or(literal_context_type(expected))
+    }
+
+    fn natural_operands_type(
+        &self,
+        left: &Expression<'input>,
+        right: &Expression<'input>,
+    ) -> Option<Type> {
+        match (self.natural_type(left), self.natural_type(right)) {
+            (None, None) => None,
+            // A literal can never be a bool
+            (Some(ty), None) | (None, Some(ty)) => Some(promote_bool(ty)),
+            // If the types are not compatible, the error is reported on the right one
+            (Some(left), Some(right)) if left.can_widen_to(right) => Some(right),
+            (Some(left), Some(_)) => Some(left),
+        }
+    }
+
+    /// The type of the expression regardless of its context, or `None` if it only
+    /// contains literals and constants, which can take any type
+    fn natural_type(&self, expression: &Expression<'input>) -> Option<Type> {
+        match &expression.kind {
+            ExpressionKind::Number(_) | ExpressionKind::UnsignedNumber(_) => None,
+            ExpressionKind::Float(_) => Some(Type::F64),
+            ExpressionKind::Boolean(_) => Some(Type::Bool),
+            ExpressionKind::Identifier(name) => match self.lookup_variable(name) {
+                Some(ty) => Some(ty),
+                None if self.constants.contains_key(name) => None,
+                None => Some(Type::I64),
+            },
+            ExpressionKind::Negate(op)
+            | ExpressionKind::BitwiseNot(op)
+            | ExpressionKind::ShiftLeft(op, _)
+            | ExpressionKind::ShiftRight(op, _) => self.natural_type(op).map(promote_bool),
+            ExpressionKind::Add(left, right)
+            | ExpressionKind::Sub(left, right)
+            | ExpressionKind::Mul(left, right)
+            | ExpressionKind::Div(left, right)
+            | ExpressionKind::Rem(left, right) => {
+                self.natural_operands_type(left, right).map(promote_bool)
+            }
+            ExpressionKind::BitwiseAnd(left, right)
+            | ExpressionKind::BitwiseOr(left, right)
+            | ExpressionKind::BitwiseXor(left, right) => self.natural_operands_type(left, right),
+            ExpressionKind::Equal(_, _)
+            | ExpressionKind::NotEqual(_, _)
+            | ExpressionKind::LessThan(_, _)
+            | ExpressionKind::LessThanOrEqual(_, _)
+            | ExpressionKind::GreaterThan(_, _)
+            | ExpressionKind::GreaterThanOrEqual(_, _)
+            | ExpressionKind::LogicalNot(_)
+            | ExpressionKind::LogicalAnd(_, _)
+            | ExpressionKind::LogicalOr(_, _) => Some(Type::Bool),
+            ExpressionKind::FunctionHandle(_) => Some(Type::I64),
+            ExpressionKind::FunctionCall(call) => {
+                if Intrinsic::from_name(call.name).is_some()
+                    || self.lookup_variable(call.name).is_some()
+                {
+                    return Some(Type::I64);
+                }
+                Some(
+                    self.lookup_function(call.name)
+                        .map_or(Type::I64, |signature| signature.return_type),
+                )
+            }
+            ExpressionKind::Cast(_, ty) => Some(*ty),
+            ExpressionKind::Match { arms, .. } => self.natural_match_type(arms),
+        }
+    }
+
+    /// The widest of the natural types of the arms. If two of them are not compatible,
+    /// the error is reported on the arm that does not fit the first one
+    fn natural_match_type(&self, arms: &[MatchArm<'input>]) -> Option<Type> {
+        arms.iter()
+            .filter_map(|arm| self.natural_type(&arm.expression))
+            .reduce(|ty, other| if ty.can_widen_to(other) { other } else { ty })
+    }
+}
+
+/// Arithmetic is never done on bools, which are treated as `i64` instead
+fn promote_bool(ty: Type) -> Type {
+    match ty {
+        Type::Bool => Type::I64,
+        ty => ty,
+    }
+}
+
+/// The type of a literal used where `expected` is required. A literal is never a bool
+fn literal_context_type(expected: Option<Type>) -> Type {
+    match expected {
+        Some(Type::Bool) | None => Type::I64,
+        Some(ty) => ty,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        ast::{BlockElementKind, ExpressionKind, Type},
+        frontend::FrontendOptions,
+        frontend_types::{check_module_types, check_types},
+        modules::{InMemoryFiles, SourceFiles},
+        parser::parse_program,
+    };
+
+    fn type_errors(source: &str) -> Vec<String> {
+        let mut program = parse_program(source).expect("program should parse");
+        check_types(&mut program, &FrontendOptions::default())
+            .iter()
+            .map(|error| error.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn values_can_be_widened_implicitly() {
+        assert_eq!(
+            type_errors(
+                "fn f(x: u8, y: i32, b: bool) -> i64 {
+                    let z: u32 = x;
+                    let w: u64 = z + b;
+                    return y + x;
+                }"
+            ),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn values_cannot_be_narrowed_implicitly() {
+        assert_eq!(
+            type_errors("fn f(x) -> u8 { return x; }"),
+            vec!["mismatched types: expected u8, found i64"]
+        );
+        assert_eq!(
+            type_errors("fn f(x: i32, y: u32) { let z = x + y; return 0; }"),
+            vec!["mismatched types: expected i32, found u32"]
+        );
+        assert_eq!(
+            type_errors("fn f(x: u64) { let y: i64 = x; return y; }"),
+            vec!["mismatched types: expected i64, found u64"]
+        );
+        assert_eq!(
+            type_errors("fn f(x) -> bool { return x; }"),
+            vec!["mismatched types: expected bool, found i64"]
+        );
+        assert_eq!(
+            type_errors("fn f(x: u8) { x = x as i32; return x; }"),
+            vec!["mismatched types: expected u8, found i32"]
+        );
+    }
+
+    #[test]
+    fn only_functions_with_i64_values_can_be_called_indirectly() {
+        assert_eq!(
+            type_errors(
+                "extern fn h(x: u8) -> bool;
+                fn g(x: u32) -> u32 { return x; }
+                fn f(x: i32) { let a = &g; let b = &f; let c = &h; return x(a, b) + f(1); }"
+            ),
+            vec![
+                "function \"g\" cannot be referenced, since only functions taking and returning i64 can be called indirectly",
+                "function \"f\" cannot be referenced, since only functions taking and returning i64 can be called indirectly",
+                "mismatched types: expected i64, found i32",
+            ]
+        );
+    }
+
+    #[test]
+    fn match_arms_are_converted_to_a_common_type() {
+        assert_eq!(
+            type_errors(
+                "fn f(x: u8, y: i32, z: f64) -> i64 {
+                    let a: i32 = match x { 0 => x, 1 | 255 => y, _ => 7 };
+                    let b = match y { -1 => z, 0 => 1, _ => y };
+                    let c = match z { _ => 1 };
+                    let d = match x { 256 => 1, _ => 0 };
+                    return match x > 1 { 1 => a, _ => 0 };
+                }"
+            ),
+            vec![
+                "mismatched types: expected f64, found i32",
+                "operator match cannot be applied to values of type f64",
+                "literal 256 does not fit in type u8",
+            ]
+        );
+    }
+
+    #[test]
+    fn function_arguments_are_checked_against_the_signature() {
+        assert_eq!(
+            type_errors(
+                "extern fn h(x: u8) -> bool;
+                fn g(x: u32) -> u32 { return x; }
+                fn f(y) -> u32 { let b: bool = h(y); return g(b) + g(1); }"
+            ),
+            vec!["mismatched types: expected u8, found i64"]
+        );
+    }
+
+    #[test]
+    fn calls_to_imported_modules_are_checked_against_their_signature() {
+        let mut provider = InMemoryFiles::default();
+        provider
+            .add(
+                "main.mj",
+                "import \"util.mj\";\nfn f(y: u32) -> u8 { let a = &util::g; return util::g(y) + util::h(300); }",
+            )
+            .add(
+                "util.mj",
+                "fn g(x: u32) -> u8 { return 1; }\nfn h(x: u8) -> u8 { return x; }",
+            );
+        let files = SourceFiles::resolve("main.mj", &provider).unwrap();
+        let mut modules = files.parse().unwrap();
+        assert_eq!(
+            check_module_types(&mut modules, &FrontendOptions::default())
+                .iter()
+                .map(|error| error.to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "function \"util::g\" cannot be referenced, since only functions taking and returning i64 can be called indirectly",
+                "literal 300 does not fit in type u8",
+            ]
+        );
+    }
+
+    #[test]
+    fn literals_must_fit_in_the_type_of_their_context() {
+        assert_eq!(
+            type_errors("const BIG = 300; fn f(x: u8) -> u8 { return x + 255 + BIG; }"),
+            vec!["literal 300 does not fit in type u8"]
+        );
+        assert_eq!(
+            type_errors("fn f() { let x: u64 = -1; let y: i32 = 2147483648; return 0; }"),
+            vec![
+                "literal -1 does not fit in type u64",
+                "literal 2147483648 does not fit in type i32"
+            ]
+        );
+        assert!(type_errors(
+            "fn f(x: u64) -> u64 { let y: u64 = 18446744073709551614; return x + y + 0xFFFF_FFFF_FFFF_FFFF; }"
+        )
+        .is_empty());
+        assert_eq!(
+            type_errors("fn f() { let y = 9223372036854775808; return - 9223372036854775808; }"),
+            vec![
+                "literal 9223372036854775808 does not fit in type i64",
+                "literal 9223372036854775808 does not fit in type i64"
+            ]
+        );
+    }
+
+    #[test]
+    fn unsigned_values_cannot_be_negated() {
+        assert_eq!(
+            type_errors("fn f(x: u32) { let y = -x; return -(x as i64); }"),
+            vec!["cannot negate a value of unsigned type u32"]
+        );
+    }
+
+    #[test]
+    fn floats_only_support_arithmetic_and_comparisons() {
+        assert_eq!(
+            type_errors(
+                "fn f(x: f64, n) -> f64 {
+                    let y = -x * 2 + 1.5 / x;
+                    let b: bool = y < 0 && n > 0;
+                    return (y as i64 + n) as f64;
+                }"
+            ),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            type_errors(
+                "fn f(x: f64, y: f64) -> f64 {
+                    let z = x % y;
+                    let w = x << 1;
+                    if (x) { return 0; }
+                    return x + 1;
+                }"
+            ),
+            vec![
+                "operator % cannot be applied to values of type f64",
+                "operator << cannot be applied to values of type f64",
+                "mismatched types: expected bool, found f64",
+            ]
+        );
+    }
+
+    #[test]
+    fn floats_are_never_converted_implicitly() {
+        assert_eq!(
+            type_errors(
+                "fn f(x: f64, y: i32) -> f64 { let z: f64 = y; return x + 1 + 9007199254740993; }"
+            ),
+            vec![
+                "mismatched types: expected f64, found i32",
+                "literal 9007199254740993 does not fit in type f64"
+            ]
+        );
+        assert_eq!(
+            type_errors("fn f(x: f64) -> i64 { return x; }"),
+            vec!["mismatched types: expected i64, found f64"]
+        );
+        assert_eq!(
+            type_errors("fn f(x: f64, y: u8) -> u8 { let z = y as f64; return x as u8; }"),
+            vec![
+                "cannot cast u8 to f64, f64 can only be converted to and from i64",
+                "cannot cast f64 to u8, f64 can only be converted to and from i64"
+            ]
+        );
+    }
+
+    #[test]
+    fn records_the_types_and_the_implicit_conversions() {
+        let mut program = parse_program("fn f(x: u8) -> i64 { return x * 2 < 3; }").unwrap();
+        assert!(check_types(&mut program, &FrontendOptions::default()).is_empty());
+
+        let BlockElementKind::ReturnStatement(expression) = &program.functions[0].block[0].kind
+        else {
+            panic!("expected a return statement");
+        };
+        assert_eq!(Type::I64, expression.ty);
+        let ExpressionKind::Cast(comparison, Type::I64) = &expression.kind else {
+            panic!("expected an implicit cast, found {:?}", expression.kind);
+        };
+        assert_eq!(Type::Bool, comparison.ty);
+        let ExpressionKind::LessThan(left, right) = &comparison.kind else {
+            panic!("expected a comparison");
+        };
+        assert_eq!((Type::U8, Type::U8), (left.ty, right.ty));
+        let ExpressionKind::Mul(_, two) = &left.kind else {
+            panic!("expected a multiplication");
+        };
+        assert_eq!(Type::U8, two.ty);
+    }
+}
@@ -9,10 +9,26 @@ use pest_derive::Parser;
 pub struct EmjayGrammar;
 
 static EMJAY_PRATT_PARSER: LazyLock<PrattParser<Rule>> = LazyLock::new(|| {
+    // Same precedence as C, from the lowest to the highest
     PrattParser::new()
+        .op(Op::infix(Rule::or, Assoc::Left))
+        .op(Op::infix(Rule::and, Assoc::Left))
+        .op(Op::infix(Rule::bitOr, Assoc::Left))
+        .op(Op::infix(Rule::bitXor, Assoc::Left))
+        .op(Op::infix(Rule::bitAnd, Assoc::Left))
+        .op(Op::infix(Rule::eq, Assoc::Left) | Op::infix(Rule::ne, Assoc::Left))
+        .op(Op::infix(Rule::lt, Assoc::Left)
+            | Op::infix(Rule::le, Assoc::Left)
+            | Op::infix(Rule::gt, Assoc::Left)
+            | Op::infix(Rule::ge, Assoc::Left))
+        .op(Op::infix(Rule::shl, Assoc::Left) | Op::infix(Rule::shr, Assoc::Left))
         .op(Op::infix(Rule::add, Assoc::Left) | Op::infix(Rule::sub, Assoc::Left))
-        .op(Op::infix(Rule::mul, Assoc::Left) | Op::infix(Rule::div, Assoc::Left))
-        .op(Op::prefix(Rule::neg))
+        .op(Op::infix(Rule::mul, Assoc::Left)
+            | Op::infix(Rule::div, Assoc::Left)
+            | Op::infix(Rule::rem, Assoc::Left))
+        // Like in Rust, `-x as u8` means `(-x) as u8`
+        .op(Op::postfix(Rule::cast))
+        .op(Op::prefix(Rule::neg) | Op::prefix(Rule::not) | Op::prefix(Rule::bitNot))
 });
 
 pub fn pratt_parser() -> &'static PrattParser<Rule> {
@@ -36,6 +52,19 @@ mod tests {
         assert_can_be_parsed_as("-123", Rule::number);
         assert_can_be_parsed_as("0x42A", Rule::number);
         assert_can_be_parsed_as("-0x42A", Rule::number);
+        assert_can_be_parsed_as("0b1010", Rule::number);
+        assert_can_be_parsed_as("0o755", Rule::number);
+        assert_can_be_parsed_as("1_000_000", Rule::number);
+        assert_can_be_parsed_as("0xFFFF_FFFF", Rule::number);
+        assert_can_be_parsed_as("-0b1_0", Rule::number);
+    }
+
+    #[test]
+    fn grammar_rejects_invalid_numbers() {
+        assert!(EmjayGrammar::parse(Rule::number, "_1").is_err());
+        assert!(EmjayGrammar::parse(Rule::program, "fn f() { return 0x_1; }").is_err());
+        assert!(EmjayGrammar::parse(Rule::program, "fn f() { return 0b102; }").is_err());
+        assert!(EmjayGrammar::parse(Rule::program, "fn f() { return 0o8; }").is_err());
     }
 
     #[test]
@@ -43,6 +72,15 @@ mod tests {
         assert_can_be_parsed_as("x", Rule::identifier);
         assert_can_be_parsed_as("x_32", Rule::identifier);
         assert_can_be_parsed_as("éñò", Rule::identifier);
+        assert_can_be_parsed_as("iffy", Rule::identifier);
+    }
+
+    #[test]
+    fn grammar_does_not_accept_keywords_as_identifiers() {
+        assert!(EmjayGrammar::parse(Rule::identifier, "if").is_err());
+        assert!(EmjayGrammar::parse(Rule::identifier, "return").is_err());
+        assert!(EmjayGrammar::parse(Rule::identifier, "while").is_err());
+        assert!(EmjayGrammar::parse(Rule::identifier, "break").is_err());
     }
 
     #[test]
@@ -60,17 +98,57 @@ mod tests {
         assert_can_be_parsed_as("3 * 4 + g", Rule::expression);
         assert_can_be_parsed_as("-(1 + x) * 4 - f()", Rule::expression);
         assert_can_be_parsed_as("f()", Rule::expression);
+        assert_can_be_parsed_as("a <= b && b != c || !d", Rule::expression);
+        assert_can_be_parsed_as("!!(x < 0) == -y > 1", Rule::expression);
+        assert_can_be_parsed_as("a & b | c ^ ~d", Rule::expression);
+        assert_can_be_parsed_as("a << 2 >> b % 3 && c || d", Rule::expression);
+        assert_can_be_parsed_as("x as u8 + true", Rule::expression);
+        assert_can_be_parsed_as("-f(x) as i32 as u64 < false", Rule::expression);
+        assert_can_be_parsed_as("match x { 0 => 1, 1 | -2 => y, _ => z }", Rule::expression);
+        assert_can_be_parsed_as("match f(x) { _ => 0, } * 2", Rule::expression);
+    }
+
+    #[test]
+    fn grammar_rejects_invalid_match() {
+        assert!(EmjayGrammar::parse(Rule::matchExpression, "match x { }").is_err());
+        assert!(EmjayGrammar::parse(Rule::matchExpression, "match x { y => 1 }").is_err());
+        assert!(EmjayGrammar::parse(Rule::matchExpression, "match x { 1 | _ => 1 }").is_err());
+        assert!(EmjayGrammar::parse(Rule::identifier, "match").is_err());
+        assert_can_be_parsed_as("matches", Rule::identifier);
+    }
+
+    #[test]
+    fn grammar_requires_a_separate_as_keyword() {
+        assert!(EmjayGrammar::parse(Rule::program, "fn f(x) { return x asu8; }").is_err());
+        assert!(EmjayGrammar::parse(Rule::identifier, "as").is_err());
+        assert!(EmjayGrammar::parse(Rule::identifier, "true").is_err());
+        assert_can_be_parsed_as("truest", Rule::identifier);
     }
 
     #[test]
     fn grammar_can_parse_statement_let() {
         assert_can_be_parsed_as("let x = 1", Rule::letStatement);
         assert_can_be_parsed_as("let y_3π = 1 + x", Rule::letStatement);
+        assert_can_be_parsed_as("let y: u8 = 1", Rule::letStatement);
     }
 
     #[test]
     fn grammar_can_parse_statement_assignment() {
         assert_can_be_parsed_as("x = x + 1", Rule::assignmentStatement);
+        assert_can_be_parsed_as("x += 1", Rule::assignmentStatement);
+        assert_can_be_parsed_as("x <<= y * 2", Rule::assignmentStatement);
+        assert_can_be_parsed_as("x &= ~y", Rule::assignmentStatement);
+        assert_can_be_parsed_as("x++", Rule::incrementStatement);
+        assert_can_be_parsed_as("x--", Rule::incrementStatement);
+        assert!(EmjayGrammar::parse(Rule::assignmentStatement, "x + = 1").is_err());
+        // Comparisons are still expressions, rather than assignments
+        assert_can_be_parsed_as("{ x == 1; x <= 2; x >= 3; x - -1; }", Rule::block);
+    }
+
+    #[test]
+    fn grammar_can_parse_expression_statement() {
+        assert_can_be_parsed_as("{ store(a, 0, x + 1); }", Rule::block);
+        assert_can_be_parsed_as("{ f(); 1 + 2; }", Rule::block);
     }
 
     #[test]
@@ -78,11 +156,32 @@ mod tests {
         assert_can_be_parsed_as("return 42", Rule::returnStatement);
     }
 
+    #[test]
+    fn grammar_can_parse_statement_if() {
+        assert_can_be_parsed_as("if (x) { y = 1; }", Rule::ifStatement);
+        assert_can_be_parsed_as("if (x) { y = 1; } else { y = 2; }", Rule::ifStatement);
+        assert_can_be_parsed_as(
+            "if (x) { y = 1; } else if (z) { y = 2; } else {}",
+            Rule::ifStatement,
+        );
+    }
+
+    #[test]
+    fn grammar_can_parse_statement_while() {
+        assert_can_be_parsed_as("while (x) { x = x - 1; }", Rule::whileStatement);
+        assert_can_be_parsed_as("while (1) { break; }", Rule::whileStatement);
+        assert_can_be_parsed_as(
+            "while (x) { if (y) { continue; } break; }",
+            Rule::whileStatement,
+        );
+    }
+
     #[test]
     fn grammar_can_parse_block() {
         assert_can_be_parsed_as("{}", Rule::block);
         assert_can_be_parsed_as("{ x = y; }", Rule::block);
         assert_can_be_parsed_as("{ let x = y; { {} } let z = x; }", Rule::block);
+        assert_can_be_parsed_as("{ if (x) { return 1; } return 2; }", Rule::block);
     }
 
     #[test]
@@ -90,10 +189,88 @@ mod tests {
         assert_can_be_parsed_as("fn main() { let x = y; }", Rule::functionDeclaration);
         assert_can_be_parsed_as("fn f(a) {}", Rule::functionDeclaration);
         assert_can_be_parsed_as("fn f(a, b, c, d, e) {}", Rule::functionDeclaration);
+        assert_can_be_parsed_as("fn f(x: u32, y) -> u64 {}", Rule::functionDeclaration);
+    }
+
+    #[test]
+    fn grammar_can_parse_extern_function() {
+        assert_can_be_parsed_as("extern fn f();", Rule::externFunctionDeclaration);
+        assert_can_be_parsed_as("extern fn lookup(key, x);", Rule::externFunctionDeclaration);
+        assert_can_be_parsed_as(
+            "extern fn lookup(key: u8) -> bool;",
+            Rule::externFunctionDeclaration,
+        );
+        assert!(EmjayGrammar::parse(Rule::program, "extern fn f() { }").is_err());
+        assert!(EmjayGrammar::parse(Rule::identifier, "extern").is_err());
+    }
+
+    #[test]
+    fn grammar_can_parse_constant() {
+        assert_can_be_parsed_as("const X = 1;", Rule::constDeclaration);
+        assert_can_be_parsed_as("const SCALE = 1000 * (Y + 3);", Rule::constDeclaration);
+        assert_can_be_parsed_as("/// Doc\nconst X = 1;", Rule::constDeclaration);
+        assert!(EmjayGrammar::parse(Rule::identifier, "const").is_err());
     }
 
     #[test]
     fn grammar_can_parse_program() {
         assert_can_be_parsed_as("fn main() { }\nfn foo() { let x = 1; }", Rule::program);
+        assert_can_be_parsed_as("const X = 1;\nfn main() { }\nconst Y = X;", Rule::program);
+    }
+
+    #[test]
+    fn grammar_can_parse_imports_and_qualified_names() {
+        assert_can_be_parsed_as("import \"lib/finance.mj\";", Rule::importDeclaration);
+        assert_can_be_parsed_as(
+            "import \"a.mj\";\nimport \"b.mj\";\nfn main() { return a::f(&b::g); }",
+            Rule::program,
+        );
+        assert_can_be_parsed_as("finance::npv(rate, 2)", Rule::expression);
+        assert!(EmjayGrammar::parse(Rule::program, "fn f() { }\nimport \"a.mj\";").is_err());
+        assert!(EmjayGrammar::parse(Rule::importDeclaration, "import a;").is_err());
+        assert!(EmjayGrammar::parse(Rule::qualifiedName, "a :: b").is_err());
+        assert!(EmjayGrammar::parse(Rule::identifier, "import").is_err());
+    }
+
+    #[test]
+    fn grammar_accepts_comments_between_statements() {
+        assert_can_be_parsed_as(
+            "{ // first\n let x = 1; /* second */ x = 2; // last }\n}",
+            Rule::block,
+        );
+        assert_can_be_parsed_as("{ let x = 1; //// not a doc comment\n }", Rule::block);
+    }
+
+    #[test]
+    fn grammar_accepts_comments_inside_expressions() {
+        assert_can_be_parsed_as("1 /* one */ + // two\n 2", Rule::expression);
+        assert_can_be_parsed_as("f(/* no args */)", Rule::expression);
+        assert_can_be_parsed_as("a / /* not a line comment */ b", Rule::expression);
+    }
+
+    #[test]
+    fn grammar_accepts_comments_between_functions() {
+        assert_can_be_parsed_as(
+            "// header\nfn main() { }\n/* between */\nfn foo() { } // trailing",
+            Rule::program,
+        );
+    }
+
+    #[test]
+    fn grammar_accepts_nested_block_comments() {
+        assert_can_be_parsed_as(
+            "fn f() { /* outer /* inner */ still a comment */ return 1; }",
+            Rule::functionDeclaration,
+        );
+        assert!(EmjayGrammar::parse(Rule::program, "fn f() { /* /* */ return 1; }").is_err());
+    }
+
+    #[test]
+    fn grammar_accepts_doc_comments_only_before_functions() {
+        assert_can_be_parsed_as(
+            "/// Does something\n///\n/// Really\nfn f() { }",
+            Rule::functionDeclaration,
+        );
+        assert!(EmjayGrammar::parse(Rule::program, "fn f() { /// nope\n }").is_err());
     }
 }
@@ -1,6 +1,10 @@
 use core::fmt;
+use std::{borrow::Cow, collections::HashSet};
 
-use crate::frontend::FunctionId;
+use crate::{
+    frontend::{FunctionId, HostFunctionId},
+    types::Type,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
 pub struct IrRegister(pub usize);
@@ -46,12 +50,237 @@ impl fmt::Display for ArgumentIndex {
     }
 }
 
+/// The kind of hardware registers that a value needs: integers, pointers and bools
+/// live in the general purpose registers, while `f64` values live in the
+/// floating-point ones. The two are allocated independently, and the calling
+/// conventions also assign the arguments of each class to their own registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RegisterClass {
+    Integer,
+    Float,
+}
+
+impl From<Type> for RegisterClass {
+    fn from(ty: Type) -> Self {
+        match ty {
+            Type::F64 => RegisterClass::Float,
+            _ => RegisterClass::Integer,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
+pub struct BlockId(pub usize);
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "b{}", self.0)
+    }
+}
+
+/// What happens when the result of an integer `+`, `-` or `*`, including a negation,
+/// does not fit in its type. The mode is chosen for the whole program, and the
+/// optimizer, the constants and the generated code all follow it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowMode {
+    /// The result wraps around, as in two's complement arithmetic
+    #[default]
+    Wrapping,
+    /// The program stops with an error
+    Checked,
+    /// The result is clamped to the closest value of the type
+    Saturating,
+}
+
+impl fmt::Display for OverflowMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OverflowMode::Wrapping => write!(f, "wrapping"),
+            OverflowMode::Checked => write!(f, "checked"),
+            OverflowMode::Saturating => write!(f, "saturating"),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum BinOpOperator {
     Add,
     Sub,
     Mul,
     Div,
+    /// Remainder of the division, with the same sign as the dividend
+    Rem,
+    BitwiseAnd,
+    BitwiseOr,
+    BitwiseXor,
+    /// `op1 << op2`, or 0 if `op2` is not in the range 0..64
+    ShiftLeft,
+    /// Arithmetic shift `op1 >> op2`. If `op2` is not in the range 0..64, the result
+    /// is 0 or -1 depending on the sign of `op1`, as if it was shifted by 63. For
+    /// unsigned types, this is a logical shift, and the result is 0 in that case
+    ShiftRight,
+    // Comparisons produce 1 if true, 0 if false
+    Equal,
+    NotEqual,
+    LessThan,
+    LessThanOrEqual,
+    GreaterThan,
+    GreaterThanOrEqual,
+    Min,
+    Max,
+    /// `op1` raised to `op2`, where the exponent is unsigned, so that a negative one
+    /// is a huge exponent. It is computed by repeated squaring, with multiplications
+    /// that behave like `Mul`. The backends expand it to a loop
+    Pow,
+    /// Greatest common divisor of the absolute values, which is 0 only if both values
+    /// are 0. For `i64`, the result of `gcd(i64::MIN, 0)` does not fit and wraps around
+    /// to `i64::MIN`. The backends expand it to a loop
+    Gcd,
+}
+
+impl BinOpOperator {
+    /// Computes the result of the operation on two values of the type `ty`, with the
+    /// same semantics as the generated code: in particular, arithmetic wraps around on
+    /// overflow of 64 bits, and the result is not normalized to `ty`. Returns `None` for
+    /// the divisions that have no meaningful result, i.e. dividing by zero or
+    /// `i64::MIN / -1`
+    pub fn evaluate(&self, ty: Type, value1: i64, value2: i64) -> Option<i64> {
+        if ty.is_float() {
+            return self
+                .evaluate_float(f64::from_bits(value1 as u64), f64::from_bits(value2 as u64));
+        }
+        if ty.is_unsigned() {
+            let (unsigned1, unsigned2) = (value1 as u64, value2 as u64);
+            match self {
+                BinOpOperator::Div => return Some(unsigned1.checked_div(unsigned2)? as i64),
+                BinOpOperator::Rem => return Some(unsigned1.checked_rem(unsigned2)? as i64),
+                BinOpOperator::ShiftRight if (0..64).contains(&value2) => {
+                    return Some((unsigned1 >> value2) as i64)
+                }
+                BinOpOperator::ShiftRight => return Some(0),
+                BinOpOperator::LessThan => return Some((unsigned1 < unsigned2) as i64),
+                BinOpOperator::LessThanOrEqual => return Some((unsigned1 <= unsigned2) as i64),
+                BinOpOperator::GreaterThan => return Some((unsigned1 > unsigned2) as i64),
+                BinOpOperator::GreaterThanOrEqual => return Some((unsigned1 >= unsigned2) as i64),
+                BinOpOperator::Min => return Some(unsigned1.min(unsigned2) as i64),
+                BinOpOperator::Max => return Some(unsigned1.max(unsigned2) as i64),
+                BinOpOperator::Gcd => return Some(gcd(unsigned1, unsigned2) as i64),
+                _ => {}
+            }
+        }
+
+        Some(match self {
+            BinOpOperator::Add => value1.wrapping_add(value2),
+            BinOpOperator::Sub => value1.wrapping_sub(value2),
+            BinOpOperator::Mul => value1.wrapping_mul(value2),
+            BinOpOperator::Div => value1.checked_div(value2)?,
+            BinOpOperator::Rem => value1.checked_rem(value2)?,
+            BinOpOperator::BitwiseAnd => value1 & value2,
+            BinOpOperator::BitwiseOr => value1 | value2,
+            BinOpOperator::BitwiseXor => value1 ^ value2,
+            BinOpOperator::ShiftLeft if (0..64).contains(&value2) => value1 << value2,
+            BinOpOperator::ShiftLeft => 0,
+            BinOpOperator::ShiftRight if (0..64).contains(&value2) => value1 >> value2,
+            BinOpOperator::ShiftRight => value1 >> 63,
+            BinOpOperator::Equal => (value1 == value2) as i64,
+            BinOpOperator::NotEqual => (value1 != value2) as i64,
+            BinOpOperator::LessThan => (value1 < value2) as i64,
+            BinOpOperator::LessThanOrEqual => (value1 <= value2) as i64,
+            BinOpOperator::GreaterThan => (value1 > value2) as i64,
+            BinOpOperator::GreaterThanOrEqual => (value1 >= value2) as i64,
+            BinOpOperator::Min => value1.min(value2),
+            BinOpOperator::Max => value1.max(value2),
+            BinOpOperator::Pow => power(value1, value2 as u64, 1, i64::wrapping_mul),
+            BinOpOperator::Gcd => gcd(value1.unsigned_abs(), value2.unsigned_abs()) as i64,
+        })
+    }
+
+    /// Whether the result of the operation can be outside of the range of its type,
+    /// and is thus subject to the `OverflowMode`
+    pub fn can_overflow(&self, ty: Type) -> bool {
+        !ty.is_float()
+            && matches!(
+                self,
+                BinOpOperator::Add | BinOpOperator::Sub | BinOpOperator::Mul | BinOpOperator::Pow
+            )
+    }
+
+    /// Like `evaluate`, but the operations that can overflow follow the given mode, and
+    /// their result is already normalized to `ty`. Also returns `None` for a checked
+    /// operation that overflows, since it has no result either
+    pub fn evaluate_with_overflow(
+        &self,
+        ty: Type,
+        mode: OverflowMode,
+        value1: i64,
+        value2: i64,
+    ) -> Option<i64> {
+        // Narrower values are divided in 64 bits, where `MIN / -1` does have a result
+        let narrow_division_overflow = matches!(self, BinOpOperator::Div | BinOpOperator::Rem)
+            && ty.is_narrow()
+            && !ty.is_unsigned()
+            && ty.bounds().0 == value1 as i128
+            && value2 == -1;
+        if narrow_division_overflow {
+            match (mode, self) {
+                (OverflowMode::Checked, _) => return None,
+                (OverflowMode::Saturating, BinOpOperator::Div) => return Some(-(value1 + 1)),
+                _ => {}
+            }
+        }
+        if mode == OverflowMode::Wrapping || !self.can_overflow(ty) {
+            return self.evaluate(ty, value1, value2);
+        }
+
+        // The exact result always fits in an i128, except for some products of two
+        // u64, which are way above the maximum anyway
+        let widen = |value: i64| match ty.is_unsigned() {
+            true => value as u64 as i128,
+            false => value as i128,
+        };
+        let (value1, value2) = (widen(value1), widen(value2));
+        // Saturating keeps the sign of the results that do not fit in an i128
+        let exact = match self {
+            BinOpOperator::Add => value1 + value2,
+            BinOpOperator::Sub => value1 - value2,
+            BinOpOperator::Pow => power(value1, value2 as u64, 1, i128::saturating_mul),
+            _ => value1.saturating_mul(value2),
+        };
+        let (min, max) = ty.bounds();
+        match mode {
+            OverflowMode::Checked if exact < min || exact > max => None,
+            _ => Some(exact.clamp(min, max) as i64),
+        }
+    }
+
+    /// Like `evaluate`, for operations between two `f64`. Arithmetic produces the bits
+    /// of the result, and comparisons are false if either value is NaN
+    fn evaluate_float(&self, value1: f64, value2: f64) -> Option<i64> {
+        let bits = |value: f64| Some(value.to_bits() as i64);
+        match self {
+            BinOpOperator::Add => bits(value1 + value2),
+            BinOpOperator::Sub => bits(value1 - value2),
+            BinOpOperator::Mul => bits(value1 * value2),
+            BinOpOperator::Div => bits(value1 / value2),
+            BinOpOperator::Equal => Some((value1 == value2) as i64),
+            BinOpOperator::NotEqual => Some((value1 != value2) as i64),
+            BinOpOperator::LessThan => Some((value1 < value2) as i64),
+            BinOpOperator::LessThanOrEqual => Some((value1 <= value2) as i64),
+            BinOpOperator::GreaterThan => Some((value1 > value2) as i64),
+            BinOpOperator::GreaterThanOrEqual => Some((value1 >= value2) as i64),
+            // Rejected by the type checker
+            BinOpOperator::Rem
+            | BinOpOperator::Min
+            | BinOpOperator::Max
+            | BinOpOperator::Pow
+            | BinOpOperator::Gcd
+            | BinOpOperator::BitwiseAnd
+            | BinOpOperator::BitwiseOr
+            | BinOpOperator::BitwiseXor
+            | BinOpOperator::ShiftLeft
+            | BinOpOperator::ShiftRight => None,
+        }
+    }
 }
 
 impl fmt::Display for BinOpOperator {
@@ -61,10 +290,51 @@ impl fmt::Display for BinOpOperator {
             BinOpOperator::Sub => write!(f, "sub"),
             BinOpOperator::Mul => write!(f, "mul"),
             BinOpOperator::Div => write!(f, "div"),
+            BinOpOperator::Rem => write!(f, "rem"),
+            BinOpOperator::BitwiseAnd => write!(f, "and"),
+            BinOpOperator::BitwiseOr => write!(f, "or"),
+            BinOpOperator::BitwiseXor => write!(f, "xor"),
+            BinOpOperator::ShiftLeft => write!(f, "shl"),
+            BinOpOperator::ShiftRight => write!(f, "shr"),
+            BinOpOperator::Equal => write!(f, "eq"),
+            BinOpOperator::NotEqual => write!(f, "ne"),
+            BinOpOperator::LessThan => write!(f, "lt"),
+            BinOpOperator::LessThanOrEqual => write!(f, "le"),
+            BinOpOperator::GreaterThan => write!(f, "gt"),
+            BinOpOperator::GreaterThanOrEqual => write!(f, "ge"),
+            BinOpOperator::Min => write!(f, "min"),
+            BinOpOperator::Max => write!(f, "max"),
+            BinOpOperator::Pow => write!(f, "pow"),
+            BinOpOperator::Gcd => write!(f, "gcd"),
+        }
+    }
+}
+
+/// `base` raised to `exponent` by repeated squaring, which is also how the generated
+/// code computes it. The base is only squared when a higher bit of the exponent needs
+/// it, so that the result overflows only if the exact one does
+fn power<T: Copy>(base: T, exponent: u64, one: T, multiply: impl Fn(T, T) -> T) -> T {
+    let (mut result, mut base, mut exponent) = (one, base, exponent);
+    loop {
+        if exponent & 1 == 1 {
+            result = multiply(result, base);
         }
+        exponent >>= 1;
+        if exponent == 0 {
+            return result;
+        }
+        base = multiply(base, base);
     }
 }
 
+/// Euclid's algorithm
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum IrInstruction {
     Mvi {
@@ -75,9 +345,27 @@ pub enum IrInstruction {
         dest: IrRegister,
         arg: ArgumentIndex,
     },
+    /// Plain copy between registers. It is never generated by the frontend, since
+    /// it would break the SSA form, but it is used when lowering `Phi` instructions
+    Mv {
+        dest: IrRegister,
+        src: IrRegister,
+    },
+    /// Selects the value of `dest` depending on which predecessor block we came from.
+    /// Phi instructions must appear at the beginning of a block.
+    Phi {
+        dest: IrRegister,
+        sources: Vec<(BlockId, IrRegister)>,
+    },
 
+    /// An operation between two values of type `ty`, which is only relevant for
+    /// divisions, shifts and comparisons, whose result depends on the signedness,
+    /// and for `f64`, which uses floating-point arithmetic. When the function's
+    /// `OverflowMode` is not `Wrapping`, the type also gives the range of `+`, `-` and
+    /// `*`, which detect overflows and produce an already normalized result
     BinOp {
         operator: BinOpOperator,
+        ty: Type,
         dest: IrRegister,
         op1: IrRegister,
         op2: IrRegister,
@@ -86,47 +374,505 @@ pub enum IrInstruction {
         dest: IrRegister,
         op: IrRegister,
     },
+    /// Bitwise complement, i.e. `~op`
+    BitwiseNot {
+        dest: IrRegister,
+        op: IrRegister,
+    },
+    /// Absolute value, which wraps around to `i64::MIN` for `i64::MIN`
+    Abs {
+        dest: IrRegister,
+        op: IrRegister,
+    },
+    /// -1, 0 or 1, depending on the sign of `op`
+    Sign {
+        dest: IrRegister,
+        op: IrRegister,
+    },
+    /// The number of bits set in `op`
+    Popcount {
+        dest: IrRegister,
+        op: IrRegister,
+    },
+    /// Converts `op` to `ty`, i.e. truncates it to the size of `ty` and then sign- or
+    /// zero-extends it to 64 bits, as in `Type::normalize`
+    Cast {
+        dest: IrRegister,
+        op: IrRegister,
+        ty: Type,
+    },
+    /// Converts the signed integer `op` to the nearest `f64`
+    IntToFloat {
+        dest: IrRegister,
+        op: IrRegister,
+    },
+    /// Converts the `f64` in `op` to an integer, rounding towards zero. Like the `as`
+    /// of Rust, values out of range saturate to `i64::MIN` or `i64::MAX`, and NaN
+    /// becomes 0
+    FloatToInt {
+        dest: IrRegister,
+        op: IrRegister,
+    },
 
     Ret {
         reg: IrRegister,
     },
+    Jmp {
+        target: BlockId,
+    },
+    /// Jumps to `if_true` if `cond` is not zero, to `if_false` otherwise
+    Br {
+        cond: IrRegister,
+        if_true: BlockId,
+        if_false: BlockId,
+    },
+    /// Jumps to `targets[value - first]`, or to `default` if there is no such target.
+    /// The backends compile it to a jump table, so it is only used for dense values
+    Switch {
+        value: IrRegister,
+        first: i64,
+        targets: Vec<BlockId>,
+        default: BlockId,
+    },
     Call {
         dest: IrRegister,
         name: String,
         function_id: FunctionId,
         args: Vec<IrRegister>,
     },
+    /// Calls the function whose `FunctionId` is the value of `handle`, as created by a
+    /// `&name` expression. Its arguments and result are always `i64`, and the generated
+    /// code traps if the handle is not valid or if the function takes a different number
+    /// of arguments.
+    CallIndirect {
+        dest: IrRegister,
+        handle: IrRegister,
+        args: Vec<IrRegister>,
+    },
+    /// Calls a function provided by the host program. Its address is already known when
+    /// compiling, so it can be called directly without going through the trampoline.
+    CallHost {
+        dest: IrRegister,
+        name: String,
+        host_function_id: HostFunctionId,
+        args: Vec<IrRegister>,
+    },
+    /// Reads the element `base + index` of the memory supplied by the host, trapping if
+    /// it is out of bounds
+    Load {
+        dest: IrRegister,
+        base: IrRegister,
+        index: IrRegister,
+    },
+    /// Writes `value` into the element `base + index` of the memory supplied by the host,
+    /// trapping if it is out of bounds
+    Store {
+        base: IrRegister,
+        index: IrRegister,
+        value: IrRegister,
+    },
 }
 
 impl IrInstruction {
+    /// All the registers referenced by the instruction: the destination (if any),
+    /// followed by the registers being read
     pub fn operands(&self) -> impl Iterator<Item = IrRegister> {
+        self.dest()
+            .into_iter()
+            .chain(self.uses())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    /// The register written by this instruction, if any
+    pub fn dest(&self) -> Option<IrRegister> {
         match self {
-            IrInstruction::Mvi { dest, .. } => vec![*dest].into_iter(),
-            IrInstruction::MvArg { dest, .. } => vec![*dest].into_iter(),
-            IrInstruction::Neg { dest, op } => vec![*dest, *op].into_iter(),
-            IrInstruction::BinOp {
-                operator: _,
-                dest,
-                op1,
-                op2,
-            } => vec![*dest, *op1, *op2].into_iter(),
+            IrInstruction::Mvi { dest, .. }
+            | IrInstruction::MvArg { dest, .. }
+            | IrInstruction::Mv { dest, .. }
+            | IrInstruction::Phi { dest, .. }
+            | IrInstruction::BinOp { dest, .. }
+            | IrInstruction::Neg { dest, .. }
+            | IrInstruction::BitwiseNot { dest, .. }
+            | IrInstruction::Abs { dest, .. }
+            | IrInstruction::Sign { dest, .. }
+            | IrInstruction::Popcount { dest, .. }
+            | IrInstruction::Cast { dest, .. }
+            | IrInstruction::IntToFloat { dest, .. }
+            | IrInstruction::FloatToInt { dest, .. }
+            | IrInstruction::Call { dest, .. }
+            | IrInstruction::CallIndirect { dest, .. }
+            | IrInstruction::CallHost { dest, .. }
+            | IrInstruction::Load { dest, .. } => Some(*dest),
+            IrInstruction::Ret { .. }
+            | IrInstruction::Jmp { .. }
+            | IrInstruction::Br { .. }
+            | IrInstruction::Switch { .. }
+            | IrInstruction::Store { .. } => None,
+        }
+    }
+
+    /// The registers read by this instruction
+    pub fn uses(&self) -> impl Iterator<Item = IrRegister> {
+        match self {
+            IrInstruction::Mvi { .. } | IrInstruction::MvArg { .. } | IrInstruction::Jmp { .. } => {
+                vec![].into_iter()
+            }
+            IrInstruction::Mv { src, .. } => vec![*src].into_iter(),
+            IrInstruction::Phi { sources, .. } => sources
+                .iter()
+                .map(|(_, reg)| *reg)
+                .collect::<Vec<_>>()
+                .into_iter(),
+            IrInstruction::Neg { op, .. }
+            | IrInstruction::BitwiseNot { op, .. }
+            | IrInstruction::Abs { op, .. }
+            | IrInstruction::Sign { op, .. }
+            | IrInstruction::Popcount { op, .. }
+            | IrInstruction::Cast { op, .. }
+            | IrInstruction::IntToFloat { op, .. }
+            | IrInstruction::FloatToInt { op, .. } => vec![*op].into_iter(),
+            IrInstruction::BinOp { op1, op2, .. } => vec![*op1, *op2].into_iter(),
             IrInstruction::Ret { reg } => vec![*reg].into_iter(),
-            IrInstruction::Call { dest, args, .. } => vec![*dest]
-                .into_iter()
+            IrInstruction::Br { cond, .. } => vec![*cond].into_iter(),
+            IrInstruction::Switch { value, .. } => vec![*value].into_iter(),
+            IrInstruction::Call { args, .. } | IrInstruction::CallHost { args, .. } => {
+                args.clone().into_iter()
+            }
+            IrInstruction::CallIndirect { handle, args, .. } => std::iter::once(*handle)
                 .chain(args.iter().copied())
                 .collect::<Vec<_>>()
                 .into_iter(),
+            IrInstruction::Load { base, index, .. } => vec![*base, *index].into_iter(),
+            IrInstruction::Store { base, index, value } => vec![*base, *index, *value].into_iter(),
+        }
+    }
+
+    /// Whether executing this instruction can have effects other than writing its
+    /// destination, such as calling other code or trapping, in which case it must be
+    /// kept even if its result is never used. This includes integer divisions, and, in
+    /// checked mode, the arithmetic that can overflow
+    pub fn has_side_effects(&self, overflow_mode: OverflowMode) -> bool {
+        match self {
+            IrInstruction::Call { .. }
+            | IrInstruction::CallIndirect { .. }
+            | IrInstruction::CallHost { .. }
+            | IrInstruction::Load { .. }
+            | IrInstruction::Store { .. } => true,
+            IrInstruction::BinOp {
+                operator: BinOpOperator::Div | BinOpOperator::Rem,
+                ty,
+                ..
+            } => !ty.is_float(),
+            IrInstruction::BinOp { operator, ty, .. } => {
+                overflow_mode == OverflowMode::Checked && operator.can_overflow(*ty)
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns a copy of this instruction, with all registers (both written and read)
+    /// replaced by the result of `f`
+    pub fn map_registers(&self, mut f: impl FnMut(IrRegister) -> IrRegister) -> IrInstruction {
+        match self {
+            IrInstruction::Mvi { dest, val } => IrInstruction::Mvi {
+                dest: f(*dest),
+                val: *val,
+            },
+            IrInstruction::MvArg { dest, arg } => IrInstruction::MvArg {
+                dest: f(*dest),
+                arg: *arg,
+            },
+            IrInstruction::Mv { dest, src } => IrInstruction::Mv {
+                dest: f(*dest),
+                src: f(*src),
+            },
+            IrInstruction::Phi { dest, sources } => IrInstruction::Phi {
+                dest: f(*dest),
+                sources: sources
+                    .iter()
+                    .map(|(block, reg)| (*block, f(*reg)))
+                    .collect(),
+            },
+            IrInstruction::BinOp {
+                operator,
+                ty,
+                dest,
+                op1,
+                op2,
+            } => IrInstruction::BinOp {
+                operator: *operator,
+                ty: *ty,
+                dest: f(*dest),
+                op1: f(*op1),
+                op2: f(*op2),
+            },
+            IrInstruction::Neg { dest, op } => IrInstruction::Neg {
+                dest: f(*dest),
+                op: f(*op),
+            },
+            IrInstruction::BitwiseNot { dest, op } => IrInstruction::BitwiseNot {
+                dest: f(*dest),
+                op: f(*op),
+            },
+            IrInstruction::Abs { dest, op } => IrInstruction::Abs {
+                dest: f(*dest),
+                op: f(*op),
+            },
+            IrInstruction::Sign { dest, op } => IrInstruction::Sign {
+                dest: f(*dest),
+                op: f(*op),
+            },
+            IrInstruction::Popcount { dest, op } => IrInstruction::Popcount {
+                dest: f(*dest),
+                op: f(*op),
+            },
+            IrInstruction::Cast { dest, op, ty } => IrInstruction::Cast {
+                dest: f(*dest),
+                op: f(*op),
+                ty: *ty,
+            },
+            IrInstruction::IntToFloat { dest, op } => IrInstruction::IntToFloat {
+                dest: f(*dest),
+                op: f(*op),
+            },
+            IrInstruction::FloatToInt { dest, op } => IrInstruction::FloatToInt {
+                dest: f(*dest),
+                op: f(*op),
+            },
+            IrInstruction::Ret { reg } => IrInstruction::Ret { reg: f(*reg) },
+            IrInstruction::Jmp { target } => IrInstruction::Jmp { target: *target },
+            IrInstruction::Br {
+                cond,
+                if_true,
+                if_false,
+            } => IrInstruction::Br {
+                cond: f(*cond),
+                if_true: *if_true,
+                if_false: *if_false,
+            },
+            IrInstruction::Switch {
+                value,
+                first,
+                targets,
+                default,
+            } => IrInstruction::Switch {
+                value: f(*value),
+                first: *first,
+                targets: targets.clone(),
+                default: *default,
+            },
+            IrInstruction::Call {
+                dest,
+                name,
+                function_id,
+                args,
+            } => IrInstruction::Call {
+                dest: f(*dest),
+                name: name.clone(),
+                function_id: *function_id,
+                args: args.iter().map(|arg| f(*arg)).collect(),
+            },
+            IrInstruction::CallIndirect { dest, handle, args } => IrInstruction::CallIndirect {
+                dest: f(*dest),
+                handle: f(*handle),
+                args: args.iter().map(|arg| f(*arg)).collect(),
+            },
+            IrInstruction::CallHost {
+                dest,
+                name,
+                host_function_id,
+                args,
+            } => IrInstruction::CallHost {
+                dest: f(*dest),
+                name: name.clone(),
+                host_function_id: *host_function_id,
+                args: args.iter().map(|arg| f(*arg)).collect(),
+            },
+            IrInstruction::Load { dest, base, index } => IrInstruction::Load {
+                dest: f(*dest),
+                base: f(*base),
+                index: f(*index),
+            },
+            IrInstruction::Store { base, index, value } => IrInstruction::Store {
+                base: f(*base),
+                index: f(*index),
+                value: f(*value),
+            },
+        }
+    }
+
+    /// Returns a copy of this instruction, with all referenced blocks replaced by the
+    /// result of `f`
+    pub fn map_blocks(&self, mut f: impl FnMut(BlockId) -> BlockId) -> IrInstruction {
+        match self {
+            IrInstruction::Phi { dest, sources } => IrInstruction::Phi {
+                dest: *dest,
+                sources: sources
+                    .iter()
+                    .map(|(block, reg)| (f(*block), *reg))
+                    .collect(),
+            },
+            IrInstruction::Jmp { target } => IrInstruction::Jmp { target: f(*target) },
+            IrInstruction::Br {
+                cond,
+                if_true,
+                if_false,
+            } => IrInstruction::Br {
+                cond: *cond,
+                if_true: f(*if_true),
+                if_false: f(*if_false),
+            },
+            IrInstruction::Switch {
+                value,
+                first,
+                targets,
+                default,
+            } => IrInstruction::Switch {
+                value: *value,
+                first: *first,
+                targets: targets.iter().map(|target| f(*target)).collect(),
+                default: f(*default),
+            },
+            _ => self.clone(),
+        }
+    }
+
+    /// Whether this instruction ends a basic block
+    pub fn is_terminator(&self) -> bool {
+        matches!(
+            self,
+            IrInstruction::Ret { .. }
+                | IrInstruction::Jmp { .. }
+                | IrInstruction::Br { .. }
+                | IrInstruction::Switch { .. }
+        )
+    }
+
+    /// The blocks to which this instruction can transfer control
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            IrInstruction::Jmp { target } => vec![*target],
+            IrInstruction::Br {
+                if_true, if_false, ..
+            } => vec![*if_true, *if_false],
+            // A block can be the target of many values, but it is a single successor
+            IrInstruction::Switch {
+                targets, default, ..
+            } => {
+                let mut successors = Vec::with_capacity(targets.len() + 1);
+                for target in targets.iter().chain(std::iter::once(default)) {
+                    if !successors.contains(target) {
+                        successors.push(*target);
+                    }
+                }
+                successors
+            }
+            _ => vec![],
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct BasicBlock {
+    pub id: BlockId,
+    pub body: Vec<IrInstruction>,
+}
+
+impl BasicBlock {
+    pub fn new(id: BlockId) -> Self {
+        Self {
+            id,
+            body: Vec::new(),
         }
     }
+
+    pub fn is_terminated(&self) -> bool {
+        self.body.last().is_some_and(|i| i.is_terminator())
+    }
+
+    pub fn successors(&self) -> Vec<BlockId> {
+        self.body.last().map(|i| i.successors()).unwrap_or_default()
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CompiledFunction<'input> {
-    pub name: &'input str,
+    /// The name of the function, qualified with the one of its module, such as
+    /// `finance::npv`, unless it belongs to the root module of the program
+    pub name: Cow<'input, str>,
     pub id: FunctionId,
-    pub num_args: usize,
-    pub body: Vec<IrInstruction>,
+    /// The class of each argument, which also gives their number
+    pub arg_classes: Vec<RegisterClass>,
+    /// The basic blocks of the function. The id of each block matches its index,
+    /// and the first block is the entry point
+    pub blocks: Vec<BasicBlock>,
     pub num_used_registers: usize,
+    /// The registers holding `f64` values. All the other ones hold integers
+    pub float_registers: HashSet<IrRegister>,
+    /// How the integer arithmetic of the function behaves on overflow
+    pub overflow_mode: OverflowMode,
+}
+
+impl CompiledFunction<'_> {
+    /// Iterates over all the instructions of the function, block after block
+    pub fn instructions(&self) -> impl Iterator<Item = &IrInstruction> {
+        self.blocks.iter().flat_map(|block| block.body.iter())
+    }
+
+    pub fn num_args(&self) -> usize {
+        self.arg_classes.len()
+    }
+
+    pub fn register_class(&self, reg: IrRegister) -> RegisterClass {
+        if self.float_registers.contains(&reg) {
+            RegisterClass::Float
+        } else {
+            RegisterClass::Integer
+        }
+    }
+
+    /// The position of the argument among the ones of the same class, which is what
+    /// the calling conventions use to choose the register where it is passed
+    pub fn position_in_class(&self, arg: ArgumentIndex) -> usize {
+        let index = usize::from(arg);
+        let class = self.arg_classes[index];
+        self.arg_classes[..index]
+            .iter()
+            .filter(|other| **other == class)
+            .count()
+    }
+
+    /// The position of the argument among the ones passed on the stack, when the
+    /// calling convention has the given number of registers for each class. The ones
+    /// that do not fit in the registers are pushed in order, regardless of their class
+    pub fn position_on_stack(
+        &self,
+        arg: ArgumentIndex,
+        num_integer_registers: usize,
+        num_float_registers: usize,
+    ) -> usize {
+        (0..usize::from(arg))
+            .filter(|index| {
+                let num_registers = match self.arg_classes[*index] {
+                    RegisterClass::Integer => num_integer_registers,
+                    RegisterClass::Float => num_float_registers,
+                };
+                self.position_in_class(ArgumentIndex::from(*index)) >= num_registers
+            })
+            .count()
+    }
+
+    /// The class of the value returned by the function. All the `ret` have the same
+    /// one, and a function without any of them never returns, so it does not matter
+    pub fn return_class(&self) -> RegisterClass {
+        self.instructions()
+            .find_map(|instruction| match instruction {
+                IrInstruction::Ret { reg } => Some(self.register_class(*reg)),
+                _ => None,
+            })
+            .unwrap_or(RegisterClass::Integer)
+    }
 }
 
 impl fmt::Display for IrInstruction {
@@ -134,16 +880,64 @@ impl fmt::Display for IrInstruction {
         match self {
             IrInstruction::Mvi { dest, val } => write!(f, "mvi  @r{}, {}", dest, val),
             IrInstruction::MvArg { dest, arg } => write!(f, "mva  @r{}, a{}", dest, arg),
+            IrInstruction::Mv { dest, src } => write!(f, "mv   @r{}, r{}", dest, src),
+            IrInstruction::Phi { dest, sources } => {
+                write!(f, "phi  @r{}, [", dest)?;
+                for (i, (block, reg)) in sources.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: r{}", block, reg)?;
+                }
+                write!(f, "]")
+            }
             IrInstruction::Neg { dest, op } => write!(f, "neg @r{}, r{}", dest, op),
+            IrInstruction::BitwiseNot { dest, op } => write!(f, "not @r{}, r{}", dest, op),
+            IrInstruction::Abs { dest, op } => write!(f, "abs @r{}, r{}", dest, op),
+            IrInstruction::Sign { dest, op } => write!(f, "sign @r{}, r{}", dest, op),
+            IrInstruction::Popcount { dest, op } => write!(f, "popc @r{}, r{}", dest, op),
+            IrInstruction::Cast { dest, op, ty } => {
+                write!(f, "cast @r{}, r{} as {}", dest, op, ty)
+            }
+            IrInstruction::IntToFloat { dest, op } => write!(f, "itof @r{}, r{}", dest, op),
+            IrInstruction::FloatToInt { dest, op } => write!(f, "ftoi @r{}, r{}", dest, op),
             IrInstruction::BinOp {
                 operator,
+                ty,
                 dest,
                 op1,
                 op2,
             } => {
-                write!(f, "{}  @r{}, r{}, r{}", operator, dest, op1, op2)
+                write!(f, "{}  @r{}, r{}, r{}", operator, dest, op1, op2)?;
+                // Most operations are the same for all types, so we only show the
+                // ones that are not the default
+                if *ty != Type::I64 {
+                    write!(f, " ({})", ty)?;
+                }
+                Ok(())
             }
             IrInstruction::Ret { reg } => write!(f, "ret  r{}", reg),
+            IrInstruction::Jmp { target } => write!(f, "jmp  {}", target),
+            IrInstruction::Br {
+                cond,
+                if_true,
+                if_false,
+            } => write!(f, "br   r{}, {}, {}", cond, if_true, if_false),
+            IrInstruction::Switch {
+                value,
+                first,
+                targets,
+                default,
+            } => {
+                write!(f, "swch r{}, [", value)?;
+                for (i, target) in targets.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", first.wrapping_add(i as i64), target)?;
+                }
+                write!(f, "], {}", default)
+            }
             IrInstruction::Call {
                 dest,
                 function_id,
@@ -151,27 +945,73 @@ impl fmt::Display for IrInstruction {
                 args,
             } => {
                 write!(f, "call @r{}, {}:{}(", dest, name, function_id.0)?;
-                for (i, arg) in args.iter().enumerate() {
-                    if i > 0 {
-                        write!(f, ", ")?;
-                    }
-                    write!(f, "r{}", arg)?;
-                }
-                write!(f, ")")
+                write_call_args(f, args)
+            }
+            IrInstruction::CallIndirect { dest, handle, args } => {
+                write!(f, "call @r{}, *r{}(", dest, handle)?;
+                write_call_args(f, args)
+            }
+            IrInstruction::CallHost {
+                dest,
+                name,
+                host_function_id,
+                args,
+            } => {
+                write!(
+                    f,
+                    "call @r{}, extern {}:{}(",
+                    dest, name, host_function_id.0
+                )?;
+                write_call_args(f, args)
+            }
+            IrInstruction::Load { dest, base, index } => {
+                write!(f, "load @r{}, [r{} + r{}]", dest, base, index)
+            }
+            IrInstruction::Store { base, index, value } => {
+                write!(f, "stor [r{} + r{}], r{}", base, index, value)
             }
         }
     }
 }
 
+fn write_call_args(f: &mut fmt::Formatter<'_>, args: &[IrRegister]) -> fmt::Result {
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "r{}", arg)?;
+    }
+    write!(f, ")")
+}
+
 impl fmt::Display for CompiledFunction<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(
+        write!(
             f,
-            "fn {} - #args: {}, #reg: {} {{",
-            self.name, self.num_args, self.num_used_registers
+            "fn {} - #args: {}, #reg: {}",
+            self.name,
+            self.num_args(),
+            self.num_used_registers
         )?;
-        for (i, instr) in self.body.iter().enumerate() {
-            writeln!(f, "  {:-3}:  {}", i, instr)?;
+        if !self.float_registers.is_empty() {
+            let mut float_registers = self.float_registers.iter().collect::<Vec<_>>();
+            float_registers.sort_by_key(|reg| reg.0);
+            write!(f, ", float:")?;
+            for reg in float_registers {
+                write!(f, " r{}", reg)?;
+            }
+        }
+        if self.overflow_mode != OverflowMode::Wrapping {
+            write!(f, ", overflow: {}", self.overflow_mode)?;
+        }
+        writeln!(f, " {{")?;
+        let mut i = 0;
+        for block in self.blocks.iter() {
+            writeln!(f, "  {}:", block.id)?;
+            for instr in block.body.iter() {
+                writeln!(f, "    {:-3}:  {}", i, instr)?;
+                i += 1;
+            }
         }
         write!(f, "}}")
     }
@@ -202,9 +1042,38 @@ pub mod builders {
         }
     }
 
+    pub fn not(dest: usize, op: usize) -> IrInstruction {
+        IrInstruction::BitwiseNot {
+            dest: IrRegister::new(dest),
+            op: IrRegister::new(op),
+        }
+    }
+
+    pub fn abs(dest: usize, op: usize) -> IrInstruction {
+        IrInstruction::Abs {
+            dest: IrRegister::new(dest),
+            op: IrRegister::new(op),
+        }
+    }
+
+    pub fn sign(dest: usize, op: usize) -> IrInstruction {
+        IrInstruction::Sign {
+            dest: IrRegister::new(dest),
+            op: IrRegister::new(op),
+        }
+    }
+
+    pub fn popcount(dest: usize, op: usize) -> IrInstruction {
+        IrInstruction::Popcount {
+            dest: IrRegister::new(dest),
+            op: IrRegister::new(op),
+        }
+    }
+
     pub fn add(dest: usize, op1: usize, op2: usize) -> IrInstruction {
         IrInstruction::BinOp {
             operator: BinOpOperator::Add,
+            ty: Type::I64,
             dest: IrRegister::new(dest),
             op1: IrRegister::new(op1),
             op2: IrRegister::new(op2),
@@ -214,6 +1083,7 @@ pub mod builders {
     pub fn sub(dest: usize, op1: usize, op2: usize) -> IrInstruction {
         IrInstruction::BinOp {
             operator: BinOpOperator::Sub,
+            ty: Type::I64,
             dest: IrRegister::new(dest),
             op1: IrRegister::new(op1),
             op2: IrRegister::new(op2),
@@ -223,6 +1093,7 @@ pub mod builders {
     pub fn mul(dest: usize, op1: usize, op2: usize) -> IrInstruction {
         IrInstruction::BinOp {
             operator: BinOpOperator::Mul,
+            ty: Type::I64,
             dest: IrRegister::new(dest),
             op1: IrRegister::new(op1),
             op2: IrRegister::new(op2),
@@ -232,18 +1103,114 @@ pub mod builders {
     pub fn div(dest: usize, op1: usize, op2: usize) -> IrInstruction {
         IrInstruction::BinOp {
             operator: BinOpOperator::Div,
+            ty: Type::I64,
+            dest: IrRegister::new(dest),
+            op1: IrRegister::new(op1),
+            op2: IrRegister::new(op2),
+        }
+    }
+
+    pub fn binop(operator: BinOpOperator, dest: usize, op1: usize, op2: usize) -> IrInstruction {
+        IrInstruction::BinOp {
+            operator,
+            ty: Type::I64,
+            dest: IrRegister::new(dest),
+            op1: IrRegister::new(op1),
+            op2: IrRegister::new(op2),
+        }
+    }
+
+    pub fn typed_binop(
+        operator: BinOpOperator,
+        ty: Type,
+        dest: usize,
+        op1: usize,
+        op2: usize,
+    ) -> IrInstruction {
+        IrInstruction::BinOp {
+            operator,
+            ty,
             dest: IrRegister::new(dest),
             op1: IrRegister::new(op1),
             op2: IrRegister::new(op2),
         }
     }
 
+    pub fn cast(dest: usize, op: usize, ty: Type) -> IrInstruction {
+        IrInstruction::Cast {
+            dest: IrRegister::new(dest),
+            op: IrRegister::new(op),
+            ty,
+        }
+    }
+
+    pub fn itof(dest: usize, op: usize) -> IrInstruction {
+        IrInstruction::IntToFloat {
+            dest: IrRegister::new(dest),
+            op: IrRegister::new(op),
+        }
+    }
+
+    pub fn ftoi(dest: usize, op: usize) -> IrInstruction {
+        IrInstruction::FloatToInt {
+            dest: IrRegister::new(dest),
+            op: IrRegister::new(op),
+        }
+    }
+
     pub fn ret(reg: usize) -> IrInstruction {
         IrInstruction::Ret {
             reg: IrRegister::new(reg),
         }
     }
 
+    pub fn mv(dest: usize, src: usize) -> IrInstruction {
+        IrInstruction::Mv {
+            dest: IrRegister::new(dest),
+            src: IrRegister::new(src),
+        }
+    }
+
+    pub fn phi(dest: usize, sources: Vec<(usize, usize)>) -> IrInstruction {
+        IrInstruction::Phi {
+            dest: IrRegister::new(dest),
+            sources: sources
+                .into_iter()
+                .map(|(block, reg)| (BlockId(block), IrRegister::new(reg)))
+                .collect(),
+        }
+    }
+
+    pub fn jmp(target: usize) -> IrInstruction {
+        IrInstruction::Jmp {
+            target: BlockId(target),
+        }
+    }
+
+    pub fn br(cond: usize, if_true: usize, if_false: usize) -> IrInstruction {
+        IrInstruction::Br {
+            cond: IrRegister::new(cond),
+            if_true: BlockId(if_true),
+            if_false: BlockId(if_false),
+        }
+    }
+
+    pub fn switch(value: usize, first: i64, targets: Vec<usize>, default: usize) -> IrInstruction {
+        IrInstruction::Switch {
+            value: IrRegister::new(value),
+            first,
+            targets: targets.into_iter().map(BlockId).collect(),
+            default: BlockId(default),
+        }
+    }
+
+    pub fn block(id: usize, body: Vec<IrInstruction>) -> BasicBlock {
+        BasicBlock {
+            id: BlockId(id),
+            body,
+        }
+    }
+
     pub fn call(dest: usize, name: &str, id: usize, args: Vec<usize>) -> IrInstruction {
         IrInstruction::Call {
             dest: IrRegister::new(dest),
@@ -252,4 +1219,37 @@ pub mod builders {
             args: args.into_iter().map(IrRegister::new).collect(),
         }
     }
+
+    pub fn call_indirect(dest: usize, handle: usize, args: Vec<usize>) -> IrInstruction {
+        IrInstruction::CallIndirect {
+            dest: IrRegister::new(dest),
+            handle: IrRegister::new(handle),
+            args: args.into_iter().map(IrRegister::new).collect(),
+        }
+    }
+
+    pub fn call_host(dest: usize, name: &str, id: usize, args: Vec<usize>) -> IrInstruction {
+        IrInstruction::CallHost {
+            dest: IrRegister::new(dest),
+            host_function_id: HostFunctionId(id),
+            name: name.to_string(),
+            args: args.into_iter().map(IrRegister::new).collect(),
+        }
+    }
+
+    pub fn load(dest: usize, base: usize, index: usize) -> IrInstruction {
+        IrInstruction::Load {
+            dest: IrRegister::new(dest),
+            base: IrRegister::new(base),
+            index: IrRegister::new(index),
+        }
+    }
+
+    pub fn store(base: usize, index: usize, value: usize) -> IrInstruction {
+        IrInstruction::Store {
+            base: IrRegister::new(base),
+            index: IrRegister::new(index),
+            value: IrRegister::new(value),
+        }
+    }
 }
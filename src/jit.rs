@@ -1,17 +1,19 @@
+use std::marker::PhantomData;
+
 #[allow(unused)]
 use rustix::mm::{mmap_anonymous, mprotect, MapFlags, MprotectFlags, ProtFlags};
 use thiserror::Error;
-use tracing::{debug, info};
-
-#[allow(unused)]
-use crate::backend_aarch64::Aarch64Generator;
-#[allow(unused)]
-use crate::backend_x64_linux::X64LinuxGenerator;
+use tracing::debug;
 
 use crate::{
-    backend::{BackendError, CompiledFunctionCatalog, JitFn, MachineCodeGenerator},
-    frontend::{self, FrontendError, FunctionId},
-    optimization, parser,
+    backend::{BackendError, CompiledFunctionCatalog, JitFn, TrapCode},
+    engine::{Backend, Engine},
+    frontend::{
+        FrontendError, FrontendOptions, FrontendWarning, FunctionId, HostFunctionSignature,
+    },
+    ir::RegisterClass,
+    modules::{FileProvider, InMemoryFiles, ModuleError, SourceFiles},
+    parser,
 };
 
 #[derive(Debug, Error)]
@@ -31,22 +33,23 @@ impl From<rustix::io::Errno> for MmapError {
 }
 
 // Converts the given slice bytes, containing machine code, into a function pointer. It does so by
-// mmapping a new page, copying the bytes, and then performing a cast.
-unsafe fn to_function_pointer(bytes: &[u8]) -> Result<JitFn, MmapError> {
+// mmapping a new page, copying the bytes, and then performing a cast. The page must also be
+// readable, since the jump tables are stored after the code.
+pub(crate) unsafe fn to_function_pointer(bytes: &[u8]) -> Result<JitFn, MmapError> {
     #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
     {
         let size = bytes.len();
         let map = mmap_anonymous(
             std::ptr::null_mut(),
             size,
-            ProtFlags::WRITE | ProtFlags::EXEC,
+            ProtFlags::READ | ProtFlags::WRITE | ProtFlags::EXEC,
             MapFlags::PRIVATE,
         )?;
 
         debug!("mmapped address: {:?}", map);
         std::ptr::copy_nonoverlapping(bytes.as_ptr(), map as *mut u8, size);
 
-        let f: fn() -> i64 = std::mem::transmute(map);
+        let f: JitFn = std::mem::transmute(map);
         Ok(f)
     }
 
@@ -63,7 +66,7 @@ unsafe fn to_function_pointer(bytes: &[u8]) -> Result<JitFn, MmapError> {
         debug!("mmapped address: {:?}", map);
         std::ptr::copy_nonoverlapping(bytes.as_ptr(), map as *mut u8, size);
 
-        mprotect(map, size, MprotectFlags::EXEC)?;
+        mprotect(map, size, MprotectFlags::READ | MprotectFlags::EXEC)?;
         debug!("mprotected: {:?}", map);
 
         let f: JitFn = std::mem::transmute(map);
@@ -74,82 +77,513 @@ unsafe fn to_function_pointer(bytes: &[u8]) -> Result<JitFn, MmapError> {
 #[derive(Debug, Error)]
 pub enum JitError {
     #[error("{0}")]
-    Parser(#[from] Box<parser::ParseError>),
+    Module(#[from] ModuleError),
     #[error("{0}")]
-    Frontend(#[from] FrontendError),
+    Parser(#[from] Box<parser::ParseError>),
+    #[error("{message}")]
+    Frontend {
+        /// The first errors found, at most `MAX_REPORTED_FRONTEND_ERRORS`
+        errors: Vec<FrontendError>,
+        /// The number of errors found, including the ones that were not reported
+        total_errors: usize,
+        message: String,
+    },
     #[error("{0}")]
     Backend(#[from] BackendError),
     #[error("{0}")]
     Jit(#[from] MmapError),
     #[error("main function {0} not found")]
     MainFunctionNotFound(String),
+    #[error("code generated for {0:?} cannot run on this machine")]
+    ForeignBackend(Backend),
+}
+
+/// An error detected by the generated code while running. When it happens, the
+/// execution stops immediately
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum RuntimeError {
+    #[error("memory access out of bounds: element {index} of {length}")]
+    OutOfBounds { index: i64, length: usize },
+    #[error("arithmetic overflow")]
+    Overflow,
+    #[error("division by zero")]
+    DivisionByZero,
+    #[error("division overflow: the minimum of a signed type cannot be divided by -1")]
+    DivisionOverflow,
+    #[error("indirect call through an invalid function handle")]
+    InvalidFunctionHandle,
+    #[error("function called indirectly with the wrong number of arguments")]
+    ArityMismatch,
+}
+
+/// An error of a call made by the host to a function of the program
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum CallError {
+    #[error("function {0} not found")]
+    FunctionNotFound(String),
+    #[error("function {name} takes {expected} argument(s), but {actual} were given")]
+    ArityMismatch {
+        name: String,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("function {name} is {declared}, but it was called as {requested}")]
+    SignatureMismatch {
+        name: String,
+        declared: String,
+        requested: String,
+    },
+    #[error(
+        "function {name} takes {num_args} arguments, but the host can call functions with at most {MAX_HOST_CALL_ARGUMENTS}"
+    )]
+    TooManyArguments { name: String, num_args: usize },
+    #[error("{0}")]
+    Runtime(#[from] RuntimeError),
+}
+
+/// The maximum number of arguments of the functions that the host can call, via
+/// `JitProgram::run`, `JitProgram::call` or `JitProgram::get`, which return
+/// `CallError::TooManyArguments` for those above this limit. This is more than the
+/// program itself can pass: its calls go through `jit_call_trampoline`, so they can
+/// only reach functions with at most 6 integer and 8 float arguments
+pub const MAX_HOST_CALL_ARGUMENTS: usize = 12;
+
+/// Expands to `i64` for any value, to repeat the type once for each argument
+macro_rules! integer_type {
+    ($value:ident) => {
+        i64
+    };
+}
+
+/// Calls the function with the arguments in the slice, through the `JitFunction` of the
+/// signature taking as many integers, for each of the given numbers of arguments
+macro_rules! call_with_integers {
+    ($program:ident, $memory:ident, $name:ident, $args:ident, $([$($value:ident),*])*) => {
+        match *$args {
+            $([$($value),*] => Ok($program
+                .get::<fn($(integer_type!($value)),*) -> i64>($name)?
+                .call_with_memory($memory, $($value),*)?),)*
+            _ => {
+                $program.find_function($name, $args.len())?;
+                Err(CallError::TooManyArguments {
+                    name: $name.to_string(),
+                    num_args: $args.len(),
+                })
+            }
+        }
+    };
 }
 
 #[derive(Debug)]
 pub struct JitProgram {
-    pub function_catalog: Box<CompiledFunctionCatalog>,
-    pub main_function: JitFn,
+    pub(crate) function_catalog: Box<CompiledFunctionCatalog>,
+    /// The function called by `run`
+    pub(crate) main_function: FunctionId,
+    pub warnings: Vec<FrontendWarning>,
 }
 
-pub fn jit_compile_program(source: &str, main_function_name: &str) -> Result<JitProgram, JitError> {
-    info!("source: \n{}", source);
+impl JitProgram {
+    /// Runs the main function with the given arguments. Like for `call`, it must take
+    /// and return integers, and `args` must have one value for each of its arguments.
+    /// Since no memory is supplied, any `load` or `store` will fail.
+    pub fn run(&self, args: &[i64]) -> Result<i64, CallError> {
+        self.run_with_memory(&mut [], args)
+    }
 
-    let program = parser::parse_program(source)?;
-    let compiled_functions = frontend::compile(program)?;
+    /// Runs the main function with the given arguments, letting the program access
+    /// `memory` via `load` and `store`. Every access is checked against the length of
+    /// `memory`, so the program can never touch anything else.
+    pub fn run_with_memory(&self, memory: &mut [i64], args: &[i64]) -> Result<i64, CallError> {
+        let name = &self.function_catalog.signature(self.main_function).name;
+        self.call_with_memory(memory, name, args)
+    }
 
-    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
-    let mut gen = X64LinuxGenerator::default();
-    #[cfg(target_arch = "aarch64")]
-    let mut gen = Aarch64Generator::default();
+    /// Calls a function of any module, which must take and return integers, with the
+    /// given arguments, of which there can be at most `MAX_HOST_CALL_ARGUMENTS`. The
+    /// functions of imported modules are found by their qualified name, such as
+    /// `finance::npv`:
+    /// ```
+    /// # use emjay::Engine;
+    /// let source = "fn add(x, y) { return x + y; }";
+    /// let program = Engine::builder().build().compile(source).unwrap();
+    /// let program = program.into_jit_program("add").unwrap();
+    /// assert_eq!(Ok(3), program.call("add", &[1, 2]));
+    /// assert!(program.call("add", &[1]).is_err());
+    /// ```
+    pub fn call(&self, name: &str, args: &[i64]) -> Result<i64, CallError> {
+        self.call_with_memory(&mut [], name, args)
+    }
+
+    /// Like `call`, but the function can access `memory`, like in `run_with_memory`
+    pub fn call_with_memory(
+        &self,
+        memory: &mut [i64],
+        name: &str,
+        args: &[i64],
+    ) -> Result<i64, CallError> {
+        call_with_integers!(
+            self,
+            memory,
+            name,
+            args,
+            [] [a0] [a0, a1] [a0, a1, a2] [a0, a1, a2, a3] [a0, a1, a2, a3, a4]
+            [a0, a1, a2, a3, a4, a5] [a0, a1, a2, a3, a4, a5, a6]
+            [a0, a1, a2, a3, a4, a5, a6, a7] [a0, a1, a2, a3, a4, a5, a6, a7, a8]
+            [a0, a1, a2, a3, a4, a5, a6, a7, a8, a9]
+            [a0, a1, a2, a3, a4, a5, a6, a7, a8, a9, a10]
+            [a0, a1, a2, a3, a4, a5, a6, a7, a8, a9, a10, a11]
+        )
+    }
+
+    /// Finds a function of any module, checking that it has the signature `F`, which
+    /// is a function type taking up to `MAX_HOST_CALL_ARGUMENTS` `i64`s or `f64`s and
+    /// returning one of them. The result can then be called without any further check:
+    /// ```
+    /// # use emjay::Engine;
+    /// let source = "fn scale(x: f64, times) -> f64 { return x * times as f64; }";
+    /// let program = Engine::builder().build().compile(source).unwrap();
+    /// let program = program.into_jit_program("scale").unwrap();
+    /// let scale = program.get::<fn(f64, i64) -> f64>("scale").unwrap();
+    /// assert_eq!(Ok(7.5), scale.call(2.5, 3));
+    /// assert!(program.get::<fn(i64, i64) -> i64>("scale").is_err());
+    /// ```
+    pub fn get<F: JitSignature>(&self, name: &str) -> Result<JitFunction<'_, F>, CallError> {
+        let argument_classes = F::argument_classes();
+        let id = self.find_function(name, argument_classes.len())?;
+        let signature = self.function_catalog.signature(id);
+        if signature.argument_classes != argument_classes
+            || signature.return_class != F::return_class()
+        {
+            return Err(CallError::SignatureMismatch {
+                name: name.to_string(),
+                declared: HostFunctionSignature::describe(
+                    &signature.argument_classes,
+                    signature.return_class,
+                ),
+                requested: HostFunctionSignature::describe(&argument_classes, F::return_class()),
+            });
+        }
+        Ok(JitFunction {
+            program: self,
+            function: self.function_catalog.get_function_pointer(id),
+            signature: PhantomData,
+        })
+    }
 
-    // Create the function catalog and stores it in a box, to ensure that it will be at a fixed
-    // address and not be de-allocated
-    let mut function_catalog = Box::new(CompiledFunctionCatalog::new(&compiled_functions));
-    let function_catalog_ptr: *const CompiledFunctionCatalog = &*function_catalog;
-    debug!("function catalog: {:0X}", function_catalog_ptr as usize);
+    /// Finds a function by name, checking that it takes `num_args` arguments
+    fn find_function(&self, name: &str, num_args: usize) -> Result<FunctionId, CallError> {
+        let id = self
+            .function_catalog
+            .find_function(name)
+            .ok_or_else(|| CallError::FunctionNotFound(name.to_string()))?;
+        let expected = self.function_catalog.signature(id).argument_classes.len();
+        if expected != num_args {
+            return Err(CallError::ArityMismatch {
+                name: name.to_string(),
+                expected,
+                actual: num_args,
+            });
+        }
+        Ok(id)
+    }
 
-    let mut main_function = None;
-    for function in compiled_functions.iter() {
-        debug!("compiling function: {}", function.name);
-        debug!("base ir:\n{}", function);
+    /// Calls the generated code via `call`, letting it access `memory`, and converts
+    /// the trap that stopped it, if any, to an error
+    fn with_memory<R>(
+        &self,
+        memory: &mut [i64],
+        call: impl FnOnce() -> R,
+    ) -> Result<R, RuntimeError> {
+        let context = self.function_catalog.runtime_context();
+        context.memory.set(memory.as_mut_ptr() as i64);
+        context.memory_length.set(memory.len() as i64);
+        context.trap.set(0);
 
-        let function = &optimization::optimize_fun(function);
-        debug!("optimized ir:\n{}", function);
+        let result = call();
 
-        let machine_code = gen.generate_machine_code(function, &function_catalog)?;
-        debug!("asm:\n{}", machine_code.asm);
+        // The memory is only borrowed for the duration of this call
+        context.memory.set(0);
+        context.memory_length.set(0);
+        match context.trap.replace(0) {
+            0 => Ok(result),
+            code => match TrapCode::from_value(code).expect("unknown trap code") {
+                TrapCode::OutOfBounds => Err(RuntimeError::OutOfBounds {
+                    index: context.trap_value.get(),
+                    length: memory.len(),
+                }),
+                TrapCode::Overflow => Err(RuntimeError::Overflow),
+                TrapCode::DivisionByZero => Err(RuntimeError::DivisionByZero),
+                TrapCode::DivisionOverflow => Err(RuntimeError::DivisionOverflow),
+                TrapCode::InvalidFunctionHandle => Err(RuntimeError::InvalidFunctionHandle),
+                TrapCode::ArityMismatch => Err(RuntimeError::ArityMismatch),
+            },
+        }
+    }
+}
 
-        let machine_code_for_debug: String = machine_code
-            .machine_code
-            .iter()
-            .enumerate()
-            .map(|(index, byte)| {
-                if index % 4 == 3 {
-                    format!("{:02X}\n", byte)
-                } else {
-                    format!("{:02X} ", byte)
-                }
-            })
-            .collect();
-        debug!("Machine code:\n{}", machine_code_for_debug);
+/// The type of a function of the program that the host can call, such as
+/// `fn(i64, f64) -> f64`. It is implemented for up to `MAX_HOST_CALL_ARGUMENTS`
+/// arguments. See `JitProgram::get`
+pub trait JitSignature {
+    fn argument_classes() -> Vec<RegisterClass>;
 
-        let fun_ptr = unsafe { to_function_pointer(&machine_code.machine_code)? };
-        function_catalog.store_function_pointer(function.id, fun_ptr);
+    fn return_class() -> RegisterClass;
+}
 
-        if main_function_name == function.name {
-            main_function = Some(fun_ptr);
+/// A function of a `JitProgram` whose signature has been checked to be `F`. It borrows
+/// the program, since the machine code belongs to it
+#[derive(Debug, Clone, Copy)]
+pub struct JitFunction<'p, F> {
+    program: &'p JitProgram,
+    function: JitFn,
+    signature: PhantomData<F>,
+}
+
+macro_rules! impl_jit_signature {
+    ($($arg:ident $value:ident),*) => {
+        impl<R: HostValue, $($arg: HostValue),*> JitSignature for fn($($arg),*) -> R {
+            fn argument_classes() -> Vec<RegisterClass> {
+                vec![$($arg::CLASS),*]
+            }
+
+            fn return_class() -> RegisterClass {
+                R::CLASS
+            }
         }
+
+        impl<R: HostValue, $($arg: HostValue),*> JitFunction<'_, fn($($arg),*) -> R> {
+            /// Calls the function. Since no memory is supplied, any `load` or `store`
+            /// will fail
+            #[allow(clippy::too_many_arguments)]
+            pub fn call(&self, $($value: $arg),*) -> Result<R, RuntimeError> {
+                self.call_with_memory(&mut [], $($value),*)
+            }
+
+            /// Calls the function, letting it access `memory` via `load` and `store`
+            #[allow(clippy::too_many_arguments)]
+            pub fn call_with_memory(
+                &self,
+                memory: &mut [i64],
+                $($value: $arg),*
+            ) -> Result<R, RuntimeError> {
+                // Safety: the signature has been checked against the one of the function
+                let function: extern "C" fn($($arg),*) -> R =
+                    unsafe { std::mem::transmute(self.function) };
+                self.program.with_memory(memory, || function($($value),*))
+            }
+        }
+    };
+}
+
+impl_jit_signature!();
+impl_jit_signature!(A0 a0);
+impl_jit_signature!(A0 a0, A1 a1);
+impl_jit_signature!(A0 a0, A1 a1, A2 a2);
+impl_jit_signature!(A0 a0, A1 a1, A2 a2, A3 a3);
+impl_jit_signature!(A0 a0, A1 a1, A2 a2, A3 a3, A4 a4);
+impl_jit_signature!(A0 a0, A1 a1, A2 a2, A3 a3, A4 a4, A5 a5);
+impl_jit_signature!(A0 a0, A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6);
+impl_jit_signature!(A0 a0, A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6, A7 a7);
+impl_jit_signature!(A0 a0, A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6, A7 a7, A8 a8);
+impl_jit_signature!(A0 a0, A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6, A7 a7, A8 a8, A9 a9);
+impl_jit_signature!(
+    A0 a0, A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6, A7 a7, A8 a8, A9 a9, A10 a10
+);
+impl_jit_signature!(
+    A0 a0, A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6, A7 a7, A8 a8, A9 a9, A10 a10, A11 a11
+);
+
+/// A value that can be passed to or returned from a host function: either an `i64`,
+/// which can be declared as any integer type by the `extern fn`, or an `f64`
+pub trait HostValue {
+    const CLASS: RegisterClass;
+}
+
+impl HostValue for i64 {
+    const CLASS: RegisterClass = RegisterClass::Integer;
+}
+
+impl HostValue for f64 {
+    const CLASS: RegisterClass = RegisterClass::Float;
+}
+
+/// A function of the host program that the jitted code can call, after declaring it
+/// with `extern fn`. It must use the C calling convention, and take and return `i64`s
+/// or `f64`s:
+/// ```
+/// # use emjay::HostFunctions;
+/// extern "C" fn lookup(key: i64) -> i64 { key * 2 }
+///
+/// let mut host_functions = HostFunctions::default();
+/// host_functions.register("lookup", lookup as extern "C" fn(i64) -> i64);
+/// ```
+pub trait HostCallback {
+    fn argument_classes() -> Vec<RegisterClass>;
+
+    fn return_class() -> RegisterClass;
+
+    fn address(self) -> usize;
+}
+
+macro_rules! impl_host_callback {
+    ($($arg:ident),*) => {
+        impl<R: HostValue, $($arg: HostValue),*> HostCallback for extern "C" fn($($arg),*) -> R {
+            fn argument_classes() -> Vec<RegisterClass> {
+                vec![$($arg::CLASS),*]
+            }
+
+            fn return_class() -> RegisterClass {
+                R::CLASS
+            }
+
+            fn address(self) -> usize {
+                self as usize
+            }
+        }
+    };
+}
+
+// The generated code passes the arguments of host functions in registers only, so at
+// most six are supported
+impl_host_callback!();
+impl_host_callback!(A0);
+impl_host_callback!(A0, A1);
+impl_host_callback!(A0, A1, A2);
+impl_host_callback!(A0, A1, A2, A3);
+impl_host_callback!(A0, A1, A2, A3, A4);
+impl_host_callback!(A0, A1, A2, A3, A4, A5);
+
+#[derive(Debug, Clone)]
+struct HostFunction {
+    signature: HostFunctionSignature,
+    address: usize,
+}
+
+/// The host functions that a program can call, by name
+#[derive(Debug, Clone, Default)]
+pub struct HostFunctions {
+    functions: Vec<HostFunction>,
+}
+
+impl HostFunctions {
+    /// Registers a host function, replacing any previous one with the same name
+    pub fn register<C: HostCallback>(&mut self, name: &str, callback: C) -> &mut Self {
+        let function = HostFunction {
+            signature: HostFunctionSignature {
+                name: name.to_string(),
+                argument_classes: C::argument_classes(),
+                return_class: C::return_class(),
+            },
+            address: callback.address(),
+        };
+
+        match self
+            .functions
+            .iter_mut()
+            .find(|existing| existing.signature.name == name)
+        {
+            Some(existing) => *existing = function,
+            None => self.functions.push(function),
+        }
+        self
     }
 
-    if let Some(main_function) = main_function {
-        Ok(JitProgram {
-            function_catalog,
-            main_function,
-        })
-    } else {
-        Err(JitError::MainFunctionNotFound(
-            main_function_name.to_string(),
-        ))
+    pub(crate) fn signatures(&self) -> Vec<HostFunctionSignature> {
+        self.functions
+            .iter()
+            .map(|function| function.signature.clone())
+            .collect()
+    }
+
+    pub(crate) fn addresses(&self) -> Vec<usize> {
+        self.functions
+            .iter()
+            .map(|function| function.address)
+            .collect()
+    }
+}
+
+/// The name used in error messages for programs that do not come from a file
+pub(crate) const ANONYMOUS_SOURCE_PATH: &str = "<input>";
+
+pub fn jit_compile_program(source: &str, main_function_name: &str) -> Result<JitProgram, JitError> {
+    jit_compile_program_with_host_functions(source, main_function_name, &HostFunctions::default())
+}
+
+/// Like `jit_compile_program`, but the program can call the given host functions
+pub fn jit_compile_program_with_host_functions(
+    source: &str,
+    main_function_name: &str,
+    host_functions: &HostFunctions,
+) -> Result<JitProgram, JitError> {
+    jit_compile_file(
+        ANONYMOUS_SOURCE_PATH,
+        source,
+        main_function_name,
+        &FrontendOptions::default(),
+        host_functions,
+    )
+}
+
+/// Like `jit_compile_program_with_host_functions`, but error messages will refer to the
+/// source as `path`
+pub fn jit_compile_file(
+    path: &str,
+    source: &str,
+    main_function_name: &str,
+    options: &FrontendOptions,
+    host_functions: &HostFunctions,
+) -> Result<JitProgram, JitError> {
+    let mut files = InMemoryFiles::default();
+    files.add(path, source);
+    jit_compile_files(path, &files, main_function_name, options, host_functions)
+}
+
+/// Compiles the file at `root_path` together with all the files it imports, which are
+/// read from `provider`
+pub fn jit_compile_files(
+    root_path: &str,
+    provider: &dyn FileProvider,
+    main_function_name: &str,
+    options: &FrontendOptions,
+    host_functions: &HostFunctions,
+) -> Result<JitProgram, JitError> {
+    let files = SourceFiles::resolve(root_path, provider)?;
+    Engine::builder()
+        .frontend_options(*options)
+        .host_functions(host_functions.clone())
+        .build()
+        .compile_files(&files)?
+        .into_jit_program(main_function_name)
+}
+
+/// The maximum number of frontend errors reported when compiling a program. Reporting
+/// more than this would just bury the first ones, which are usually the interesting ones
+pub const MAX_REPORTED_FRONTEND_ERRORS: usize = 20;
+
+pub(crate) fn frontend_errors_to_jit_error(
+    mut errors: Vec<FrontendError>,
+    files: &SourceFiles,
+) -> JitError {
+    let total_errors = errors.len();
+    errors.truncate(MAX_REPORTED_FRONTEND_ERRORS);
+
+    let mut message = errors
+        .iter()
+        .map(|error| error.format_with_sources(files))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    if total_errors > errors.len() {
+        message.push_str(&format!(
+            "\n\n{} more error(s) not reported",
+            total_errors - errors.len()
+        ));
+    }
+
+    JitError::Frontend {
+        errors,
+        total_errors,
+        message,
     }
 }
 
@@ -161,8 +595,10 @@ pub fn jit_compile_program(source: &str, main_function_name: &str) -> Result<Jit
 /// the actual address to which the callee has been mapped and will then invoke it.
 /// As usual, most problems in computer science can be solved with an additional level of
 /// indirection :-)
+/// Floats are passed in their own registers, independently of the integers, so the
+/// trampoline simply forwards all of them: the callee ignores the ones it does not take.
 #[allow(clippy::too_many_arguments)]
-pub fn jit_call_trampoline(
+pub(crate) extern "C" fn jit_call_trampoline(
     function_catalog_ptr: *const CompiledFunctionCatalog,
     function_index: usize,
     a0: i64,
@@ -171,31 +607,113 @@ pub fn jit_call_trampoline(
     a3: i64,
     a4: i64,
     a5: i64,
+    f0: f64,
+    f1: f64,
+    f2: f64,
+    f3: f64,
+    f4: f64,
+    f5: f64,
+    f6: f64,
+    f7: f64,
 ) -> i64 {
     debug!(
-        "inside trampoline, with args {:?} {} - {} {} {} {} {} {}",
-        function_catalog_ptr, function_index, a0, a1, a2, a3, a4, a5,
+        "inside trampoline, with args {:?} {} - {} {} {} {} {} {} - {} {} {} {} {} {} {} {}",
+        function_catalog_ptr,
+        function_index,
+        a0,
+        a1,
+        a2,
+        a3,
+        a4,
+        a5,
+        f0,
+        f1,
+        f2,
+        f3,
+        f4,
+        f5,
+        f6,
+        f7
     );
-    let function_catalog = unsafe { &*function_catalog_ptr };
-    let fun = function_catalog.get_function_pointer(FunctionId(function_index));
-    debug!("  function pointer found: {:?}", fun);
+    let fun: JitFnWithFloats<i64> = resolve_callee(function_catalog_ptr, function_index);
+    let result = fun(a0, a1, a2, a3, a4, a5, f0, f1, f2, f3, f4, f5, f6, f7);
+
+    debug!("  callee function result: {}", result);
+    result
+}
 
-    let result = fun(a0, a1, a2, a3, a4, a5);
+/// Like `jit_call_trampoline`, for the functions returning an `f64`
+#[allow(clippy::too_many_arguments)]
+pub(crate) extern "C" fn jit_call_trampoline_float(
+    function_catalog_ptr: *const CompiledFunctionCatalog,
+    function_index: usize,
+    a0: i64,
+    a1: i64,
+    a2: i64,
+    a3: i64,
+    a4: i64,
+    a5: i64,
+    f0: f64,
+    f1: f64,
+    f2: f64,
+    f3: f64,
+    f4: f64,
+    f5: f64,
+    f6: f64,
+    f7: f64,
+) -> f64 {
+    debug!(
+        "inside float trampoline, with args {:?} {} - {} {} {} {} {} {} - {} {} {} {} {} {} {} {}",
+        function_catalog_ptr,
+        function_index,
+        a0,
+        a1,
+        a2,
+        a3,
+        a4,
+        a5,
+        f0,
+        f1,
+        f2,
+        f3,
+        f4,
+        f5,
+        f6,
+        f7
+    );
+    let fun: JitFnWithFloats<f64> = resolve_callee(function_catalog_ptr, function_index);
+    let result = fun(a0, a1, a2, a3, a4, a5, f0, f1, f2, f3, f4, f5, f6, f7);
 
     debug!("  callee function result: {}", result);
     result
 }
 
+/// How the trampolines see a jitted function: up to six integer and eight float arguments
+type JitFnWithFloats<R> =
+    extern "C" fn(i64, i64, i64, i64, i64, i64, f64, f64, f64, f64, f64, f64, f64, f64) -> R;
+
+fn resolve_callee<R>(
+    function_catalog_ptr: *const CompiledFunctionCatalog,
+    function_index: usize,
+) -> JitFnWithFloats<R> {
+    let function_catalog = unsafe { &*function_catalog_ptr };
+    let fun = function_catalog.get_function_pointer(FunctionId(function_index));
+    debug!("  function pointer found: {:?}", fun);
+    // Only the signature changes, the function is the same
+    unsafe { std::mem::transmute::<JitFn, JitFnWithFloats<R>>(fun) }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ir::OverflowMode;
 
     #[test]
     fn can_generate_valid_basic_function() {
         let source = "fn test() { let a = 2; return -a + 1; }";
         let program = super::jit_compile_program(source, "test").expect("function should compile");
-        let res = (program.main_function)(0, 0, 0, 0, 0, 0); // Call it!
-        assert_eq!(res, -1);
+        let res = program.run(&[]); // Call it!
+        assert_eq!(res, Ok(-1));
     }
 
     #[test]
@@ -205,8 +723,449 @@ mod tests {
         fn g() { return 1; }
         ";
         let program = super::jit_compile_program(source, "f").expect("function should compile");
-        let res = (program.main_function)(4, 0, 0, 0, 0, 0); // Call it!
-        assert_eq!(res, 5);
+        let res = program.run(&[4]); // Call it!
+        assert_eq!(res, Ok(5));
+    }
+
+    #[test]
+    fn can_generate_if_else() {
+        let source = "
+        fn f(x) {
+            let a = 1;
+            if (x) {
+                a = 2;
+            } else {
+                a = 3;
+            }
+            return a;
+        }
+        ";
+        let program = super::jit_compile_program(source, "f").expect("function should compile");
+        assert_eq!(program.run(&[0]), Ok(3));
+        assert_eq!(program.run(&[5]), Ok(2));
+    }
+
+    #[test]
+    fn can_generate_nested_if_with_early_returns() {
+        let source = "
+        fn f(x, y) {
+            if (x) {
+                if (y) {
+                    return g(x) + y;
+                }
+                return 10;
+            } else if (y) {
+                return 20;
+            }
+            return 30;
+        }
+        fn g(x) { return x * 2; }
+        ";
+        let program = super::jit_compile_program(source, "f").expect("function should compile");
+        assert_eq!(program.run(&[1, 2]), Ok(4));
+        assert_eq!(program.run(&[1, 0]), Ok(10));
+        assert_eq!(program.run(&[0, 2]), Ok(20));
+        assert_eq!(program.run(&[0, 0]), Ok(30));
+    }
+
+    #[test]
+    fn can_generate_while_loops() {
+        let source = "
+        fn sum_up_to(n) {
+            let sum = 0;
+            while (n) {
+                sum = sum + n;
+                n = n - 1;
+            }
+            return sum;
+        }
+        ";
+        let program =
+            super::jit_compile_program(source, "sum_up_to").expect("function should compile");
+        assert_eq!(program.run(&[0]), Ok(0));
+        assert_eq!(program.run(&[10]), Ok(55));
+    }
+
+    #[test]
+    fn can_generate_compound_assignments() {
+        let source = "
+        fn f(n, x: u8) {
+            let product = 1;
+            while (n > 0) {
+                product *= n;
+                n--;
+                if (n % 2 == 1) {
+                    x += 100;
+                }
+            }
+            product <<= 1;
+            product |= x;
+            return product;
+        }
+        ";
+        let program = super::jit_compile_program(source, "f").expect("function should compile");
+        // The arguments are updated like any other variable
+        assert_eq!(program.run(&[5, 0]), Ok(240 | 200));
+        assert_eq!(program.run(&[4, 7]), Ok(48 | 207));
+        // u8 wraps around
+        assert_eq!(program.run(&[6, 0]), Ok(1440 | 44));
+    }
+
+    const MATCH_SOURCE: &str = "
+        fn dense(x) {
+            return match x { 0 => 10, 1 | 2 => 20, 3 => 30, 5 => 50, _ => -1 };
+        }
+        fn sparse(x) {
+            return match x {
+                -1000 => 1,
+                7 | 8 => 2,
+                1000000 => 3,
+                9223372036854775807 => 4,
+                -9223372036854775808 => 5,
+                _ => 0,
+            };
+        }
+        fn negative(x: i32) {
+            return match x { -3 => 1, -2 => 2, -1 => 3, 0 => 4, 1 => 5, _ => 6 } * 10;
+        }
+        fn nested(x: u8, y) {
+            let z = match x { 1 => match y { 1 | 2 | 3 | 4 => y, _ => 0 }, 2 => y * 2, _ => 100 };
+            return z + 1;
+        }
+        fn float(x) -> f64 {
+            return match x % 3 { 0 => 0.5, 1 => x as f64, _ => 2 };
+        }
+    ";
+
+    /// Runs the main function like `run`, but `args` can have more values than it takes
+    fn run_main(program: &JitProgram, args: &[i64]) -> Result<i64, RuntimeError> {
+        let signature = program.function_catalog.signature(program.main_function);
+        let num_args = signature.argument_classes.len();
+        program.run(&args[..num_args]).map_err(|error| match error {
+            CallError::Runtime(error) => error,
+            error => panic!("{}", error),
+        })
+    }
+
+    fn run_match(function: &str, args: [i64; 2]) -> i64 {
+        let program =
+            super::jit_compile_program(MATCH_SOURCE, function).expect("program should compile");
+        run_main(&program, &args).expect("program should run")
+    }
+
+    #[test]
+    fn can_generate_match_expressions() {
+        let dense = [-1, 10, 20, 20, 30, -1, 50, -1];
+        for (x, expected) in (-1..7).zip(dense) {
+            assert_eq!(run_match("dense", [x, 0]), expected, "dense({})", x);
+        }
+        for x in [i64::MIN, i64::MAX, 1 << 32] {
+            assert_eq!(run_match("dense", [x, 0]), -1, "dense({})", x);
+        }
+
+        let sparse = [
+            (-1000, 1),
+            (7, 2),
+            (8, 2),
+            (1000000, 3),
+            (i64::MAX, 4),
+            (i64::MIN, 5),
+            (0, 0),
+            (9, 0),
+            (-999, 0),
+            (999999, 0),
+            (i64::MAX - 1, 0),
+        ];
+        for (x, expected) in sparse {
+            assert_eq!(run_match("sparse", [x, 0]), expected, "sparse({})", x);
+        }
+
+        let negative = [60, 10, 20, 30, 40, 50, 60];
+        for (x, expected) in (-4..3).zip(negative) {
+            assert_eq!(run_match("negative", [x, 0]), expected, "negative({})", x);
+        }
+
+        assert_eq!(run_match("nested", [1, 3]), 4);
+        assert_eq!(run_match("nested", [1, 5]), 1);
+        assert_eq!(run_match("nested", [2, 5]), 11);
+        assert_eq!(run_match("nested", [3, 5]), 101);
+
+        let program =
+            super::jit_compile_program(MATCH_SOURCE, "float").expect("program should compile");
+        let f = program.get::<fn(i64) -> f64>("float").unwrap();
+        assert_eq!(f.call(3), Ok(0.5));
+        assert_eq!(f.call(4), Ok(4.0));
+        assert_eq!(f.call(5), Ok(2.0));
+    }
+
+    #[test]
+    fn can_generate_nested_loops_with_break_and_continue() {
+        let source = "
+        fn f(n) {
+            let total = 0;
+            let i = n;
+            while (i) {
+                i = i - 1;
+                if (i - i / 2 * 2) {
+                    continue;
+                }
+                let j = i;
+                while (1) {
+                    if (j) {
+                        total = total + g(j);
+                        j = j - 1;
+                    } else {
+                        break;
+                    }
+                }
+            }
+            return total;
+        }
+        fn g(x) { return x * 10; }
+        ";
+        let program = super::jit_compile_program(source, "f").expect("function should compile");
+        // Even i in 0..6 are 0, 2, 4, so the total is (0) + (2+1) + (4+3+2+1) = 13, times 10
+        assert_eq!(program.run(&[6]), Ok(130));
+    }
+
+    #[test]
+    fn can_generate_comparisons() {
+        let source = "
+        fn compare(a, b) {
+            return (a == b) + (a != b) * 10 + (a < b) * 100 + (a <= b) * 1000
+                + (a > b) * 10000 + (a >= b) * 100000 + !a * 1000000;
+        }
+        ";
+        let program =
+            super::jit_compile_program(source, "compare").expect("function should compile");
+        assert_eq!(program.run(&[1, 2]), Ok(1110));
+        assert_eq!(program.run(&[2, 2]), Ok(101001));
+        assert_eq!(program.run(&[-3, -5]), Ok(110010));
+        assert_eq!(program.run(&[0, 1]), Ok(1001110));
+    }
+
+    #[test]
+    fn logical_operators_short_circuit() {
+        // If the right operands were evaluated, we would divide by zero
+        let source = "
+        fn f(x) {
+            if (x != 0 && 10 / x > 1) {
+                return 1;
+            }
+            if (x == 0 || 10 / x < 1) {
+                return 2;
+            }
+            return 3;
+        }
+        ";
+        let program = super::jit_compile_program(source, "f").expect("function should compile");
+        assert_eq!(program.run(&[5]), Ok(1));
+        assert_eq!(program.run(&[0]), Ok(2));
+        assert_eq!(program.run(&[20]), Ok(2));
+        assert_eq!(program.run(&[10]), Ok(3));
+    }
+
+    #[test]
+    fn can_compute_gcd_with_a_loop() {
+        let source = "
+        fn euclid(a, b) {
+            while (b != 0) {
+                let t = b;
+                b = a - a / b * b;
+                a = t;
+            }
+            return a;
+        }
+        ";
+        let program =
+            super::jit_compile_program(source, "euclid").expect("function should compile");
+        assert_eq!(program.run(&[48, 18]), Ok(6));
+        assert_eq!(program.run(&[17, 5]), Ok(1));
+    }
+
+    #[test]
+    fn can_generate_bitwise_operators() {
+        let source = "
+        fn f(a, b) {
+            return ((a & b) ^ (a | b)) + (~a) * 100 + (a % b) * 10000;
+        }
+        ";
+        let program = super::jit_compile_program(source, "f").expect("function should compile");
+        // (12 & 10) ^ (12 | 10) = 8 ^ 14 = 6, ~12 = -13, 12 % 10 = 2
+        assert_eq!(program.run(&[12, 10]), Ok(6 - 1300 + 20000));
+        // The remainder has the sign of the dividend
+        assert_eq!(
+            program.run(&[-7, 3]),
+            Ok(((-7 & 3) ^ (-7 | 3)) + 600 - 10000)
+        );
+    }
+
+    #[test]
+    fn shifts_are_well_defined_for_any_amount() {
+        let source = "
+        fn shl(a, b) { return a << b; }
+        fn shr(a, b) { return a >> b; }
+        ";
+        let shl = super::jit_compile_program(source, "shl").expect("function should compile");
+        let shr = super::jit_compile_program(source, "shr").expect("function should compile");
+        let cases: [(i64, i64, i64, i64); 6] = [
+            (3, 2, 12, 0),
+            (-8, 1, -16, -4),
+            (1, 63, i64::MIN, 0),
+            (-1, 64, 0, -1),
+            (5, 100, 0, 0),
+            (-5, -1, 0, -1),
+        ];
+        for (a, b, expected_shl, expected_shr) in cases {
+            assert_eq!(shl.run(&[a, b]), Ok(expected_shl));
+            assert_eq!(shr.run(&[a, b]), Ok(expected_shr));
+        }
+    }
+
+    #[test]
+    fn can_use_all_kinds_of_integer_literals() {
+        let source = "
+        fn f(x) {
+            if (x == 0) { return 0x7FFF_FFFF_FFFF_FFFF; }
+            if (x == 1) { return -9223372036854775808; }
+            return 0xFF + 0b101 + 0o17 + 1_000;
+        }
+        ";
+        let program = super::jit_compile_program(source, "f").expect("function should compile");
+        assert_eq!(program.run(&[0]), Ok(i64::MAX));
+        assert_eq!(program.run(&[1]), Ok(i64::MIN));
+        assert_eq!(program.run(&[2]), Ok(255 + 5 + 15 + 1000));
+    }
+
+    #[test]
+    fn can_use_constants() {
+        let source = "
+        const SCALE = 1000 * 3;
+        fn f(x) { return x * SCALE + LIMIT; }
+        const LIMIT = SCALE / 2 - 1;
+        ";
+        let program = super::jit_compile_program(source, "f").expect("function should compile");
+        assert_eq!(program.run(&[2]), Ok(7499));
+    }
+
+    #[test]
+    fn narrow_types_wrap_around() {
+        let source = "
+        fn add_u8(x: u8) -> u8 { return x + 200; }
+        fn double_i32(x: i32) -> i32 { return x * 2; }
+        fn mix(x) -> i64 { return (x as u8) + (x as i32 as i64) * 1000; }
+        ";
+        let add_u8 = super::jit_compile_program(source, "add_u8").expect("function should compile");
+        let double_i32 =
+            super::jit_compile_program(source, "double_i32").expect("function should compile");
+        let mix = super::jit_compile_program(source, "mix").expect("function should compile");
+        assert_eq!(add_u8.run(&[100]), Ok(44));
+        assert_eq!(double_i32.run(&[0x7FFF_FFFF]), Ok(-2));
+        // Arguments are truncated to their declared type
+        assert_eq!(double_i32.run(&[0x1_0000_0005]), Ok(10));
+        assert_eq!(
+            mix.run(&[0x1_8000_0101]),
+            Ok(1 + (i32::MIN as i64 + 0x101) * 1000)
+        );
+    }
+
+    #[test]
+    fn unsigned_operations_do_not_use_the_sign() {
+        let source = "
+        fn f(x: u64, y: u64) -> u64 {
+            return x / y + (x % y) * 10 + (x >> 60) * 100 + (x > y) as u64 * 10000;
+        }
+        fn g(x: i64) -> bool { return x as u8 as bool; }
+        fn h() -> u64 { return f(18446744073709551614, 3); }
+        ";
+        let f = super::jit_compile_program(source, "f").expect("function should compile");
+        let g = super::jit_compile_program(source, "g").expect("function should compile");
+        let h = super::jit_compile_program(source, "h").expect("function should compile");
+        let x = u64::MAX - 1;
+        assert_eq!(
+            f.run(&[x as i64, 3]).map(|result| result as u64),
+            Ok(x / 3 + (x % 3) * 10 + (x >> 60) * 100 + 10000)
+        );
+        assert_eq!(h.run(&[]), f.run(&[x as i64, 3]));
+        assert_eq!(f.run(&[2, 7]), Ok(2 * 10));
+        assert_eq!(g.run(&[0x100]), Ok(0));
+        assert_eq!(g.run(&[0x142]), Ok(1));
+
+        // Constants are evaluated as `i64`
+        let err =
+            super::jit_compile_program("const C = 9223372036854775808; fn f() { return 0; }", "f")
+                .expect_err("should have not compiled");
+        assert!(err
+            .to_string()
+            .contains("literal 9223372036854775808 does not fit in type i64"));
+    }
+
+    #[test]
+    fn type_errors_are_reported() {
+        let source = "fn f(x: u8) -> u8 {\n    let y: i64 = x;\n    return y;\n}";
+        let err = super::jit_compile_program(source, "f").expect_err("should have not compiled");
+        let JitError::Frontend { errors, .. } = err else {
+            panic!("expected a frontend error");
+        };
+        assert_eq!(
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            vec!["mismatched types: expected u8, found i64"]
+        );
+    }
+
+    extern "C" fn host_double(x: i64) -> i64 {
+        x * 2
+    }
+
+    extern "C" fn host_weighted_sum(a: i64, b: i64, c: i64, d: i64, e: i64, f: i64) -> i64 {
+        a + 2 * b + 3 * c + 4 * d + 5 * e + 6 * f
+    }
+
+    extern "C" fn host_answer() -> i64 {
+        42
+    }
+
+    #[test]
+    fn can_call_host_functions() {
+        let source = "
+        extern fn double(x);
+        extern fn weighted_sum(a, b, c, d, e, f);
+        extern fn answer();
+        fn f(x) {
+            let y = double(x + 1);
+            return weighted_sum(x, y, 1, 0, 0, answer()) + y;
+        }
+        ";
+        let mut host_functions = HostFunctions::default();
+        host_functions
+            .register("answer", host_answer as extern "C" fn() -> i64)
+            .register("double", host_double as extern "C" fn(i64) -> i64)
+            .register(
+                "weighted_sum",
+                host_weighted_sum as extern "C" fn(i64, i64, i64, i64, i64, i64) -> i64,
+            );
+        let program = super::jit_compile_program_with_host_functions(source, "f", &host_functions)
+            .expect("function should compile");
+        assert_eq!(program.run(&[3]), Ok(3 + 2 * 8 + 3 + 6 * 42 + 8));
+    }
+
+    #[test]
+    fn extern_functions_must_be_provided_by_the_host() {
+        let source = "extern fn double(x, y);\nextern fn missing();\nfn f() { return 0; }";
+        let mut host_functions = HostFunctions::default();
+        host_functions.register("double", host_double as extern "C" fn(i64) -> i64);
+        let err = super::jit_compile_program_with_host_functions(source, "f", &host_functions)
+            .expect_err("should have not compiled");
+        let JitError::Frontend { errors, .. } = err else {
+            panic!("expected a frontend error");
+        };
+        assert_eq!(
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            vec![
+                "extern function \"double\" is declared with 2 argument(s) but the host provides it with 1",
+                "extern function \"missing\" is not provided by the host",
+            ]
+        );
     }
 
     #[test]
@@ -216,6 +1175,256 @@ mod tests {
         assert!(matches!(err, JitError::Parser(_)));
     }
 
+    #[test]
+    fn frontend_errors_point_at_the_source() {
+        let source = "fn f(x) {\n    return x + y;\n}";
+        let err = super::jit_compile_file(
+            "test.mj",
+            source,
+            "f",
+            &FrontendOptions::default(),
+            &HostFunctions::default(),
+        )
+        .expect_err("should have not compiled");
+        let JitError::Frontend { errors, .. } = &err else {
+            panic!("expected a frontend error");
+        };
+        assert!(matches!(
+            errors.as_slice(),
+            [FrontendError::VariableNotDefined { .. }]
+        ));
+        assert_eq!(
+            err.to_string(),
+            [
+                " --> test.mj:2:16",
+                "  |",
+                "2 |     return x + y;",
+                "  |                ^",
+                "  |",
+                "  = variable \"y\" not defined",
+            ]
+            .join("\n")
+        );
+    }
+
+    fn finance_files(npv: &str) -> InMemoryFiles {
+        let mut files = InMemoryFiles::default();
+        files
+            .add(
+                "main.mj",
+                "import \"lib/finance.mj\";\nfn main(x) { return finance::npv(10, -200, 110, x); }",
+            )
+            .add(
+                "lib/finance.mj",
+                &format!(
+                    "fn pv(amount, rate) {{ return amount * 100 / (100 + rate); }}\n{}",
+                    npv
+                ),
+            );
+        files
+    }
+
+    #[test]
+    fn can_call_functions_of_imported_files() {
+        let files = finance_files(
+            "fn npv(rate, c0, c1, c2) { return c0 + pv(c1, rate) + pv(pv(c2, rate), rate); }",
+        );
+        let program = super::jit_compile_files(
+            "main.mj",
+            &files,
+            "main",
+            &FrontendOptions::default(),
+            &HostFunctions::default(),
+        )
+        .expect("program should compile");
+        assert_eq!(Ok(0), program.run(&[121]));
+        assert_eq!(Ok(100), program.run(&[242]));
+    }
+
+    #[test]
+    fn imported_files_can_define_functions_with_the_same_name() {
+        let mut files = InMemoryFiles::default();
+        files
+            .add(
+                "main.mj",
+                "import \"a.mj\";\nimport \"b.mj\";\nfn g(x) { return a::g(x) * 10 + b::g(x); }",
+            )
+            .add("a.mj", "fn g(x) { return x + 1; }")
+            .add("b.mj", "fn g(x) { return x + 2; }");
+        let program = super::jit_compile_files(
+            "main.mj",
+            &files,
+            "g",
+            &FrontendOptions::default(),
+            &HostFunctions::default(),
+        )
+        .expect("program should compile");
+        assert_eq!(Ok(12), program.run(&[0]));
+        assert_eq!(Ok(6), program.call("a::g", &[5]));
+        assert_eq!(Ok(7), program.call("b::g", &[5]));
+    }
+
+    #[test]
+    fn errors_point_at_the_imported_file() {
+        let files = finance_files("fn npv(rate, c0, c1, c2) {\n    return c0 + pv(c1);\n}");
+        let err = super::jit_compile_files(
+            "main.mj",
+            &files,
+            "main",
+            &FrontendOptions::default(),
+            &HostFunctions::default(),
+        )
+        .expect_err("should have not compiled");
+        assert!(
+            err.to_string().starts_with(" --> lib/finance.mj:3:17"),
+            "{}",
+            err
+        );
+
+        let err = super::jit_compile_files(
+            "other.mj",
+            &files,
+            "main",
+            &FrontendOptions::default(),
+            &HostFunctions::default(),
+        )
+        .expect_err("should have not compiled");
+        assert!(matches!(
+            err,
+            JitError::Module(ModuleError::FileNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn all_frontend_errors_are_reported() {
+        let source = "
+        fn f(x) { return x + y; }
+        fn g() { break; return f(1, 2); }
+        ";
+        let err = super::jit_compile_program(source, "f").expect_err("should have not compiled");
+        let JitError::Frontend {
+            errors,
+            total_errors,
+            message,
+        } = err
+        else {
+            panic!("expected a frontend error");
+        };
+        assert_eq!(total_errors, 3);
+        assert_eq!(
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            vec![
+                "variable \"y\" not defined",
+                "break statement outside of a loop",
+                "function \"f\" requires 1 argument(s) but was called with 2",
+            ]
+        );
+        assert!(message.contains("--> <input>:2:30"));
+        assert!(message.contains("--> <input>:3:18"));
+        assert!(message.contains("--> <input>:3:32"));
+    }
+
+    #[test]
+    fn the_number_of_reported_frontend_errors_is_limited() {
+        let source = format!(
+            "fn f() {{ {} return 0; }}",
+            "x = 1; ".repeat(MAX_REPORTED_FRONTEND_ERRORS + 5)
+        );
+        let err = super::jit_compile_program(&source, "f").expect_err("should have not compiled");
+        let JitError::Frontend {
+            errors,
+            total_errors,
+            message,
+        } = err
+        else {
+            panic!("expected a frontend error");
+        };
+        assert_eq!(errors.len(), MAX_REPORTED_FRONTEND_ERRORS);
+        assert_eq!(total_errors, MAX_REPORTED_FRONTEND_ERRORS + 5);
+        assert!(message.ends_with("5 more error(s) not reported"));
+    }
+
+    #[test]
+    fn duplicate_definitions_point_at_both_definitions() {
+        let source = "fn f() { return 1; }\nfn f() { return 2; }";
+        let err = super::jit_compile_file(
+            "test.mj",
+            source,
+            "f",
+            &FrontendOptions::default(),
+            &HostFunctions::default(),
+        )
+        .expect_err("should have not compiled");
+        assert_eq!(
+            err.to_string(),
+            [
+                " --> test.mj:2:4",
+                "  |",
+                "2 | fn f() { return 2; }",
+                "  |    ^",
+                "  |",
+                "  = function \"f\" already defined",
+                " --> test.mj:1:4",
+                "  |",
+                "1 | fn f() { return 1; }",
+                "  |    ^",
+                "  |",
+                "  = previously defined here",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn functions_can_implicitly_return_zero() {
+        let source = "fn f(x) { if (x) { return 42; } }";
+        let err = super::jit_compile_program(source, "f").expect_err("should have not compiled");
+        assert!(err
+            .to_string()
+            .contains("function \"f\" does not return a value in all paths"));
+
+        let options = FrontendOptions {
+            implicit_return_zero: true,
+            ..Default::default()
+        };
+        let program =
+            super::jit_compile_file("test.mj", source, "f", &options, &HostFunctions::default())
+                .expect("function should compile");
+        assert_eq!(program.run(&[1]), Ok(42));
+        assert_eq!(program.run(&[0]), Ok(0));
+
+        let source = "fn f(x) -> f64 { if (x) { return 4.2; } }";
+        let program =
+            super::jit_compile_file("test.mj", source, "f", &options, &HostFunctions::default())
+                .expect("function should compile");
+        let f = program.get::<fn(i64) -> f64>("f").unwrap();
+        assert_eq!(f.call(1), Ok(4.2));
+        assert_eq!(f.call(0), Ok(0.0));
+    }
+
+    #[test]
+    fn warnings_are_returned_with_the_program() {
+        let source = "fn f() { return 1; return 2; }";
+        let program = super::jit_compile_program(source, "f").expect("function should compile");
+        assert!(matches!(
+            program.warnings.as_slice(),
+            [FrontendWarning::UnreachableStatement { .. }]
+        ));
+    }
+
+    #[test]
+    fn syntax_errors_mention_the_file() {
+        let err = super::jit_compile_file(
+            "test.mj",
+            "fn invalid",
+            "f",
+            &FrontendOptions::default(),
+            &HostFunctions::default(),
+        )
+        .expect_err("should have not compiled");
+        assert!(err.to_string().contains("--> test.mj:1:1"));
+    }
+
     #[test]
     fn main_function_not_found_is_an_error() {
         let source = "fn f() { return 42; }";
@@ -223,4 +1432,857 @@ mod tests {
             .expect_err("should not have found the main function");
         assert!(matches!(err, JitError::MainFunctionNotFound(_)));
     }
+
+    #[test]
+    fn run_checks_the_signature_of_the_main_function() {
+        let source = "
+        fn sum(a, b, c, d, e, f, g, h) { return a + b + c + d + e + f + g + h; }
+        fn half(x) -> f64 { return x as f64 / 2.0; }
+        ";
+        let program = super::jit_compile_program(source, "sum").expect("program should compile");
+        assert_eq!(Ok(36), program.run(&[1, 2, 3, 4, 5, 6, 7, 8]));
+        assert_eq!(
+            Err(CallError::ArityMismatch {
+                name: "sum".to_string(),
+                expected: 8,
+                actual: 6
+            }),
+            program.run(&[1, 2, 3, 4, 5, 6])
+        );
+
+        let program = super::jit_compile_program(source, "half").expect("program should compile");
+        assert!(matches!(
+            program.run(&[4]),
+            Err(CallError::SignatureMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn can_load_and_store_host_memory() {
+        let source = "
+        fn sum(base, n) {
+            let total = 0;
+            let i = 0;
+            while (i < n) {
+                total = total + load(base, i);
+                i = i + 1;
+            }
+            return total;
+        }
+        ";
+        let program = super::jit_compile_program(source, "sum").expect("function should compile");
+        let mut memory = [1, 2, 3, 4, 5];
+        assert_eq!(program.run_with_memory(&mut memory, &[0, 5]), Ok(15));
+        assert_eq!(program.run_with_memory(&mut memory, &[2, 3]), Ok(12));
+
+        let source = "
+        fn squares(n) {
+            while (n > 0) {
+                n = n - 1;
+                store(0, n, n * n);
+            }
+            return store(n, 5, 42);
+        }
+        ";
+        let program =
+            super::jit_compile_program(source, "squares").expect("function should compile");
+        let mut memory = [-1; 6];
+        assert_eq!(program.run_with_memory(&mut memory, &[4]), Ok(42));
+        assert_eq!(memory, [0, 1, 4, 9, -1, 42]);
+    }
+
+    #[test]
+    fn out_of_bounds_accesses_are_runtime_errors() {
+        let source = "
+        fn f(base, index) {
+            store(base, index, 1);
+            return 7;
+        }
+        fn g(base, index) {
+            let x = f(base, index);
+            store(0, 0, 2);
+            return x;
+        }
+        ";
+        let f = super::jit_compile_program(source, "f").expect("function should compile");
+        let mut memory = [0; 4];
+        assert_eq!(f.run_with_memory(&mut memory, &[1, 2]), Ok(7));
+        assert_eq!(
+            f.run_with_memory(&mut memory, &[2, 2]),
+            Err(CallError::Runtime(RuntimeError::OutOfBounds {
+                index: 4,
+                length: 4
+            }))
+        );
+        assert_eq!(
+            f.run_with_memory(&mut memory, &[0, -1]),
+            Err(CallError::Runtime(RuntimeError::OutOfBounds {
+                index: -1,
+                length: 4
+            }))
+        );
+        assert_eq!(
+            f.run(&[0, 0]),
+            Err(CallError::Runtime(RuntimeError::OutOfBounds {
+                index: 0,
+                length: 0
+            }))
+        );
+        assert_eq!(memory, [0, 0, 0, 1]);
+
+        // The trap stops the caller too, so g never writes its own value
+        let g = super::jit_compile_program(source, "g").expect("function should compile");
+        assert_eq!(
+            g.run_with_memory(&mut memory, &[i64::MAX, 1]),
+            Err(CallError::Runtime(RuntimeError::OutOfBounds {
+                index: i64::MIN,
+                length: 4
+            }))
+        );
+        assert_eq!(memory, [0, 0, 0, 1]);
+        assert_eq!(g.run_with_memory(&mut memory, &[0, 1]), Ok(7));
+        assert_eq!(memory, [2, 1, 0, 1]);
+    }
+
+    const OVERFLOW_SOURCE: &str = "
+        fn add(x, y) { return x + y; }
+        fn sub(x, y) { return x - y; }
+        fn mul(x, y) { return x * y; }
+        fn neg(x) { return -x; }
+        fn add_u64(x: u64, y: u64) -> u64 { return x + y; }
+        fn sub_u64(x: u64, y: u64) -> u64 { return x - y; }
+        fn mul_u64(x: u64, y: u64) -> u64 { return x * y; }
+        fn mul_i32(x: i32, y: i32) -> i32 { return x * y; }
+        fn sub_i32(x: i32, y: i32) -> i32 { return x - y; }
+        fn add_u8(x: u8, y: u8) -> u8 { return x + y; }
+        fn sub_u8(x: u8, y: u8) -> u8 { return x - y; }
+        fn mul_u8(x: u8, y: u8) -> u8 { return x * y; }
+        fn folded() { return 9223372036854775807 + 1; }
+        fn unused(x) { let y = x + 1; return 0; }
+        ";
+
+    /// Runs one function of `OVERFLOW_SOURCE` with the given overflow mode
+    fn run_with_overflow_mode(
+        mode: OverflowMode,
+        function: &str,
+        args: [i64; 2],
+    ) -> Result<i64, RuntimeError> {
+        let options = FrontendOptions {
+            overflow_mode: mode,
+            ..Default::default()
+        };
+        let program = super::jit_compile_file(
+            "test.mj",
+            OVERFLOW_SOURCE,
+            function,
+            &options,
+            &HostFunctions::default(),
+        )
+        .expect("function should compile");
+        run_main(&program, &args)
+    }
+
+    #[test]
+    fn arithmetic_wraps_around_by_default() {
+        let run = |function, args| run_with_overflow_mode(OverflowMode::Wrapping, function, args);
+        assert_eq!(run("add", [i64::MAX, 1]), Ok(i64::MIN));
+        assert_eq!(run("neg", [i64::MIN, 0]), Ok(i64::MIN));
+        assert_eq!(run("sub_u64", [1, 2]), Ok(-1));
+        assert_eq!(run("mul_i32", [0x10000, 0x10000]), Ok(0));
+        assert_eq!(run("add_u8", [200, 100]), Ok(44));
+        assert_eq!(run("folded", [0, 0]), Ok(i64::MIN));
+    }
+
+    #[test]
+    fn checked_arithmetic_traps_on_overflow() {
+        let run = |function, args| run_with_overflow_mode(OverflowMode::Checked, function, args);
+        let overflow = Err(RuntimeError::Overflow);
+        assert_eq!(run("add", [i64::MAX - 1, 1]), Ok(i64::MAX));
+        assert_eq!(run("add", [i64::MAX, 1]), overflow);
+        assert_eq!(run("sub", [i64::MIN, 1]), overflow);
+        assert_eq!(run("mul", [-(1 << 32), 1 << 31]), Ok(i64::MIN));
+        assert_eq!(run("mul", [1 << 32, 1 << 31]), overflow);
+        assert_eq!(run("neg", [i64::MIN + 1, 0]), Ok(i64::MAX));
+        assert_eq!(run("neg", [i64::MIN, 0]), overflow);
+        assert_eq!(run("add_u64", [-2, 1]), Ok(-1));
+        assert_eq!(run("add_u64", [-1, 1]), overflow);
+        assert_eq!(run("sub_u64", [1, 2]), overflow);
+        assert_eq!(run("mul_u64", [1 << 32, (1 << 32) - 1]), Ok(-(1 << 32)));
+        assert_eq!(run("mul_u64", [1 << 32, 1 << 32]), overflow);
+        assert_eq!(run("mul_i32", [0x10000, -0x8000]), Ok(i32::MIN as i64));
+        assert_eq!(run("mul_i32", [0x10000, 0x8000]), overflow);
+        assert_eq!(run("sub_i32", [i32::MIN as i64, 1]), overflow);
+        assert_eq!(run("add_u8", [200, 55]), Ok(255));
+        assert_eq!(run("add_u8", [200, 56]), overflow);
+        assert_eq!(run("sub_u8", [10, 20]), overflow);
+        // The optimizer leaves the overflow to the generated code, and does not remove
+        // it even if the result is unused
+        assert_eq!(run("folded", [0, 0]), overflow);
+        assert_eq!(run("unused", [i64::MAX, 0]), overflow);
+    }
+
+    #[test]
+    fn saturating_arithmetic_clamps_to_the_bounds() {
+        let run = |function, args| run_with_overflow_mode(OverflowMode::Saturating, function, args);
+        assert_eq!(run("add", [1, 2]), Ok(3));
+        assert_eq!(run("add", [i64::MAX, 1]), Ok(i64::MAX));
+        assert_eq!(run("add", [i64::MIN, -1]), Ok(i64::MIN));
+        assert_eq!(run("sub", [i64::MIN, 1]), Ok(i64::MIN));
+        assert_eq!(run("sub", [0, i64::MIN]), Ok(i64::MAX));
+        assert_eq!(run("mul", [1 << 32, 1 << 32]), Ok(i64::MAX));
+        assert_eq!(run("mul", [-(1 << 32), 1 << 32]), Ok(i64::MIN));
+        assert_eq!(run("mul", [-(1 << 32), -(1 << 32)]), Ok(i64::MAX));
+        assert_eq!(run("mul", [-3, 5]), Ok(-15));
+        assert_eq!(run("neg", [i64::MIN, 0]), Ok(i64::MAX));
+        assert_eq!(run("add_u64", [-1, 1]), Ok(-1));
+        assert_eq!(run("sub_u64", [1, 2]), Ok(0));
+        assert_eq!(run("mul_u64", [1 << 32, 1 << 32]), Ok(-1));
+        assert_eq!(run("mul_i32", [0x10000, 0x10000]), Ok(i32::MAX as i64));
+        assert_eq!(run("mul_i32", [0x10000, -0x10000]), Ok(i32::MIN as i64));
+        assert_eq!(run("sub_i32", [i32::MIN as i64, 1]), Ok(i32::MIN as i64));
+        assert_eq!(run("add_u8", [200, 100]), Ok(255));
+        assert_eq!(run("sub_u8", [10, 20]), Ok(0));
+        assert_eq!(run("mul_u8", [16, 16]), Ok(255));
+        assert_eq!(run("mul_u8", [15, 17]), Ok(255));
+        assert_eq!(run("folded", [0, 0]), Ok(i64::MAX));
+    }
+
+    #[test]
+    fn constants_that_overflow_in_checked_mode_are_errors() {
+        let options = FrontendOptions {
+            overflow_mode: OverflowMode::Checked,
+            ..Default::default()
+        };
+        let source = "const BIG = 9223372036854775807 * 2; fn f() { return BIG; }";
+        let err =
+            super::jit_compile_file("test.mj", source, "f", &options, &HostFunctions::default())
+                .expect_err("should have not compiled");
+        assert!(err
+            .to_string()
+            .contains("division by zero or overflow in the value of a constant"));
+
+        let options = FrontendOptions {
+            overflow_mode: OverflowMode::Saturating,
+            ..Default::default()
+        };
+        let program =
+            super::jit_compile_file("test.mj", source, "f", &options, &HostFunctions::default())
+                .expect("function should compile");
+        assert_eq!(program.run(Default::default()), Ok(i64::MAX));
+    }
+
+    const INTRINSICS_SOURCE: &str = "
+        fn absolute(x) { return abs(x); }
+        fn minimum(x, y) { return min(x, y); }
+        fn maximum(x, y) { return max(x, y); }
+        fn clamped(x, low, high) { return clamp(x, low, high); }
+        fn power(x, y) { return pow(x, y); }
+        fn divisor(x, y) { return gcd(x, y); }
+        fn signum(x) { return sign(x); }
+        fn bits(x) { return popcount(x); }
+        fn combined(x, y) {
+            let result = pow(x, 2) + pow(y, 3) + gcd(x, y);
+            if (result > 100) {
+                return result - 100;
+            }
+            return result;
+        }
+        fn loop(n) {
+            let total = 0;
+            let i = 1;
+            while (i <= n) {
+                total += gcd(i, n) * pow(-1, i);
+                i++;
+            }
+            return total;
+        }
+        ";
+
+    /// Runs one function of `INTRINSICS_SOURCE` with the given overflow mode
+    fn run_intrinsic(
+        mode: OverflowMode,
+        function: &str,
+        args: [i64; 3],
+    ) -> Result<i64, RuntimeError> {
+        let options = FrontendOptions {
+            overflow_mode: mode,
+            ..Default::default()
+        };
+        let program = super::jit_compile_file(
+            "test.mj",
+            INTRINSICS_SOURCE,
+            function,
+            &options,
+            &HostFunctions::default(),
+        )
+        .expect("function should compile");
+        run_main(&program, &args)
+    }
+
+    #[test]
+    fn can_generate_math_intrinsics() {
+        let run = |function, args| run_intrinsic(OverflowMode::Wrapping, function, args);
+        assert_eq!(run("absolute", [-5, 0, 0]), Ok(5));
+        assert_eq!(run("absolute", [7, 0, 0]), Ok(7));
+        assert_eq!(run("absolute", [i64::MIN, 0, 0]), Ok(i64::MIN));
+        assert_eq!(run("minimum", [-3, 2, 0]), Ok(-3));
+        assert_eq!(run("maximum", [-3, 2, 0]), Ok(2));
+        assert_eq!(run("maximum", [i64::MIN, i64::MAX, 0]), Ok(i64::MAX));
+        assert_eq!(run("clamped", [15, 0, 10]), Ok(10));
+        assert_eq!(run("clamped", [-15, 0, 10]), Ok(0));
+        assert_eq!(run("clamped", [5, 0, 10]), Ok(5));
+        assert_eq!(run("clamped", [5, 10, 0]), Ok(0));
+        assert_eq!(run("power", [3, 4, 0]), Ok(81));
+        assert_eq!(run("power", [-2, 63, 0]), Ok(i64::MIN));
+        assert_eq!(run("power", [3, 0, 0]), Ok(1));
+        assert_eq!(run("power", [0, 0, 0]), Ok(1));
+        assert_eq!(run("power", [2, 64, 0]), Ok(0));
+        assert_eq!(run("power", [-1, -1, 0]), Ok(-1));
+        assert_eq!(run("power", [3, 41, 0]), Ok(3i64.wrapping_pow(41)));
+        assert_eq!(run("divisor", [48, 18, 0]), Ok(6));
+        assert_eq!(run("divisor", [-48, 18, 0]), Ok(6));
+        assert_eq!(run("divisor", [0, -7, 0]), Ok(7));
+        assert_eq!(run("divisor", [0, 0, 0]), Ok(0));
+        assert_eq!(run("divisor", [i64::MIN, 6, 0]), Ok(2));
+        assert_eq!(run("divisor", [i64::MIN, 0, 0]), Ok(i64::MIN));
+        assert_eq!(run("signum", [-42, 0, 0]), Ok(-1));
+        assert_eq!(run("signum", [0, 0, 0]), Ok(0));
+        assert_eq!(run("signum", [i64::MAX, 0, 0]), Ok(1));
+        assert_eq!(run("bits", [0b1011, 0, 0]), Ok(3));
+        assert_eq!(run("bits", [-1, 0, 0]), Ok(64));
+        assert_eq!(run("bits", [i64::MIN, 0, 0]), Ok(1));
+        // 4^2 + 6^3 + 2 = 234
+        assert_eq!(run("combined", [4, 6, 0]), Ok(134));
+        assert_eq!(run("combined", [1, 2, 0]), Ok(10));
+        // Alternating sum of gcd(i, 6): -1 + 2 - 3 + 2 - 1 + 6
+        assert_eq!(run("loop", [6, 0, 0]), Ok(5));
+    }
+
+    #[test]
+    fn math_intrinsics_follow_the_overflow_mode() {
+        let checked = |function, args| run_intrinsic(OverflowMode::Checked, function, args);
+        assert_eq!(
+            checked("absolute", [i64::MIN, 0, 0]),
+            Err(RuntimeError::Overflow)
+        );
+        assert_eq!(checked("absolute", [i64::MIN + 1, 0, 0]), Ok(i64::MAX));
+        assert_eq!(checked("power", [-2, 63, 0]), Ok(i64::MIN));
+        assert_eq!(checked("power", [2, 63, 0]), Err(RuntimeError::Overflow));
+        // The base is squared only while it is needed
+        assert_eq!(checked("power", [1 << 32, 1, 0]), Ok(1 << 32));
+
+        let saturating = |function, args| run_intrinsic(OverflowMode::Saturating, function, args);
+        assert_eq!(saturating("absolute", [i64::MIN, 0, 0]), Ok(i64::MAX));
+        assert_eq!(saturating("power", [10, 30, 0]), Ok(i64::MAX));
+        assert_eq!(saturating("power", [-10, 31, 0]), Ok(i64::MIN));
+    }
+
+    const DIVISION_SOURCE: &str = "
+        fn div(x, y) { return x / y; }
+        fn rem(x, y) { return x % y; }
+        fn div_u64(x: u64, y: u64) -> u64 { return x / y; }
+        fn rem_u64(x: u64, y: u64) -> u64 { return x % y; }
+        fn div_i32(x: i32, y: i32) -> i32 { return x / y; }
+        fn rem_i32(x: i32, y: i32) -> i32 { return x % y; }
+        fn div_i32_by_minus_one(x: i32) -> i32 { return x / -1; }
+        fn folded_i32() -> i32 { let x: i32 = -2147483647 - 1; return x / -1; }
+        fn caller(x, y) { return div(x, y) + 1; }
+        fn unused(x, y) { let z = x / y; return 0; }
+        ";
+
+    fn run_division(function: &str, args: [i64; 2]) -> Result<i64, RuntimeError> {
+        run_division_with_overflow_mode(OverflowMode::Wrapping, function, args)
+    }
+
+    fn run_division_with_overflow_mode(
+        mode: OverflowMode,
+        function: &str,
+        args: [i64; 2],
+    ) -> Result<i64, RuntimeError> {
+        let options = FrontendOptions {
+            overflow_mode: mode,
+            ..Default::default()
+        };
+        let program = super::jit_compile_file(
+            "test.mj",
+            DIVISION_SOURCE,
+            function,
+            &options,
+            &HostFunctions::default(),
+        )
+        .expect("function should compile");
+        run_main(&program, &args)
+    }
+
+    #[test]
+    fn invalid_divisions_are_runtime_errors() {
+        let by_zero = Err(RuntimeError::DivisionByZero);
+        let overflow = Err(RuntimeError::DivisionOverflow);
+        assert_eq!(run_division("div", [7, 2]), Ok(3));
+        assert_eq!(run_division("div", [7, 0]), by_zero);
+        assert_eq!(run_division("rem", [7, 0]), by_zero);
+        assert_eq!(run_division("div_u64", [-1, 0]), by_zero);
+        assert_eq!(run_division("rem_u64", [-1, 0]), by_zero);
+        assert_eq!(run_division("div", [i64::MIN, -1]), overflow);
+        assert_eq!(run_division("rem", [i64::MIN, -1]), overflow);
+        assert_eq!(run_division("div", [i64::MIN, 1]), Ok(i64::MIN));
+        assert_eq!(run_division("div", [i64::MAX, -1]), Ok(-i64::MAX));
+        // For unsigned values, -1 is just a very large divisor
+        assert_eq!(run_division("div_u64", [i64::MIN, -1]), Ok(0));
+        // Narrower values are divided in 64 bits, so the result simply wraps around
+        assert_eq!(
+            run_division("div_i32", [i32::MIN as i64, -1]),
+            Ok(i32::MIN as i64)
+        );
+        assert_eq!(run_division("rem_i32", [i32::MIN as i64, -1]), Ok(0));
+        assert_eq!(run_division("folded_i32", [0, 0]), Ok(i32::MIN as i64));
+        assert_eq!(run_division("caller", [1, 0]), by_zero);
+        // Like overflows in checked mode, divisions are never removed by the optimizer
+        assert_eq!(run_division("unused", [1, 0]), by_zero);
+    }
+
+    #[test]
+    fn checked_narrow_divisions_trap_on_overflow() {
+        let run =
+            |function, args| run_division_with_overflow_mode(OverflowMode::Checked, function, args);
+        let overflow = Err(RuntimeError::DivisionOverflow);
+        let min = i32::MIN as i64;
+        assert_eq!(run("div_i32", [min, -1]), overflow);
+        assert_eq!(run("rem_i32", [min, -1]), overflow);
+        assert_eq!(run("div_i32_by_minus_one", [min, 0]), overflow);
+        assert_eq!(run("div_i32", [min, 1]), Ok(min));
+        assert_eq!(run("div_i32", [min + 1, -1]), Ok(i32::MAX as i64));
+        // The optimizer leaves the overflow to the generated code
+        assert_eq!(run("folded_i32", [0, 0]), overflow);
+        assert_eq!(run("div", [i64::MIN, -1]), overflow);
+    }
+
+    #[test]
+    fn saturating_narrow_divisions_clamp_to_the_maximum() {
+        let run = |function, args| {
+            run_division_with_overflow_mode(OverflowMode::Saturating, function, args)
+        };
+        let min = i32::MIN as i64;
+        assert_eq!(run("div_i32", [min, -1]), Ok(i32::MAX as i64));
+        assert_eq!(run("div_i32_by_minus_one", [min, 0]), Ok(i32::MAX as i64));
+        assert_eq!(run("folded_i32", [0, 0]), Ok(i32::MAX as i64));
+        assert_eq!(run("div_i32", [min, 2]), Ok(min / 2));
+        assert_eq!(run("div_i32", [-7, 2]), Ok(-3));
+        assert_eq!(run("rem_i32", [min, -1]), Ok(0));
+        // Like in the other modes, there is no 128-bit result to clamp for i64
+        assert_eq!(
+            run("div", [i64::MIN, -1]),
+            Err(RuntimeError::DivisionOverflow)
+        );
+    }
+
+    #[test]
+    fn constant_invalid_divisions_are_errors() {
+        let compile = |source| {
+            super::jit_compile_file(
+                "test.mj",
+                source,
+                "f",
+                &FrontendOptions::default(),
+                &HostFunctions::default(),
+            )
+        };
+        let err = compile("const ZERO = 0; fn f(x) { return x / ZERO; }")
+            .expect_err("should have not compiled");
+        assert!(err.to_string().contains("division by zero"));
+        let err = compile("fn f() { return (-9223372036854775807 - 1) % -1; }")
+            .expect_err("should have not compiled");
+        assert!(err.to_string().contains("division overflow"));
+        let program = compile("fn f() -> i32 { return (-2147483647 - 1) / -1; }")
+            .expect("function should compile");
+        assert_eq!(program.run(Default::default()), Ok(i32::MIN as i64));
+        let checked = FrontendOptions {
+            overflow_mode: OverflowMode::Checked,
+            ..Default::default()
+        };
+        let err = super::jit_compile_file(
+            "test.mj",
+            "fn f() -> i32 { return (-2147483647 - 1) / -1; }",
+            "f",
+            &checked,
+            &HostFunctions::default(),
+        )
+        .expect_err("should have not compiled");
+        assert!(err.to_string().contains("division overflow"));
+        let program = compile("fn f() { return 1 / (2 - 1); }").expect("function should compile");
+        assert_eq!(program.run(Default::default()), Ok(1));
+    }
+
+    const INDIRECT_CALLS_SOURCE: &str = "
+        fn add(a, b) { return a + b; }
+        fn mul(a, b) { return a * b; }
+        fn neg(a) { return -a; }
+        fn apply(f, a, b) { return f(a, b); }
+        fn sum_and_product(x) { return apply(&add, x, 2) * 100 + apply(&mul, x, 3); }
+        fn choose(x) {
+            let f = &add;
+            if (x < 0) {
+                f = &mul;
+            }
+            return f(x, x);
+        }
+        fn neg_handle() { return &neg; }
+        fn call_with_two_args(h) { return apply(h, 1, 2); }
+        ";
+
+    fn run_indirect_call(function: &str, arg: i64) -> Result<i64, RuntimeError> {
+        let program = super::jit_compile_file(
+            "test.mj",
+            INDIRECT_CALLS_SOURCE,
+            function,
+            &FrontendOptions::default(),
+            &HostFunctions::default(),
+        )
+        .expect("function should compile");
+        run_main(&program, &[arg])
+    }
+
+    #[test]
+    fn can_call_functions_through_their_handles() {
+        assert_eq!(run_indirect_call("sum_and_product", 4), Ok(612));
+        assert_eq!(run_indirect_call("choose", 5), Ok(10));
+        assert_eq!(run_indirect_call("choose", -5), Ok(25));
+        // Handles are the ids of the functions, in order of declaration
+        assert_eq!(run_indirect_call("neg_handle", 0), Ok(2));
+        assert_eq!(run_indirect_call("call_with_two_args", 1), Ok(2));
+    }
+
+    #[test]
+    fn invalid_indirect_calls_are_runtime_errors() {
+        let invalid_handle = Err(RuntimeError::InvalidFunctionHandle);
+        assert_eq!(run_indirect_call("call_with_two_args", 8), invalid_handle);
+        assert_eq!(run_indirect_call("call_with_two_args", -1), invalid_handle);
+        assert_eq!(
+            run_indirect_call("call_with_two_args", 2),
+            Err(RuntimeError::ArityMismatch)
+        );
+    }
+
+    #[test]
+    fn functions_with_floats_cannot_be_called_through_handles() {
+        let source = "
+        fn apply(f, a) { return f(a) + 1; }
+        fn apply_in_tail_position(f, a) { return f(a); }
+        fn half(x) -> f64 { return x as f64 / 2.0; }
+        fn double(x: f64) { return x as i64 * 2; }
+        fn neg(x) { return -x; }
+        ";
+        let program = super::jit_compile_program(source, "apply").expect("program should compile");
+        let invalid_handle = Err(CallError::Runtime(RuntimeError::InvalidFunctionHandle));
+        for function in ["apply", "apply_in_tail_position"] {
+            assert_eq!(program.call(function, &[2, 4]), invalid_handle);
+            assert_eq!(program.call(function, &[3, 4]), invalid_handle);
+        }
+        assert_eq!(program.call("apply", &[4, 4]), Ok(-3));
+    }
+
+    const TAIL_CALLS_SOURCE: &str = "
+        fn count(n, acc) {
+            if (n == 0) {
+                return acc;
+            }
+            return count(n - 1, acc + 1);
+        }
+        fn is_even(n) {
+            if (n == 0) {
+                return 1;
+            }
+            return is_odd(n - 1);
+        }
+        fn is_odd(n) {
+            if (n == 0) {
+                return 0;
+            }
+            return is_even(n - 1);
+        }
+        fn divide_at_the_end(n) {
+            if (n == 0) {
+                return 1 / n;
+            }
+            return divide_at_the_end(n - 1);
+        }
+        fn halve(x: f64, n) -> f64 {
+            if (n == 0) {
+                return x;
+            }
+            return halve(x / 2.0, n - 1);
+        }
+        fn start_halving(n) { return halve(1048576.0, n) as i64; }
+        fn count_through(k, n) {
+            if (n == 0) {
+                return 7;
+            }
+            return k(k, n - 1);
+        }
+        fn start_counting(n) { return count_through(&count_through, n); }
+        fn continue_with(k, n) {
+            if (n == 0) {
+                return k(k, n, n);
+            }
+            return k(k, n - 1);
+        }
+        fn start_continuing(n) { return continue_with(&continue_with, n); }
+        ";
+
+    fn run_tail_call(function: &str, arg: i64) -> Result<i64, RuntimeError> {
+        let program = super::jit_compile_file(
+            "test.mj",
+            TAIL_CALLS_SOURCE,
+            function,
+            &FrontendOptions::default(),
+            &HostFunctions::default(),
+        )
+        .expect("function should compile");
+        run_main(&program, &[arg, 0])
+    }
+
+    #[test]
+    fn returned_calls_run_in_constant_stack_space() {
+        // Without tail calls, these would overflow the stack of the test thread
+        assert_eq!(run_tail_call("count", 10_000_000), Ok(10_000_000));
+        assert_eq!(run_tail_call("is_even", 10_000_000), Ok(1));
+        assert_eq!(run_tail_call("is_odd", 10_000_000), Ok(0));
+        assert_eq!(run_tail_call("start_halving", 20), Ok(1));
+        assert_eq!(
+            run_tail_call("divide_at_the_end", 10_000_000),
+            Err(RuntimeError::DivisionByZero)
+        );
+        // Indirect calls too, after their handle has been checked
+        assert_eq!(run_tail_call("start_counting", 10_000_000), Ok(7));
+        assert_eq!(
+            run_tail_call("start_continuing", 10_000_000),
+            Err(RuntimeError::ArityMismatch)
+        );
+    }
+
+    #[test]
+    fn can_compute_with_floats() {
+        let source = "
+        fn f(n, x: f64, m: i32, y: f64) -> f64 {
+            let rate = 2.5e-1;
+            return (x - y) / rate * -(n as f64) + (m as i64 as f64) * 1_000.5;
+        }
+        ";
+        let program = super::jit_compile_program(source, "f").expect("function should compile");
+        let f = program.get::<fn(i64, f64, i64, f64) -> f64>("f").unwrap();
+        assert_eq!(f.call(2, 3.5, 4, 1.0), Ok(-20.0 + 4002.0));
+        assert_eq!(f.call(-1, 0.0, 0, 0.25), Ok(-1.0));
+    }
+
+    #[test]
+    fn float_comparisons_are_false_for_nan() {
+        let source = "
+        fn compare(a: f64, b: f64) -> i64 {
+            return (a < b) as i64 + (a <= b) as i64 * 2 + (a > b) as i64 * 4
+                + (a >= b) as i64 * 8 + (a == b) as i64 * 16 + (a != b) as i64 * 32;
+        }
+        ";
+        let program =
+            super::jit_compile_program(source, "compare").expect("function should compile");
+        let compare = program.get::<fn(f64, f64) -> i64>("compare").unwrap();
+        assert_eq!(compare.call(1.0, 2.0), Ok(1 + 2 + 32));
+        assert_eq!(compare.call(2.0, 2.0), Ok(2 + 8 + 16));
+        assert_eq!(compare.call(3.0, -2.0), Ok(4 + 8 + 32));
+        assert_eq!(compare.call(f64::NAN, 1.0), Ok(32));
+        assert_eq!(compare.call(1.0, f64::NAN), Ok(32));
+        assert_eq!(compare.call(f64::NAN, f64::NAN), Ok(32));
+    }
+
+    #[test]
+    fn float_to_int_conversions_saturate() {
+        let source = "
+        fn to_int(x: f64) -> i64 { return x as i64; }
+        fn to_float(x) -> f64 { return x as f64; }
+        ";
+        let program =
+            super::jit_compile_program(source, "to_int").expect("function should compile");
+        let to_int = program.get::<fn(f64) -> i64>("to_int").unwrap();
+        for value in [2.9, -2.9, 1e300, -1e300, 9.3e18, f64::NAN, f64::INFINITY] {
+            assert_eq!(to_int.call(value), Ok(value as i64), "converting {}", value);
+        }
+
+        let program =
+            super::jit_compile_program(source, "to_float").expect("function should compile");
+        let to_float = program.get::<fn(i64) -> f64>("to_float").unwrap();
+        for value in [0, -7, i64::MAX, i64::MIN] {
+            assert_eq!(to_float.call(value), Ok(value as f64));
+        }
+    }
+
+    #[test]
+    fn floats_survive_calls_and_spills() {
+        let source = "
+        fn f(x: f64, n) -> f64 {
+            let a = x + 1.0;
+            let b = x + 2.0;
+            let c = x + 3.0;
+            let d = x + 4.0;
+            let e = x + 5.0;
+            let g = x + 6.0;
+            let h = x + 7.0;
+            let i = x + 8.0;
+            let j = x + 9.0;
+            let k = scale(n, a, 10, b);
+            return a + b + c + d + e + g + h + i + j + k;
+        }
+        fn scale(n, x: f64, m, y: f64) -> f64 { return (x + y) * (n * m) as f64; }
+        ";
+        let program = super::jit_compile_program(source, "f").expect("function should compile");
+        let f = program.get::<fn(f64, i64) -> f64>("f").unwrap();
+        assert_eq!(f.call(0.5, 2), Ok(49.5 + (1.5 + 2.5) * 20.0));
+    }
+
+    extern "C" fn host_sqrt(x: f64) -> f64 {
+        x.sqrt()
+    }
+
+    extern "C" fn host_scale(x: f64, n: i64) -> f64 {
+        x * n as f64
+    }
+
+    #[test]
+    fn can_call_host_functions_with_floats() {
+        let source = "
+        extern fn sqrt(x: f64) -> f64;
+        extern fn scale(x: f64, n) -> f64;
+        fn f(x: f64, y: f64) -> f64 {
+            let h = sqrt(x * x + y * y);
+            return scale(h, 3) + h;
+        }
+        ";
+        let mut host_functions = HostFunctions::default();
+        host_functions
+            .register("sqrt", host_sqrt as extern "C" fn(f64) -> f64)
+            .register("scale", host_scale as extern "C" fn(f64, i64) -> f64);
+        let program = super::jit_compile_program_with_host_functions(source, "f", &host_functions)
+            .expect("function should compile");
+        let f = program.get::<fn(f64, f64) -> f64>("f").unwrap();
+        assert_eq!(f.call(3.0, 4.0), Ok(20.0));
+
+        let mut host_functions = HostFunctions::default();
+        host_functions
+            .register("sqrt", host_double as extern "C" fn(i64) -> i64)
+            .register("scale", host_scale as extern "C" fn(f64, i64) -> f64);
+        let err = super::jit_compile_program_with_host_functions(source, "f", &host_functions)
+            .expect_err("should have not compiled");
+        let JitError::Frontend { errors, .. } = err else {
+            panic!("expected a frontend error");
+        };
+        assert_eq!(
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            vec!["extern function \"sqrt\" is declared as fn(float) -> float but the host provides it as fn(int) -> int"]
+        );
+    }
+
+    #[test]
+    fn can_call_functions_by_name_checking_their_arity() {
+        let source = "
+        fn f(x, y) { return x / y + g(x); }
+        fn g(x) { return x * 2; }
+        fn h(x: f64) -> f64 { return x; }
+        ";
+        let program = super::jit_compile_program(source, "f").expect("function should compile");
+        assert_eq!(Ok(14), program.call("f", &[6, 3]));
+        assert_eq!(Ok(8), program.call("g", &[4]));
+        assert_eq!(
+            Err(CallError::Runtime(RuntimeError::DivisionByZero)),
+            program.call("f", &[1, 0])
+        );
+        assert_eq!(
+            Err(CallError::ArityMismatch {
+                name: "f".to_string(),
+                expected: 2,
+                actual: 1
+            }),
+            program.call("f", &[1])
+        );
+        assert_eq!(
+            Err(CallError::FunctionNotFound("main".to_string())),
+            program.call("main", &[])
+        );
+        assert_eq!(
+            "function h is fn(float) -> float, but it was called as fn(int) -> int",
+            program.call("h", &[1]).unwrap_err().to_string()
+        );
+        assert!(matches!(
+            program.call("g", &[0; 13]),
+            Err(CallError::ArityMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn can_get_functions_with_a_checked_signature() {
+        let source = "
+        fn f(x: f64, n) -> f64 { return x * n as f64; }
+        fn g(n) { return n + 1; }
+        ";
+        let program = super::jit_compile_program(source, "f").expect("function should compile");
+        let f = program
+            .get::<fn(f64, i64) -> f64>("f")
+            .expect("signature should match");
+        assert_eq!(Ok(7.5), f.call(2.5, 3));
+        assert_eq!(Ok(3), program.get::<fn(i64) -> i64>("g").unwrap().call(2));
+
+        assert!(matches!(
+            program.get::<fn(i64, i64) -> f64>("f"),
+            Err(CallError::SignatureMismatch { .. })
+        ));
+        assert!(matches!(
+            program.get::<fn(f64, i64) -> i64>("f"),
+            Err(CallError::SignatureMismatch { .. })
+        ));
+        assert!(matches!(
+            program.get::<fn(i64, i64) -> i64>("g"),
+            Err(CallError::ArityMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn can_call_functions_with_arguments_passed_on_the_stack() {
+        let source = "
+        fn f(a, b, c, d, e, f, g, h, i) {
+            return a + b * 2 + c * 3 + d * 4 + e * 5 + f * 6 + g * 7 + h * 8 + i * 9;
+        }
+        fn g(a: f64, b: f64, c: f64, d: f64, e: f64, f: f64, g: f64, h: f64, i: f64, n, m) -> f64 {
+            return (a + b + c + d + e + f + g + h) * i + (n - m) as f64;
+        }
+        fn h(a, b, c, d, e, f, g, h, i, j, k, l, m) { return a + m; }
+        ";
+        let program = super::jit_compile_program(source, "f").expect("function should compile");
+        assert_eq!(Ok(285), program.call("f", &[1, 2, 3, 4, 5, 6, 7, 8, 9]));
+
+        let g = program
+            .get::<fn(f64, f64, f64, f64, f64, f64, f64, f64, f64, i64, i64) -> f64>("g")
+            .expect("signature should match");
+        assert_eq!(
+            Ok(36.0 * 0.5 + 4.0),
+            g.call(1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 0.5, 7, 3)
+        );
+
+        assert_eq!(
+            Err(CallError::TooManyArguments {
+                name: "h".to_string(),
+                num_args: 13
+            }),
+            program.call("h", &[1; 13])
+        );
+    }
+
+    #[test]
+    fn the_program_can_call_functions_with_at_most_six_integer_arguments() {
+        let source = "
+        fn g(a, b, c, d, e, f, h) { return a + b + c + d + e + f + h; }
+        fn f(x) { return g(x, x, x, x, x, x, x); }
+        ";
+        let err = super::jit_compile_program(source, "f").expect_err("should have not compiled");
+        assert!(matches!(
+            err,
+            JitError::Backend(BackendError::NotImplemented(_))
+        ));
+    }
 }
@@ -0,0 +1,42 @@
+//! EmJay is a toy JIT compiler for a very simple programming language. Programs are
+//! compiled by an [`Engine`], which gives access to the artifacts of each step:
+//! ```
+//! use emjay::Engine;
+//!
+//! let engine = Engine::builder().build();
+//! let program = engine.compile("fn double(x) { return x * 2; }").unwrap();
+//! let double = program.function("double").unwrap();
+//! println!("{}\n{}", double.optimized_ir, double.machine_code.asm);
+//!
+//! let program = program.into_jit_program("double").unwrap();
+//! assert_eq!(Ok(42), program.run(&[21]));
+//! ```
+#![allow(dead_code)]
+
+pub mod ast;
+pub mod backend;
+mod backend_aarch64;
+mod backend_intrinsic_expansion;
+mod backend_phi_elimination;
+mod backend_register_allocator;
+mod backend_x64_linux;
+pub mod engine;
+pub mod frontend;
+mod frontend_constants;
+mod frontend_types;
+mod grammar;
+pub mod ir;
+pub mod jit;
+pub mod modules;
+mod optimization;
+pub mod parser;
+mod program_counter;
+pub mod types;
+
+pub use engine::{Backend, Engine, EngineBuilder, OptimizationLevel, Program, ProgramFunction};
+pub use frontend::FrontendOptions;
+pub use ir::OverflowMode;
+pub use jit::{
+    CallError, HostFunctions, JitError, JitFunction, JitProgram, JitSignature, RuntimeError,
+};
+pub use modules::{FileProvider, FileSystemFiles, InMemoryFiles, SourceFiles};
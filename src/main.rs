@@ -1,22 +1,7 @@
-#![allow(dead_code)]
-
-use jit::jit_compile_program;
-use tracing::{info, Level};
+use emjay::Engine;
+use tracing::{error, info, Level};
 use tracing_subscriber::FmtSubscriber;
 
-mod ast;
-mod backend;
-mod backend_aarch64;
-mod backend_register_allocator;
-mod backend_x64_linux;
-mod frontend;
-mod grammar;
-mod ir;
-mod jit;
-mod optimization;
-mod parser;
-mod program_counter;
-
 fn main() {
     let subscriber = FmtSubscriber::builder()
         .with_max_level(Level::TRACE)
@@ -34,8 +19,14 @@ fn main() {
 
     ";
 
-    let jit_program = jit_compile_program(source, "main").expect("program should compile");
+    let engine = Engine::builder().build();
+    let jit_program = engine
+        .compile(source)
+        .and_then(|program| program.into_jit_program("main"))
+        .expect("program should compile");
     info!("program compiled, running it!");
-    let fun = jit_program.main_function;
-    info!("main function result: {}", fun(0, 0, 0, 0, 0, 0));
+    match jit_program.run(&[0]) {
+        Ok(result) => info!("main function result: {}", result),
+        Err(err) => error!("main function failed: {}", err),
+    }
 }
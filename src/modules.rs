@@ -0,0 +1,345 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use pest::Parser;
+use thiserror::Error;
+
+use crate::{
+    ast::{Module, Span},
+    grammar::{EmjayGrammar, Rule},
+    parser::{self, ParseError},
+};
+
+/// Where the resolver reads the source files from. Paths are the ones written in the
+/// `import` declarations, so they are all relative to the same root.
+pub trait FileProvider {
+    /// Returns the content of the file, or `None` if it does not exist
+    fn read(&self, path: &str) -> Option<String>;
+}
+
+/// Files that are kept in memory, for programs that do not come from the disk
+#[derive(Debug, Default)]
+pub struct InMemoryFiles {
+    files: HashMap<String, String>,
+}
+
+impl InMemoryFiles {
+    pub fn add(&mut self, path: &str, source: &str) -> &mut Self {
+        self.files.insert(path.to_string(), source.to_string());
+        self
+    }
+}
+
+impl FileProvider for InMemoryFiles {
+    fn read(&self, path: &str) -> Option<String> {
+        self.files.get(path).cloned()
+    }
+}
+
+/// Files read from a directory of the disk
+#[derive(Debug)]
+pub struct FileSystemFiles {
+    pub root: PathBuf,
+}
+
+impl FileProvider for FileSystemFiles {
+    fn read(&self, path: &str) -> Option<String> {
+        std::fs::read_to_string(self.root.join(path)).ok()
+    }
+}
+
+/// The name used to qualify the functions of a file, i.e. the name of the file
+/// without the directories and the `.mj` extension
+pub fn module_name(path: &str) -> &str {
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    file_name.strip_suffix(".mj").unwrap_or(file_name)
+}
+
+/// Errors found while looking for the files of a program. Their messages already point
+/// at the import that caused them
+#[derive(Debug, Error)]
+pub enum ModuleError {
+    #[error("{message}")]
+    FileNotFound { path: String, message: String },
+    #[error("{message}")]
+    ImportCycle {
+        /// The paths of the files in the cycle, starting and ending with the same one
+        cycle: Vec<String>,
+        message: String,
+    },
+    #[error("{message}")]
+    InvalidModuleName { path: String, message: String },
+    #[error("{message}")]
+    DuplicateModuleName { name: String, message: String },
+}
+
+/// A source file of a program. Spans of the whole program are offsets into the
+/// concatenation of all the files, each one starting at `offset`
+#[derive(Debug)]
+pub struct SourceFile {
+    pub path: String,
+    pub source: String,
+    pub offset: usize,
+}
+
+/// All the files of a program: the root one, followed by the ones it imports, directly
+/// or not, in the order in which they were found
+#[derive(Debug)]
+pub struct SourceFiles {
+    pub files: Vec<SourceFile>,
+}
+
+impl SourceFiles {
+    /// Reads the file at `root_path` and, recursively, all the ones it imports. A file
+    /// imported by more than one other file is read only once, but a file cannot import
+    /// itself, not even indirectly.
+    pub fn resolve(root_path: &str, provider: &dyn FileProvider) -> Result<Self, ModuleError> {
+        let mut files = SourceFiles { files: Vec::new() };
+        files.load(root_path, None, &mut Vec::new(), provider)?;
+        Ok(files)
+    }
+
+    /// Loads a file and its imports. `importer` is the index of the file containing the
+    /// import and the span of its path, while `stack` has the paths of the files whose
+    /// imports are being loaded, to detect cycles.
+    fn load(
+        &mut self,
+        path: &str,
+        importer: Option<(usize, Span)>,
+        stack: &mut Vec<String>,
+        provider: &dyn FileProvider,
+    ) -> Result<(), ModuleError> {
+        if let Some(start) = stack.iter().position(|visited| visited == path) {
+            let mut cycle = stack[start..].to_vec();
+            cycle.push(path.to_string());
+            let message = self
+                .format_import_error(importer, &format!("import cycle: {}", cycle.join(" -> ")));
+            return Err(ModuleError::ImportCycle { cycle, message });
+        }
+        if self.files.iter().any(|file| file.path == path) {
+            return Ok(());
+        }
+
+        let Some(source) = provider.read(path) else {
+            return Err(ModuleError::FileNotFound {
+                path: path.to_string(),
+                message: self.format_import_error(importer, &format!("file {} not found", path)),
+            });
+        };
+        let offset = self
+            .files
+            .last()
+            .map_or(0, |last| last.offset + last.source.len() + 1);
+        let index = self.files.len();
+        self.files.push(SourceFile {
+            path: path.to_string(),
+            source,
+            offset,
+        });
+
+        let imports = parser::parse_imports(&self.files[index].source)
+            .into_iter()
+            .map(|import| (import.path.to_string(), import.span))
+            .collect::<Vec<_>>();
+        let mut names: HashMap<&str, &str> = HashMap::new();
+        for (import_path, span) in imports.iter() {
+            let name = module_name(import_path);
+            if !is_identifier(name) {
+                return Err(ModuleError::InvalidModuleName {
+                    path: import_path.clone(),
+                    message: self.format_import_error(
+                        Some((index, *span)),
+                        &format!("module name {} is not a valid identifier", name),
+                    ),
+                });
+            }
+            match names.insert(name, import_path) {
+                Some(other_path) if other_path != import_path => {
+                    return Err(ModuleError::DuplicateModuleName {
+                        name: name.to_string(),
+                        message: self.format_import_error(
+                            Some((index, *span)),
+                            &format!("module {} is already imported from {}", name, other_path),
+                        ),
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        stack.push(path.to_string());
+        for (import_path, span) in imports.iter() {
+            self.load(import_path, Some((index, *span)), stack, provider)?;
+        }
+        stack.pop();
+        Ok(())
+    }
+
+    /// Formats an error pointing at the import, given as a span local to the file
+    fn format_import_error(&self, importer: Option<(usize, Span)>, message: &str) -> String {
+        match importer {
+            Some((index, span)) => {
+                let file = &self.files[index];
+                parser::format_error_at(&file.source, &file.path, span, message)
+            }
+            None => message.to_string(),
+        }
+    }
+
+    /// Parses all the files, moving the spans of each one by its offset
+    pub fn parse(&self) -> Result<Vec<Module<'_>>, Box<ParseError>> {
+        self.files
+            .iter()
+            .map(|file| {
+                let mut program = parser::parse_program(&file.source)
+                    .map_err(|err| Box::new(err.with_path(&file.path)))?;
+                program.shift_spans(file.offset);
+                Ok(Module {
+                    path: &file.path,
+                    program,
+                })
+            })
+            .collect()
+    }
+
+    /// Formats a message pointing at a span of the whole program, showing the path of
+    /// the file that contains it
+    pub fn format_error_at(&self, span: Span, message: &str) -> String {
+        let file = self
+            .files
+            .iter()
+            .rev()
+            .find(|file| file.offset <= span.start)
+            .expect("there is always a file at offset zero");
+        let local_span = Span::new(span.start - file.offset, span.end - file.offset);
+        parser::format_error_at(&file.source, &file.path, local_span, message)
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    EmjayGrammar::parse(Rule::identifier, name)
+        .is_ok_and(|mut parsed| parsed.next().unwrap().as_str() == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        ast::Span,
+        modules::{module_name, InMemoryFiles, ModuleError, SourceFiles},
+    };
+
+    fn paths(files: &SourceFiles) -> Vec<(&str, usize)> {
+        files
+            .files
+            .iter()
+            .map(|file| (file.path.as_str(), file.offset))
+            .collect()
+    }
+
+    #[test]
+    fn can_resolve_imports_reading_each_file_once() {
+        let mut provider = InMemoryFiles::default();
+        provider
+            .add(
+                "main.mj",
+                "import \"a.mj\";\nimport \"lib/b.mj\";\nfn main() { return 0; }",
+            )
+            .add("a.mj", "import \"lib/b.mj\";\nfn f() { return 1; }")
+            .add("lib/b.mj", "fn g() { return 2; }")
+            .add("unused.mj", "fn h() { return 3; }");
+
+        let files = SourceFiles::resolve("main.mj", &provider).expect("should resolve");
+        assert_eq!(
+            vec![("main.mj", 0), ("a.mj", 58), ("lib/b.mj", 98)],
+            paths(&files)
+        );
+
+        let modules = files.parse().expect("should parse");
+        let g = &modules[2].program.functions[0];
+        assert_eq!("lib/b.mj", modules[2].path);
+        assert_eq!(Span::new(101, 102), g.name_span);
+        assert!(files
+            .format_error_at(g.name_span, "oops")
+            .contains("lib/b.mj:1:4"));
+    }
+
+    #[test]
+    fn import_cycles_are_errors() {
+        let mut provider = InMemoryFiles::default();
+        provider
+            .add("main.mj", "import \"a.mj\";\nfn main() { return 0; }")
+            .add("a.mj", "import \"b.mj\";\nfn f() { return 1; }")
+            .add("b.mj", "import \"a.mj\";\nfn g() { return 2; }");
+
+        let error = SourceFiles::resolve("main.mj", &provider).expect_err("should be a cycle");
+        let ModuleError::ImportCycle { cycle, .. } = &error else {
+            panic!("expected a cycle, got {:?}", error);
+        };
+        assert_eq!(&vec!["a.mj", "b.mj", "a.mj"], cycle);
+        let message = error.to_string();
+        assert!(
+            message.contains("import cycle: a.mj -> b.mj -> a.mj"),
+            "{}",
+            message
+        );
+        assert!(message.contains("b.mj:1:8"), "{}", message);
+
+        provider.add("main.mj", "import \"main.mj\";\nfn main() { return 0; }");
+        assert!(matches!(
+            SourceFiles::resolve("main.mj", &provider),
+            Err(ModuleError::ImportCycle { .. })
+        ));
+    }
+
+    #[test]
+    fn missing_files_are_errors_pointing_at_the_import() {
+        let mut provider = InMemoryFiles::default();
+        provider.add("main.mj", "fn f() { return 0; }\n");
+        assert_eq!(
+            "file other.mj not found",
+            SourceFiles::resolve("other.mj", &provider)
+                .unwrap_err()
+                .to_string()
+        );
+
+        provider.add("main.mj", "import \"finance.mj\";\nfn f() { return 0; }");
+        let message = SourceFiles::resolve("main.mj", &provider)
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("main.mj:1:8"), "{}", message);
+        assert!(message.contains("file finance.mj not found"), "{}", message);
+    }
+
+    #[test]
+    fn module_names_must_be_unique_identifiers() {
+        assert_eq!("finance", module_name("lib/finance.mj"));
+        assert_eq!("util", module_name("util"));
+
+        let mut provider = InMemoryFiles::default();
+        provider
+            .add("a/util.mj", "fn f() { return 1; }")
+            .add("b/util.mj", "fn g() { return 2; }")
+            .add("my-lib.mj", "fn h() { return 3; }");
+
+        provider.add(
+            "main.mj",
+            "import \"a/util.mj\";\nimport \"a/util.mj\";\nfn main() { return 0; }",
+        );
+        assert!(SourceFiles::resolve("main.mj", &provider).is_ok());
+
+        provider.add(
+            "main.mj",
+            "import \"a/util.mj\";\nimport \"b/util.mj\";\nfn main() { return 0; }",
+        );
+        let error = SourceFiles::resolve("main.mj", &provider).unwrap_err();
+        assert!(matches!(error, ModuleError::DuplicateModuleName { .. }));
+        assert!(error
+            .to_string()
+            .contains("module util is already imported from a/util.mj"));
+
+        provider.add("main.mj", "import \"my-lib.mj\";\nfn main() { return 0; }");
+        assert!(matches!(
+            SourceFiles::resolve("main.mj", &provider),
+            Err(ModuleError::InvalidModuleName { .. })
+        ));
+    }
+}
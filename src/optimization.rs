@@ -1,9 +1,9 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use crate::ir::{BinOpOperator::*, CompiledFunction, IrInstruction, IrRegister};
+use crate::ir::{BasicBlock, CompiledFunction, IrInstruction, IrRegister, OverflowMode};
 
 /// Replaces algebraic expressions with their computed values, if possible. For example:
-/// ```
+/// ```text
 /// mov r0, 1
 /// mov r1, 2
 /// add r2, r0, r1
@@ -11,74 +11,189 @@ use crate::ir::{BinOpOperator::*, CompiledFunction, IrInstruction, IrRegister};
 ///
 /// becomes
 ///
-/// ```
+/// ```text
 /// mov r0, 1
 /// mov r1, 2
 /// mov r2, 3
 /// ````
-fn propagate_constants(body: &[IrInstruction], num_used_registers: usize) -> Vec<IrInstruction> {
+///
+/// Arithmetic follows `overflow_mode`, and a checked operation that overflows is left
+/// to the generated code, which will report the error.
+fn propagate_constants(
+    blocks: &[BasicBlock],
+    num_used_registers: usize,
+    overflow_mode: OverflowMode,
+) -> Vec<BasicBlock> {
     let mut known_constants: Vec<Option<i64>> = vec![None; num_used_registers];
 
-    let mut result = Vec::with_capacity(body.len());
-    for instruction in body {
-        match instruction {
-            IrInstruction::Mvi { dest, val } => {
-                known_constants[dest.0] = Some(*val);
-                result.push(instruction.clone());
-            }
-            IrInstruction::BinOp {
-                operator,
-                dest,
-                op1,
-                op2,
-            } => {
-                if let (Some(value1), Some(value2)) =
-                    (known_constants[op1.0], known_constants[op2.0])
-                {
-                    let computed_value = match operator {
-                        Add => value1 + value2,
-                        Sub => value1 - value2,
-                        Mul => value1 * value2,
-                        Div => value1 / value2,
-                    };
-                    known_constants[dest.0] = Some(computed_value);
-                    result.push(IrInstruction::Mvi {
-                        dest: *dest,
-                        val: computed_value,
-                    })
-                } else {
-                    // Not a known constant, leave as-is
-                    result.push(instruction.clone());
+    // Blocks are sorted so that definitions are always visited before their usages,
+    // except for phi sources coming from back edges - which we simply treat as unknown
+    let mut result = Vec::with_capacity(blocks.len());
+    for block in blocks {
+        let mut body = Vec::with_capacity(block.body.len());
+        for instruction in block.body.iter() {
+            match instruction {
+                IrInstruction::Mvi { dest, val } => {
+                    known_constants[dest.0] = Some(*val);
+                    body.push(instruction.clone());
                 }
-            }
-            IrInstruction::Neg { dest, op } => {
-                if let Some(value) = known_constants[op.0] {
-                    // Replace with a constant
-                    let computed_value = -value;
-                    known_constants[dest.0] = Some(computed_value);
-                    result.push(IrInstruction::Mvi {
-                        dest: *dest,
-                        val: computed_value,
-                    })
-                } else {
-                    // Not a known constant, leave as-is
-                    result.push(instruction.clone());
+                IrInstruction::BinOp {
+                    operator,
+                    ty,
+                    dest,
+                    op1,
+                    op2,
+                } => {
+                    // Divisions without a meaningful result are left to the generated code
+                    if let Some(computed_value) = known_constants[op1.0]
+                        .zip(known_constants[op2.0])
+                        .and_then(|(value1, value2)| {
+                            operator.evaluate_with_overflow(*ty, overflow_mode, value1, value2)
+                        })
+                    {
+                        known_constants[dest.0] = Some(computed_value);
+                        body.push(IrInstruction::Mvi {
+                            dest: *dest,
+                            val: computed_value,
+                        })
+                    } else {
+                        // Not a known constant, leave as-is
+                        body.push(instruction.clone());
+                    }
+                }
+                IrInstruction::Neg { dest, op } => {
+                    if let Some(value) = known_constants[op.0] {
+                        // Replace with a constant
+                        let computed_value = value.wrapping_neg();
+                        known_constants[dest.0] = Some(computed_value);
+                        body.push(IrInstruction::Mvi {
+                            dest: *dest,
+                            val: computed_value,
+                        })
+                    } else {
+                        // Not a known constant, leave as-is
+                        body.push(instruction.clone());
+                    }
+                }
+                IrInstruction::BitwiseNot { dest, op } => {
+                    if let Some(value) = known_constants[op.0] {
+                        let computed_value = !value;
+                        known_constants[dest.0] = Some(computed_value);
+                        body.push(IrInstruction::Mvi {
+                            dest: *dest,
+                            val: computed_value,
+                        })
+                    } else {
+                        body.push(instruction.clone());
+                    }
+                }
+                IrInstruction::Abs { dest, op }
+                | IrInstruction::Sign { dest, op }
+                | IrInstruction::Popcount { dest, op } => {
+                    if let Some(value) = known_constants[op.0] {
+                        let computed_value = match instruction {
+                            IrInstruction::Abs { .. } => value.wrapping_abs(),
+                            IrInstruction::Sign { .. } => value.signum(),
+                            _ => value.count_ones() as i64,
+                        };
+                        known_constants[dest.0] = Some(computed_value);
+                        body.push(IrInstruction::Mvi {
+                            dest: *dest,
+                            val: computed_value,
+                        })
+                    } else {
+                        body.push(instruction.clone());
+                    }
+                }
+                IrInstruction::Cast { dest, op, ty } => {
+                    if let Some(value) = known_constants[op.0] {
+                        let computed_value = ty.normalize(value);
+                        known_constants[dest.0] = Some(computed_value);
+                        body.push(IrInstruction::Mvi {
+                            dest: *dest,
+                            val: computed_value,
+                        })
+                    } else {
+                        body.push(instruction.clone());
+                    }
+                }
+                IrInstruction::IntToFloat { dest, op } => {
+                    if let Some(value) = known_constants[op.0] {
+                        let computed_value = (value as f64).to_bits() as i64;
+                        known_constants[dest.0] = Some(computed_value);
+                        body.push(IrInstruction::Mvi {
+                            dest: *dest,
+                            val: computed_value,
+                        })
+                    } else {
+                        body.push(instruction.clone());
+                    }
+                }
+                IrInstruction::FloatToInt { dest, op } => {
+                    if let Some(value) = known_constants[op.0] {
+                        // Like the generated code, `as` saturates and maps NaN to 0
+                        let computed_value = f64::from_bits(value as u64) as i64;
+                        known_constants[dest.0] = Some(computed_value);
+                        body.push(IrInstruction::Mvi {
+                            dest: *dest,
+                            val: computed_value,
+                        })
+                    } else {
+                        body.push(instruction.clone());
+                    }
+                }
+                IrInstruction::Mv { dest, src } => {
+                    if let Some(value) = known_constants[src.0] {
+                        known_constants[dest.0] = Some(value);
+                        body.push(IrInstruction::Mvi {
+                            dest: *dest,
+                            val: value,
+                        })
+                    } else {
+                        body.push(instruction.clone());
+                    }
+                }
+                IrInstruction::Phi { dest, sources } => {
+                    // If all the incoming values are the same constant, the phi is a constant too
+                    let first_value = known_constants[sources[0].1 .0];
+                    match first_value {
+                        Some(value)
+                            if sources
+                                .iter()
+                                .all(|(_, reg)| known_constants[reg.0] == first_value) =>
+                        {
+                            known_constants[dest.0] = first_value;
+                            body.push(IrInstruction::Mvi {
+                                dest: *dest,
+                                val: value,
+                            })
+                        }
+                        _ => body.push(instruction.clone()),
+                    }
+                }
+                IrInstruction::Ret { .. }
+                | IrInstruction::MvArg { .. }
+                | IrInstruction::Jmp { .. }
+                | IrInstruction::Br { .. }
+                | IrInstruction::Switch { .. }
+                | IrInstruction::Call { .. }
+                | IrInstruction::CallIndirect { .. }
+                | IrInstruction::CallHost { .. }
+                | IrInstruction::Load { .. }
+                | IrInstruction::Store { .. } => {
+                    // Can't optimize
+                    body.push(instruction.clone());
                 }
-            }
-            IrInstruction::Ret { .. }
-            | IrInstruction::MvArg { .. }
-            | IrInstruction::Call { .. } => {
-                // Can't optimize
-                result.push(instruction.clone());
             }
         }
+        result.push(BasicBlock { id: block.id, body });
     }
     result
 }
 
 /// Deduplicates constant assignments, retaining only the first and and replacing any reference
 /// to the second register with a reference to the first. Meaning:
-/// ```
+/// ```text
 /// mov r0, 1
 /// mov r1, 1
 /// ret r1
@@ -86,75 +201,59 @@ fn propagate_constants(body: &[IrInstruction], num_used_registers: usize) -> Vec
 ///
 /// becomes
 ///
-/// ```
+/// ```text
 /// mov r0, 1
 /// ret r0
 /// ```
-fn deduplicate_constants(body: &[IrInstruction], num_used_registers: usize) -> Vec<IrInstruction> {
+///
+/// Since a constant is only available in the blocks dominated by the one defining it,
+/// constants are only deduplicated within the same block. Integers and floats live in
+/// different registers, so they are never merged, even if they have the same bits.
+fn deduplicate_constants(
+    blocks: &[BasicBlock],
+    num_used_registers: usize,
+    float_registers: &HashSet<IrRegister>,
+) -> Vec<BasicBlock> {
     // By default, each register maps to itself
     let mut register_replacement: Vec<IrRegister> = Vec::with_capacity(num_used_registers);
     for i in 0..num_used_registers {
         register_replacement.push(IrRegister::new(i));
     }
 
-    let mut constant_values: HashMap<i64, IrRegister> = HashMap::new();
-
-    let mut result = Vec::new();
-    for instruction in body {
-        match instruction {
-            IrInstruction::Mvi { dest, val } => {
-                let register_containing_value = constant_values.get(val);
+    // First pass: find the duplicated constants
+    let mut result = Vec::with_capacity(blocks.len());
+    for block in blocks {
+        let mut constant_values: HashMap<(i64, bool), IrRegister> = HashMap::new();
+        let mut body = Vec::with_capacity(block.body.len());
+        for instruction in block.body.iter() {
+            if let IrInstruction::Mvi { dest, val } = instruction {
+                let key = (*val, float_registers.contains(dest));
+                let register_containing_value = constant_values.get(&key);
                 if let Some(register_containing_value) = register_containing_value {
                     // Replace register with cached version in successive instructions, and skip it
                     register_replacement[dest.0] = *register_containing_value;
-                } else {
-                    constant_values.insert(*val, *dest);
-                    result.push(instruction.clone());
+                    continue;
                 }
+                constant_values.insert(key, *dest);
             }
-            IrInstruction::MvArg { .. } => {
-                result.push(instruction.clone());
-            }
-            IrInstruction::BinOp {
-                operator,
-                dest,
-                op1,
-                op2,
-            } => result.push(IrInstruction::BinOp {
-                operator: *operator,
-                dest: *dest,
-                op1: register_replacement[op1.0],
-                op2: register_replacement[op2.0],
-            }),
-            IrInstruction::Neg { dest, op } => result.push(IrInstruction::Neg {
-                dest: *dest,
-                op: register_replacement[op.0],
-            }),
-            IrInstruction::Ret { reg } => result.push(IrInstruction::Ret {
-                reg: register_replacement[reg.0],
-            }),
-            IrInstruction::Call {
-                dest,
-                name,
-                function_id,
-                args,
-            } => {
-                let args = args.iter().map(|arg| register_replacement[arg.0]).collect();
-                result.push(IrInstruction::Call {
-                    dest: *dest,
-                    name: name.clone(),
-                    function_id: *function_id,
-                    args,
-                })
-            }
+            body.push(instruction.clone());
+        }
+        result.push(BasicBlock { id: block.id, body });
+    }
+
+    // Second pass: replace the usages. We need to do it separately because phis can
+    // refer to registers defined in blocks that come later
+    for block in result.iter_mut() {
+        for instruction in block.body.iter_mut() {
+            *instruction = instruction.map_registers(|reg| register_replacement[reg.0]);
         }
     }
     result
 }
 
 /// Removes dead store allocations, i.e. movements to registers that aren't used
-/// in any `ret` statement. For example:
-/// ```
+/// in any `ret` or branch statement. For example:
+/// ```text
 /// mov r0, 1
 /// mov r1, 2
 /// ret r0
@@ -162,74 +261,71 @@ fn deduplicate_constants(body: &[IrInstruction], num_used_registers: usize) -> V
 ///
 /// becomes
 ///
-/// ```
+/// ```text
 /// mov r0, 1
 /// ret r0
 /// ````
-fn dead_store_elimination(body: &[IrInstruction], num_used_registers: usize) -> Vec<IrInstruction> {
-    let mut used_registers = vec![false; num_used_registers];
+fn dead_store_elimination(
+    blocks: &[BasicBlock],
+    num_used_registers: usize,
+    overflow_mode: OverflowMode,
+) -> Vec<BasicBlock> {
+    // Since the ir is in SSA form, every register has exactly one definition
+    let mut definitions: Vec<Option<&IrInstruction>> = vec![None; num_used_registers];
+    for instruction in blocks.iter().flat_map(|block| block.body.iter()) {
+        if let Some(dest) = instruction.dest() {
+            definitions[dest.0] = Some(instruction);
+        }
+    }
 
-    // Start from the last instruction (which should be a `ret`) and propagate
-    // used registers backwards.
-    let mut result = Vec::new();
-    for instruction in body.iter().rev() {
-        match instruction {
-            IrInstruction::Ret { reg } => {
-                used_registers[reg.0] = true;
-                result.push(instruction.clone());
-            }
-            IrInstruction::Mvi { dest, .. } => {
-                if used_registers[dest.0] {
-                    result.push(instruction.clone());
-                }
-            }
-            IrInstruction::MvArg { dest, .. } => {
-                if used_registers[dest.0] {
-                    result.push(instruction.clone());
-                }
-            }
-            IrInstruction::BinOp {
-                dest,
-                op1,
-                op2,
-                operator: _,
-            } => {
-                if used_registers[dest.0] {
-                    used_registers[op1.0] = true;
-                    used_registers[op2.0] = true;
-                    result.push(instruction.clone());
-                }
-            }
-            IrInstruction::Neg { dest, op } => {
-                if used_registers[dest.0] {
-                    let op: usize = op.0;
-                    used_registers[op] = true;
-                    result.push(instruction.clone());
-                }
-            }
-            IrInstruction::Call { dest, ref args, .. } => {
-                if used_registers[dest.0] {
-                    for arg in args {
-                        used_registers[arg.0] = true;
-                    }
-                    result.push(instruction.clone());
-                }
+    // Start from the instructions that do not write a register (i.e. `ret`, branches and
+    // stores) or that have side effects, and propagate the used registers to the
+    // instructions defining them
+    let mut used_registers = vec![false; num_used_registers];
+    let mut to_visit: Vec<IrRegister> = blocks
+        .iter()
+        .flat_map(|block| block.body.iter())
+        .filter(|instruction| {
+            instruction.dest().is_none() || instruction.has_side_effects(overflow_mode)
+        })
+        .flat_map(|instruction| instruction.uses())
+        .collect();
+    while let Some(reg) = to_visit.pop() {
+        if !used_registers[reg.0] {
+            used_registers[reg.0] = true;
+            if let Some(definition) = definitions[reg.0] {
+                to_visit.extend(definition.uses());
             }
         }
     }
 
-    // Reorder the instruction
-    result.reverse();
-    result
+    blocks
+        .iter()
+        .map(|block| BasicBlock {
+            id: block.id,
+            body: block
+                .body
+                .iter()
+                .filter(|instruction| match instruction.dest() {
+                    Some(dest) => {
+                        used_registers[dest.0] || instruction.has_side_effects(overflow_mode)
+                    }
+                    None => true,
+                })
+                .cloned()
+                .collect(),
+        })
+        .collect()
 }
 
 struct OptimizedBody {
-    body: Vec<IrInstruction>,
+    blocks: Vec<BasicBlock>,
     num_used_registers: usize,
+    float_registers: HashSet<IrRegister>,
 }
 
 /// Renames registers to be dense, starting from zero. For example:
-/// ```
+/// ```text
 /// mov r0, 1
 /// mov r2, 2
 /// add r4, r2, r0
@@ -237,159 +333,104 @@ struct OptimizedBody {
 ///
 /// becomes:
 ///
-/// ```
+/// ```text
 /// mov r0, 1
 /// mov r1, 2
 /// add r2, r1, r0
 /// ```
-fn rename_registers(body: Vec<IrInstruction>, num_used_registers: usize) -> OptimizedBody {
+fn rename_registers(
+    blocks: Vec<BasicBlock>,
+    num_used_registers: usize,
+    float_registers: &HashSet<IrRegister>,
+) -> OptimizedBody {
     // By default, each register maps to itself
     let mut register_replacement: Vec<IrRegister> = Vec::with_capacity(num_used_registers);
     for i in 0..num_used_registers {
         register_replacement.push(IrRegister::new(i));
     }
 
+    // Assign the new names in order of definition first, since phis can refer to
+    // registers defined later on
     let mut next_expected_register = 0;
-    let mut result = Vec::with_capacity(body.len());
-    for instruction in body {
-        match instruction {
-            IrInstruction::Ret { reg } => {
-                // Ret is the only instruction that does increment next_expect_register
-                // because it does not alllocate a new register
-                result.push(IrInstruction::Ret {
-                    reg: register_replacement[reg.0],
-                });
-            }
-            IrInstruction::Mvi { dest, val } => {
-                if next_expected_register == dest.0 {
-                    result.push(instruction.clone());
-                } else {
-                    let replaced_register = IrRegister::new(next_expected_register);
-                    result.push(IrInstruction::Mvi {
-                        dest: replaced_register,
-                        val,
-                    });
-                    register_replacement[dest.0] = replaced_register;
-                }
-                next_expected_register += 1;
-            }
-            IrInstruction::MvArg { dest, arg } => {
-                if next_expected_register == dest.0 {
-                    result.push(instruction.clone());
-                } else {
-                    let replaced_register = IrRegister::new(next_expected_register);
-                    result.push(IrInstruction::MvArg {
-                        dest: replaced_register,
-                        arg,
-                    });
-                    register_replacement[dest.0] = replaced_register;
-                }
-                next_expected_register += 1;
-            }
-            IrInstruction::BinOp {
-                operator,
-                dest,
-                op1,
-                op2,
-            } => {
-                if next_expected_register == dest.0 {
-                    result.push(IrInstruction::BinOp {
-                        operator,
-                        dest,
-                        op1: register_replacement[op1.0],
-                        op2: register_replacement[op2.0],
-                    });
-                } else {
-                    let replaced_register = IrRegister::new(next_expected_register);
-                    result.push(IrInstruction::BinOp {
-                        operator,
-                        dest: replaced_register,
-                        op1: register_replacement[op1.0],
-                        op2: register_replacement[op2.0],
-                    });
-                    register_replacement[dest.0] = replaced_register;
-                }
-                next_expected_register += 1;
-            }
-            IrInstruction::Neg { dest, op } => {
-                if next_expected_register == dest.0 {
-                    result.push(IrInstruction::Neg {
-                        dest,
-                        op: register_replacement[op.0],
-                    });
-                } else {
-                    let replaced_register = IrRegister::new(next_expected_register);
-                    result.push(IrInstruction::Neg {
-                        dest: replaced_register,
-                        op: register_replacement[op.0],
-                    });
-                    register_replacement[dest.0] = replaced_register;
-                }
-                next_expected_register += 1;
-            }
-            IrInstruction::Call {
-                dest,
-                name,
-                function_id,
-                args,
-            } => {
-                let args = args
-                    .into_iter()
-                    .map(|arg| register_replacement[arg.0])
-                    .collect();
-
-                if next_expected_register == dest.0 {
-                    result.push(IrInstruction::Call {
-                        dest,
-                        name,
-                        function_id,
-                        args,
-                    });
-                } else {
-                    let replaced_register = IrRegister::new(next_expected_register);
-                    result.push(IrInstruction::Call {
-                        dest: replaced_register,
-                        name,
-                        function_id,
-                        args,
-                    });
-                    register_replacement[dest.0] = replaced_register;
-                }
-                next_expected_register += 1;
-            }
+    for instruction in blocks.iter().flat_map(|block| block.body.iter()) {
+        if let Some(dest) = instruction.dest() {
+            register_replacement[dest.0] = IrRegister::new(next_expected_register);
+            next_expected_register += 1;
         }
     }
 
+    // Removed registers are not in the replacement, so they are dropped here
+    let float_registers = blocks
+        .iter()
+        .flat_map(|block| block.body.iter())
+        .filter_map(|instruction| instruction.dest())
+        .filter(|dest| float_registers.contains(dest))
+        .map(|dest| register_replacement[dest.0])
+        .collect();
+
+    let blocks = blocks
+        .into_iter()
+        .map(|block| BasicBlock {
+            id: block.id,
+            body: block
+                .body
+                .iter()
+                .map(|instruction| instruction.map_registers(|reg| register_replacement[reg.0]))
+                .collect(),
+        })
+        .collect();
+
     OptimizedBody {
-        body: result,
+        blocks,
         num_used_registers: next_expected_register,
+        float_registers,
     }
 }
 
-fn optimize_fun_body(body: &[IrInstruction], num_used_registers: usize) -> OptimizedBody {
-    let body = propagate_constants(body, num_used_registers);
-    let body = deduplicate_constants(&body, num_used_registers);
-    let body = dead_store_elimination(&body, num_used_registers);
-    rename_registers(body, num_used_registers)
+fn optimize_fun_body(
+    blocks: &[BasicBlock],
+    num_used_registers: usize,
+    float_registers: &HashSet<IrRegister>,
+    overflow_mode: OverflowMode,
+) -> OptimizedBody {
+    let blocks = propagate_constants(blocks, num_used_registers, overflow_mode);
+    let blocks = deduplicate_constants(&blocks, num_used_registers, float_registers);
+    let blocks = dead_store_elimination(&blocks, num_used_registers, overflow_mode);
+    rename_registers(blocks, num_used_registers, float_registers)
 }
 
 pub fn optimize_fun<'a>(fun: &CompiledFunction<'a>) -> CompiledFunction<'a> {
     let OptimizedBody {
-        body,
+        blocks,
         num_used_registers,
-    } = optimize_fun_body(&fun.body, fun.num_used_registers);
+        float_registers,
+    } = optimize_fun_body(
+        &fun.blocks,
+        fun.num_used_registers,
+        &fun.float_registers,
+        fun.overflow_mode,
+    );
     CompiledFunction {
-        name: fun.name,
+        name: fun.name.clone(),
         id: fun.id,
-        num_args: fun.num_args,
-        body,
+        arg_classes: fun.arg_classes.clone(),
+        blocks,
         num_used_registers,
+        float_registers,
+        overflow_mode: fun.overflow_mode,
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::ir::builders::{add, call, mul, mvarg, mvi, ret};
+    use crate::ir::{
+        builders::{
+            abs, add, binop, block, br, call, call_host, cast, div, ftoi, itof, jmp, load, mul,
+            mvarg, mvi, not, phi, popcount, ret, sign, store, typed_binop,
+        },
+        BinOpOperator::*,
+    };
+    use crate::types::Type;
 
     use super::*;
 
@@ -404,18 +445,377 @@ mod tests {
             mvi(5, 5),
             add(6, 5, 3),
         ];
-        let optimized = propagate_constants(&body, 7);
+        let optimized = propagate_constants(&[block(0, body)], 7, OverflowMode::Wrapping);
 
         assert_eq!(
-            vec![
-                mvi(0, 1),
-                mvi(1, 2),
-                mvarg(2, 0),
-                mvi(3, 3),
-                add(4, 3, 2),
-                mvi(5, 5),
-                mvi(6, 8)
+            vec![block(
+                0,
+                vec![
+                    mvi(0, 1),
+                    mvi(1, 2),
+                    mvarg(2, 0),
+                    mvi(3, 3),
+                    add(4, 3, 2),
+                    mvi(5, 5),
+                    mvi(6, 8)
+                ]
+            )],
+            optimized,
+        );
+    }
+
+    #[test]
+    fn can_propagate_constants_through_comparisons() {
+        let body = vec![
+            mvi(0, 1),
+            mvi(1, 2),
+            binop(Equal, 2, 0, 1),
+            binop(NotEqual, 3, 0, 1),
+            binop(LessThan, 4, 0, 1),
+            binop(LessThanOrEqual, 5, 1, 1),
+            binop(GreaterThan, 6, 0, 1),
+            binop(GreaterThanOrEqual, 7, 0, 1),
+        ];
+        let optimized = propagate_constants(&[block(0, body)], 8, OverflowMode::Wrapping);
+
+        assert_eq!(
+            vec![block(
+                0,
+                vec![
+                    mvi(0, 1),
+                    mvi(1, 2),
+                    mvi(2, 0),
+                    mvi(3, 1),
+                    mvi(4, 1),
+                    mvi(5, 1),
+                    mvi(6, 0),
+                    mvi(7, 0),
+                ]
+            )],
+            optimized,
+        );
+    }
+
+    #[test]
+    fn can_propagate_constants_through_bitwise_operators() {
+        let body = vec![
+            mvi(0, 0b1100),
+            mvi(1, 0b1010),
+            binop(BitwiseAnd, 2, 0, 1),
+            binop(BitwiseOr, 3, 0, 1),
+            binop(BitwiseXor, 4, 0, 1),
+            not(5, 0),
+            binop(Rem, 6, 0, 1),
+        ];
+        let optimized = propagate_constants(&[block(0, body)], 7, OverflowMode::Wrapping);
+
+        assert_eq!(
+            vec![block(
+                0,
+                vec![
+                    mvi(0, 0b1100),
+                    mvi(1, 0b1010),
+                    mvi(2, 0b1000),
+                    mvi(3, 0b1110),
+                    mvi(4, 0b0110),
+                    mvi(5, -13),
+                    mvi(6, 2),
+                ]
+            )],
+            optimized,
+        );
+    }
+
+    #[test]
+    fn can_propagate_constants_through_intrinsics() {
+        let body = vec![
+            mvi(0, -12),
+            mvi(1, 18),
+            binop(Min, 2, 0, 1),
+            binop(Max, 3, 0, 1),
+            binop(Gcd, 4, 0, 1),
+            binop(Pow, 5, 0, 0),
+            abs(6, 0),
+            sign(7, 0),
+            popcount(8, 0),
+            typed_binop(Min, Type::U64, 9, 0, 1),
+        ];
+        let optimized = propagate_constants(&[block(0, body)], 10, OverflowMode::Wrapping);
+
+        assert_eq!(
+            vec![block(
+                0,
+                vec![
+                    mvi(0, -12),
+                    mvi(1, 18),
+                    mvi(2, -12),
+                    mvi(3, 18),
+                    mvi(4, 6),
+                    // A negative exponent is a huge even one
+                    mvi(5, 0),
+                    mvi(6, 12),
+                    mvi(7, -1),
+                    mvi(8, 61),
+                    mvi(9, 18),
+                ]
+            )],
+            optimized,
+        );
+    }
+
+    #[test]
+    fn folding_of_pow_follows_the_overflow_mode() {
+        let body = vec![
+            mvi(0, -2),
+            mvi(1, 63),
+            binop(Pow, 2, 0, 1),
+            mvi(3, 64),
+            binop(Pow, 4, 0, 3),
+        ];
+        let fold = |mode| {
+            propagate_constants(&[block(0, body.clone())], 5, mode)[0]
+                .body
+                .clone()
+        };
+
+        assert_eq!(mvi(2, i64::MIN), fold(OverflowMode::Wrapping)[2]);
+        assert_eq!(mvi(4, 0), fold(OverflowMode::Wrapping)[4]);
+        assert_eq!(mvi(4, i64::MAX), fold(OverflowMode::Saturating)[4]);
+        // -2^63 fits, and only the overflow is left to the generated code
+        assert_eq!(mvi(2, i64::MIN), fold(OverflowMode::Checked)[2]);
+        assert_eq!(binop(Pow, 4, 0, 3), fold(OverflowMode::Checked)[4]);
+    }
+
+    #[test]
+    fn folding_respects_the_types() {
+        let body = vec![
+            mvi(0, -2),
+            mvi(1, 2),
+            typed_binop(Div, Type::U64, 2, 0, 1),
+            typed_binop(ShiftRight, Type::U64, 3, 0, 1),
+            typed_binop(LessThan, Type::U64, 4, 1, 0),
+            cast(5, 0, Type::U8),
+            cast(6, 0, Type::Bool),
+        ];
+        let optimized = propagate_constants(&[block(0, body)], 7, OverflowMode::Wrapping);
+
+        assert_eq!(
+            vec![block(
+                0,
+                vec![
+                    mvi(0, -2),
+                    mvi(1, 2),
+                    mvi(2, i64::MAX),
+                    mvi(3, (u64::MAX >> 2) as i64),
+                    mvi(4, 1),
+                    mvi(5, 254),
+                    mvi(6, 1),
+                ]
+            )],
+            optimized,
+        );
+    }
+
+    #[test]
+    fn folding_wraps_around_and_leaves_invalid_divisions_alone() {
+        let body = vec![
+            mvi(0, i64::MAX),
+            mvi(1, 1),
+            mvi(2, 0),
+            mvi(3, i64::MIN),
+            mvi(4, -1),
+            add(5, 0, 1),
+            binop(Div, 6, 1, 2),
+            binop(Div, 7, 3, 4),
+            binop(Rem, 8, 1, 2),
+        ];
+        let optimized = propagate_constants(&[block(0, body)], 9, OverflowMode::Wrapping);
+
+        assert_eq!(
+            vec![block(
+                0,
+                vec![
+                    mvi(0, i64::MAX),
+                    mvi(1, 1),
+                    mvi(2, 0),
+                    mvi(3, i64::MIN),
+                    mvi(4, -1),
+                    mvi(5, i64::MIN),
+                    binop(Div, 6, 1, 2),
+                    binop(Div, 7, 3, 4),
+                    binop(Rem, 8, 1, 2),
+                ]
+            )],
+            optimized
+        );
+    }
+
+    #[test]
+    fn shifts_by_64_or_more_are_well_defined_when_folded() {
+        let body = vec![
+            mvi(0, -8),
+            mvi(1, 2),
+            mvi(2, 64),
+            mvi(3, -1),
+            binop(ShiftLeft, 4, 0, 1),
+            binop(ShiftRight, 5, 0, 1),
+            binop(ShiftLeft, 6, 0, 2),
+            binop(ShiftRight, 7, 0, 2),
+            binop(ShiftLeft, 8, 0, 3),
+            binop(ShiftRight, 9, 1, 3),
+        ];
+        let optimized = propagate_constants(&[block(0, body)], 10, OverflowMode::Wrapping);
+
+        assert_eq!(
+            vec![block(
+                0,
+                vec![
+                    mvi(0, -8),
+                    mvi(1, 2),
+                    mvi(2, 64),
+                    mvi(3, -1),
+                    mvi(4, -32),
+                    mvi(5, -2),
+                    mvi(6, 0),
+                    mvi(7, -1),
+                    mvi(8, 0),
+                    mvi(9, 0),
+                ]
+            )],
+            optimized,
+        );
+    }
+
+    #[test]
+    fn can_fold_floats_and_conversions() {
+        let float = |value: f64| value.to_bits() as i64;
+        let body = vec![
+            mvi(0, float(1.5)),
+            mvi(1, float(0.0)),
+            typed_binop(Div, Type::F64, 2, 0, 1),
+            mvi(3, float(f64::NAN)),
+            typed_binop(LessThan, Type::F64, 4, 1, 0),
+            typed_binop(Equal, Type::F64, 5, 3, 3),
+            ftoi(6, 2),
+            ftoi(7, 3),
+            mvi(8, -3),
+            itof(9, 8),
+        ];
+        let optimized = propagate_constants(&[block(0, body)], 10, OverflowMode::Wrapping);
+
+        assert_eq!(
+            vec![block(
+                0,
+                vec![
+                    mvi(0, float(1.5)),
+                    mvi(1, float(0.0)),
+                    mvi(2, float(f64::INFINITY)),
+                    mvi(3, float(f64::NAN)),
+                    mvi(4, 1),
+                    mvi(5, 0),
+                    mvi(6, i64::MAX),
+                    mvi(7, 0),
+                    mvi(8, -3),
+                    mvi(9, float(-3.0)),
+                ]
+            )],
+            optimized,
+        );
+    }
+
+    #[test]
+    fn folding_follows_the_overflow_mode() {
+        let body = vec![
+            mvi(0, i64::MAX),
+            mvi(1, 2),
+            mul(2, 0, 1),
+            mvi(3, 200),
+            typed_binop(Add, Type::U8, 4, 3, 3),
+            typed_binop(Sub, Type::U64, 5, 1, 3),
+            typed_binop(Mul, Type::I32, 6, 3, 1),
+        ];
+        let fold = |mode| match &propagate_constants(&[block(0, body.clone())], 7, mode)[0].body[..]
+        {
+            [_, _, result1, _, result2, result3, result4] => [
+                result1.clone(),
+                result2.clone(),
+                result3.clone(),
+                result4.clone(),
             ],
+            _ => unreachable!(),
+        };
+
+        assert_eq!(
+            fold(OverflowMode::Wrapping),
+            [mvi(2, -2), mvi(4, 400), mvi(5, -198), mvi(6, 400)]
+        );
+        assert_eq!(
+            fold(OverflowMode::Saturating),
+            [mvi(2, i64::MAX), mvi(4, 255), mvi(5, 0), mvi(6, 400)]
+        );
+        // Overflows are left to the generated code, which will trap
+        assert_eq!(
+            fold(OverflowMode::Checked),
+            [
+                mul(2, 0, 1),
+                typed_binop(Add, Type::U8, 4, 3, 3),
+                typed_binop(Sub, Type::U64, 5, 1, 3),
+                mvi(6, 400)
+            ]
+        );
+    }
+
+    #[test]
+    fn checked_arithmetic_is_never_dead() {
+        let body = vec![mvarg(0, 0), mvi(1, 1), add(2, 0, 1), ret(1)];
+        let optimized = dead_store_elimination(&[block(0, body.clone())], 3, OverflowMode::Checked);
+        assert_eq!(vec![block(0, body)], optimized);
+    }
+
+    #[test]
+    fn integer_divisions_are_never_dead() {
+        let body = vec![
+            mvarg(0, 0),
+            mvarg(1, 1),
+            div(2, 0, 1),
+            typed_binop(Div, Type::F64, 3, 0, 1),
+            ret(0),
+        ];
+        let optimized = dead_store_elimination(&[block(0, body)], 4, OverflowMode::Wrapping);
+        assert_eq!(
+            vec![block(
+                0,
+                vec![mvarg(0, 0), mvarg(1, 1), div(2, 0, 1), ret(0)]
+            )],
+            optimized
+        );
+    }
+
+    #[test]
+    fn floats_and_integers_with_the_same_bits_are_different_constants() {
+        let body = vec![
+            mvi(0, 0),
+            mvi(1, 0),
+            mvi(2, 0),
+            typed_binop(Add, Type::F64, 3, 1, 2),
+            add(4, 0, 0),
+        ];
+        let optimized = deduplicate_constants(
+            &[block(0, body)],
+            5,
+            &HashSet::from([IrRegister(1), IrRegister(2), IrRegister(3)]),
+        );
+
+        assert_eq!(
+            vec![block(
+                0,
+                vec![
+                    mvi(0, 0),
+                    mvi(1, 0),
+                    typed_binop(Add, Type::F64, 3, 1, 1),
+                    add(4, 0, 0),
+                ]
+            )],
             optimized,
         );
     }
@@ -429,15 +829,18 @@ mod tests {
             add(3, 1, 2),
             call(4, "f", 0, vec![3, 0, 2]),
         ];
-        let optimized = deduplicate_constants(&body, 4);
+        let optimized = deduplicate_constants(&[block(0, body)], 5, &HashSet::new());
 
         assert_eq!(
-            vec![
-                mvi(0, 1),
-                mvi(1, 2),
-                add(3, 1, 0),
-                call(4, "f", 0, vec![3, 0, 0])
-            ],
+            vec![block(
+                0,
+                vec![
+                    mvi(0, 1),
+                    mvi(1, 2),
+                    add(3, 1, 0),
+                    call(4, "f", 0, vec![3, 0, 0])
+                ]
+            )],
             optimized,
         );
     }
@@ -452,16 +855,50 @@ mod tests {
             call(4, "f", 0, vec![3]),
             ret(4),
         ];
-        let optimized = dead_store_elimination(&body, 5);
+        let optimized = dead_store_elimination(&[block(0, body)], 5, OverflowMode::Wrapping);
 
         assert_eq!(
-            vec![
-                mvi(0, 1),
-                mvi(1, 2),
-                add(3, 1, 0),
-                call(4, "f", 0, vec![3]),
-                ret(4)
-            ],
+            vec![block(
+                0,
+                vec![
+                    mvi(0, 1),
+                    mvi(1, 2),
+                    add(3, 1, 0),
+                    call(4, "f", 0, vec![3]),
+                    ret(4)
+                ]
+            )],
+            optimized,
+        );
+    }
+
+    #[test]
+    fn dead_store_elimination_keeps_instructions_with_side_effects() {
+        let body = vec![
+            mvarg(0, 0),
+            mvi(1, 1),
+            call(2, "f", 0, vec![0]),
+            call_host(3, "g", 0, vec![1]),
+            load(4, 0, 1),
+            store(0, 1, 1),
+            mvi(5, 2),
+            ret(1),
+        ];
+        let optimized = dead_store_elimination(&[block(0, body)], 6, OverflowMode::Wrapping);
+
+        assert_eq!(
+            vec![block(
+                0,
+                vec![
+                    mvarg(0, 0),
+                    mvi(1, 1),
+                    call(2, "f", 0, vec![0]),
+                    call_host(3, "g", 0, vec![1]),
+                    load(4, 0, 1),
+                    store(0, 1, 1),
+                    ret(1)
+                ]
+            )],
             optimized,
         );
     }
@@ -469,11 +906,14 @@ mod tests {
     #[test]
     fn can_rename_registers() {
         let body = vec![mvi(1, 1), add(3, 1, 1), call(4, "f", 0, vec![3])];
-        let optimized = rename_registers(body, 5);
+        let optimized = rename_registers(vec![block(0, body)], 5, &HashSet::new());
 
         assert_eq!(
-            vec![mvi(0, 1), add(1, 0, 0), call(2, "f", 0, vec![1])],
-            optimized.body,
+            vec![block(
+                0,
+                vec![mvi(0, 1), add(1, 0, 0), call(2, "f", 0, vec![1])]
+            )],
+            optimized.blocks,
         );
         assert_eq!(3, optimized.num_used_registers);
     }
@@ -489,9 +929,89 @@ mod tests {
             mvi(5, 42),
             ret(4),
         ];
-        let optimized = optimize_fun_body(&body, 6);
+        let optimized = optimize_fun_body(
+            &[block(0, body)],
+            6,
+            &HashSet::new(),
+            OverflowMode::Wrapping,
+        );
 
-        assert_eq!(vec![mvi(0, 9), ret(0)], optimized.body);
+        assert_eq!(vec![block(0, vec![mvi(0, 9), ret(0)])], optimized.blocks);
         assert_eq!(1, optimized.num_used_registers);
     }
+
+    #[test]
+    fn can_propagate_constants_through_phis() {
+        let blocks = vec![
+            block(0, vec![mvarg(0, 0), mvi(1, 1), br(0, 1, 2)]),
+            block(1, vec![mvi(2, 1), jmp(3)]),
+            block(2, vec![mvi(3, 2), jmp(3)]),
+            block(
+                3,
+                vec![phi(4, vec![(1, 2), (2, 1)]), phi(5, vec![(1, 2), (2, 3)])],
+            ),
+        ];
+        let optimized = propagate_constants(&blocks, 6, OverflowMode::Wrapping);
+
+        assert_eq!(
+            block(3, vec![mvi(4, 1), phi(5, vec![(1, 2), (2, 3)])]),
+            optimized[3]
+        );
+    }
+
+    #[test]
+    fn constants_are_deduplicated_only_within_a_block() {
+        let blocks = vec![
+            block(0, vec![mvarg(0, 0), mvi(1, 1), br(0, 1, 2)]),
+            block(1, vec![mvi(2, 1), mvi(3, 1), add(4, 2, 3), ret(4)]),
+            block(2, vec![mvi(5, 1), ret(5)]),
+        ];
+        let optimized = deduplicate_constants(&blocks, 6, &HashSet::new());
+
+        assert_eq!(
+            vec![
+                block(0, vec![mvarg(0, 0), mvi(1, 1), br(0, 1, 2)]),
+                block(1, vec![mvi(2, 1), add(4, 2, 2), ret(4)]),
+                block(2, vec![mvi(5, 1), ret(5)]),
+            ],
+            optimized
+        );
+    }
+
+    #[test]
+    fn dead_store_elimination_keeps_values_used_by_branches_and_phis() {
+        let blocks = vec![
+            block(0, vec![mvarg(0, 0), mvi(1, 1), mvi(2, 7), br(0, 1, 2)]),
+            block(1, vec![mvi(3, 2), jmp(2)]),
+            block(2, vec![phi(4, vec![(1, 3), (0, 1)]), ret(4)]),
+        ];
+        let optimized = dead_store_elimination(&blocks, 5, OverflowMode::Wrapping);
+
+        assert_eq!(
+            vec![
+                block(0, vec![mvarg(0, 0), mvi(1, 1), br(0, 1, 2)]),
+                block(1, vec![mvi(3, 2), jmp(2)]),
+                block(2, vec![phi(4, vec![(1, 3), (0, 1)]), ret(4)]),
+            ],
+            optimized
+        );
+    }
+
+    #[test]
+    fn can_rename_registers_referenced_by_phis_before_definition() {
+        let blocks = vec![
+            block(0, vec![mvi(3, 0), jmp(1)]),
+            block(1, vec![phi(5, vec![(0, 3), (1, 7)]), mvi(7, 1), jmp(1)]),
+        ];
+        let optimized = rename_registers(blocks, 8, &HashSet::new());
+
+        assert_eq!(
+            vec![
+                block(0, vec![mvi(0, 0), jmp(1)]),
+                block(1, vec![phi(1, vec![(0, 0), (1, 2)]), mvi(2, 1), jmp(1)]),
+            ],
+            optimized.blocks
+        );
+        assert_eq!(3, optimized.num_used_registers);
+    }
 }
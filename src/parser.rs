@@ -1,37 +1,237 @@
-use pest::error::Error;
-use pest::iterators::Pair;
+use pest::error::{Error, ErrorVariant};
+use pest::iterators::{Pair, Pairs};
 use pest::Parser;
+use std::iter::Peekable;
 use thiserror::Error;
 use tracing::debug;
 
-use crate::ast::{Block, BlockElement, Expression, Function, FunctionCall, Program};
+use crate::ast::{
+    Block, BlockElement, BlockElementKind, Constant, Expression, ExpressionKind, ExternFunction,
+    Function, FunctionArgument, FunctionCall, Import, MatchArm, MatchPattern, Program, Span, Type,
+};
 use crate::grammar::{EmjayGrammar, Rule};
 
-fn parse_expression(rule: Pair<'_, Rule>) -> Expression {
+fn span_of(rule: &Pair<'_, Rule>) -> Span {
+    let span = rule.as_span();
+    Span::new(span.start(), span.end())
+}
+
+/// Errors found after the grammar has matched, such as invalid literals
+type ParseResult<T> = Result<T, Box<ParseError>>;
+
+fn error_at(rule: &Pair<'_, Rule>, message: String) -> Box<ParseError> {
+    Box::new(ParseError::from(Error::new_from_span(
+        ErrorVariant::CustomError { message },
+        rule.as_span(),
+    )))
+}
+
+/// Parses an integer literal, which can be in decimal, hexadecimal (`0x`), binary (`0b`)
+/// or octal (`0o`), can contain underscores and can have a leading minus sign. The sign
+/// is part of the literal, so that `i64::MIN` can be written.
+fn parse_number(rule: &Pair<'_, Rule>) -> ParseResult<i64> {
+    let (radix, digits) = literal_digits(rule.as_str());
+    i64::from_str_radix(&digits, radix).map_err(|_| out_of_range(rule, i64::MAX.into()))
+}
+
+/// Parses an integer literal used as an expression. Unlike in the other places, it
+/// can also be above `i64::MAX`, in which case only a `u64` can hold it
+fn parse_integer_literal<'a>(rule: &Pair<'a, Rule>) -> ParseResult<ExpressionKind<'a>> {
+    let (radix, digits) = literal_digits(rule.as_str());
+    if let Ok(value) = i64::from_str_radix(&digits, radix) {
+        return Ok(ExpressionKind::Number(value));
+    }
+    u64::from_str_radix(&digits, radix)
+        .map(|value| ExpressionKind::UnsignedNumber(value as i64))
+        .map_err(|_| out_of_range(rule, u64::MAX.into()))
+}
+
+/// The radix of an integer literal and its digits, preceded by the sign if any
+fn literal_digits(text: &str) -> (u32, String) {
+    let (sign, unsigned) = match text.strip_prefix('-') {
+        Some(unsigned) => ("-", unsigned),
+        None => ("", text),
+    };
+    let (radix, digits) = match unsigned.get(..2) {
+        Some("0x") => (16, &unsigned[2..]),
+        Some("0b") => (2, &unsigned[2..]),
+        Some("0o") => (8, &unsigned[2..]),
+        _ => (10, unsigned),
+    };
+    (radix, format!("{}{}", sign, digits.replace('_', "")))
+}
+
+fn out_of_range(rule: &Pair<'_, Rule>, max: i128) -> Box<ParseError> {
+    error_at(
+        rule,
+        format!(
+            "integer literal {} is out of range, it must be between {} and {}",
+            rule.as_str(),
+            i64::MIN,
+            max
+        ),
+    )
+}
+
+/// Parses a floating-point literal, such as `1.5`, `-0.25` or `1e-3`. Literals whose
+/// magnitude is too big to be represented are errors, rather than infinities.
+fn parse_float(rule: &Pair<'_, Rule>) -> ParseResult<f64> {
+    let text = rule.as_str();
+    match text.replace('_', "").parse::<f64>() {
+        Ok(value) if value.is_finite() => Ok(value),
+        _ => Err(error_at(
+            rule,
+            format!("float literal {} is out of range", text),
+        )),
+    }
+}
+
+/// Parses the name of a type. Types are not keywords, so the grammar accepts any name
+fn parse_type(rule: &Pair<'_, Rule>) -> ParseResult<Type> {
+    Type::from_name(rule.as_str()).ok_or_else(|| {
+        error_at(
+            rule,
+            format!(
+                "unknown type {}, expected one of i64, i32, u64, u32, u8, f64 or bool",
+                rule.as_str()
+            ),
+        )
+    })
+}
+
+fn parse_expression(rule: Pair<'_, Rule>) -> ParseResult<Expression<'_>> {
     let pratt = crate::grammar::pratt_parser();
     pratt
-        .map_primary(|primary| match primary.as_rule() {
-            Rule::number => Expression::Number(primary.as_str().parse().unwrap()),
-            Rule::identifier => Expression::Identifier(primary.as_str()),
-            Rule::expression => parse_expression(primary),
-            Rule::functionCall => Expression::FunctionCall(parse_function_call(primary)),
-            _ => unreachable!(""),
+        .map_primary(|primary| {
+            let span = span_of(&primary);
+            let kind = match primary.as_rule() {
+                Rule::number => parse_integer_literal(&primary)?,
+                Rule::float => ExpressionKind::Float(parse_float(&primary)?),
+                Rule::boolean => ExpressionKind::Boolean(primary.as_str() == "true"),
+                Rule::identifier => ExpressionKind::Identifier(primary.as_str()),
+                Rule::parenthesized => {
+                    // Keep the parentheses in the span, so that it covers the whole
+                    // operand when this expression is used in a bigger one
+                    let inner = parse_expression(primary.into_inner().next().unwrap())?;
+                    return Ok(Expression { span, ..inner });
+                }
+                Rule::functionCall => ExpressionKind::FunctionCall(parse_function_call(primary)?),
+                Rule::matchExpression => parse_match(primary)?,
+                Rule::functionHandle => {
+                    ExpressionKind::FunctionHandle(primary.into_inner().next().unwrap().as_str())
+                }
+                _ => unreachable!(""),
+            };
+            Ok(Expression {
+                kind,
+                span,
+                ty: Type::default(),
+            })
         })
-        .map_prefix(|prefix, right| match prefix.as_rule() {
-            Rule::neg => Expression::Negate(Box::new(right)),
-            _ => unreachable!(),
+        .map_prefix(|prefix, right| {
+            let right = right?;
+            let span = span_of(&prefix).to(right.span);
+            let right = Box::new(right);
+            let kind = match prefix.as_rule() {
+                Rule::neg => ExpressionKind::Negate(right),
+                Rule::not => ExpressionKind::LogicalNot(right),
+                Rule::bitNot => ExpressionKind::BitwiseNot(right),
+                _ => unreachable!(),
+            };
+            Ok(Expression {
+                kind,
+                span,
+                ty: Type::default(),
+            })
         })
-        .map_infix(|left, op, right| match op.as_rule() {
-            Rule::add => Expression::Add(Box::new(left), Box::new(right)),
-            Rule::sub => Expression::Sub(Box::new(left), Box::new(right)),
-            Rule::mul => Expression::Mul(Box::new(left), Box::new(right)),
-            Rule::div => Expression::Div(Box::new(left), Box::new(right)),
-            _ => unreachable!(),
+        .map_postfix(|left, postfix| {
+            let left = left?;
+            let span = left.span.to(span_of(&postfix));
+            let ty = parse_type(&postfix.into_inner().next().unwrap())?;
+            Ok(Expression {
+                kind: ExpressionKind::Cast(Box::new(left), ty),
+                span,
+                ty: Type::default(),
+            })
         })
+        .map_infix(|left, op, right| Ok(binary_expression(op.as_rule(), left?, right?)))
         .parse(rule.into_inner())
 }
 
-fn parse_function_call(rule: Pair<'_, Rule>) -> FunctionCall {
+fn binary_expression<'input>(
+    operator: Rule,
+    left: Expression<'input>,
+    right: Expression<'input>,
+) -> Expression<'input> {
+    let span = left.span.to(right.span);
+    let (left, right) = (Box::new(left), Box::new(right));
+    let kind = match operator {
+        Rule::add => ExpressionKind::Add(left, right),
+        Rule::sub => ExpressionKind::Sub(left, right),
+        Rule::mul => ExpressionKind::Mul(left, right),
+        Rule::div => ExpressionKind::Div(left, right),
+        Rule::rem => ExpressionKind::Rem(left, right),
+        Rule::bitAnd => ExpressionKind::BitwiseAnd(left, right),
+        Rule::bitOr => ExpressionKind::BitwiseOr(left, right),
+        Rule::bitXor => ExpressionKind::BitwiseXor(left, right),
+        Rule::shl => ExpressionKind::ShiftLeft(left, right),
+        Rule::shr => ExpressionKind::ShiftRight(left, right),
+        Rule::eq => ExpressionKind::Equal(left, right),
+        Rule::ne => ExpressionKind::NotEqual(left, right),
+        Rule::lt => ExpressionKind::LessThan(left, right),
+        Rule::le => ExpressionKind::LessThanOrEqual(left, right),
+        Rule::gt => ExpressionKind::GreaterThan(left, right),
+        Rule::ge => ExpressionKind::GreaterThanOrEqual(left, right),
+        Rule::and => ExpressionKind::LogicalAnd(left, right),
+        Rule::or => ExpressionKind::LogicalOr(left, right),
+        _ => unreachable!(),
+    };
+    Expression {
+        kind,
+        span,
+        ty: Type::default(),
+    }
+}
+
+fn parse_match(rule: Pair<'_, Rule>) -> ParseResult<ExpressionKind<'_>> {
+    let mut inner = rule.into_inner();
+    let scrutinee = parse_expression(inner.next().unwrap())?;
+    let arms = inner
+        .map(|arm| {
+            let start = span_of(&arm).start;
+            let mut arm_inner = arm.into_inner();
+            let patterns = arm_inner.next().unwrap();
+            let patterns = match patterns.as_rule() {
+                Rule::wildcard => None,
+                Rule::matchValues => Some(
+                    patterns
+                        .into_inner()
+                        .map(|value| {
+                            Ok(MatchPattern {
+                                value: parse_number(&value)?,
+                                span: span_of(&value),
+                            })
+                        })
+                        .collect::<ParseResult<_>>()?,
+                ),
+                _ => unreachable!(),
+            };
+            // The rule of the arm also includes the whitespace following its expression
+            let expression = parse_expression(arm_inner.next().unwrap())?;
+            Ok(MatchArm {
+                patterns,
+                span: Span::new(start, expression.span.end),
+                expression,
+            })
+        })
+        .collect::<ParseResult<_>>()?;
+    Ok(ExpressionKind::Match {
+        scrutinee: Box::new(scrutinee),
+        arms,
+    })
+}
+
+fn parse_function_call(rule: Pair<'_, Rule>) -> ParseResult<FunctionCall<'_>> {
     let mut inner = rule.into_inner();
     let name = inner.next().unwrap().as_str();
     let args = inner
@@ -39,53 +239,231 @@ fn parse_function_call(rule: Pair<'_, Rule>) -> FunctionCall {
         .unwrap()
         .into_inner()
         .map(parse_expression)
-        .collect();
-    FunctionCall { name, args }
+        .collect::<ParseResult<_>>()?;
+    Ok(FunctionCall { name, args })
 }
 
-fn parse_statement_let(rule: Pair<'_, Rule>) -> BlockElement {
+fn parse_statement_let(rule: Pair<'_, Rule>) -> ParseResult<BlockElementKind<'_>> {
     let mut inner = rule.into_inner();
     let name = inner.next().unwrap().as_str();
-    let expression = parse_expression(inner.next().unwrap());
-    BlockElement::LetStatement { name, expression }
+    let mut next = inner.next().unwrap();
+    let ty = if next.as_rule() == Rule::typeName {
+        let ty = parse_type(&next)?;
+        next = inner.next().unwrap();
+        Some(ty)
+    } else {
+        None
+    };
+    let expression = parse_expression(next)?;
+    Ok(BlockElementKind::LetStatement {
+        name,
+        ty,
+        expression,
+    })
 }
 
-fn parse_statement_assignment(rule: Pair<'_, Rule>) -> BlockElement {
+fn parse_statement_assignment(rule: Pair<'_, Rule>) -> ParseResult<BlockElementKind<'_>> {
     let mut inner = rule.into_inner();
-    let name = inner.next().unwrap().as_str();
-    let expression = parse_expression(inner.next().unwrap());
-    BlockElement::AssignmentStatement { name, expression }
+    let target = inner.next().unwrap();
+    let operator = inner.next().unwrap().into_inner().next();
+    let mut expression = parse_expression(inner.next().unwrap())?;
+    // A compound assignment `x op= y` is simply `x = x op y`
+    if let Some(operator) = operator {
+        expression = binary_expression(operator.as_rule(), identifier(&target), expression);
+    }
+    Ok(BlockElementKind::AssignmentStatement {
+        name: target.as_str(),
+        expression,
+    })
 }
 
-fn parse_statement_return(rule: Pair<'_, Rule>) -> BlockElement {
+/// Parses `x++` as `x = x + 1` and `x--` as `x = x - 1`
+fn parse_statement_increment(rule: Pair<'_, Rule>) -> ParseResult<BlockElementKind<'_>> {
     let mut inner = rule.into_inner();
-    let expression = parse_expression(inner.next().unwrap());
-    BlockElement::ReturnStatement(expression)
+    let target = inner.next().unwrap();
+    let operator = inner.next().unwrap();
+    let operator_rule = match operator.as_rule() {
+        Rule::increment => Rule::add,
+        Rule::decrement => Rule::sub,
+        _ => unreachable!(),
+    };
+    let one = Expression {
+        kind: ExpressionKind::Number(1),
+        span: span_of(&operator),
+        ty: Type::default(),
+    };
+    Ok(BlockElementKind::AssignmentStatement {
+        name: target.as_str(),
+        expression: binary_expression(operator_rule, identifier(&target), one),
+    })
 }
 
-fn parse_block(rule: Pair<'_, Rule>) -> Block {
-    rule.into_inner()
-        .map(|statement| match statement.as_rule() {
-            Rule::letStatement => parse_statement_let(statement),
-            Rule::assignmentStatement => parse_statement_assignment(statement),
-            Rule::returnStatement => parse_statement_return(statement),
-            Rule::block => BlockElement::NestedBlock(parse_block(statement)),
+fn identifier<'input>(rule: &Pair<'input, Rule>) -> Expression<'input> {
+    Expression {
+        kind: ExpressionKind::Identifier(rule.as_str()),
+        span: span_of(rule),
+        ty: Type::default(),
+    }
+}
+
+fn parse_statement_return(rule: Pair<'_, Rule>) -> ParseResult<BlockElementKind<'_>> {
+    let mut inner = rule.into_inner();
+    let expression = parse_expression(inner.next().unwrap())?;
+    Ok(BlockElementKind::ReturnStatement(expression))
+}
+
+fn parse_statement_if(rule: Pair<'_, Rule>) -> ParseResult<BlockElementKind<'_>> {
+    let mut inner = rule.into_inner();
+    let condition = parse_expression(inner.next().unwrap())?;
+    let then_block = parse_block(inner.next().unwrap())?;
+    let else_block = inner
+        .next()
+        .map(|else_rule| match else_rule.as_rule() {
+            // An `else if` is simply an `else` whose block contains only another `if`
+            Rule::ifStatement => Ok(vec![BlockElement {
+                span: span_of(&else_rule),
+                kind: parse_statement_if(else_rule)?,
+            }]),
+            Rule::block => parse_block(else_rule),
             _ => unreachable!(),
         })
+        .transpose()?;
+    Ok(BlockElementKind::IfStatement {
+        condition,
+        then_block,
+        else_block,
+    })
+}
+
+fn parse_statement_while(rule: Pair<'_, Rule>) -> ParseResult<BlockElementKind<'_>> {
+    let mut inner = rule.into_inner();
+    let condition = parse_expression(inner.next().unwrap())?;
+    let body = parse_block(inner.next().unwrap())?;
+    Ok(BlockElementKind::WhileStatement { condition, body })
+}
+
+fn parse_block(rule: Pair<'_, Rule>) -> ParseResult<Block<'_>> {
+    rule.into_inner()
+        .map(|statement| {
+            let span = span_of(&statement);
+            let kind = match statement.as_rule() {
+                Rule::letStatement => parse_statement_let(statement)?,
+                Rule::assignmentStatement => parse_statement_assignment(statement)?,
+                Rule::incrementStatement => parse_statement_increment(statement)?,
+                Rule::returnStatement => parse_statement_return(statement)?,
+                Rule::ifStatement => parse_statement_if(statement)?,
+                Rule::whileStatement => parse_statement_while(statement)?,
+                Rule::breakStatement => BlockElementKind::BreakStatement,
+                Rule::continueStatement => BlockElementKind::ContinueStatement,
+                Rule::expressionStatement => BlockElementKind::ExpressionStatement(
+                    parse_expression(statement.into_inner().next().unwrap())?,
+                ),
+                Rule::block => BlockElementKind::NestedBlock(parse_block(statement)?),
+                _ => unreachable!(),
+            };
+            Ok(BlockElement { kind, span })
+        })
         .collect()
 }
 
-fn parse_function(rule: Pair<'_, Rule>) -> Function {
-    let mut rule = rule.into_inner();
-    let name = rule.next().unwrap().as_str();
-    let args = rule
-        .next()
-        .unwrap()
-        .into_inner()
-        .map(|arg| arg.as_str())
-        .collect();
-    let block = parse_block(rule.next().unwrap());
-    Function { name, args, block }
+/// Consumes the `///` comments at the start of a declaration, returning their text
+fn parse_doc_comment<'input>(rule: &mut Peekable<Pairs<'input, Rule>>) -> Option<String> {
+    let mut doc_lines = Vec::new();
+    while let Some(doc_comment) = rule.next_if(|pair| pair.as_rule() == Rule::docComment) {
+        let text = doc_comment.into_inner().next().unwrap().as_str();
+        // Like in Rust, `/// text` is the preferred style, so we drop the first space
+        doc_lines.push(text.strip_prefix(' ').unwrap_or(text));
+    }
+    (!doc_lines.is_empty()).then(|| doc_lines.join("\n"))
+}
+
+fn parse_function_arguments(rule: Pair<'_, Rule>) -> ParseResult<Vec<FunctionArgument<'_>>> {
+    rule.into_inner()
+        .map(|arg| {
+            let mut inner = arg.into_inner();
+            let name = inner.next().unwrap();
+            let ty = match inner.next() {
+                Some(ty) => parse_type(&ty)?,
+                None => Type::default(),
+            };
+            Ok(FunctionArgument {
+                name: name.as_str(),
+                ty,
+                span: span_of(&name),
+            })
+        })
+        .collect()
+}
+
+/// Parses the optional `-> type` of a function. Without it, the function returns `i64`
+fn parse_return_type(rule: &mut Peekable<Pairs<'_, Rule>>) -> ParseResult<Type> {
+    match rule.next_if(|pair| pair.as_rule() == Rule::returnType) {
+        Some(return_type) => parse_type(&return_type.into_inner().next().unwrap()),
+        None => Ok(Type::default()),
+    }
+}
+
+fn parse_function(rule: Pair<'_, Rule>) -> ParseResult<Function<'_>> {
+    let span = span_of(&rule);
+    let mut rule = rule.into_inner().peekable();
+    let doc_comment = parse_doc_comment(&mut rule);
+
+    let name = rule.next().unwrap();
+    let args = parse_function_arguments(rule.next().unwrap())?;
+    let return_type = parse_return_type(&mut rule)?;
+    let block = parse_block(rule.next().unwrap())?;
+    Ok(Function {
+        name: name.as_str(),
+        args,
+        return_type,
+        block,
+        span,
+        name_span: span_of(&name),
+        doc_comment,
+    })
+}
+
+fn parse_extern_function(rule: Pair<'_, Rule>) -> ParseResult<ExternFunction<'_>> {
+    let span = span_of(&rule);
+    let mut rule = rule.into_inner().peekable();
+    let doc_comment = parse_doc_comment(&mut rule);
+
+    let name = rule.next().unwrap();
+    let args = parse_function_arguments(rule.next().unwrap())?;
+    let return_type = parse_return_type(&mut rule)?;
+    Ok(ExternFunction {
+        name: name.as_str(),
+        args,
+        return_type,
+        span,
+        name_span: span_of(&name),
+        doc_comment,
+    })
+}
+
+fn parse_constant(rule: Pair<'_, Rule>) -> ParseResult<Constant<'_>> {
+    let span = span_of(&rule);
+    let mut rule = rule.into_inner().peekable();
+    let doc_comment = parse_doc_comment(&mut rule);
+
+    let name = rule.next().unwrap();
+    let expression = parse_expression(rule.next().unwrap())?;
+    Ok(Constant {
+        name: name.as_str(),
+        expression,
+        span,
+        name_span: span_of(&name),
+        doc_comment,
+    })
+}
+
+/// Parses an `importDeclaration`, whose span is the one of the quoted path
+fn parse_import(rule: Pair<'_, Rule>) -> Import<'_> {
+    let path = rule.into_inner().next().unwrap();
+    Import {
+        span: span_of(&path),
+        path: path.into_inner().next().unwrap().as_str(),
+    }
 }
 
 #[derive(Debug, Error)]
@@ -95,35 +473,195 @@ pub struct ParseError {
     wrapped: Error<Rule>,
 }
 
-pub fn parse_program(program: &str) -> Result<Program, Box<ParseError>> {
+impl ParseError {
+    /// Sets the name of the file that was parsed, so that it appears in the error message
+    pub fn with_path(self, path: &str) -> Self {
+        Self {
+            wrapped: self.wrapped.with_path(path),
+        }
+    }
+}
+
+/// Formats a message pointing at the given span of the source, underlining it the same
+/// way pest does for syntax errors
+pub fn format_error_at(source: &str, path: &str, span: Span, message: &str) -> String {
+    let variant = ErrorVariant::<Rule>::CustomError {
+        message: message.to_string(),
+    };
+    let error = match pest::Span::new(source, span.start, span.end) {
+        Some(pest_span) => Error::new_from_span(variant, pest_span),
+        None => Error::new_from_pos(variant, pest::Position::from_start(source)),
+    };
+    error.with_path(path).to_string()
+}
+
+pub fn parse_program(program: &str) -> Result<Program<'_>, Box<ParseError>> {
     let mut parsed = EmjayGrammar::parse(Rule::program, program).map_err(ParseError::from)?;
     let parsed = parsed.next().unwrap();
 
-    let mut functions: Program = Default::default();
+    let mut program: Program = Default::default();
     for rule in parsed.into_inner() {
         match rule.as_rule() {
+            Rule::importDeclaration => program.imports.push(parse_import(rule)),
             Rule::functionDeclaration => {
-                let function = parse_function(rule);
+                let function = parse_function(rule)?;
                 debug!("ast: {:?}", function);
-                functions.push(function);
+                program.functions.push(function);
+            }
+            Rule::externFunctionDeclaration => {
+                let extern_function = parse_extern_function(rule)?;
+                debug!("ast: {:?}", extern_function);
+                program.extern_functions.push(extern_function);
+            }
+            Rule::constDeclaration => {
+                let constant = parse_constant(rule)?;
+                debug!("ast: {:?}", constant);
+                program.constants.push(constant);
             }
             Rule::EOI => {}
             _ => unreachable!(),
         }
     }
-    Ok(functions)
+    Ok(program)
+}
+
+/// Parses just the imports at the beginning of a file, which is all that is needed to
+/// find the other files of a program before parsing them. It never fails: the errors
+/// in the rest of the file are reported when it is parsed for real
+pub fn parse_imports(source: &str) -> Vec<Import<'_>> {
+    EmjayGrammar::parse(Rule::imports, source)
+        .map(|mut parsed| {
+            parsed
+                .next()
+                .unwrap()
+                .into_inner()
+                .map(parse_import)
+                .collect()
+        })
+        .unwrap_or_default()
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{
-        ast::{BlockElement, Expression, Function, FunctionCall},
-        parser::parse_program,
+        ast::{
+            Block, BlockElement, BlockElementKind, Expression, ExpressionKind, FunctionCall,
+            Import, MatchArm, MatchPattern, Span, Type,
+        },
+        parser::{format_error_at, parse_imports, parse_program},
     };
 
+    fn expr(kind: ExpressionKind) -> Expression {
+        Expression {
+            kind,
+            span: Span::default(),
+            ty: Type::default(),
+        }
+    }
+
+    fn boxed(kind: ExpressionKind) -> Box<Expression> {
+        Box::new(expr(kind))
+    }
+
+    fn stmt(kind: BlockElementKind) -> BlockElement {
+        BlockElement {
+            kind,
+            span: Span::default(),
+        }
+    }
+
+    /// Resets all the spans in the given block, so that we can compare just the structure
+    /// of the parsed AST
+    fn without_spans(mut block: Block) -> Block {
+        clear_block_spans(&mut block);
+        block
+    }
+
+    fn clear_block_spans(block: &mut Block) {
+        for element in block.iter_mut() {
+            element.span = Span::default();
+            match &mut element.kind {
+                BlockElementKind::LetStatement { expression, .. }
+                | BlockElementKind::AssignmentStatement { expression, .. }
+                | BlockElementKind::ReturnStatement(expression)
+                | BlockElementKind::ExpressionStatement(expression) => {
+                    clear_expression_spans(expression)
+                }
+                BlockElementKind::IfStatement {
+                    condition,
+                    then_block,
+                    else_block,
+                } => {
+                    clear_expression_spans(condition);
+                    clear_block_spans(then_block);
+                    if let Some(else_block) = else_block {
+                        clear_block_spans(else_block);
+                    }
+                }
+                BlockElementKind::WhileStatement { condition, body } => {
+                    clear_expression_spans(condition);
+                    clear_block_spans(body);
+                }
+                BlockElementKind::NestedBlock(nested) => clear_block_spans(nested),
+                BlockElementKind::BreakStatement | BlockElementKind::ContinueStatement => {}
+            }
+        }
+    }
+
+    fn clear_expression_spans(expression: &mut Expression) {
+        expression.span = Span::default();
+        match &mut expression.kind {
+            ExpressionKind::Identifier(_)
+            | ExpressionKind::Number(_)
+            | ExpressionKind::UnsignedNumber(_)
+            | ExpressionKind::Float(_)
+            | ExpressionKind::Boolean(_)
+            | ExpressionKind::FunctionHandle(_) => {}
+            ExpressionKind::Negate(op)
+            | ExpressionKind::BitwiseNot(op)
+            | ExpressionKind::LogicalNot(op)
+            | ExpressionKind::Cast(op, _) => clear_expression_spans(op),
+            ExpressionKind::Add(left, right)
+            | ExpressionKind::Sub(left, right)
+            | ExpressionKind::Mul(left, right)
+            | ExpressionKind::Div(left, right)
+            | ExpressionKind::Rem(left, right)
+            | ExpressionKind::BitwiseAnd(left, right)
+            | ExpressionKind::BitwiseOr(left, right)
+            | ExpressionKind::BitwiseXor(left, right)
+            | ExpressionKind::ShiftLeft(left, right)
+            | ExpressionKind::ShiftRight(left, right)
+            | ExpressionKind::Equal(left, right)
+            | ExpressionKind::NotEqual(left, right)
+            | ExpressionKind::LessThan(left, right)
+            | ExpressionKind::LessThanOrEqual(left, right)
+            | ExpressionKind::GreaterThan(left, right)
+            | ExpressionKind::GreaterThanOrEqual(left, right)
+            | ExpressionKind::LogicalAnd(left, right)
+            | ExpressionKind::LogicalOr(left, right) => {
+                clear_expression_spans(left);
+                clear_expression_spans(right);
+            }
+            ExpressionKind::FunctionCall(call) => {
+                call.args.iter_mut().for_each(clear_expression_spans)
+            }
+            ExpressionKind::Match { scrutinee, arms } => {
+                clear_expression_spans(scrutinee);
+                for arm in arms.iter_mut() {
+                    arm.span = Span::default();
+                    arm.patterns
+                        .iter_mut()
+                        .flatten()
+                        .for_each(|pattern| pattern.span = Span::default());
+                    clear_expression_spans(&mut arm.expression);
+                }
+            }
+        }
+    }
+
     #[test]
     fn can_parse_program() {
-        let program = parse_program(
+        let mut program = parse_program(
             r"fn foo(y, w) {
             let x = -y + 3 * (z(2, w) - 1);
             {
@@ -133,39 +671,647 @@ mod tests {
         }",
         )
         .expect("should have been able to parse program");
+        assert_eq!(1, program.functions.len());
+        let function = program.functions.remove(0);
+        assert_eq!("foo", function.name);
         assert_eq!(
-            vec![Function {
-                name: "foo",
-                args: vec!["y", "w"],
-                block: vec![
-                    BlockElement::LetStatement {
-                        name: "x",
-                        expression: Expression::Add(
-                            Box::new(Expression::Negate(Box::new(Expression::Identifier("y")))),
-                            Box::new(Expression::Mul(
-                                Box::new(Expression::Number(3)),
-                                Box::new(Expression::Sub(
-                                    Box::new(Expression::FunctionCall(FunctionCall {
-                                        name: "z",
-                                        args: vec![
-                                            Expression::Number(2),
-                                            Expression::Identifier("w")
-                                        ]
-                                    })),
-                                    Box::new(Expression::Number(1))
-                                ))
+            vec!["y", "w"],
+            function.args.iter().map(|arg| arg.name).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![
+                stmt(BlockElementKind::LetStatement {
+                    name: "x",
+                    ty: None,
+                    expression: expr(ExpressionKind::Add(
+                        boxed(ExpressionKind::Negate(boxed(ExpressionKind::Identifier(
+                            "y"
+                        )))),
+                        boxed(ExpressionKind::Mul(
+                            boxed(ExpressionKind::Number(3)),
+                            boxed(ExpressionKind::Sub(
+                                boxed(ExpressionKind::FunctionCall(FunctionCall {
+                                    name: "z",
+                                    args: vec![
+                                        expr(ExpressionKind::Number(2)),
+                                        expr(ExpressionKind::Identifier("w"))
+                                    ]
+                                })),
+                                boxed(ExpressionKind::Number(1))
                             ))
-                        )
-                    },
-                    BlockElement::NestedBlock(vec![BlockElement::LetStatement {
+                        ))
+                    ))
+                }),
+                stmt(BlockElementKind::NestedBlock(vec![stmt(
+                    BlockElementKind::LetStatement {
                         name: "z",
-                        expression: Expression::Number(42)
-                    }]),
-                    BlockElement::ReturnStatement(Expression::Identifier("x")),
-                ]
-            }],
+                        ty: None,
+                        expression: expr(ExpressionKind::Number(42))
+                    }
+                )])),
+                stmt(BlockElementKind::ReturnStatement(expr(
+                    ExpressionKind::Identifier("x")
+                ))),
+            ],
+            without_spans(function.block)
+        );
+    }
+
+    #[test]
+    fn can_record_spans() {
+        let source = "fn f(a) {\n  let x = -a + g(a, 2);\n  return x;\n}";
+        let program = parse_program(source).expect("should have been able to parse program");
+        let text = |span: Span| &source[span.start..span.end];
+
+        let function = &program.functions[0];
+        assert_eq!(source, text(function.span));
+        assert_eq!("f", text(function.name_span));
+        assert_eq!("a", text(function.args[0].span));
+
+        let let_statement = &function.block[0];
+        assert_eq!("let x = -a + g(a, 2)", text(let_statement.span));
+        let BlockElementKind::LetStatement { expression, .. } = &let_statement.kind else {
+            panic!("expected a let statement");
+        };
+        assert_eq!("-a + g(a, 2)", text(expression.span));
+        let ExpressionKind::Add(left, right) = &expression.kind else {
+            panic!("expected an addition");
+        };
+        assert_eq!("-a", text(left.span));
+        assert_eq!("g(a, 2)", text(right.span));
+        let ExpressionKind::FunctionCall(call) = &right.kind else {
+            panic!("expected a function call");
+        };
+        assert_eq!("2", text(call.args[1].span));
+
+        assert_eq!("return x", text(function.block[1].span));
+    }
+
+    #[test]
+    fn parenthesized_expressions_include_the_parentheses_in_their_span() {
+        let source = "fn f(a) { return (a + 1) * 2; }";
+        let program = parse_program(source).expect("should have been able to parse program");
+        let BlockElementKind::ReturnStatement(expression) = &program.functions[0].block[0].kind
+        else {
+            panic!("expected a return statement");
+        };
+        let ExpressionKind::Mul(left, _) = &expression.kind else {
+            panic!("expected a multiplication");
+        };
+        assert_eq!(
+            "(a + 1) * 2",
+            &source[expression.span.start..expression.span.end]
+        );
+        assert_eq!("(a + 1)", &source[left.span.start..left.span.end]);
+    }
+
+    #[test]
+    fn can_format_errors_pointing_at_a_span() {
+        let source = "fn f() {\n  return a + 1;\n}";
+        let message = format_error_at(source, "test.mj", Span::new(18, 19), "oops");
+        assert_eq!(
+            message,
+            [
+                " --> test.mj:2:10",
+                "  |",
+                "2 |   return a + 1;",
+                "  |          ^",
+                "  |",
+                "  = oops",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn can_parse_if_else_chains() {
+        let mut program = parse_program(
+            r"fn f(x) {
+            if (x) {
+                return 1;
+            } else if (x - 1) {
+                return 2;
+            } else {
+                return 3;
+            }
+        }",
+        )
+        .expect("should have been able to parse program");
+        assert_eq!(
+            vec![stmt(BlockElementKind::IfStatement {
+                condition: expr(ExpressionKind::Identifier("x")),
+                then_block: vec![stmt(BlockElementKind::ReturnStatement(expr(
+                    ExpressionKind::Number(1)
+                )))],
+                else_block: Some(vec![stmt(BlockElementKind::IfStatement {
+                    condition: expr(ExpressionKind::Sub(
+                        boxed(ExpressionKind::Identifier("x")),
+                        boxed(ExpressionKind::Number(1))
+                    )),
+                    then_block: vec![stmt(BlockElementKind::ReturnStatement(expr(
+                        ExpressionKind::Number(2)
+                    )))],
+                    else_block: Some(vec![stmt(BlockElementKind::ReturnStatement(expr(
+                        ExpressionKind::Number(3)
+                    )))]),
+                })]),
+            })],
+            without_spans(program.functions.remove(0).block)
+        );
+    }
+
+    #[test]
+    fn can_parse_comparison_and_logical_operators_with_precedence() {
+        let mut program = parse_program(r"fn f(a, b) { return !a || a + 1 < b && a != 0; }")
+            .expect("should have been able to parse program");
+        assert_eq!(
+            vec![stmt(BlockElementKind::ReturnStatement(expr(
+                ExpressionKind::LogicalOr(
+                    boxed(ExpressionKind::LogicalNot(boxed(
+                        ExpressionKind::Identifier("a")
+                    ))),
+                    boxed(ExpressionKind::LogicalAnd(
+                        boxed(ExpressionKind::LessThan(
+                            boxed(ExpressionKind::Add(
+                                boxed(ExpressionKind::Identifier("a")),
+                                boxed(ExpressionKind::Number(1))
+                            )),
+                            boxed(ExpressionKind::Identifier("b"))
+                        )),
+                        boxed(ExpressionKind::NotEqual(
+                            boxed(ExpressionKind::Identifier("a")),
+                            boxed(ExpressionKind::Number(0))
+                        ))
+                    ))
+                )
+            )))],
+            without_spans(program.functions.remove(0).block)
+        );
+    }
+
+    #[test]
+    fn can_parse_bitwise_operators_with_c_precedence() {
+        let mut program = parse_program(r"fn f(a, b) { return a | b ^ ~a & b << 1 == 2 % a; }")
+            .expect("should have been able to parse program");
+        assert_eq!(
+            vec![stmt(BlockElementKind::ReturnStatement(expr(
+                ExpressionKind::BitwiseOr(
+                    boxed(ExpressionKind::Identifier("a")),
+                    boxed(ExpressionKind::BitwiseXor(
+                        boxed(ExpressionKind::Identifier("b")),
+                        boxed(ExpressionKind::BitwiseAnd(
+                            boxed(ExpressionKind::BitwiseNot(boxed(
+                                ExpressionKind::Identifier("a")
+                            ))),
+                            boxed(ExpressionKind::Equal(
+                                boxed(ExpressionKind::ShiftLeft(
+                                    boxed(ExpressionKind::Identifier("b")),
+                                    boxed(ExpressionKind::Number(1))
+                                )),
+                                boxed(ExpressionKind::Rem(
+                                    boxed(ExpressionKind::Number(2)),
+                                    boxed(ExpressionKind::Identifier("a"))
+                                ))
+                            ))
+                        ))
+                    ))
+                )
+            )))],
+            without_spans(program.functions.remove(0).block)
+        );
+    }
+
+    #[test]
+    fn can_parse_while_with_break_and_continue() {
+        let mut program = parse_program(
+            r"fn f(x) {
+            while (x) {
+                if (x - 1) {
+                    break;
+                }
+                continue;
+            }
+        }",
+        )
+        .expect("should have been able to parse program");
+        assert_eq!(
+            vec![stmt(BlockElementKind::WhileStatement {
+                condition: expr(ExpressionKind::Identifier("x")),
+                body: vec![
+                    stmt(BlockElementKind::IfStatement {
+                        condition: expr(ExpressionKind::Sub(
+                            boxed(ExpressionKind::Identifier("x")),
+                            boxed(ExpressionKind::Number(1))
+                        )),
+                        then_block: vec![stmt(BlockElementKind::BreakStatement)],
+                        else_block: None,
+                    }),
+                    stmt(BlockElementKind::ContinueStatement),
+                ],
+            })],
+            without_spans(program.functions.remove(0).block)
+        );
+    }
+
+    #[test]
+    fn can_parse_compound_assignments() {
+        let compound = parse_program(
+            r"fn f(x, y) {
+            x += 2;
+            x -= y - 1;
+            x <<= y * 3;
+            x %= y;
+            x ^= 1;
+            x++;
+            x--;
+        }",
+        )
+        .expect("should have been able to parse program");
+        let expanded = parse_program(
+            r"fn f(x, y) {
+            x = x + 2;
+            x = x - (y - 1);
+            x = x << (y * 3);
+            x = x % y;
+            x = x ^ 1;
+            x = x + 1;
+            x = x - 1;
+        }",
+        )
+        .expect("should have been able to parse program");
+        assert_eq!(
+            without_spans(expanded.functions.into_iter().next().unwrap().block),
+            without_spans(compound.functions.into_iter().next().unwrap().block)
+        );
+    }
+
+    #[test]
+    fn can_parse_expression_statements() {
+        let mut program = parse_program(r"fn f(a) { store(a, 0, 1); return 0; }")
+            .expect("should have been able to parse program");
+        assert_eq!(
+            vec![
+                stmt(BlockElementKind::ExpressionStatement(expr(
+                    ExpressionKind::FunctionCall(FunctionCall {
+                        name: "store",
+                        args: vec![
+                            expr(ExpressionKind::Identifier("a")),
+                            expr(ExpressionKind::Number(0)),
+                            expr(ExpressionKind::Number(1)),
+                        ],
+                    })
+                ))),
+                stmt(BlockElementKind::ReturnStatement(expr(
+                    ExpressionKind::Number(0)
+                ))),
+            ],
+            without_spans(program.functions.remove(0).block)
+        );
+    }
+
+    #[test]
+    fn can_parse_types_and_casts() {
+        let mut program =
+            parse_program(r"fn f(x: u32, y) -> u64 { let z: u8 = -x as u8 + true; return z; }")
+                .expect("should have been able to parse program");
+        let function = program.functions.remove(0);
+        assert_eq!(
+            vec![Type::U32, Type::I64],
+            function.args.iter().map(|arg| arg.ty).collect::<Vec<_>>()
+        );
+        assert_eq!(Type::U64, function.return_type);
+        assert_eq!(
+            stmt(BlockElementKind::LetStatement {
+                name: "z",
+                ty: Some(Type::U8),
+                expression: expr(ExpressionKind::Add(
+                    boxed(ExpressionKind::Cast(
+                        boxed(ExpressionKind::Negate(boxed(ExpressionKind::Identifier(
+                            "x"
+                        )))),
+                        Type::U8
+                    )),
+                    boxed(ExpressionKind::Boolean(true))
+                )),
+            }),
+            without_spans(function.block).remove(0)
+        );
+    }
+
+    #[test]
+    fn unknown_types_are_parse_errors() {
+        let error = parse_program("fn f(x: u16) { return x; }").unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("unknown type u16, expected one of i64, i32, u64, u32, u8, f64 or bool"));
+        assert!(parse_program("fn f() -> i8 { return 1; }").is_err());
+        assert!(parse_program("fn f() { return 1 as int; }").is_err());
+    }
+
+    #[test]
+    fn can_parse_doc_comments() {
+        let program = parse_program(
+            r"
+            /// Computes the answer.
+            ///
+            ///  Indented.
+            fn f() { return 42; }
+
+            // Not a doc comment
+            fn g() { return 0; }
+            ",
+        )
+        .expect("should have been able to parse program");
+        assert_eq!(
+            Some("Computes the answer.\n\n Indented."),
+            program.functions[0].doc_comment.as_deref()
+        );
+        assert_eq!(None, program.functions[1].doc_comment);
+    }
+
+    #[test]
+    fn can_parse_constants() {
+        let source = r"
+            /// The scale
+            const SCALE = 1000 * 3;
+            fn f() { return SCALE; }
+            const OFFSET = SCALE + 1;
+            ";
+        let mut program = parse_program(source).expect("should have been able to parse program");
+        assert_eq!(1, program.functions.len());
+        assert_eq!(
+            vec!["SCALE", "OFFSET"],
             program
+                .constants
+                .iter()
+                .map(|constant| constant.name)
+                .collect::<Vec<_>>()
+        );
+
+        let scale = &program.constants[0];
+        assert_eq!(Some("The scale"), scale.doc_comment.as_deref());
+        assert_eq!("SCALE", &source[scale.name_span.start..scale.name_span.end]);
+        assert_eq!(
+            "/// The scale\n            const SCALE = 1000 * 3;",
+            &source[scale.span.start..scale.span.end]
+        );
+
+        let mut offset = program.constants.remove(1).expression;
+        clear_expression_spans(&mut offset);
+        assert_eq!(
+            ExpressionKind::Add(
+                boxed(ExpressionKind::Identifier("SCALE")),
+                boxed(ExpressionKind::Number(1))
+            ),
+            offset.kind
+        );
+    }
+
+    #[test]
+    fn can_parse_extern_functions() {
+        let source = "/// Provided by the host\nextern fn lookup(key, default);";
+        let program = parse_program(source).expect("should have been able to parse program");
+        assert!(program.functions.is_empty());
+
+        let lookup = &program.extern_functions[0];
+        assert_eq!("lookup", lookup.name);
+        assert_eq!(
+            vec!["key", "default"],
+            lookup.args.iter().map(|arg| arg.name).collect::<Vec<_>>()
+        );
+        assert_eq!(Some("Provided by the host"), lookup.doc_comment.as_deref());
+        assert_eq!(source, &source[lookup.span.start..lookup.span.end]);
+    }
+
+    fn function_returning(expression: &str) -> String {
+        format!("fn f(x) {{ return {}; }}", expression)
+    }
+
+    /// Parses a program made by `function_returning`, and returns its expression
+    fn parse_returned_expression(source: &str) -> ExpressionKind<'_> {
+        let mut program = parse_program(source).expect("should have been able to parse program");
+        let block = without_spans(program.functions.remove(0).block);
+        let Some(BlockElement {
+            kind: BlockElementKind::ReturnStatement(expression),
+            ..
+        }) = block.into_iter().next()
+        else {
+            panic!("expected a return statement");
+        };
+        expression.kind
+    }
+
+    #[test]
+    fn can_parse_integer_literals() {
+        let cases = [
+            ("42", 42),
+            ("-42", -42),
+            ("1_000_000", 1_000_000),
+            ("0x42A", 0x42A),
+            ("-0xff", -0xFF),
+            ("0b1010_1010", 0b1010_1010),
+            ("0o755", 0o755),
+            ("9223372036854775807", i64::MAX),
+            ("-9223372036854775808", i64::MIN),
+            ("0x7FFF_FFFF_FFFF_FFFF", i64::MAX),
+            ("-0x8000000000000000", i64::MIN),
+        ];
+        for (literal, expected) in cases {
+            assert_eq!(
+                ExpressionKind::Number(expected),
+                parse_returned_expression(&function_returning(literal)),
+                "parsing {}",
+                literal
+            );
+        }
+    }
+
+    #[test]
+    fn literals_above_i64_max_are_kept_as_their_bit_pattern() {
+        let cases = [
+            ("9223372036854775808", i64::MIN),
+            ("18446744073709551614", -2),
+            ("0xFFFF_FFFF_FFFF_FFFF", -1),
+        ];
+        for (literal, expected) in cases {
+            assert_eq!(
+                ExpressionKind::UnsignedNumber(expected),
+                parse_returned_expression(&function_returning(literal)),
+                "parsing {}",
+                literal
+            );
+        }
+
+        let error = parse_program("fn f() { return 18446744073709551616; }")
+            .expect_err("should be out of range");
+        assert!(error
+            .to_string()
+            .contains("must be between -9223372036854775808 and 18446744073709551615"));
+    }
+
+    #[test]
+    fn can_parse_float_literals() {
+        let cases = [
+            ("1.5", 1.5),
+            ("-0.25", -0.25),
+            ("1_000.000_1", 1000.0001),
+            ("1e3", 1000.0),
+            ("2.5E-2", 0.025),
+        ];
+        for (literal, expected) in cases {
+            assert_eq!(
+                ExpressionKind::Float(expected),
+                parse_returned_expression(&function_returning(literal)),
+                "parsing {}",
+                literal
+            );
+        }
+
+        let error = parse_program("fn f() { return 1e999; }").expect_err("should be out of range");
+        assert!(error
+            .to_string()
+            .contains("float literal 1e999 is out of range"));
+    }
+
+    #[test]
+    fn minus_is_the_sign_of_a_literal_only_when_attached_to_it() {
+        assert_eq!(
+            ExpressionKind::Negate(boxed(ExpressionKind::Number(5))),
+            parse_returned_expression(&function_returning("- 5"))
         );
+        assert_eq!(
+            ExpressionKind::Negate(boxed(ExpressionKind::Identifier("x"))),
+            parse_returned_expression(&function_returning("-x"))
+        );
+        assert_eq!(
+            ExpressionKind::Sub(
+                boxed(ExpressionKind::Identifier("x")),
+                boxed(ExpressionKind::Number(1))
+            ),
+            parse_returned_expression(&function_returning("x-1"))
+        );
+        assert_eq!(
+            ExpressionKind::Sub(
+                boxed(ExpressionKind::Identifier("x")),
+                boxed(ExpressionKind::Number(-1))
+            ),
+            parse_returned_expression(&function_returning("x - -1"))
+        );
+    }
+
+    #[test]
+    fn can_parse_function_handles() {
+        assert_eq!(
+            ExpressionKind::FunctionCall(FunctionCall {
+                name: "apply",
+                args: vec![
+                    expr(ExpressionKind::FunctionHandle("f")),
+                    expr(ExpressionKind::Identifier("x"))
+                ]
+            }),
+            parse_returned_expression(&function_returning("apply(&f, x)"))
+        );
+        assert_eq!(
+            ExpressionKind::BitwiseAnd(
+                boxed(ExpressionKind::Identifier("x")),
+                boxed(ExpressionKind::FunctionHandle("f"))
+            ),
+            parse_returned_expression(&function_returning("x & &f"))
+        );
+        assert_eq!(
+            ExpressionKind::LogicalAnd(
+                boxed(ExpressionKind::Identifier("x")),
+                boxed(ExpressionKind::Identifier("f"))
+            ),
+            parse_returned_expression(&function_returning("x && f"))
+        );
+    }
+
+    #[test]
+    fn can_parse_match() {
+        let pattern = |value| MatchPattern {
+            value,
+            span: Span::default(),
+        };
+        assert_eq!(
+            ExpressionKind::Add(
+                boxed(ExpressionKind::Match {
+                    scrutinee: boxed(ExpressionKind::Mul(
+                        boxed(ExpressionKind::Identifier("x")),
+                        boxed(ExpressionKind::Number(2))
+                    )),
+                    arms: vec![
+                        MatchArm {
+                            patterns: Some(vec![pattern(0)]),
+                            expression: expr(ExpressionKind::Identifier("x")),
+                            span: Span::default(),
+                        },
+                        MatchArm {
+                            patterns: Some(vec![pattern(-1), pattern(0x10)]),
+                            expression: expr(ExpressionKind::Number(2)),
+                            span: Span::default(),
+                        },
+                        MatchArm {
+                            patterns: None,
+                            expression: expr(ExpressionKind::Number(3)),
+                            span: Span::default(),
+                        },
+                    ],
+                }),
+                boxed(ExpressionKind::Number(1))
+            ),
+            parse_returned_expression(&function_returning(
+                "match x * 2 { 0 => x, -1 | 0x10 => 2, _ => 3, } + 1"
+            ))
+        );
+    }
+
+    #[test]
+    fn can_parse_imports_and_qualified_names() {
+        let source = "import \"finance.mj\";\nimport \"lib/util.mj\";\nfn f() { return finance::npv(&util::g); }";
+        let program = parse_program(source).expect("should have been able to parse program");
+        assert_eq!(
+            vec![
+                Import {
+                    path: "finance.mj",
+                    span: Span::new(7, 19),
+                },
+                Import {
+                    path: "lib/util.mj",
+                    span: Span::new(28, 41),
+                },
+            ],
+            program.imports
+        );
+        assert_eq!(program.imports, parse_imports(source));
+        assert_eq!(
+            ExpressionKind::FunctionCall(FunctionCall {
+                name: "finance::npv",
+                args: vec![expr(ExpressionKind::FunctionHandle("util::g"))]
+            }),
+            parse_returned_expression(
+                "import \"finance.mj\"; fn f(x) { return finance::npv(&util::g); }"
+            )
+        );
+
+        // Only the imports are needed, so the rest of the file can be invalid
+        assert_eq!(1, parse_imports("import \"a.mj\"; fn {").len());
+        assert!(parse_imports("fn f() { return 1; }").is_empty());
+    }
+
+    #[test]
+    fn out_of_range_literals_are_located_errors() {
+        for literal in [
+            "-9223372036854775809",
+            "18446744073709551616",
+            "-0xFFFF_FFFF_FFFF_FFFF",
+        ] {
+            let source = format!("fn f() {{\n  return 1 + {};\n}}", literal);
+            let error = parse_program(&source).expect_err("literal should be out of range");
+            let message = error.to_string();
+            assert!(message.contains(" --> 2:"), "{}", message);
+            assert!(message.contains("is out of range"), "{}", message);
+        }
     }
 
     #[test]
@@ -0,0 +1,149 @@
+use std::fmt;
+
+/// The types of the values that a program can manipulate. All of them are stored in
+/// 64-bit registers: narrower values are always kept sign-extended (for signed types)
+/// or zero-extended (for unsigned types and bool), so that widening them is free.
+/// `f64` values live in the floating-point registers, and the compiler handles them as
+/// their bits wherever it needs an `i64`, such as for constants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
+pub enum Type {
+    #[default]
+    I64,
+    I32,
+    U64,
+    U32,
+    U8,
+    /// Either 0 or 1
+    Bool,
+    F64,
+}
+
+impl Type {
+    pub fn from_name(name: &str) -> Option<Type> {
+        match name {
+            "i64" => Some(Type::I64),
+            "i32" => Some(Type::I32),
+            "u64" => Some(Type::U64),
+            "u32" => Some(Type::U32),
+            "u8" => Some(Type::U8),
+            "bool" => Some(Type::Bool),
+            "f64" => Some(Type::F64),
+            _ => None,
+        }
+    }
+
+    pub fn is_unsigned(&self) -> bool {
+        matches!(self, Type::U64 | Type::U32 | Type::U8)
+    }
+
+    pub fn is_float(&self) -> bool {
+        *self == Type::F64
+    }
+
+    /// Whether values of this type do not use the whole register, and thus need to be
+    /// normalized after operations that can overflow
+    pub fn is_narrow(&self) -> bool {
+        !matches!(self, Type::I64 | Type::U64 | Type::F64)
+    }
+
+    /// Whether every value of this type is also a value of `other`, with the same
+    /// representation. These are the only conversions that happen implicitly.
+    pub fn can_widen_to(&self, other: Type) -> bool {
+        match self {
+            _ if *self == other => true,
+            Type::Bool => !matches!(other, Type::Bool | Type::F64),
+            Type::U8 => matches!(other, Type::U32 | Type::U64 | Type::I32 | Type::I64),
+            Type::U32 => matches!(other, Type::U64 | Type::I64),
+            Type::I32 => other == Type::I64,
+            Type::I64 | Type::U64 | Type::F64 => false,
+        }
+    }
+
+    /// Converts a 64-bit value to this type, truncating it and then sign- or
+    /// zero-extending it. Any non-zero value becomes 1 when converted to `bool`.
+    /// The bits of an `f64` are left untouched.
+    pub fn normalize(&self, value: i64) -> i64 {
+        match self {
+            Type::I64 | Type::U64 | Type::F64 => value,
+            Type::I32 => value as i32 as i64,
+            Type::U32 => value as u32 as i64,
+            Type::U8 => value as u8 as i64,
+            Type::Bool => (value != 0) as i64,
+        }
+    }
+
+    /// The smallest and the largest value of an integer type
+    pub fn bounds(&self) -> (i128, i128) {
+        match self {
+            Type::I64 => (i64::MIN as i128, i64::MAX as i128),
+            Type::I32 => (i32::MIN as i128, i32::MAX as i128),
+            Type::U64 => (0, u64::MAX as i128),
+            Type::U32 => (0, u32::MAX as i128),
+            Type::U8 => (0, u8::MAX as i128),
+            Type::Bool => (0, 1),
+            Type::F64 => unreachable!("f64 is not an integer type"),
+        }
+    }
+
+    /// Whether an integer literal with the given value can have this type. For `f64`,
+    /// the value must be converted exactly
+    pub fn can_represent(&self, value: i64) -> bool {
+        match self {
+            Type::Bool => false,
+            Type::U64 => value >= 0,
+            Type::F64 => value.unsigned_abs() <= 1 << f64::MANTISSA_DIGITS,
+            _ => self.normalize(value) == value,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::I64 => write!(f, "i64"),
+            Type::I32 => write!(f, "i32"),
+            Type::U64 => write!(f, "u64"),
+            Type::U32 => write!(f, "u32"),
+            Type::U8 => write!(f, "u8"),
+            Type::Bool => write!(f, "bool"),
+            Type::F64 => write!(f, "f64"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Type;
+
+    #[test]
+    fn only_lossless_conversions_are_widening() {
+        assert!(Type::U8.can_widen_to(Type::I32));
+        assert!(Type::U32.can_widen_to(Type::U64));
+        assert!(Type::Bool.can_widen_to(Type::U8));
+        assert!(!Type::I32.can_widen_to(Type::U64));
+        assert!(!Type::U64.can_widen_to(Type::I64));
+        assert!(!Type::I64.can_widen_to(Type::I32));
+        assert!(!Type::U8.can_widen_to(Type::Bool));
+        assert!(!Type::I32.can_widen_to(Type::F64));
+        assert!(!Type::Bool.can_widen_to(Type::F64));
+        assert!(!Type::F64.can_widen_to(Type::I64));
+    }
+
+    #[test]
+    fn can_normalize_values() {
+        assert_eq!(Type::U8.normalize(300), 44);
+        assert_eq!(Type::U8.normalize(-1), 255);
+        assert_eq!(Type::I32.normalize(0x8000_0000), i32::MIN as i64);
+        assert_eq!(Type::U32.normalize(-1), u32::MAX as i64);
+        assert_eq!(Type::Bool.normalize(42), 1);
+        assert_eq!(Type::U64.normalize(-1), -1);
+        assert!(Type::U8.can_represent(255));
+        assert!(!Type::U8.can_represent(256));
+        assert!(!Type::U64.can_represent(-1));
+        assert!(Type::I32.can_represent(i32::MIN as i64));
+        assert!(Type::F64.can_represent(-(1 << 53)));
+        assert!(!Type::F64.can_represent((1 << 53) + 1));
+        assert_eq!(Type::U64.bounds(), (0, u64::MAX as i128));
+        assert_eq!(Type::I32.bounds(), (i32::MIN as i128, i32::MAX as i128));
+    }
+}